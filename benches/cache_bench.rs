@@ -0,0 +1,116 @@
+use std::{
+    fmt::Debug,
+    fs,
+    io::Write,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fuser::MountOption;
+use temp_dir::TempDir;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    util::SubscriberInitExt,
+    Layer,
+};
+use zip::{write::SimpleFileOptions, ZipWriter};
+use zipfs::{CacheConfig, ZipFs};
+
+const ARCHIVES: usize = 20;
+
+static ARCHIVE_OPENS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the `open_zip` debug events, i.e. archives opened on a cache miss
+struct OpenCounter;
+
+struct OpenVisitor(bool);
+
+impl Visit for OpenVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" && format!("{:?}", value).starts_with("open_zip") {
+            self.0 = true;
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for OpenCounter {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::DEBUG {
+            return;
+        }
+
+        let mut visitor = OpenVisitor(false);
+        event.record(&mut visitor);
+
+        if visitor.0 {
+            ARCHIVE_OPENS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn create_archives() -> TempDir {
+    let data = TempDir::new().unwrap();
+
+    for i in 0..ARCHIVES {
+        let file = fs::File::create(data.path().join(format!("archive-{i}.zip"))).unwrap();
+        let mut zip = ZipWriter::new(file);
+        zip.start_file("file.txt", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all("some content\n".repeat(15).as_bytes())
+            .unwrap();
+        zip.finish().unwrap();
+    }
+
+    data
+}
+
+fn bench_round_robin(c: &mut Criterion) {
+    tracing_subscriber::registry().with(OpenCounter).init();
+
+    let data = create_archives();
+    let mut group = c.benchmark_group("cache_eviction");
+
+    for cache_size in [4, 20] {
+        let mnt = TempDir::new().unwrap();
+        let caches = CacheConfig {
+            archive_entries: NonZeroUsize::new(cache_size).unwrap(),
+            content_bytes: 0,
+            ..CacheConfig::default()
+        };
+        let fs = ZipFs::new(data.path().to_path_buf(), caches, None);
+        let guard = fuser::spawn_mount2(fs, mnt.path(), &[MountOption::RO]).unwrap();
+
+        ARCHIVE_OPENS.store(0, Ordering::Relaxed);
+        let mut rounds = 0;
+
+        group.bench_function(BenchmarkId::new("round_robin", cache_size), |b| {
+            b.iter(|| {
+                for i in 0..ARCHIVES {
+                    let path = mnt.path().join(format!("archive-{i}.zip/file.txt"));
+                    fs::read(path).unwrap();
+                }
+
+                rounds += 1;
+            })
+        });
+
+        println!(
+            "cache_size = {}: {} archive opens over {} rounds",
+            cache_size,
+            ARCHIVE_OPENS.load(Ordering::Relaxed),
+            rounds
+        );
+
+        drop(guard);
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_round_robin);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    num::NonZeroUsize,
+    os::fd::AsRawFd,
+    ptr, slice,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fuser::MountOption;
+use temp_dir::TempDir;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+use zipfs::{CacheConfig, ZipFs};
+
+const ENTRY_SIZE: usize = 64 * 1024 * 1024;
+const BUF_SIZE: usize = 128 * 1024;
+
+fn create_archive() -> TempDir {
+    let data = TempDir::new().unwrap();
+
+    let file = fs::File::create(data.path().join("stored.zip")).unwrap();
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+    zip.start_file("large.bin", options).unwrap();
+
+    let block = (0..BUF_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    for _ in 0..ENTRY_SIZE / BUF_SIZE {
+        zip.write_all(&block).unwrap();
+    }
+    zip.finish().unwrap();
+
+    data
+}
+
+/// Touch every byte of `file` through a shared read-only mapping
+fn sum_mapped(file: &fs::File) -> u64 {
+    // SAFETY: The mapping is private to this function and the file isn't modified meanwhile
+    unsafe {
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            ENTRY_SIZE,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+
+        let data = slice::from_raw_parts(addr as *const u8, ENTRY_SIZE);
+        let sum = data.iter().map(|b| *b as u64).sum();
+
+        libc::munmap(addr, ENTRY_SIZE);
+        sum
+    }
+}
+
+fn sum_read(file: &mut fs::File) -> u64 {
+    let mut buf = vec![0; BUF_SIZE];
+    let mut sum = 0;
+
+    loop {
+        match file.read(&mut buf).unwrap() {
+            0 => break sum,
+            read => sum += buf[..read].iter().map(|b| *b as u64).sum::<u64>(),
+        }
+    }
+}
+
+fn bench_stored_entry(c: &mut Criterion) {
+    let data = create_archive();
+
+    let mut group = c.benchmark_group("stored_entry");
+    group.throughput(Throughput::Bytes(ENTRY_SIZE as u64));
+    group.sample_size(10);
+
+    // NOTE: Direct handles keep the pages cached across opens, the iterations after the first
+    // one don't reach the file system at all
+    for (name, direct_stored) in [("", false), ("_direct", true)] {
+        let mnt = TempDir::new().unwrap();
+        let caches = CacheConfig {
+            archive_entries: NonZeroUsize::new(4).unwrap(),
+            content_bytes: 0,
+            ..CacheConfig::default()
+        };
+        let fs =
+            ZipFs::new(data.path().to_path_buf(), caches, None).with_direct_stored(direct_stored);
+        let guard = fuser::spawn_mount2(fs, mnt.path(), &[MountOption::RO]).unwrap();
+        let path = mnt.path().join("stored.zip/large.bin");
+
+        group.bench_function(format!("read{}", name), |b| {
+            b.iter(|| sum_read(&mut fs::File::open(&path).unwrap()))
+        });
+
+        group.bench_function(format!("mmap{}", name), |b| {
+            b.iter(|| sum_mapped(&fs::File::open(&path).unwrap()))
+        });
+
+        drop(guard);
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_stored_entry);
+criterion_main!(benches);
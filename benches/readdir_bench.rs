@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use zipfs::{dir_children_parallel, dir_children_serial};
+
+const ENTRIES: usize = 50_000;
+
+fn entry_names() -> Vec<String> {
+    (0..ENTRIES)
+        .map(|i| format!("dir-{}/nested/file-{i}.txt", i % 100))
+        .collect()
+}
+
+fn bench_readdir(c: &mut Criterion) {
+    let names = entry_names();
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("readdir_50000");
+
+    for dir in ["", "dir-42/nested/"] {
+        group.bench_function(format!("serial {:?}", dir), |b| {
+            b.iter(|| dir_children_serial(&names, dir))
+        });
+        group.bench_function(format!("parallel {:?}", dir), |b| {
+            b.iter(|| dir_children_parallel(&names, dir))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_readdir);
+criterion_main!(benches);
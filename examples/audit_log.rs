@@ -0,0 +1,68 @@
+//! Mount the data directory given as first argument at the mount point given as second, and
+//! print an audit line for every file opened or read until unmounted with `fusermount -u`
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use color_eyre::eyre::eyre;
+use fuser::MountOption;
+use zipfs::{EventListener, Requester, ZipFsBuilder};
+
+enum Event {
+    Open(PathBuf, Requester),
+    Read(PathBuf, u64, usize, Requester),
+}
+
+/// Hands the events over to the printing thread, so that serving the mount never waits on it
+struct AuditLog(Sender<Event>);
+
+impl EventListener for AuditLog {
+    fn on_open(&self, path: &Path, requester: Requester) {
+        let _ = self.0.send(Event::Open(path.to_path_buf(), requester));
+    }
+
+    fn on_read(&self, path: &Path, offset: u64, len: usize, requester: Requester) {
+        let _ = self
+            .0
+            .send(Event::Read(path.to_path_buf(), offset, len, requester));
+    }
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let mut args = env::args_os().skip(1);
+    let (Some(data_dir), Some(mount_point)) = (args.next(), args.next()) else {
+        return Err(eyre!("Usage: audit_log <DATA_DIR> <MOUNT_POINT>"));
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let printer = thread::spawn(move || {
+        for event in rx {
+            match event {
+                Event::Open(path, who) => {
+                    println!("uid={} pid={} opened {:?}", who.uid, who.pid, path);
+                }
+                Event::Read(path, offset, len, who) => {
+                    println!(
+                        "uid={} pid={} read {} bytes at {} from {:?}",
+                        who.uid, who.pid, len, offset, path
+                    );
+                }
+            }
+        }
+    });
+
+    let mount = ZipFsBuilder::new(data_dir)
+        .event_listener(AuditLog(tx))
+        .spawn_mount(mount_point, &[MountOption::RO])?;
+    mount.join()?;
+
+    // NOTE: The listener, and with it the sender, is dropped along with the file system
+    printer.join().unwrap();
+    Ok(())
+}
@@ -0,0 +1,18 @@
+//! Regenerate the archives in `tests/data`, or in the directory given as first argument
+
+use std::{env, path::PathBuf};
+
+#[path = "../tests/fixtures/mod.rs"]
+mod fixtures;
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let dir = env::args_os()
+        .nth(1)
+        .map_or_else(|| PathBuf::from("tests/data"), PathBuf::from);
+
+    fixtures::create(&dir)?;
+    println!("Created test data in {:?}", dir);
+    Ok(())
+}
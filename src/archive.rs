@@ -0,0 +1,517 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs::File,
+    io::{self, Read, Seek},
+    path::Path,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+use zip::{read::ZipFile, CompressionMethod, ZipArchive};
+
+use crate::{
+    error::ZipFsError,
+    filesystem::is_archive_name,
+    options::Options,
+    positioned_reader::{PositionedReader, ReadAtSize},
+};
+
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+
+/// Fields shared by local and central headers, with their offsets in each of them
+const HEADER_FIELDS: [(&str, usize, usize); 3] = [
+    ("crc32", 14, 16),
+    ("compressed size", 18, 20),
+    ("uncompressed size", 22, 24),
+];
+
+/// Leading bytes of formats an archive might turn out to be, the first match wins
+const SIGNATURES: [(&[u8], &str); 7] = [
+    (b"PK\x03\x04", "zip"),
+    (b"PK\x05\x06", "zip (empty)"),
+    (b"PK\x07\x08", "zip (spanned)"),
+    (b"\x1f\x8b", "gzip"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"Rar!\x1a\x07", "rar"),
+];
+
+/// Extension of Python wheels, which have their metadata aliased at their root
+const WHEEL_EXT: &str = "whl";
+
+/// Name of the alias of the metadata of a wheel, at its root
+pub(crate) const WHEEL_METADATA: &str = "METADATA";
+
+/// Parse the central directory of the archive in `file`, giving up after `timeout`.
+///
+/// NOTE: The worker can't be cancelled, on timeout it is left to finish in the background
+pub(crate) fn read_archive(
+    source: Box<dyn ReadAtSize>,
+    timeout: Option<Duration>,
+) -> Result<ZipArchive<PositionedReader>, ZipFsError> {
+    let reader = PositionedReader::new(source);
+    let Some(timeout) = timeout else {
+        return Ok(ZipArchive::new(reader)?);
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(ZipArchive::new(reader)));
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+    }
+}
+
+/// Whether `name` has empty, `.` or `..` components, apart from the trailing `/` of directories
+fn needs_normalizing(name: &str) -> bool {
+    let name = name.strip_suffix('/').unwrap_or(name);
+    name.split('/')
+        .any(|component| matches!(component, "" | "." | ".."))
+}
+
+/// `name` without empty and `.` components and with `..` resolved, keeping the trailing `/` of
+/// directories. `None` for names left empty or climbing out of the archive.
+pub(crate) fn normalize_entry_name(name: &str) -> Option<Cow<'_, str>> {
+    if !needs_normalizing(name) {
+        return Some(Cow::Borrowed(name));
+    }
+
+    let mut components = Vec::new();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+
+    if components.is_empty() {
+        return None;
+    }
+
+    let mut normalized = components.join("/");
+    if name.ends_with('/') {
+        normalized.push('/');
+    }
+
+    Some(Cow::Owned(normalized))
+}
+
+/// Normalized entry names of `archive` in archive order, entries normalizing to the same name
+/// only listed once
+pub(crate) fn entry_names<R: Read + Seek>(archive: &ZipArchive<R>) -> Vec<Cow<'_, str>> {
+    let mut seen = HashSet::new();
+
+    archive
+        .file_names()
+        .filter_map(normalize_entry_name)
+        .filter(|name| seen.insert(name.clone()))
+        .collect()
+}
+
+/// Index of the entry whose normalized name is `name`, the first one if several are
+pub(crate) fn entry_index<R: Read + Seek>(archive: &ZipArchive<R>, name: &str) -> Option<usize> {
+    if let Some(index) = archive.index_for_name(name) {
+        return Some(index);
+    }
+
+    (0..archive.len()).find(|&index| {
+        archive.name_for_index(index).is_some_and(|raw| {
+            needs_normalizing(raw) && normalize_entry_name(raw).as_deref() == Some(name)
+        })
+    })
+}
+
+/// Name of the `METADATA` entry aliased at the root of the wheel at `zip_path`. Only wheels with
+/// a single `.dist-info` directory and no `METADATA` of their own at the root have one.
+pub(crate) fn wheel_metadata<R: Read + Seek>(
+    zip_path: &Path,
+    archive: &ZipArchive<R>,
+) -> Option<String> {
+    let is_wheel = zip_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(WHEEL_EXT));
+    if !is_wheel || entry_index(archive, WHEEL_METADATA).is_some() {
+        return None;
+    }
+
+    let names = entry_names(archive);
+    let dist_infos = names
+        .iter()
+        .filter_map(|name| name.split_once('/'))
+        .map(|(dir, _)| dir)
+        .filter(|dir| dir.ends_with(".dist-info"))
+        .collect::<HashSet<_>>();
+
+    let [dist_info] = dist_infos.into_iter().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+
+    let metadata = format!("{}/{}", dist_info, WHEEL_METADATA);
+    entry_index(archive, &metadata).map(|_| metadata)
+}
+
+/// Normalized `name` of an entry of the archive at `zip_path`, or of the entry it aliases
+pub(crate) fn resolve_alias<'a, R: Read + Seek>(
+    zip_path: &Path,
+    archive: &ZipArchive<R>,
+    name: &'a str,
+) -> Cow<'a, str> {
+    match name == WHEEL_METADATA {
+        true => wheel_metadata(zip_path, archive).map_or(Cow::Borrowed(name), Cow::Owned),
+        false => Cow::Borrowed(name),
+    }
+}
+
+/// Whether some entry of `archive` is below the directory `name`, which may have no entry of
+/// its own
+pub(crate) fn is_implicit_dir<R: Read + Seek>(archive: &ZipArchive<R>, name: &str) -> bool {
+    let prefix = format!("{}/", name);
+    archive
+        .file_names()
+        .filter_map(normalize_entry_name)
+        .any(|entry| entry.starts_with(&prefix))
+}
+
+/// Entry `index` of `archive` for reading, decrypted with `password` if it is encrypted.
+/// Encrypted entries can't be opened without one, nor entries compressed with a method this
+/// build can't decompress.
+pub(crate) fn open_entry<'a, R: Read + Seek>(
+    archive: &'a mut ZipArchive<R>,
+    index: usize,
+    password: Option<&[u8]>,
+) -> Result<ZipFile<'a>, ZipFsError> {
+    check_method(archive, index)?;
+
+    let entry = match password {
+        // NOTE: The password is ignored for entries which aren't encrypted
+        Some(password) => archive.by_index_decrypt(index, password)?,
+        None => archive.by_index(index)?,
+    };
+
+    Ok(entry)
+}
+
+/// Decompressed contents of entry `index` of `archive`, see [`open_entry`]. The zip crate
+/// misreads WinZip LZMA entries, which are decoded with lzma-rs instead.
+pub(crate) fn read_entry<'a, R: Read + Seek>(
+    archive: &'a mut ZipArchive<R>,
+    index: usize,
+    password: Option<&[u8]>,
+) -> Result<Box<dyn Read + 'a>, ZipFsError> {
+    #[cfg(feature = "lzma")]
+    if archive
+        .by_index_raw(index)
+        .is_ok_and(|entry| entry.compression() == CompressionMethod::Lzma && !entry.encrypted())
+    {
+        let entry = archive.by_index_raw(index)?;
+        return Ok(Box::new(crate::lzma::LzmaReader::new(entry)?));
+    }
+
+    Ok(Box::new(open_entry(archive, index, password)?))
+}
+
+/// Entry `index` of `archive` for its size, time and type. Entries compressed with an
+/// unsupported method come from the central directory, so that they are still listed as files.
+pub(crate) fn entry_metadata<'a, R: Read + Seek>(
+    archive: &'a mut ZipArchive<R>,
+    index: usize,
+    password: Option<&[u8]>,
+) -> Option<ZipFile<'a>> {
+    match check_method(archive, index) {
+        Ok(()) => open_entry(archive, index, password).ok(),
+        Err(_) => archive.by_index_raw(index).ok(),
+    }
+}
+
+/// Fail with `UnsupportedCompression` if the method of entry `index` can't be decompressed
+fn check_method<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+) -> Result<(), ZipFsError> {
+    match unsupported_method(&archive.by_index_raw(index)?) {
+        Some(method) => Err(ZipFsError::UnsupportedCompression(method)),
+        None => Ok(()),
+    }
+}
+
+/// Name of the entry stored in `archive` for the normalized `name`, `name` itself if there is
+/// no such entry
+pub(crate) fn raw_entry_name<R: Read + Seek>(archive: &ZipArchive<R>, name: &str) -> String {
+    entry_index(archive, name)
+        .and_then(|index| archive.name_for_index(index))
+        .unwrap_or(name)
+        .to_string()
+}
+
+/// Fields of the local file header of entry `index` disagreeing with its central directory
+/// record. The data is located through the former but read according to the latter.
+pub(crate) fn local_header_mismatches(
+    archive: &mut ZipArchive<PositionedReader>,
+    index: usize,
+) -> Result<Vec<&'static str>, ZipFsError> {
+    let (header_start, central_header_start) = archive
+        .by_index_raw(index)
+        .map(|entry| (entry.header_start(), entry.central_header_start()))?;
+
+    let reader = archive.clone().into_inner();
+    let mut local = [0; LOCAL_HEADER_SIZE];
+    let mut central = [0; CENTRAL_HEADER_SIZE];
+    reader.source().read_exact_at(&mut local, header_start)?;
+    reader
+        .source()
+        .read_exact_at(&mut central, central_header_start)?;
+
+    let u16_at = |buf: &[u8], at: usize| u16::from_le_bytes([buf[at], buf[at + 1]]);
+    let u32_at = |buf: &[u8], at: usize| {
+        u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+    };
+
+    let mut mismatches = Vec::new();
+    if u16_at(&local, 8) != u16_at(&central, 10) {
+        mismatches.push("compression method");
+    }
+
+    // NOTE: With a data descriptor the local header doesn't carry sizes or crc32
+    if u16_at(&local, 6) & 0x08 == 0 {
+        for (field, local_at, central_at) in HEADER_FIELDS {
+            if u32_at(&local, local_at) != u32_at(&central, central_at) {
+                mismatches.push(field);
+            }
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// How the mount presents a file of the data directory
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Presentation {
+    /// Not an archive by its extension, served as is
+    File,
+    Directory,
+    /// An archive that failed to open, served as a directory without entries
+    EmptyDirectory,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InspectedEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub method: String,
+    pub encrypted: bool,
+}
+
+/// What the mount would make of an archive, as reported by `zipfs inspect`
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    /// Guessed from the leading bytes of the file
+    pub format: &'static str,
+    pub presented_as: Presentation,
+    pub entry_count: usize,
+    /// The first entries, by order in the central directory
+    pub entries: Vec<InspectedEntry>,
+    pub warnings: Vec<String>,
+    /// Why the archive could not be opened
+    pub error: Option<String>,
+}
+
+/// Open the archive at `path` the way the mount would and describe up to `limit` entries
+pub fn inspect(path: &Path, options: &Options, limit: usize) -> io::Result<Inspection> {
+    let mut file = File::open(path)?;
+    let mut magic = [0; 6];
+    let read = file.read(&mut magic)?;
+
+    let format = SIGNATURES
+        .iter()
+        .find(|(signature, _)| magic[..read].starts_with(signature))
+        .map_or("unknown", |(_, format)| format);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut inspection = Inspection {
+        format,
+        presented_as: Presentation::File,
+        entry_count: 0,
+        entries: Vec::new(),
+        warnings: Vec::new(),
+        error: None,
+    };
+
+    if !options.chroot_into_zip && !is_archive_name(&name, &options.archive_exts) {
+        inspection.warnings.push(format!(
+            "Extension is not one of the archive extensions: {}",
+            options.archive_exts.join(", ")
+        ));
+    } else {
+        inspection.presented_as = Presentation::Directory;
+    }
+
+    let timeout = options.open_timeout.map(Duration::from_millis);
+    let mut archive = match read_archive(Box::new(File::open(path)?), timeout) {
+        Ok(archive) => archive,
+        Err(err) => {
+            if inspection.presented_as == Presentation::Directory {
+                inspection.presented_as = Presentation::EmptyDirectory;
+            }
+
+            inspection.error = Some(err.to_string());
+            return Ok(inspection);
+        }
+    };
+
+    inspection.entry_count = archive.len();
+    for index in 0..archive.len() {
+        let (entry, mut warnings) = match archive.by_index_raw(index) {
+            Ok(entry) => inspect_entry(&entry),
+            Err(err) => {
+                inspection
+                    .warnings
+                    .push(format!("Entry {} is unreadable: {}", index, err));
+                continue;
+            }
+        };
+
+        match local_header_mismatches(&mut archive, index) {
+            Ok(mismatches) if mismatches.is_empty() => {}
+            Ok(mismatches) => warnings.push(format!(
+                "local header disagrees with the central directory: {}",
+                mismatches.join(", ")
+            )),
+            Err(err) => warnings.push(format!("local header is unreadable: {}", err)),
+        }
+
+        inspection.warnings.extend(
+            warnings
+                .into_iter()
+                .map(|warning| format!("{}: {}", entry.name, warning)),
+        );
+
+        if inspection.entries.len() < limit {
+            inspection.entries.push(entry);
+        }
+    }
+
+    Ok(inspection)
+}
+
+/// Name of the compression method `method`, as numbered in the zip specification
+pub(crate) fn method_name(method: u16) -> &'static str {
+    match method {
+        0 => "Stored",
+        8 => "Deflate",
+        9 => "Deflate64",
+        12 => "BZIP2",
+        14 => "LZMA",
+        93 => "Zstandard",
+        95 => "XZ",
+        98 => "PPMd",
+        99 => "AES",
+        _ => "unknown",
+    }
+}
+
+/// Compression method of `entry` if it can't be decompressed, either unknown to the zip crate
+/// or left out of its features, such as LZMA without the `lzma` feature
+pub(crate) fn unsupported_method(entry: &ZipFile<'_>) -> Option<u16> {
+    // NOTE: The variant is deprecated in favour of constants, which can't be matched on
+    #[allow(deprecated)]
+    match entry.compression() {
+        CompressionMethod::Unsupported(method) => Some(method),
+        _ => None,
+    }
+}
+
+fn inspect_entry(entry: &ZipFile<'_>) -> (InspectedEntry, Vec<String>) {
+    let mut warnings = Vec::new();
+
+    if entry.enclosed_name().is_none() {
+        warnings.push("name escapes the archive, the entry is hidden".to_string());
+    }
+
+    if entry.encrypted() {
+        warnings.push("encrypted, reading needs a password".to_string());
+    }
+
+    if let Some(method) = unsupported_method(entry) {
+        warnings.push(format!(
+            "unsupported compression method {} ({})",
+            method,
+            method_name(method)
+        ));
+    }
+
+    let inspected = InspectedEntry {
+        name: entry.name().to_string(),
+        is_dir: entry.is_dir(),
+        size: entry.size(),
+        compressed_size: entry.compressed_size(),
+        method: entry.compression().to_string(),
+        encrypted: entry.encrypted(),
+    };
+
+    (inspected, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    fn archive(names: &[&str]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for name in names {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        }
+
+        ZipArchive::new(zip.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_wheel_metadata() {
+        let wheel = Path::new("example-1.0-py3-none-any.whl");
+        let single = archive(&["example/__init__.py", "example-1.0.dist-info/METADATA"]);
+        assert_eq!(
+            wheel_metadata(wheel, &single).as_deref(),
+            Some("example-1.0.dist-info/METADATA")
+        );
+        assert_eq!(
+            resolve_alias(wheel, &single, "METADATA"),
+            "example-1.0.dist-info/METADATA"
+        );
+        assert_eq!(wheel_metadata(Path::new("example.zip"), &single), None);
+
+        let several = archive(&["a-1.0.dist-info/METADATA", "b-1.0.dist-info/METADATA"]);
+        assert_eq!(wheel_metadata(wheel, &several), None);
+
+        let own = archive(&["METADATA", "example-1.0.dist-info/METADATA"]);
+        assert_eq!(wheel_metadata(wheel, &own), None);
+        assert_eq!(resolve_alias(wheel, &own, "METADATA"), "METADATA");
+    }
+
+    #[test]
+    fn test_normalize_entry_name() {
+        assert_eq!(normalize_entry_name("a/b.txt").as_deref(), Some("a/b.txt"));
+        assert_eq!(normalize_entry_name("a/").as_deref(), Some("a/"));
+        assert_eq!(normalize_entry_name("./foo").as_deref(), Some("foo"));
+        assert_eq!(normalize_entry_name("a/../b").as_deref(), Some("b"));
+        assert_eq!(normalize_entry_name("/a//b/./").as_deref(), Some("a/b/"));
+
+        assert_eq!(normalize_entry_name("."), None);
+        assert_eq!(normalize_entry_name("./"), None);
+        assert_eq!(normalize_entry_name(".."), None);
+        assert_eq!(normalize_entry_name("a/../../etc/passwd"), None);
+    }
+}
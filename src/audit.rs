@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+use tracing::{error, warn};
+use zipfs::{EventListener, Requester};
+
+/// Reads waiting to be written, beyond which they are counted as dropped instead
+const AUDIT_QUEUE_SIZE: usize = 4096;
+
+struct AuditEvent {
+    ts: SystemTime,
+    path: PathBuf,
+    offset: u64,
+    size: usize,
+    requester: Requester,
+}
+
+/// Queues every read served for the [`AuditWriter`], without ever waiting on it
+pub struct AuditLog {
+    events: SyncSender<AuditEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Appends the queued reads to the audit log, one JSON line each
+pub struct AuditWriter {
+    file: File,
+    events: Receiver<AuditEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// Open the audit log at `path` for appending, creating it if missing
+pub fn open(path: &Path) -> io::Result<(AuditLog, AuditWriter)> {
+    let file = File::options().create(true).append(true).open(path)?;
+    let (tx, rx) = mpsc::sync_channel(AUDIT_QUEUE_SIZE);
+    let dropped = Arc::new(AtomicU64::new(0));
+
+    let log = AuditLog {
+        events: tx,
+        dropped: dropped.clone(),
+    };
+    let writer = AuditWriter {
+        file,
+        events: rx,
+        dropped,
+    };
+    Ok((log, writer))
+}
+
+impl EventListener for AuditLog {
+    fn on_read(&self, path: &Path, offset: u64, len: usize, requester: Requester) {
+        let event = AuditEvent {
+            ts: SystemTime::now(),
+            path: path.to_path_buf(),
+            offset,
+            size: len,
+            requester,
+        };
+
+        if let Err(TrySendError::Full(_)) = self.events.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl AuditWriter {
+    /// Write until the [`AuditLog`] is dropped along with the file system.
+    ///
+    /// NOTE: Spawn after daemonizing, a thread spawned before would not survive the fork
+    pub fn spawn(self) -> io::Result<JoinHandle<()>> {
+        thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                if let Err(err) = self.run() {
+                    error!("Error writing the audit log: {:?}", err);
+                }
+            })
+    }
+
+    fn run(self) -> io::Result<()> {
+        let mut file = BufWriter::new(self.file);
+
+        // NOTE: Flush once the queue is drained rather than after every line
+        while let Ok(event) = self.events.recv() {
+            write_event(&mut file, &event)?;
+            while let Ok(event) = self.events.try_recv() {
+                write_event(&mut file, &event)?;
+            }
+
+            let dropped = self.dropped.swap(0, Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("Audit log fell behind, {} reads were not recorded", dropped);
+                let line = json!({
+                    "ts": format_timestamp(SystemTime::now()),
+                    "dropped": dropped,
+                });
+                writeln!(file, "{}", line)?;
+            }
+
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_event(file: &mut impl Write, event: &AuditEvent) -> io::Result<()> {
+    let line = json!({
+        "ts": format_timestamp(event.ts),
+        "path": event.path.to_string_lossy(),
+        "offset": event.offset,
+        "size": event.size,
+        "uid": event.requester.uid,
+        "gid": event.requester.gid,
+        "pid": event.requester.pid,
+    });
+    writeln!(file, "{}", line)
+}
+
+/// ISO 8601 UTC time with milliseconds, e.g. `2024-03-01T12:30:05.250Z`
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Gregorian date of a count of days since 1970-01-01, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        for (secs, millis, expected) in [
+            (0, 0, "1970-01-01T00:00:00.000Z"),
+            (951782400, 0, "2000-02-29T00:00:00.000Z"),
+            (1709296205, 250, "2024-03-01T12:30:05.250Z"),
+            (4102444799, 999, "2099-12-31T23:59:59.999Z"),
+        ] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis);
+            assert_eq!(format_timestamp(time), expected);
+        }
+    }
+}
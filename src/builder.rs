@@ -0,0 +1,451 @@
+use std::{
+    ffi::CString,
+    io,
+    mem::MaybeUninit,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use fuser::{BackgroundSession, MountOption, Session};
+
+use crate::{
+    control::{CacheStats, ControlHandle},
+    events::EventListener,
+    filesystem::{ArchiveOpener, ExtractReport, ZipFs},
+    options::{CacheConfig, Options, SortOrder},
+    password::{PasswordMap, PasswordProvider, SecretString},
+    positioned_reader::ReadAtSize,
+    source::Source,
+};
+
+/// How often [`MountHandle::wait`] checks on the session
+const SESSION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Configures a [`ZipFs`], for embedding the file system rather than running the binary.
+///
+/// ```no_run
+/// # fn main() -> color_eyre::Result<()> {
+/// let mount = zipfs::ZipFsBuilder::new("/srv/archives")
+///     .password("secret.zip", "hunter2")
+///     .spawn_mount("/mnt/archives", &[fuser::MountOption::RO])?;
+///
+/// // ...
+/// mount.unmount()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ZipFsBuilder {
+    options: Options,
+    passwords: PasswordMap,
+    password_provider: Option<Box<dyn PasswordProvider>>,
+    listener: Option<Box<dyn EventListener>>,
+    source: Option<Box<dyn Source>>,
+    archive_source: Option<ArchiveOpener>,
+    umount: Option<Sender<()>>,
+}
+
+impl ZipFsBuilder {
+    /// Serve the archives and files in `data_dir`, with the defaults of the binary
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self::from_options(Options {
+            data_dir: data_dir.into(),
+            ..Options::default()
+        })
+    }
+
+    /// Start from `options`, as read from a config file. Only the options of the file system
+    /// itself are used, the ones about mounting and running the process are left to the caller.
+    pub fn from_options(options: Options) -> Self {
+        Self {
+            options,
+            passwords: PasswordMap::default(),
+            password_provider: None,
+            listener: None,
+            source: None,
+            archive_source: None,
+            umount: None,
+        }
+    }
+
+    /// Overlay `dir` below the data directories added so far, which win on name collisions
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.data_dirs.push(dir.into());
+        self
+    }
+
+    /// `data_dir` is an archive, served as the whole mount
+    pub fn chroot_into_zip(mut self, chroot: bool) -> Self {
+        self.options.chroot_into_zip = chroot;
+        self
+    }
+
+    /// Serve everything below a single directory named `name` in the root of the mount
+    pub fn virtual_root(mut self, name: impl Into<String>) -> Self {
+        self.options.virtual_root = Some(name.into());
+        self
+    }
+
+    /// Sizes of the caches, the ones of [`CacheConfig::default`] unless set
+    pub fn cache_config(mut self, caches: CacheConfig) -> Self {
+        self.options.archive_cache_entries = caches.archive_entries;
+        self.options.content_cache_bytes = caches.content_bytes;
+        self.options.attr_cache_entries = caches.attr_entries;
+        self.options.negative_cache_ttl = caches.negative_ttl.as_millis() as u64;
+        self.options.negative_lookup_entries = caches.negative_lookups;
+        self.options.negative_lookup_ttl = caches.negative_lookup_ttl.as_millis() as u64;
+        self
+    }
+
+    /// Extensions of the files mounted as directories, without the leading dot
+    pub fn archive_exts<S: Into<String>>(mut self, exts: impl IntoIterator<Item = S>) -> Self {
+        self.options.archive_exts = exts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Decrypt the archive at `archive`, relative to its data directory, with `password`
+    pub fn password(mut self, archive: impl Into<PathBuf>, password: impl Into<String>) -> Self {
+        self.passwords
+            .insert(archive, SecretString::new(password.into()));
+        self
+    }
+
+    /// Ask `provider` for the passwords of the archives without one given to
+    /// [`ZipFsBuilder::password`] or in the passwords file
+    pub fn password_provider(mut self, provider: Box<dyn PasswordProvider>) -> Self {
+        self.password_provider = Some(provider);
+        self
+    }
+
+    /// List and read the data directories from `source` rather than the local disk, see
+    /// [`ZipFs::with_source`]. Archives come from [`ZipFsBuilder::archive_source`] if set.
+    pub fn source(mut self, source: impl Source + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Read archives from what `open` returns for their paths rather than from local files,
+    /// see [`ZipFs::with_archive_source`]
+    pub fn archive_source(
+        mut self,
+        open: impl Fn(&Path) -> io::Result<Box<dyn ReadAtSize>> + Send + Sync + 'static,
+    ) -> Self {
+        self.archive_source = Some(Arc::new(open));
+        self
+    }
+
+    /// Notify `listener` of the opens, reads, listings and errors served, see
+    /// [`EventListener`] for what it may do from its callbacks
+    pub fn event_listener(mut self, listener: impl EventListener + 'static) -> Self {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Send on `umount` once the file system is unmounted and dropped
+    pub fn notify_unmount(mut self, umount: Sender<()>) -> Self {
+        self.umount = Some(umount);
+        self
+    }
+
+    /// Show only the files matching one of the `include` globs, all of them when empty
+    pub fn include<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.options
+            .include
+            .extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Hide the files matching one of the `exclude` globs
+    pub fn exclude<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.options
+            .exclude
+            .extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Hide the files inside archives matching all of `globs`
+    pub fn exclude_patterns<S: Into<String>>(mut self, globs: impl IntoIterator<Item = S>) -> Self {
+        self.options
+            .exclude_patterns
+            .extend(globs.into_iter().map(Into::into));
+        self
+    }
+
+    /// Order of directory listings, by name unless set
+    pub fn sort(mut self, sort: SortOrder) -> Self {
+        self.options.sort = sort;
+        self
+    }
+
+    /// List archives as directories named without their extension
+    pub fn strip_ext(mut self, strip_ext: bool) -> Self {
+        self.options.strip_ext = strip_ext;
+        self
+    }
+
+    /// Keep archives as files and serve their contents next to them, under `suffix`
+    pub fn dual_view(mut self, suffix: impl Into<String>) -> Self {
+        self.options.dual_view = true;
+        self.options.dual_view_suffix = suffix.into();
+        self
+    }
+
+    /// Serve archives as regular files, without looking into any of them
+    pub fn passthrough_only(mut self, passthrough_only: bool) -> Self {
+        self.options.passthrough_only = passthrough_only;
+        self
+    }
+
+    /// Shorten archive entry names longer than `len` bytes in listings
+    pub fn max_entry_name_length(mut self, len: usize) -> Self {
+        self.options.max_entry_name_length = len;
+        self
+    }
+
+    /// Permission bits cleared from every file
+    pub fn umask(mut self, umask: u16) -> Self {
+        self.options.umask = umask;
+        self
+    }
+
+    /// Report `uid` and `gid` as the owner of every file instead of the real one
+    pub fn owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.options.uid = uid;
+        self.options.gid = gid;
+        self
+    }
+
+    /// Allow creating archives through the mount, see [`ZipFs::with_writable_archives`]
+    pub fn writable_archives(mut self, max_staging_size: u64) -> Self {
+        self.options.writable_archives = true;
+        self.options.max_staging_size = max_staging_size;
+        self
+    }
+
+    /// Refuse to read entries whose local header disagrees with the central directory
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Read stored entries straight from their archive, see [`ZipFs::with_direct_stored`]
+    pub fn direct_stored(mut self, direct_stored: bool) -> Self {
+        self.options.direct_stored = direct_stored;
+        self
+    }
+
+    /// Refuse entries claiming to decompress to more than `max_ratio` times their size
+    pub fn max_ratio(mut self, max_ratio: u64) -> Self {
+        self.options.max_ratio = max_ratio;
+        self
+    }
+
+    /// Serve entries whatever their compression ratio, see [`ZipFsBuilder::max_ratio`]
+    pub fn allow_bombs(mut self, allow_bombs: bool) -> Self {
+        self.options.allow_bombs = allow_bombs;
+        self
+    }
+
+    /// Refuse to open entries while entries of `max_open_archives` other archives are open
+    pub fn max_open_archives(mut self, max_open_archives: usize) -> Self {
+        self.options.max_open_archives = Some(max_open_archives);
+        self
+    }
+
+    /// Refuse to open archives or decompress entries once the caches would take more than
+    /// `max_memory` bytes
+    pub fn max_memory(mut self, max_memory: u64) -> Self {
+        self.options.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Serve a `.zipfs/manifest.txt` listing every file in the mount
+    pub fn manifest(mut self, manifest: bool) -> Self {
+        self.options.manifest = manifest;
+        self
+    }
+
+    /// Serve a `.zipfs/status` with the configuration and live stats
+    pub fn expose_metadata(mut self, expose_metadata: bool) -> Self {
+        self.options.expose_metadata = expose_metadata;
+        self
+    }
+
+    /// Give up opening an archive whose central directory takes longer than `timeout` to read
+    pub fn open_timeout(mut self, timeout: Duration) -> Self {
+        self.options.open_timeout = Some(timeout.as_millis() as u64);
+        self
+    }
+
+    /// Threads opening the archives of listed directories in the background, none when 0
+    pub fn warm_concurrency(mut self, concurrency: usize) -> Self {
+        self.options.warm_concurrency = concurrency;
+        self
+    }
+
+    /// Serve at most `bytes_per_second` across all reads
+    pub fn rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.options.rate_limit = Some(bytes_per_second);
+        self
+    }
+
+    /// Keep the generation of the last mount in `path`, see [`ZipFs::with_generation_file`]
+    pub fn generation_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.generation_file = Some(path.into());
+        self
+    }
+
+    /// Build the file system, failing on invalid globs or an unreadable passwords file
+    pub fn build(self) -> Result<ZipFs> {
+        // NOTE: Passwords given to the builder win over the passwords file
+        let mut providers: Vec<Box<dyn PasswordProvider>> = vec![
+            Box::new(self.passwords),
+            Box::new(self.options.load_passwords()?),
+        ];
+        providers.extend(self.password_provider);
+
+        let fs = ZipFs::from_options(&self.options, self.umount)?
+            .with_password_provider(Box::new(providers));
+
+        let fs = match self.listener {
+            Some(listener) => fs.with_event_listener(listener),
+            None => fs,
+        };
+
+        let fs = match self.source {
+            Some(source) => fs.with_source(source),
+            None => fs,
+        };
+
+        Ok(match self.archive_source {
+            Some(open) => fs.with_archive_source(move |path| open(path)),
+            None => fs,
+        })
+    }
+
+    /// Mount at `mount_point` and serve requests on the current thread until unmounted
+    pub fn mount(self, mount_point: impl AsRef<Path>, options: &[MountOption]) -> Result<()> {
+        fuser::mount2(self.build()?, mount_point, options)?;
+        Ok(())
+    }
+
+    /// Mount at `mount_point` and serve requests on a background thread
+    pub fn spawn_mount(
+        self,
+        mount_point: impl AsRef<Path>,
+        options: &[MountOption],
+    ) -> Result<MountHandle> {
+        let fs = self.build()?;
+        let control = fs.control_handle();
+        let session = Session::new(fs, mount_point.as_ref(), options)?;
+
+        Ok(MountHandle::spawn(session, mount_point, control)?)
+    }
+
+    /// Write the tree the mount would present below `dest` instead of mounting, see
+    /// [`ZipFs::extract`]
+    pub fn extract(self, dest: impl AsRef<Path>) -> Result<ExtractReport> {
+        Ok(self.build()?.extract(dest.as_ref())?)
+    }
+}
+
+/// A file system mounted by [`ZipFsBuilder::spawn_mount`] or [`MountHandle::spawn`], unmounted
+/// once dropped without waiting for the session to end
+pub struct MountHandle {
+    /// Declared first to be dropped first, see [`MountHandle::with_unmount_guard`]
+    unmount_guard: Option<Box<dyn Send>>,
+    session: BackgroundSession,
+    mount_point: PathBuf,
+    control: ControlHandle,
+}
+
+impl MountHandle {
+    /// Serve a session mounted at `mount_point` on a background thread, for sessions mounted
+    /// some other way than with [`ZipFsBuilder::spawn_mount`]. `control` is the handle of the
+    /// file system of the session.
+    pub fn spawn(
+        session: Session<ZipFs>,
+        mount_point: impl AsRef<Path>,
+        control: ControlHandle,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            unmount_guard: None,
+            session: session.spawn()?,
+            mount_point: mount_point.as_ref().to_path_buf(),
+            control,
+        })
+    }
+
+    /// Drop `guard` first when unmounting, e.g. a mount made with mount(2) which the session
+    /// doesn't know how to undo
+    pub fn with_unmount_guard(mut self, guard: impl Send + 'static) -> Self {
+        self.unmount_guard = Some(Box::new(guard));
+        self
+    }
+
+    /// Where the file system is mounted
+    pub fn mount_point(&self) -> &Path {
+        &self.mount_point
+    }
+
+    /// Controls the mounted file system, see [`ZipFs::control_handle`]
+    pub fn control_handle(&self) -> &ControlHandle {
+        &self.control
+    }
+
+    /// Current state of the archive cache, see [`ControlHandle::cache_stats`]
+    pub fn stats(&self) -> CacheStats {
+        self.control.cache_stats()
+    }
+
+    /// Whether the session is still being served and the mount point answers `statfs`. Blocks
+    /// for as long as the file system takes to reply.
+    pub fn is_healthy(&self) -> bool {
+        !self.session.guard.is_finished() && answers_statfs(&self.mount_point)
+    }
+
+    /// Block until the session ends, e.g. once unmounted with `fusermount -u`
+    pub fn wait(&self) {
+        while !self.session.guard.is_finished() {
+            thread::sleep(SESSION_POLL_INTERVAL);
+        }
+    }
+
+    /// Unmount and wait for the session to end, along with the operations still being served
+    pub fn unmount(self) -> io::Result<()> {
+        drop(self.unmount_guard);
+        join_session(unmount_session(self.session))
+    }
+
+    /// Wait for the file system to be unmounted by someone else, e.g. with `fusermount -u`
+    pub fn join(self) -> io::Result<()> {
+        join_session(self.session.guard)
+    }
+}
+
+/// Unmount what the session mounted itself, leaving the thread serving it to end
+fn unmount_session(session: BackgroundSession) -> JoinHandle<io::Result<()>> {
+    // NOTE: The rest of the session, its mount among it, is dropped on return
+    let BackgroundSession { guard, .. } = session;
+    guard
+}
+
+fn join_session(guard: JoinHandle<io::Result<()>>) -> io::Result<()> {
+    match guard.join() {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::other("the session thread panicked")),
+    }
+}
+
+fn answers_statfs(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: The path is a valid C string and the buffer fits a statfs
+    unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) == 0 }
+}
@@ -0,0 +1,133 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use lru::LruCache;
+
+pub type SharedChunkCache = Arc<Mutex<ChunkCache>>;
+
+/// Decompressed chunks of archive entries, keyed by the path of the entry and the index of
+/// the chunk. The least recently used chunks are evicted once they take more than the budget.
+#[derive(Debug)]
+pub struct ChunkCache {
+    chunks: LruCache<(PathBuf, u64), Arc<[u8]>>,
+    size: u64,
+    budget: u64,
+}
+
+impl ChunkCache {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            chunks: LruCache::unbounded(),
+            size: 0,
+            budget,
+        }
+    }
+
+    pub fn get(&mut self, path: &Path, index: u64) -> Option<Arc<[u8]>> {
+        self.chunks.get(&(path.to_path_buf(), index)).cloned()
+    }
+
+    pub fn insert(&mut self, path: &Path, index: u64, chunk: Arc<[u8]>) {
+        if chunk.len() as u64 > self.budget {
+            return;
+        }
+
+        self.size += chunk.len() as u64;
+        if let Some(old) = self.chunks.put((path.to_path_buf(), index), chunk) {
+            self.size -= old.len() as u64;
+        }
+
+        while self.size > self.budget {
+            let Some((_, evicted)) = self.chunks.pop_lru() else {
+                break;
+            };
+
+            self.size -= evicted.len() as u64;
+        }
+    }
+
+    /// Drop the chunks of the entry at `path`
+    pub fn remove(&mut self, path: &Path) {
+        let keys = self
+            .chunks
+            .iter()
+            .filter(|((chunk_path, _), _)| chunk_path == path)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            if let Some(chunk) = self.chunks.pop(&key) {
+                self.size -= chunk.len() as u64;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.size = 0;
+    }
+
+    /// Bytes taken by the cached chunks
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(len: usize) -> Arc<[u8]> {
+        vec![0; len].into()
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = ChunkCache::new(30);
+        let path = Path::new("a.zip/file.txt");
+
+        cache.insert(path, 0, chunk(10));
+        cache.insert(path, 1, chunk(10));
+        cache.insert(path, 2, chunk(10));
+        assert!(cache.get(path, 0).is_some());
+
+        cache.insert(path, 3, chunk(10));
+        assert_eq!(cache.size(), 30);
+        assert!(cache.get(path, 0).is_some());
+        assert!(cache.get(path, 1).is_none());
+    }
+
+    #[test]
+    fn test_skips_chunks_over_budget() {
+        let mut cache = ChunkCache::new(10);
+        let path = Path::new("a.zip/file.txt");
+
+        cache.insert(path, 0, chunk(5));
+        cache.insert(path, 1, chunk(20));
+        assert!(cache.get(path, 0).is_some());
+        assert!(cache.get(path, 1).is_none());
+        assert_eq!(cache.size(), 5);
+    }
+
+    #[test]
+    fn test_remove_entry() {
+        let mut cache = ChunkCache::new(100);
+        let first = Path::new("a.zip/first.txt");
+        let second = Path::new("a.zip/second.txt");
+
+        cache.insert(first, 0, chunk(10));
+        cache.insert(first, 1, chunk(10));
+        cache.insert(second, 0, chunk(10));
+
+        cache.remove(first);
+        assert_eq!(cache.size(), 10);
+        assert!(cache.get(first, 0).is_none());
+        assert!(cache.get(second, 0).is_some());
+    }
+}
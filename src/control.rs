@@ -0,0 +1,301 @@
+use std::{
+    fs::{self, Permissions},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, error, info};
+
+use fuser::Notifier;
+
+use crate::{
+    filesystem::{ArchiveCache, NotifierSlot},
+    filter::PathFilter,
+    stats::Stats,
+};
+
+/// Number of most read entries reported in statistics
+const TOP_ENTRIES: usize = 10;
+
+/// State of the archive cache of a mount, see [`ControlHandle::cache_stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Archives kept open with their central directory read
+    pub cached_archives: usize,
+    pub capacity: usize,
+    /// Archives found open when looked up
+    pub hits: u64,
+    /// Archives that had to be opened
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Share of the lookups served from the cache, 0 before the first one
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// Only the owner of the mount may control it
+const SOCKET_MODE: u32 = 0o600;
+
+/// How often open files are counted while shutting down
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Replaces the tracing filter with the given directives
+type LogFilterSetter = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+enum Command {
+    /// Evict a single archive, relative to the data directory
+    #[serde(alias = "evict")]
+    Invalidate {
+        path: PathBuf,
+    },
+    Stats,
+    /// Drop every cached archive so they are read from disk again and rescan the data
+    /// directories
+    #[serde(alias = "flush")]
+    Reload,
+    /// Catch up with files added, removed or modified in the data directories
+    Rescan,
+    /// Replace the tracing filter, e.g. `zipfs=debug`
+    LogLevel {
+        filter: String,
+    },
+}
+
+/// Handle to the state of a mounted [`crate::ZipFs`], usable from other threads
+#[derive(Clone)]
+pub struct ControlHandle {
+    pub(crate) data_dirs: Vec<PathBuf>,
+    pub(crate) cache: ArchiveCache,
+    pub(crate) stats: Arc<Stats>,
+    pub(crate) filter: Arc<RwLock<PathFilter>>,
+    pub(crate) generation: Arc<AtomicU64>,
+    pub(crate) draining: Arc<AtomicBool>,
+    /// Set to stop waiting for the mount to drain
+    pub(crate) forced: Arc<AtomicBool>,
+    pub(crate) rescan_requested: Arc<AtomicBool>,
+    pub(crate) notifier: NotifierSlot,
+    pub(crate) log_filter: Option<LogFilterSetter>,
+}
+
+impl ControlHandle {
+    /// Let the `loglevel` command change the tracing filter through `set_filter`
+    pub fn with_log_filter(
+        mut self,
+        set_filter: impl Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.log_filter = Some(Arc::new(set_filter));
+        self
+    }
+
+    /// Serve newline-delimited JSON commands on a unix socket at `socket`, accessible only
+    /// to the current user
+    pub fn listen(self, socket: &Path) -> io::Result<JoinHandle<()>> {
+        let listener = UnixListener::bind(socket)?;
+        fs::set_permissions(socket, Permissions::from_mode(SOCKET_MODE))?;
+        info!("Listening for control commands on {:?}", socket);
+
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                let handle = self.clone();
+
+                match stream {
+                    Ok(stream) => {
+                        thread::spawn(move || handle.serve(stream));
+                    }
+                    Err(err) => error!("Error accepting control connection: {:?}", err),
+                }
+            }
+        }))
+    }
+
+    fn serve(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(err) => {
+                error!("Error opening control connection: {:?}", err);
+                return;
+            }
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            let response = match serde_json::from_str(&line) {
+                Ok(command) => self.handle(command),
+                Err(err) => json!({ "ok": false, "error": err.to_string() }),
+            };
+
+            if writeln!(writer, "{}", response).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Current state of the archive cache
+    pub fn cache_stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap();
+
+        CacheStats {
+            cached_archives: cache.len(),
+            capacity: cache.cap().get(),
+            hits: self.stats.cache_hits(),
+            misses: self.stats.cache_misses(),
+        }
+    }
+
+    /// Current statistics of the mount, as served by the `stats` command
+    pub fn snapshot(&self) -> Value {
+        let cache = self.cache_stats();
+
+        let top_entries = self
+            .stats
+            .top_entries(TOP_ENTRIES)
+            .into_iter()
+            .map(|(path, bytes)| json!({ "path": path, "bytes": bytes }))
+            .collect::<Vec<_>>();
+
+        json!({
+            "uptime_secs": self.stats.uptime().as_secs(),
+            "inodes": self.stats.inodes(),
+            "cached_archives": cache.cached_archives,
+            "cache_capacity": cache.capacity,
+            "cache_hits": cache.hits,
+            "cache_misses": cache.misses,
+            "cache_hit_rate": cache.hit_rate(),
+            "open_handles": self.stats.open_handles(),
+            "open_dirs": self.stats.open_dirs(),
+            "in_flight": self.stats.in_flight(),
+            "idle_secs": self.stats.idle().as_secs(),
+            "bytes_served": self.stats.bytes_served(),
+            "bytes_decompressed": self.stats.bytes_decompressed(),
+            "largest_read_request": self.stats.largest_read_request(),
+            "top_entries": top_entries,
+        })
+    }
+
+    /// Drop every cached archive, and with them the readers of open files which then continue
+    /// on freshly opened archives, and request a rescan as SIGHUP does. Returns the number of
+    /// archives evicted.
+    pub fn reload(&self) -> usize {
+        let mut cache = self.cache.lock().unwrap();
+        let evicted = cache.len();
+        cache.clear();
+
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.request_rescan();
+        evicted
+    }
+
+    /// Have the file system rescan its data directories before serving the next operation,
+    /// see [`crate::ZipFs::rescan`]
+    pub fn request_rescan(&self) {
+        self.rescan_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Let rescans invalidate what the kernel cached, through the notifier of the session
+    pub fn set_notifier(&self, notifier: Notifier) {
+        *self.notifier.lock().unwrap() = Some(Arc::new(notifier));
+    }
+
+    /// Replace the include/exclude filter of the mount
+    pub fn set_filter(&self, filter: PathFilter) {
+        *self.filter.write().unwrap() = filter;
+    }
+
+    /// Refuse to open further files and wait up to `timeout` for the open ones to be released
+    /// and the operations in flight to be replied to, so that reads in progress are not cut
+    /// short by unmounting. Stops waiting once [`Self::force_shutdown`] is called. Returns the
+    /// number of files still open.
+    pub fn shutdown(&self, timeout: Duration) -> u64 {
+        self.draining.store(true, Ordering::Relaxed);
+
+        let started = Instant::now();
+        while (self.stats.open_handles() > 0 || self.stats.in_flight() > 0)
+            && started.elapsed() < timeout
+            && !self.is_forced()
+        {
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+
+        self.stats.open_handles()
+    }
+
+    /// Have a shutdown in progress, or the next one, unmount without waiting any longer
+    pub fn force_shutdown(&self) {
+        self.forced.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.forced.load(Ordering::Relaxed)
+    }
+
+    /// Time since the latest operation on the mount, `None` while files or directories are
+    /// open as the mount isn't idle then
+    pub fn idle(&self) -> Option<Duration> {
+        match self.stats.open_handles() + self.stats.open_dirs() {
+            0 => Some(self.stats.idle()),
+            _ => None,
+        }
+    }
+
+    /// Zero the counters of the mount
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
+    fn handle(&self, command: Command) -> Value {
+        debug!("control: {:?}", command);
+
+        match command {
+            Command::Invalidate { path } => {
+                let mut cache = self.cache.lock().unwrap();
+                let evicted = self
+                    .data_dirs
+                    .iter()
+                    .filter(|data_dir| cache.pop(&data_dir.join(&path)).is_some())
+                    .count()
+                    > 0;
+                json!({ "ok": true, "evicted": evicted })
+            }
+            Command::Stats => {
+                let mut response = self.snapshot();
+                response["ok"] = json!(true);
+                response
+            }
+            Command::Reload => json!({ "ok": true, "evicted": self.reload() }),
+            Command::Rescan => {
+                self.request_rescan();
+                json!({ "ok": true })
+            }
+            Command::LogLevel { filter } => match &self.log_filter {
+                Some(set_filter) => match set_filter(&filter) {
+                    Ok(()) => json!({ "ok": true }),
+                    Err(err) => json!({ "ok": false, "error": err }),
+                },
+                None => json!({ "ok": false, "error": "log level cannot be changed" }),
+            },
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{io, path::PathBuf};
+
+use libc::{
+    EACCES, EEXIST, EFBIG, EINVAL, EIO, EISDIR, EMFILE, ENAMETOOLONG, ENODATA, ENOENT, ENOMEM,
+    ENOSPC, ENOSYS, ENOTDIR, ENOTEMPTY, EOPNOTSUPP, EROFS, ESHUTDOWN, EXDEV,
+};
+use thiserror::Error;
+use zip::result::ZipError;
+
+use crate::archive::method_name;
+
+/// Errors of the file system operations, turned into an errno at the FUSE boundary
+#[derive(Debug, Error)]
+pub enum ZipFsError {
+    #[error("No such file or directory")]
+    NotFound,
+
+    #[error("Permission denied")]
+    PermissionDenied,
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Zip error: {0}")]
+    ZipError(#[from] ZipError),
+
+    #[error("Invalid path: {0:?}")]
+    InvalidPath(PathBuf),
+
+    #[error("Entry of {expected} bytes exceeds the limit of {limit} bytes")]
+    ZipBomb { expected: u64, limit: u64 },
+
+    /// Entry claiming to decompress to many times its size, as zip bombs do
+    #[error("Entry expands {ratio}:1, beyond the limit of {limit}:1")]
+    ExcessiveRatio { ratio: u64, limit: u64 },
+
+    #[error("Invalid password")]
+    InvalidPassword,
+
+    /// Compression method of an entry this build can't decompress
+    #[error("Unsupported compression method {0} ({})", method_name(*.0))]
+    UnsupportedCompression(u16),
+
+    #[error("No path for inode {0}")]
+    UnknownInode(u64),
+
+    #[error("File exists")]
+    AlreadyExists,
+
+    #[error("Not a directory")]
+    NotADirectory,
+
+    #[error("Is a directory")]
+    IsADirectory,
+
+    #[error("Directory not empty")]
+    DirectoryNotEmpty,
+
+    #[error("File name too long")]
+    NameTooLong,
+
+    #[error("No such attribute")]
+    NoAttribute,
+
+    /// Only archives being staged can be written to
+    #[error("Read-only file system")]
+    ReadOnly,
+
+    /// Ranges are copied within the mount only into archives being staged
+    #[error("Invalid cross-device link")]
+    CrossDevice,
+
+    #[error("Staged archives would exceed the limit of {limit} bytes")]
+    StagingFull { limit: u64 },
+
+    /// Entries of as many archives as allowed are open already
+    #[error("Entries of {limit} archives are open already")]
+    TooManyOpenArchives { limit: usize },
+
+    /// Caches of the mount would take more memory than allowed
+    #[error("Caches would exceed the limit of {limit} bytes")]
+    MemoryLimit { limit: u64 },
+
+    #[error("Neither a regular file nor a directory")]
+    UnsupportedFileType,
+
+    /// Local file header of an entry disagreeing with its central directory record
+    #[error("Local header disagrees with the central directory: {0}")]
+    HeaderMismatch(String),
+
+    #[error("File handle is still stale after retrying")]
+    StaleHandle,
+
+    #[error("Shutting down")]
+    ShuttingDown,
+
+    /// Another error, along with the path it happened on
+    #[error("{}: {source}", path.display())]
+    Context {
+        path: PathBuf,
+        source: Box<ZipFsError>,
+    },
+}
+
+impl ZipFsError {
+    /// Error for an errno without a more specific variant
+    pub fn from_errno(errno: libc::c_int) -> Self {
+        Self::Io(io::Error::from_raw_os_error(errno))
+    }
+
+    /// Attach the path the error happened on, unless it already has one
+    pub fn with_path(self, path: impl Into<PathBuf>) -> Self {
+        match self {
+            Self::Context { .. } => self,
+            err => Self::Context {
+                path: path.into(),
+                source: Box::new(err),
+            },
+        }
+    }
+
+    /// The error itself, without the paths attached to it
+    pub fn inner(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.inner(),
+            err => err,
+        }
+    }
+
+    /// Errno replied to the kernel
+    pub fn errno(&self) -> libc::c_int {
+        match self {
+            ZipFsError::NotFound => ENOENT,
+            ZipFsError::PermissionDenied => EACCES,
+            ZipFsError::Io(err) => io_errno(err),
+            ZipFsError::ZipError(err) => zip_errno(err),
+            ZipFsError::InvalidPath(_) => EINVAL,
+            ZipFsError::ZipBomb { .. } => EFBIG,
+            ZipFsError::ExcessiveRatio { .. } => EFBIG,
+            ZipFsError::InvalidPassword => EACCES,
+            ZipFsError::UnsupportedCompression(_) => EOPNOTSUPP,
+            ZipFsError::UnknownInode(_) => ENOENT,
+            ZipFsError::AlreadyExists => EEXIST,
+            ZipFsError::NotADirectory => ENOTDIR,
+            ZipFsError::IsADirectory => EISDIR,
+            ZipFsError::DirectoryNotEmpty => ENOTEMPTY,
+            ZipFsError::NameTooLong => ENAMETOOLONG,
+            ZipFsError::NoAttribute => ENODATA,
+            ZipFsError::ReadOnly => EROFS,
+            ZipFsError::CrossDevice => EXDEV,
+            ZipFsError::StagingFull { .. } => ENOSPC,
+            ZipFsError::TooManyOpenArchives { .. } => EMFILE,
+            ZipFsError::MemoryLimit { .. } => ENOMEM,
+            ZipFsError::UnsupportedFileType => ENOSYS,
+            ZipFsError::HeaderMismatch(_) => EIO,
+            ZipFsError::StaleHandle => EIO,
+            ZipFsError::ShuttingDown => ESHUTDOWN,
+            ZipFsError::Context { source, .. } => source.errno(),
+        }
+    }
+}
+
+fn io_errno(err: &io::Error) -> libc::c_int {
+    if let Some(errno) = err.raw_os_error() {
+        return errno;
+    }
+
+    match err.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::Unsupported => EOPNOTSUPP,
+        _ => EIO,
+    }
+}
+
+fn zip_errno(err: &ZipError) -> libc::c_int {
+    match err {
+        ZipError::Io(err) => io_errno(err),
+        ZipError::FileNotFound => ENOENT,
+        ZipError::InvalidPassword => EACCES,
+        ZipError::UnsupportedArchive(_) => EOPNOTSUPP,
+        // NOTE: Corrupt archives, the reason is in the message logged along with the errno
+        ZipError::InvalidArchive(_) => EIO,
+        _ => EIO,
+    }
+}
+
+impl From<ZipFsError> for libc::c_int {
+    fn from(err: ZipFsError) -> Self {
+        err.errno()
+    }
+}
+
+impl From<ZipFsError> for io::Error {
+    fn from(err: ZipFsError) -> Self {
+        match err {
+            ZipFsError::Io(err) => err,
+            ZipFsError::ZipError(err) => err.into(),
+            ZipFsError::Context { source, .. } => (*source).into(),
+            // NOTE: Kept as the message, naming the method when logged
+            err @ ZipFsError::UnsupportedCompression(_) => {
+                io::Error::new(io::ErrorKind::Unsupported, err)
+            }
+            err => io::Error::from_raw_os_error(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::{EROFS, ESTALE};
+
+    use super::*;
+
+    #[test]
+    fn test_errno() {
+        let cases = [
+            (ZipFsError::NotFound, ENOENT),
+            (ZipFsError::PermissionDenied, EACCES),
+            (ZipFsError::from_errno(EROFS), EROFS),
+            (io::Error::from(io::ErrorKind::NotFound).into(), ENOENT),
+            (
+                io::Error::from(io::ErrorKind::PermissionDenied).into(),
+                EACCES,
+            ),
+            (io::Error::from(io::ErrorKind::UnexpectedEof).into(), EIO),
+            (
+                ZipError::Io(io::Error::from_raw_os_error(ESTALE)).into(),
+                ESTALE,
+            ),
+            (
+                ZipError::Io(io::Error::from(io::ErrorKind::NotFound)).into(),
+                ENOENT,
+            ),
+            (ZipError::Io(io::Error::other("truncated")).into(), EIO),
+            (ZipError::FileNotFound.into(), ENOENT),
+            (ZipError::InvalidPassword.into(), EACCES),
+            (ZipError::InvalidArchive("bad").into(), EIO),
+            (ZipError::UnsupportedArchive("lzma").into(), EOPNOTSUPP),
+            (ZipFsError::InvalidPath(PathBuf::from("..")), EINVAL),
+            (
+                ZipFsError::ZipBomb {
+                    expected: 2,
+                    limit: 1,
+                },
+                EFBIG,
+            ),
+            (
+                ZipFsError::ExcessiveRatio {
+                    ratio: 2000,
+                    limit: 1000,
+                },
+                EFBIG,
+            ),
+            (ZipFsError::InvalidPassword, EACCES),
+            (ZipFsError::UnsupportedCompression(98), EOPNOTSUPP),
+            (ZipFsError::UnknownInode(42), ENOENT),
+            (ZipFsError::AlreadyExists, EEXIST),
+            (ZipFsError::NotADirectory, ENOTDIR),
+            (ZipFsError::IsADirectory, EISDIR),
+            (ZipFsError::DirectoryNotEmpty, ENOTEMPTY),
+            (ZipFsError::NameTooLong, ENAMETOOLONG),
+            (ZipFsError::NoAttribute, ENODATA),
+            (ZipFsError::ReadOnly, EROFS),
+            (ZipFsError::CrossDevice, EXDEV),
+            (ZipFsError::StagingFull { limit: 1 }, ENOSPC),
+            (ZipFsError::TooManyOpenArchives { limit: 1 }, EMFILE),
+            (ZipFsError::MemoryLimit { limit: 1 }, ENOMEM),
+            (ZipFsError::UnsupportedFileType, ENOSYS),
+            (ZipFsError::HeaderMismatch("crc32".to_string()), EIO),
+            (ZipFsError::StaleHandle, EIO),
+            (ZipFsError::ShuttingDown, ESHUTDOWN),
+        ];
+
+        for (err, errno) in cases {
+            let message = err.to_string();
+            assert_eq!(err.errno(), errno, "{}", message);
+            assert_eq!(libc::c_int::from(err), errno, "{}", message);
+        }
+    }
+
+    #[test]
+    fn test_inner_skips_context() {
+        let err = ZipFsError::ReadOnly.with_path("a.zip/file.txt");
+        assert!(matches!(err.inner(), ZipFsError::ReadOnly));
+        assert!(matches!(ZipFsError::NotFound.inner(), ZipFsError::NotFound));
+    }
+
+    #[test]
+    fn test_context_keeps_errno() {
+        let err = ZipFsError::from_errno(EROFS).with_path("a.zip/file.txt");
+        assert_eq!(err.errno(), EROFS);
+        assert!(err.to_string().starts_with("a.zip/file.txt: "));
+
+        // NOTE: The innermost path is the most specific one
+        let err = err.with_path("a.zip");
+        assert!(err.to_string().starts_with("a.zip/file.txt: "));
+        assert_eq!(io::Error::from(err).raw_os_error(), Some(EROFS));
+    }
+
+    #[test]
+    fn test_unsupported_compression_through_io() {
+        let err = ZipFsError::from(io::Error::from(ZipFsError::UnsupportedCompression(98)));
+        assert_eq!(err.errno(), EOPNOTSUPP);
+        assert!(err.to_string().contains("method 98 (PPMd)"));
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+
+/// Process an operation was served to, as told by the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Requester {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+}
+
+impl From<&fuser::Request<'_>> for Requester {
+    fn from(req: &fuser::Request<'_>) -> Self {
+        Self {
+            uid: req.uid(),
+            gid: req.gid(),
+            pid: req.pid(),
+        }
+    }
+}
+
+/// Notified of the operations served by the mount, e.g. to emit audit events. Paths are the
+/// ones seen in the mount, starting with `/`.
+///
+/// Callbacks are invoked on the thread serving the file system, before the kernel gets its
+/// reply. They must not block: hand the event over to a channel or a logger that doesn't wait
+/// instead. Every callback does nothing unless implemented.
+pub trait EventListener: Send + Sync {
+    /// `path` was opened
+    fn on_open(&self, _path: &Path, _requester: Requester) {}
+
+    /// `len` bytes were read from `path` at `offset`
+    fn on_read(&self, _path: &Path, _offset: u64, _len: usize, _requester: Requester) {}
+
+    /// The directory at `path` was listed, called once per listing rather than per batch
+    fn on_readdir(&self, _path: &Path, _requester: Requester) {}
+
+    /// An operation on `path` failed with `errno`, including routine ones such as looking up
+    /// a missing name, for which `path` is the directory looked into
+    fn on_error(&self, _path: &Path, _errno: i32, _requester: Requester) {}
+}
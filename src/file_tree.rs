@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use bimap::BiMap;
 use fuser::FUSE_ROOT_ID;
@@ -8,25 +8,111 @@ type INode = u64;
 #[derive(Debug)]
 pub struct FileTree {
     entries: BiMap<INode, PathBuf>,
+    /// Index of the data directory each inode was found in
+    sources: HashMap<INode, usize>,
+    lookups: HashMap<INode, u64>,
+    next_inode: INode,
+    /// Inodes up to this one are never evicted
+    pinned: INode,
 }
 
 impl FileTree {
     pub fn new(data_dir: PathBuf) -> Self {
         let mut tree = Self {
             entries: BiMap::new(),
+            sources: HashMap::new(),
+            lookups: HashMap::new(),
+            next_inode: FUSE_ROOT_ID,
+            pinned: FUSE_ROOT_ID,
         };
 
-        tree.add_file(data_dir);
+        tree.add_file(data_dir, 0);
         tree
     }
 
-    pub fn add_file(&mut self, path: PathBuf) -> INode {
-        let ino = FUSE_ROOT_ID + self.entries.len() as INode;
+    /// Tree whose root has no path and holds nothing but `data_dir`, pinned as the second
+    /// inode, see [`crate::ZipFs::with_virtual_root`]
+    pub fn with_virtual_root(data_dir: PathBuf) -> Self {
+        let mut tree = Self::new(PathBuf::new());
+        tree.pinned = tree.add_file(data_dir, 0);
+        tree
+    }
+
+    pub fn add_file(&mut self, path: PathBuf, source: usize) -> INode {
+        let ino = self.next_inode;
+        self.next_inode += 1;
 
         self.entries.insert(ino, path);
+        self.sources.insert(ino, source);
         ino
     }
 
+    /// Inode of `path`, added with `source` if it isn't known yet. Checked and inserted in one
+    /// step so that a path never ends up with two inodes.
+    pub fn get_or_add_file(&mut self, path: PathBuf, source: usize) -> INode {
+        match self.find_inode_by_path(&path) {
+            Some(inode) => inode,
+            None => self.add_file(path, source),
+        }
+    }
+
+    pub fn source(&self, inode: INode) -> usize {
+        self.sources.get(&inode).copied().unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Count a reference handed out to the kernel
+    pub fn lookup(&mut self, inode: INode) {
+        *self.lookups.entry(inode).or_default() += 1;
+    }
+
+    /// Drop `nlookup` kernel references, evicting the inode once none are left.
+    /// Returns the path of the evicted inode.
+    pub fn forget(&mut self, inode: INode, nlookup: u64) -> Option<PathBuf> {
+        if inode <= self.pinned {
+            return None;
+        }
+
+        let count = self.lookups.get_mut(&inode)?;
+        *count = count.saturating_sub(nlookup);
+        if *count > 0 {
+            return None;
+        }
+
+        self.lookups.remove(&inode);
+        self.sources.remove(&inode);
+        self.entries.remove_by_left(&inode).map(|(_, path)| path)
+    }
+
+    /// Evict `inode` if the kernel holds no reference to it, such as one created for a lookup
+    /// that failed. Returns the path of the evicted inode.
+    pub fn release(&mut self, inode: INode) -> Option<PathBuf> {
+        if inode <= self.pinned || self.lookups.contains_key(&inode) {
+            return None;
+        }
+
+        self.sources.remove(&inode);
+        self.entries.remove_by_left(&inode).map(|(_, path)| path)
+    }
+
+    /// Evict `inode` whatever its kernel references, the root is never removed
+    pub fn remove(&mut self, inode: INode) -> Option<PathBuf> {
+        if inode <= self.pinned {
+            return None;
+        }
+
+        self.lookups.remove(&inode);
+        self.sources.remove(&inode);
+        self.entries.remove_by_left(&inode).map(|(_, path)| path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (INode, &PathBuf)> {
+        self.entries.iter().map(|(inode, path)| (*inode, path))
+    }
+
     pub fn find_path_by_inode(&self, inode: INode) -> Option<&PathBuf> {
         self.entries.get_by_left(&inode)
     }
@@ -35,3 +121,64 @@ impl FileTree {
         self.entries.get_by_right(path).copied()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_or_add_file_reuses_inode() {
+        let mut tree = FileTree::new(PathBuf::from("/data"));
+
+        let first = tree.get_or_add_file(PathBuf::from("/data/archive.zip"), 0);
+        let second = tree.get_or_add_file(PathBuf::from("/data/archive.zip"), 1);
+
+        assert_eq!(first, second);
+        assert_eq!(tree.source(first), 0);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_keeps_root() {
+        let mut tree = FileTree::new(PathBuf::from("/data"));
+        let ino = tree.add_file(PathBuf::from("/data/archive.zip"), 0);
+        tree.lookup(ino);
+
+        assert_eq!(tree.remove(FUSE_ROOT_ID), None);
+        assert_eq!(tree.remove(ino), Some(PathBuf::from("/data/archive.zip")));
+        assert_eq!(
+            tree.find_inode_by_path(&PathBuf::from("/data/archive.zip")),
+            None
+        );
+        assert_eq!(tree.len(), 1);
+    }
+
+    #[test]
+    fn test_release_keeps_looked_up() {
+        let mut tree = FileTree::new(PathBuf::from("/data"));
+        let missing = tree.add_file(PathBuf::from("/data/missing.zip"), 0);
+        let found = tree.add_file(PathBuf::from("/data/archive.zip"), 0);
+        tree.lookup(found);
+
+        assert_eq!(tree.release(found), None);
+        assert_eq!(
+            tree.release(missing),
+            Some(PathBuf::from("/data/missing.zip"))
+        );
+        assert_eq!(tree.release(FUSE_ROOT_ID), None);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_virtual_root_keeps_data_dir() {
+        let mut tree = FileTree::with_virtual_root(PathBuf::from("/data"));
+        let data_dir = tree.find_inode_by_path(&PathBuf::from("/data")).unwrap();
+        tree.lookup(data_dir);
+
+        assert_eq!(data_dir, FUSE_ROOT_ID + 1);
+        assert_eq!(tree.find_path_by_inode(FUSE_ROOT_ID), Some(&PathBuf::new()));
+        assert_eq!(tree.forget(data_dir, 1), None);
+        assert_eq!(tree.remove(data_dir), None);
+        assert_eq!(tree.len(), 2);
+    }
+}
@@ -1,71 +1,518 @@
 // TODO: LRU cache for the zip file handles
 use std::{
+    collections::{HashMap, HashSet},
     fs::{self, File},
-    io::Read,
-    num::NonZeroUsize,
-    os::{linux::fs::MetadataExt, unix::fs::PermissionsExt},
-    path::{Path, PathBuf},
-    sync::{mpsc::Sender, Arc},
-    time::{Duration, UNIX_EPOCH},
+    io::{self, Read, Seek, SeekFrom, Write},
+    num::{NonZeroU64, NonZeroUsize},
+    ops::Range,
+    os::unix::{ffi::OsStringExt, fs::PermissionsExt},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-type FuseError = libc::c_int;
 type INode = u64;
 type FileHandle = u64;
 
-use crate::file_tree::FileTree;
-use color_eyre::eyre::Result;
-use fuser::{FileAttr, FileType, Filesystem};
-use libc::{ENOENT, ENOSYS};
+/// Opens the archives by path, local files unless replaced with
+/// [`ZipFs::with_archive_source`]
+pub(crate) type ArchiveOpener = Arc<dyn Fn(&Path) -> io::Result<Box<dyn ReadAtSize>> + Send + Sync>;
+
+/// Open archives by path, shared with the control interface
+pub(crate) type ArchiveCache = Arc<Mutex<LruCache<PathBuf, ZipArchive<PositionedReader>>>>;
+
+/// Modification time of each archive when it was opened, shared with the prefetch
+pub(crate) type ArchiveMtimes = Arc<Mutex<HashMap<PathBuf, SystemTime>>>;
+
+/// Handed over by the control interface once the session exists, to invalidate kernel caches
+pub(crate) type NotifierSlot = Arc<Mutex<Option<Arc<Notifier>>>>;
+
+use crate::{
+    archive::{
+        entry_index, entry_metadata, entry_names, is_implicit_dir, local_header_mismatches,
+        raw_entry_name, read_archive, resolve_alias, wheel_metadata, WHEEL_METADATA,
+    },
+    chunk_cache::{ChunkCache, SharedChunkCache},
+    control::ControlHandle,
+    error::ZipFsError,
+    events::EventListener,
+    file_tree::FileTree,
+    filter::PathFilter,
+    listing::{dir_children, shorten_names, sort_entries},
+    manifest::ManifestReader,
+    negative_cache::NegativeCache,
+    options::{
+        CacheConfig, Options, SortOrder, DEFAULT_ARCHIVE_EXTS, DEFAULT_MAX_RATIO,
+        DEFAULT_MAX_READAHEAD,
+    },
+    password::{PasswordMap, PasswordProvider},
+    positioned_reader::{PositionedReader, ReadAtSize},
+    prefetch::{CacheWarmer, Prefetch, Preloader},
+    rate_limit::RateLimiter,
+    source::{map_ft, DiskSource, Source, SourceEntry},
+    staging::Staging,
+    stats::Stats,
+    stream::{EntryReader, EntryStream, CHUNK_SIZE},
+};
+use color_eyre::eyre::{ensure, Result};
+use fuser::{consts::FUSE_ASYNC_READ, FileAttr, FileType, Filesystem, Notifier, FUSE_ROOT_ID};
+use libc::{ENODATA, ENOENT, ERANGE, ESTALE, NAME_MAX, PATH_MAX};
 use lru::LruCache;
-use tracing::{debug, error};
-use zip::ZipArchive;
+use serde::Serialize;
+use serde_json::json;
+use tracing::{debug, error, field, info, trace, trace_span, warn, Span};
+use zip::{CompressionMethod, ZipArchive};
 
 // TODO: Understand what it is
 const TTL: Duration = Duration::from_secs(1);
 
-fn map_io_error<E>(err: E) -> FuseError
-where
-    E: Into<std::io::Error>,
-{
-    let err: std::io::Error = err.into();
-    err.raw_os_error().unwrap_or(libc::EIO)
+const DOS_ATTRS_XATTR: &str = "user.zipfs.dos_attrs";
+/// DOS date and time of the entry as stored, the date in the high half, in hex
+const MTIME_RAW_XATTR: &str = "user.zipfs.mtime_raw";
+/// `unix-extra` when the entry has its modification time in UTC as well, `dos` otherwise
+const MTIME_SOURCE_XATTR: &str = "user.zipfs.mtime_source";
+const POSIX_ACL_XATTR: &str = "system.posix_acl_access";
+
+/// Pause before retrying to open an archive whose NFS handle went stale
+const ESTALE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Entries being read sequentially whose decompressor is kept around
+const ENTRY_READERS: usize = 16;
+
+/// Memory accounted for each record of the central directory of a cached archive, roughly
+const ARCHIVE_ENTRY_MEMORY: u64 = 256;
+
+/// Memory accounted for each kept entry reader, a chunk along with its decompressor, roughly
+const ENTRY_READER_MEMORY: u64 = 2 * CHUNK_SIZE;
+
+/// Holds the files served by the file system itself, in the root of the mount
+const METADATA_DIR: &str = ".zipfs";
+const MANIFEST_FILE: &str = "manifest.txt";
+const STATUS_FILE: &str = "status";
+
+/// Length of the fixed part of a central directory file header, and offsets within it of the
+/// host system in "version made by" and of the external file attributes
+const CENTRAL_HEADER_LEN: usize = 46;
+const CENTRAL_HEADER_HOST: usize = 5;
+const CENTRAL_HEADER_EXTERNAL_ATTRS: usize = 38;
+
+/// Offsets within a central directory file header of the DOS modification time, followed by
+/// the date, and of the lengths of the name and the extra field following the fixed part
+const CENTRAL_HEADER_MTIME: usize = 12;
+const CENTRAL_HEADER_NAME_LEN: usize = 28;
+const CENTRAL_HEADER_EXTRA_LEN: usize = 30;
+
+/// Extra field of Info-ZIP with Unix timestamps in UTC, starting with flags of which the
+/// lowest marks the modification time as present
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+const EXTENDED_TIMESTAMP_MTIME: u8 = 0x01;
+
+/// Host system of entries whose external attributes hold a Unix mode in their high half
+const HOST_UNIX: u8 = 3;
+
+/// Layout of `system.posix_acl_access`, from `linux/posix_acl_xattr.h`
+const POSIX_ACL_XATTR_VERSION: u32 = 2;
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_OTHER: u16 = 0x20;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+const DOS_ATTRS: [(u32, &str); 5] = [
+    (0x01, "readonly"),
+    (0x02, "hidden"),
+    (0x04, "system"),
+    (0x10, "directory"),
+    (0x20, "archive"),
+];
+
+/// Entry of a missing name, remembered by the kernel for as long as it's valid. Inode 0 marks
+/// it as negative, the kernel doesn't look at the rest.
+fn negative_entry() -> FileAttr {
+    FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileType::RegularFile,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
+/// Read up to `size` bytes, tolerating short reads from truncated or misreported entries.
+/// Errors are only reported when nothing could be read at all.
+fn read_available<R: Read>(reader: &mut R, size: usize) -> Result<Vec<u8>, ZipFsError> {
+    let mut buf = vec![0; size];
+    let mut filled = 0;
+
+    while filled < size {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) if filled == 0 => return Err(err.into()),
+            Err(err) => {
+                warn!("Short read after {} bytes: {:?}", filled, err);
+                break;
+            }
+        }
+    }
+
+    buf.truncate(filled);
+    Ok(buf)
+}
+
+/// Whether `name` ends in one of `exts`, ignoring case
+pub(crate) fn is_archive_name(name: &str, exts: &[String]) -> bool {
+    let Some((stem, ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+
+    !stem.is_empty()
+        && exts
+            .iter()
+            .any(|archive_ext| archive_ext.eq_ignore_ascii_case(ext))
+}
+
+/// Render the DOS attributes from the low byte of the external attributes, e.g. `hidden,archive`
+fn format_dos_attrs(external_attributes: u32) -> String {
+    DOS_ATTRS
+        .iter()
+        .filter(|(bit, _)| external_attributes & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Whether the extra field `extra` holds an extended timestamp with the modification time
+fn has_extended_mtime(mut extra: &[u8]) -> bool {
+    while extra.len() >= 4 {
+        let id = u16::from_le_bytes([extra[0], extra[1]]);
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let Some(data) = extra.get(4..4 + len) else {
+            return false;
+        };
+
+        if id == EXTENDED_TIMESTAMP_ID {
+            return data.len() >= 5 && data[0] & EXTENDED_TIMESTAMP_MTIME != 0;
+        }
+
+        extra = &extra[4 + len..];
+    }
+
+    false
+}
+
+/// Render the minimal access ACL equivalent to the permission bits of `mode`, with an entry
+/// for the owner, the group and others
+fn format_posix_acl(mode: u32) -> Vec<u8> {
+    let mut acl = POSIX_ACL_XATTR_VERSION.to_le_bytes().to_vec();
+
+    for (tag, shift) in [(ACL_USER_OBJ, 6), (ACL_GROUP_OBJ, 3), (ACL_OTHER, 0)] {
+        acl.extend_from_slice(&tag.to_le_bytes());
+        acl.extend_from_slice(&((mode >> shift & 0o7) as u16).to_le_bytes());
+        acl.extend_from_slice(&ACL_UNDEFINED_ID.to_le_bytes());
+    }
+
+    acl
+}
+
+/// Refuse paths the kernel would reject, instead of having them fail further down.
+/// Archive entry names are not bound by either limit.
+fn check_path_length(path: &Path) -> Result<(), ZipFsError> {
+    let name_len = path.file_name().map_or(0, |name| name.len());
+    if name_len > NAME_MAX as usize || path.as_os_str().len() >= PATH_MAX as usize {
+        return Err(ZipFsError::NameTooLong);
+    }
+
+    Ok(())
+}
+
+/// Where the data of `name` sits in the archive, if it is stored as is. Entries whose sizes
+/// don't add up, e.g. from archivers leaving them to a data descriptor, are left to the zip
+/// crate to read.
+fn stored_data(
+    archive: &mut ZipArchive<PositionedReader>,
+    name: &str,
+) -> Result<Option<Range<u64>>, ZipFsError> {
+    let Some(index) = archive.index_for_name(name) else {
+        return Ok(None);
+    };
+
+    let entry = archive.by_index_raw(index)?;
+    if entry.compression() != CompressionMethod::Stored || entry.encrypted() {
+        return Ok(None);
+    }
+
+    if entry.compressed_size() == 0 || entry.compressed_size() != entry.size() {
+        return Ok(None);
+    }
+
+    Ok(Some(entry.data_start()..entry.data_start() + entry.size()))
+}
+
+/// Refuse entries whose local file header disagrees with their central directory record
+fn validate_local_header(
+    archive: &mut ZipArchive<PositionedReader>,
+    name: &str,
+) -> Result<(), ZipFsError> {
+    let Some(index) = archive.index_for_name(name) else {
+        return Ok(());
+    };
+
+    let mismatches = local_header_mismatches(archive, index)?;
+    if !mismatches.is_empty() {
+        return Err(ZipFsError::HeaderMismatch(mismatches.join(", ")).with_path(name));
+    }
+
+    Ok(())
+}
+
+/// Span around a single FUSE operation, correlated by the unique ID of the request. The path
+/// is recorded once it is resolved, the handle and range by the operations having them.
+fn op_span(op: &'static str, req: &fuser::Request<'_>, ino: INode) -> Span {
+    trace_span!(
+        "fuse",
+        op,
+        request = req.unique(),
+        ino,
+        fh = field::Empty,
+        offset = field::Empty,
+        size = field::Empty,
+        path = field::Empty,
+    )
+}
+
+/// Offsets taken by the `.` and `..` entries at the start of every listing
+const DOT_ENTRIES: i64 = 2;
+
+/// Inode listed for entries the kernel hasn't looked up yet, as libfuse does. Listing gives
+/// the kernel no reference to forget, so inodes are only created once looked up.
+const UNKNOWN_INO: INode = 0xffff_ffff;
+
+/// Bytes asked for at once when extracting a file, as many as the kernel would
+const EXTRACT_CHUNK: u32 = 128 * 1024;
+
+/// Most bytes copied per `copy_file_range`, callers carry on from where a copy stopped
+const COPY_CHUNK: u64 = 1024 * 1024;
+
+/// Takes the entries of a directory listing, the reply to the kernel or a list to extract
+trait DirSink {
+    /// Add an entry, true once full
+    fn add(&mut self, ino: INode, offset: i64, kind: FileType, name: &std::ffi::OsStr) -> bool;
+}
+
+impl DirSink for fuser::ReplyDirectory {
+    fn add(&mut self, ino: INode, offset: i64, kind: FileType, name: &std::ffi::OsStr) -> bool {
+        fuser::ReplyDirectory::add(self, ino, offset, kind, name)
+    }
+}
+
+impl DirSink for Vec<(FileType, std::ffi::OsString)> {
+    fn add(&mut self, _ino: INode, _offset: i64, kind: FileType, name: &std::ffi::OsStr) -> bool {
+        self.push((kind, name.to_os_string()));
+        false
+    }
 }
 
-fn map_ft(ft: fs::FileType) -> Result<fuser::FileType, FuseError> {
-    match ft {
-        e if e.is_dir() => Ok(fuser::FileType::Directory),
-        e if e.is_file() => Ok(fuser::FileType::RegularFile),
-        _ => Err(ENOSYS),
+/// Entries of a directory listing after `.` and `..`, whose offsets come after theirs
+struct DirListing<'a>(&'a mut dyn DirSink);
+
+impl DirListing<'_> {
+    /// Add an entry, `offset` counting from the end of the dot entries. True once full.
+    fn add<T: AsRef<std::ffi::OsStr>>(
+        &mut self,
+        ino: INode,
+        offset: i64,
+        kind: FileType,
+        name: T,
+    ) -> bool {
+        self.0.add(ino, offset + DOT_ENTRIES, kind, name.as_ref())
     }
 }
 
-fn metadata_to_file_attrs(metadata: fs::Metadata) -> Result<FileAttr, FuseError> {
-    Ok(FileAttr {
-        ino: metadata.st_ino(),
-        size: metadata.st_size(),
-        blocks: metadata.st_blocks(),
-        atime: UNIX_EPOCH + Duration::from_secs(metadata.st_atime() as u64),
-        mtime: UNIX_EPOCH + Duration::from_secs(metadata.st_mtime() as u64),
-        ctime: UNIX_EPOCH + Duration::from_secs(metadata.st_ctime() as u64),
-        crtime: UNIX_EPOCH + Duration::from_secs(metadata.st_ctime() as u64),
-        kind: map_ft(metadata.file_type())?,
-        perm: metadata.permissions().mode() as u16,
-        nlink: metadata.st_nlink() as u32,
-        uid: metadata.st_uid(),
-        gid: metadata.st_gid(),
-        rdev: metadata.st_rdev() as u32,
-        blksize: metadata.st_blksize() as u32,
-        flags: 0, // NOTE: macos only
-    })
+/// Entry of a directory inside an archive, as listed by `readdir`
+#[derive(Debug)]
+struct ZipDirEntry {
+    /// Relative to the root of the archive
+    name: String,
+    /// Name in the listing, shortened if too long
+    shown: String,
+    kind: FileType,
+}
+
+/// Outcome of [`ZipFs::extract`]
+#[derive(Debug, Default, Serialize)]
+pub struct ExtractReport {
+    pub files: usize,
+    pub directories: usize,
+    /// Files that failed to read, relative to the destination, with the reason. They are left
+    /// out rather than written partially.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Entry of a directory listed with [`ZipFs::list_path`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntryInfo {
+    pub name: std::ffi::OsString,
+    pub kind: FileType,
+    pub size: u64,
+    pub perm: u16,
+    pub mtime: SystemTime,
+}
+
+fn reply_xattr(reply: fuser::ReplyXattr, size: u32, data: Result<Vec<u8>, libc::c_int>) {
+    match data {
+        Ok(data) if size == 0 => reply.size(data.len() as u32),
+        Ok(data) if data.len() > size as usize => reply.error(ERANGE),
+        Ok(data) => reply.data(&data),
+        Err(errno) => reply.error(errno),
+    }
 }
 
+/// The file system, served by fuser or driven directly through its public methods.
+///
+/// # Locking
+///
+/// Operations take `&mut self` and fuser serves them one at a time, so the state only they
+/// touch, such as the file tree, is plain data. There is no finer locking letting them run in
+/// parallel, not even when shared between threads.
+///
+/// The state shared with the [`ControlHandle`], the prefetch and the cache warmer is locked
+/// field by field, for the lookup or update at hand:
+///
+/// - `archive_mtimes`, then `open_files` within it while a rescan drops changed archives. No
+///   other lock is ever taken while holding one of these.
+/// - `passwords`, `chunk_cache`, `filter`, `notifier` and `entry_readers` are only ever held
+///   on their own.
+///
+/// `ZipFs` is `Send + Sync`, so that it can be embedded behind an `Arc<Mutex<_>>` next to
+/// handles used from other threads. The mutex serializes the operations, only the handles run
+/// alongside them.
 pub struct ZipFs {
     umount: Option<Sender<()>>,
-    open_files: LruCache<FileHandle, ZipArchive<Arc<File>>>,
+    open_files: ArchiveCache,
+    open_file: ArchiveOpener,
+    /// Where the data directories are listed and read from
+    source: Arc<dyn Source>,
+    stats: Arc<Stats>,
+    open_timeout: Option<Duration>,
+    staging: Option<Staging>,
+    strict: bool,
+    /// Stored entries are read straight from their archive through their file handle, and
+    /// kept in the page cache of the kernel across opens
+    direct_stored: bool,
+    /// Entries claiming to decompress to more than this many times their size are refused,
+    /// whatever they claim when unset
+    max_ratio: Option<u64>,
+    /// Entries are refused with EMFILE when opening them would hold more archives open
+    /// at once, whatever the size of the archive cache
+    max_open_archives: Option<usize>,
+    /// Archive of every open entry by file handle, tracked along with the limit above
+    archive_handles: HashMap<FileHandle, PathBuf>,
+    /// Archives and entry readers are refused with ENOMEM beyond this many bytes taken by the
+    /// caches, as accounted by [`ZipFs::memory_usage`]
+    max_memory: Option<u64>,
+    /// Source and data range of the stored entries opened directly, by file handle
+    direct_handles: HashMap<FileHandle, (PositionedReader, Range<u64>)>,
+    /// Sorted listings of the directories inside archives being read, by directory handle
+    dir_listings: HashMap<FileHandle, Vec<ZipDirEntry>>,
+    next_fh: FileHandle,
+    validated_entries: HashSet<PathBuf>,
+    /// Behind a lock only for `Sync`, the decompressor of a reader hands over its chunks
+    /// through a channel
+    entry_readers: Mutex<LruCache<PathBuf, EntryReader>>,
+    /// Decompressed chunks of the entries read, if enabled
+    chunk_cache: Option<SharedChunkCache>,
+    /// Attributes of archive entries by inode, before the umask and owner are applied
+    attr_cache: Option<LruCache<INode, FileAttr>>,
+    /// Names found missing inside archives, if enabled
+    negative_lookups: Option<NegativeCache>,
+    /// Given to the kernel with missing names, they are looked up every time when zero
+    negative_ttl: Duration,
+    manifest: bool,
+    /// Serve the configuration and live stats in `.zipfs/status`
+    expose_metadata: bool,
+    manifest_reader: Option<ManifestReader>,
+    /// The data directory followed by the directories overlaid below it
+    sources: Vec<PathBuf>,
+    umask: u16,
+    /// Reported in place of the owner of every file, if set
+    uid: Option<u32>,
+    gid: Option<u32>,
+    archive_exts: Vec<String>,
+    /// The data directory is an archive serving as the whole namespace
+    chroot: bool,
+    /// Name of the only directory in the root of the mount, which holds the data directory
+    virtual_root: Option<String>,
+    sort: SortOrder,
+    /// Asked from the kernel on init, in bytes
+    max_readahead: u32,
+    /// Archive entry names longer than this are shortened in listings
+    max_name_len: usize,
+    /// Archives are listed without their extension
+    strip_ext: bool,
+    /// Archives stay files, their contents are served next to them under this suffix
+    dual_view: Option<String>,
+    /// Archives are served as the files they are, nothing is read from them
+    passthrough_only: bool,
+    /// Shared with the control interface so that it can be swapped on reload
+    filter: Arc<RwLock<PathFilter>>,
+    /// Bumped by the control interface whenever cached archives were dropped
+    generation: Arc<AtomicU64>,
+    seen_generation: u64,
+    /// Set by the control interface once shutting down, new files are no longer opened
+    draining: Arc<AtomicBool>,
+    /// Set by the control interface, the rescan happens before the next operation
+    rescan_requested: Arc<AtomicBool>,
+    notifier: NotifierSlot,
+    archive_mtimes: ArchiveMtimes,
+    /// Opens the archives of listed directories in the background, if enabled
+    warmer: Option<CacheWarmer>,
+    /// Throttles reads across the whole mount, if enabled
+    rate_limiter: Option<RateLimiter>,
+    /// Notified of opens, reads, listings and errors, if any
+    listener: Option<Box<dyn EventListener>>,
+    /// Reported along with every inode looked up, bumped on every mount so that NFS clients
+    /// tell the inodes of this mount apart from the ones of earlier mounts
+    inode_generation: u64,
+    /// Keeps the generation of the last mount, the mount time is used when unset
+    generation_file: Option<PathBuf>,
+    /// Asked for the password of an archive once one of its encrypted entries is touched
+    password_provider: Box<dyn PasswordProvider>,
+    /// Answers of the password provider by archive, kept while the archive stays cached
+    passwords: Mutex<HashMap<PathBuf, Option<Arc<[u8]>>>>,
     tree: FileTree,
 }
 
+// NOTE: Fails the build rather than a test once a field gets in the way
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ZipFs>();
+};
+
+/// Paths served by the file system itself rather than the data directory
+enum Synthetic {
+    /// The root of the mount above a virtual root, which has no path of its own
+    MountRoot,
+    MetadataDir,
+    Manifest,
+    Status,
+}
+
 impl Drop for ZipFs {
     fn drop(&mut self) {
         debug!("Drop ZipFs");
@@ -77,330 +524,3653 @@ impl Drop for ZipFs {
 }
 
 impl ZipFs {
-    pub fn new(data_dir: PathBuf, cache_size: NonZeroUsize, umount: Option<Sender<()>>) -> Self {
+    pub fn new(data_dir: PathBuf, caches: CacheConfig, umount: Option<Sender<()>>) -> Self {
         Self {
             umount,
-            open_files: LruCache::new(cache_size),
+            open_files: Arc::new(Mutex::new(LruCache::new(caches.archive_entries))),
+            open_file: Arc::new(|path: &Path| DiskSource.open(path)),
+            source: Arc::new(DiskSource),
+            stats: Arc::new(Stats::default()),
+            open_timeout: None,
+            staging: None,
+            strict: false,
+            direct_stored: false,
+            max_ratio: Some(DEFAULT_MAX_RATIO),
+            max_open_archives: None,
+            archive_handles: HashMap::new(),
+            max_memory: None,
+            direct_handles: HashMap::new(),
+            dir_listings: HashMap::new(),
+            next_fh: 0,
+            validated_entries: HashSet::new(),
+            entry_readers: Mutex::new(LruCache::new(NonZeroUsize::new(ENTRY_READERS).unwrap())),
+            chunk_cache: match caches.content_bytes {
+                0 => None,
+                size => Some(Arc::new(Mutex::new(ChunkCache::new(size)))),
+            },
+            attr_cache: NonZeroUsize::new(caches.attr_entries).map(LruCache::new),
+            negative_lookups: NonZeroUsize::new(caches.negative_lookups)
+                .map(|capacity| NegativeCache::new(capacity, caches.negative_lookup_ttl)),
+            negative_ttl: caches.negative_ttl,
+            manifest: false,
+            expose_metadata: false,
+            manifest_reader: None,
+            sources: vec![data_dir.clone()],
+            umask: 0,
+            uid: None,
+            gid: None,
+            archive_exts: DEFAULT_ARCHIVE_EXTS.map(String::from).to_vec(),
+            chroot: false,
+            virtual_root: None,
+            sort: SortOrder::default(),
+            max_readahead: DEFAULT_MAX_READAHEAD,
+            max_name_len: NAME_MAX as usize,
+            strip_ext: false,
+            dual_view: None,
+            passthrough_only: false,
+            filter: Arc::new(RwLock::new(PathFilter::default())),
+            generation: Arc::new(AtomicU64::new(0)),
+            seen_generation: 0,
+            draining: Arc::default(),
+            rescan_requested: Arc::default(),
+            notifier: Arc::default(),
+            archive_mtimes: Arc::default(),
+            warmer: None,
+            rate_limiter: None,
+            listener: None,
+            inode_generation: 0,
+            generation_file: None,
+            password_provider: Box::new(PasswordMap::default()),
+            passwords: Mutex::default(),
             tree: FileTree::new(data_dir),
         }
     }
 
-    fn get_data_path(&self, ino: INode) -> Result<PathBuf, FuseError> {
-        let Some(path) = self.tree.find_path_by_inode(ino) else {
-            error!("Path not found for ino = {}", ino);
-            return Err(ENOENT);
-        };
-
-        Ok(path.to_path_buf())
-    }
+    /// Build the file system as configured by `options`
+    pub fn from_options(options: &Options, umount: Option<Sender<()>>) -> Result<Self> {
+        ensure!(
+            !options.passthrough_only || !options.chroot_into_zip,
+            "--passthrough-only conflicts with --chroot-into-zip"
+        );
 
-    fn get_or_create_inode(&mut self, path: PathBuf) -> INode {
-        self.tree
-            .find_inode_by_path(&path)
-            .unwrap_or_else(|| self.tree.add_file(path))
-    }
+        if let Some(name) = &options.virtual_root {
+            let components = Path::new(name).components().collect::<Vec<_>>();
+            ensure!(
+                components == [Component::Normal(name.as_ref())],
+                "Invalid virtual root {:?}, expected a single name",
+                name
+            );
+        }
 
-    fn get_zip_paths(path: &Path) -> Option<(PathBuf, PathBuf)> {
-        let mut zip_index = None;
+        // NOTE: An archive given in place of the data directory is served as the whole mount,
+        // listing a file as a directory would only fail with ENOTDIR
+        let mut chroot = options.chroot_into_zip;
+        if !chroot && options.data_dir.is_file() {
+            let name = options.data_dir.file_name().unwrap_or_default();
+            ensure!(
+                !options.passthrough_only,
+                "--passthrough-only expects a directory, {:?} is a file",
+                options.data_dir
+            );
+            ensure!(
+                is_archive_name(&name.to_string_lossy(), &options.archive_exts),
+                "Data directory {:?} is a file, pass --chroot-into-zip to serve it as an archive",
+                options.data_dir
+            );
 
-        let components = path.components().rev().collect::<Vec<_>>();
-        for (index, component) in components.iter().enumerate() {
-            if let Some(extension) = component.as_os_str().to_str() {
-                if extension.ends_with(".zip") {
-                    zip_index = Some(index);
-                    break;
-                }
-            }
+            info!(
+                "Serving the archive {:?} as the whole mount",
+                options.data_dir
+            );
+            chroot = true;
         }
 
-        match zip_index {
-            Some(index) => {
-                let zip_path = components[index..].iter().rev().collect::<PathBuf>();
-                let file_path = components[..index].iter().rev().collect::<PathBuf>();
-                Some((zip_path, file_path))
-            }
-            None => None,
-        }
+        let fs = Self::new(options.data_dir.clone(), options.cache_config(), umount)
+            .with_open_timeout(options.open_timeout.map(Duration::from_millis))
+            .with_strict(options.strict)
+            .with_direct_stored(options.direct_stored)
+            .with_max_ratio((!options.allow_bombs).then_some(options.max_ratio))
+            .with_max_open_archives(options.max_open_archives)
+            .with_max_memory(options.max_memory)
+            .with_manifest(options.manifest)
+            .with_expose_metadata(options.expose_metadata)
+            .with_union_dirs(options.data_dirs.clone())
+            .with_umask(options.umask)
+            .with_owner(options.uid, options.gid)
+            .with_archive_exts(options.archive_exts.clone())
+            .with_chroot_into_zip(chroot)
+            .with_virtual_root(options.virtual_root.clone())
+            .with_sort(options.sort)
+            .with_max_readahead(options.readahead_kb * 1024)
+            .with_max_entry_name_length(options.max_entry_name_length)
+            .with_strip_ext(options.strip_ext)
+            .with_dual_view(options.dual_view.then(|| options.dual_view_suffix.clone()))
+            .with_passthrough_only(options.passthrough_only)
+            .with_warm_concurrency(options.warm_concurrency)
+            .with_rate_limit(options.rate_limit)
+            .with_generation_file(options.generation_file.clone())
+            .with_password_provider(Box::new(options.load_passwords()?))
+            .with_filter(PathFilter::from_options(options)?);
+
+        Ok(match options.writable_archives {
+            true => fs.with_writable_archives(options.max_staging_size),
+            false => fs,
+        })
     }
 
-    fn getattr_(&mut self, ino: INode) -> Result<FileAttr, FuseError> {
-        let path = self.get_data_path(ino)?;
+    /// List and read the data directories from `source` rather than the local disk, archives
+    /// included unless [`ZipFs::with_archive_source`] is called afterwards. The manifest and
+    /// prefetching still walk the disk.
+    pub fn with_source(mut self, source: Box<dyn Source>) -> Self {
+        let source: Arc<dyn Source> = Arc::from(source);
+        let opener = source.clone();
+        self.open_file = Arc::new(move |path| opener.open(path));
+        self.source = source;
+        self
+    }
 
-        if let Some((ref zip_path, file_path)) = ZipFs::get_zip_paths(&path) {
-            let metadata = fs::metadata(zip_path).map_err(map_io_error)?;
-            let mut attrs = metadata_to_file_attrs(metadata)?;
-            attrs.ino = ino;
+    /// Read archives from what `open` returns for their paths rather than from local files,
+    /// e.g. from remote objects fetched with range requests. The archives are still found by
+    /// listing the data directories.
+    pub fn with_archive_source(
+        mut self,
+        open: impl Fn(&Path) -> io::Result<Box<dyn ReadAtSize>> + Send + Sync + 'static,
+    ) -> Self {
+        self.open_file = Arc::new(open);
+        self
+    }
 
-            let Some(archive) = self.open_zip(zip_path)? else {
-                attrs.kind = FileType::Directory;
-                attrs.perm = 0o555;
-                return Ok(attrs);
-            };
+    /// Serve at most `bytes_per_second` across all reads, delaying their replies once the burst
+    /// of a tenth of a second is used up. Unlimited when `None` or 0.
+    pub fn with_rate_limit(mut self, bytes_per_second: Option<u64>) -> Self {
+        self.rate_limiter = bytes_per_second
+            .and_then(NonZeroU64::new)
+            .map(RateLimiter::new);
+        self
+    }
 
-            let is_dir = archive
-                .clone()
-                .by_name(file_path.to_string_lossy().as_ref())
-                .map(|entry| entry.is_dir())
-                .unwrap_or(true);
+    /// Notify `listener` of the operations served, from the thread serving them
+    pub fn with_event_listener(mut self, listener: Box<dyn EventListener>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
 
-            if is_dir {
-                attrs.kind = FileType::Directory;
-                attrs.perm = 0o555;
-            } else {
-                attrs.kind = FileType::RegularFile;
-                attrs.perm = 0o444;
-            }
+    /// Keep the generation of the last mount in `path`, read and bumped on every mount.
+    /// Without it the generation is the mount time in seconds, which only grows as long as
+    /// the clock does.
+    pub fn with_generation_file(mut self, path: Option<PathBuf>) -> Self {
+        self.generation_file = path;
+        self
+    }
 
-            Ok(attrs)
-        } else {
-            let metadata = fs::metadata(&path).map_err(map_io_error)?;
-            let mut attrs = metadata_to_file_attrs(metadata)?;
-            attrs.ino = ino;
-            Ok(attrs)
-        }
+    /// Decrypt the entries of the archives in `passwords`, keyed by their path relative to
+    /// their data directory. Encrypted entries of other archives are listed as directories.
+    pub fn with_passwords(self, passwords: HashMap<PathBuf, String>) -> Self {
+        self.with_password_provider(Box::new(passwords.into_iter().collect::<PasswordMap>()))
     }
 
-    fn open_zip(&mut self, zip_path: &PathBuf) -> Result<Option<ZipArchive<Arc<File>>>, FuseError> {
-        let Some(ino) = self.tree.find_inode_by_path(zip_path) else {
-            // NOTE: Maybe create the inode then?
-            error!("inode not found for zip = {:?}", zip_path);
-            return Err(ENOENT);
-        };
+    /// Decrypt encrypted entries with the passwords `provider` resolves, see
+    /// [`PasswordProvider`] for when it is asked
+    pub fn with_password_provider(mut self, provider: Box<dyn PasswordProvider>) -> Self {
+        self.password_provider = provider;
+        self.passwords.get_mut().unwrap().clear();
+        self
+    }
 
-        // Get from cache
-        if let Some(archive) = self.open_files.get(&ino) {
-            return Ok(Some(archive.clone()));
-        }
+    /// Abort opening an archive if reading its central directory takes longer than `timeout`.
+    pub fn with_open_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.open_timeout = timeout;
+        self
+    }
 
-        let file = fs::File::open(zip_path).map_err(map_io_error)?;
-        let archive = ZipArchive::new(Arc::new(file));
+    /// Allow creating new archives through the mount, holding at most `max_staging_size` bytes
+    /// in memory until they are committed with an `fsync` on the archive directory.
+    pub fn with_writable_archives(mut self, max_staging_size: u64) -> Self {
+        self.staging = Some(Staging::new(max_staging_size));
+        self
+    }
 
-        let archive = match archive {
-            Ok(archive) => archive,
-            Err(err) => {
-                error!("Error opening zip file: {:?}", err);
-                return Ok(None);
-            }
-        };
+    /// Refuse to read entries whose local header disagrees with the central directory
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        self.open_files.put(ino, archive.clone());
-        Ok(Some(archive))
+    /// Open stored entries on a handle reading straight from their archive, with their pages
+    /// kept by the kernel so that reads and mappings of cached data skip the file system
+    pub fn with_direct_stored(mut self, direct_stored: bool) -> Self {
+        self.direct_stored = direct_stored;
+        self
     }
 
-    fn readdir_zip(
-        &mut self,
-        ino: INode,
-        offset: i64,
-        zip_path: &Path,
-        file_path: &Path,
-        reply: &mut fuser::ReplyDirectory,
-    ) -> Result<(), FuseError> {
-        debug!("zip_path = {:?}, file_path = {:?}", zip_path, file_path);
-        if offset != 0 {
-            return Ok(());
+    /// Refuse to open entries whose central directory record claims they decompress to more
+    /// than `max_ratio` times their size, with EFBIG. Anything goes when unset.
+    pub fn with_max_ratio(mut self, max_ratio: Option<u64>) -> Self {
+        self.max_ratio = max_ratio;
+        self
+    }
+
+    /// Refuse to open entries with EMFILE while entries of `max_open_archives` other archives
+    /// are open. Unlike the archive cache, which closes archives to make room, this is a hard
+    /// ceiling.
+    pub fn with_max_open_archives(mut self, max_open_archives: Option<usize>) -> Self {
+        self.max_open_archives = max_open_archives;
+        self
+    }
+
+    /// Refuse with ENOMEM to open archives or start decompressing entries once the caches
+    /// would take more than `max_memory` bytes. The content cache is emptied first, and is
+    /// never given more than the whole limit.
+    pub fn with_max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+
+        if let (Some(max_memory), Some(cache)) = (max_memory, &self.chunk_cache) {
+            let budget = cache.lock().unwrap().budget();
+            if budget > max_memory {
+                self.chunk_cache = Some(Arc::new(Mutex::new(ChunkCache::new(max_memory))));
+            }
         }
 
-        let Some(mut archive) = self.open_zip(&zip_path.to_path_buf())? else {
-            return Ok(());
-        };
+        self
+    }
 
-        let file_string = file_path.to_string_lossy().to_string() + "/";
-        let file_string = file_string.strip_prefix('/').unwrap_or(&file_string);
-        let slash_count = file_string.chars().filter(|c| *c == '/').count();
+    /// Serve a `.zipfs/manifest.txt` listing every file in the mount, one per line
+    pub fn with_manifest(mut self, manifest: bool) -> Self {
+        self.manifest = manifest;
+        self
+    }
 
-        let cloned_archive = archive.clone();
-        let mut file_names = cloned_archive
-            .file_names()
-            .filter(|name| name.starts_with(file_string))
-            .filter_map(|name| name.split('/').nth(slash_count))
-            .collect::<Vec<_>>();
+    /// Serve a `.zipfs/status` with the configuration and live stats, rendered on every read
+    pub fn with_expose_metadata(mut self, expose_metadata: bool) -> Self {
+        self.expose_metadata = expose_metadata;
+        self
+    }
 
-        file_names.dedup();
-        file_names.sort_by_key(|name| name.len());
+    /// Overlay more directories below the data directory, read-only. On name collisions the
+    /// data directory wins, then the earlier of `dirs`.
+    pub fn with_union_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.sources.extend(dirs);
+        self
+    }
 
-        debug!("slash_count = {}", slash_count);
-        debug!("file_string = {:?}", file_string);
-        debug!("file_names = {:?}", file_names);
+    /// Ask the kernel to read ahead up to `max_readahead` bytes, or as much as it allows
+    pub fn with_max_readahead(mut self, max_readahead: u32) -> Self {
+        self.max_readahead = max_readahead;
+        self
+    }
 
-        for (i, name) in file_names.iter().enumerate() {
-            let Ok(entry) = archive.by_name(name) else {
-                if reply.add(ino, offset + i as i64 + 1, FileType::Directory, name) {
-                    break;
-                }
+    /// Report every file as owned by `uid` and `gid` instead of their real owner, where given
+    pub fn with_owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
 
-                continue;
-            };
+    /// Clear the bits of `umask` from every reported permission
+    pub fn with_umask(mut self, umask: u16) -> Self {
+        self.umask = umask;
+        self
+    }
 
-            let Some(file_name) = entry.enclosed_name() else {
-                error!("file name invalid = {:?}", name);
-                continue;
-            };
+    /// Mount files with any of `exts` as their extension as directories, without the dot
+    pub fn with_archive_exts(mut self, exts: Vec<String>) -> Self {
+        self.archive_exts = exts;
+        self
+    }
 
-            debug!("file_name = {:?}", file_name);
-            let ino = self.get_or_create_inode(zip_path.join(&file_name));
+    /// Treat the data directory as an archive whose root is the root of the mount. Passthrough
+    /// files and the manifest are not served, whatever the extension of the archive.
+    pub fn with_chroot_into_zip(mut self, chroot: bool) -> Self {
+        self.chroot = chroot;
+        self
+    }
 
-            debug!("offset = {}", offset,);
+    /// Serve the data directory as the only directory in the root of the mount, named `name`,
+    /// which must be a single path component. Avoids collisions with whatever else is served
+    /// below the mount point.
+    pub fn with_virtual_root(mut self, name: Option<String>) -> Self {
+        let data_dir = self.tree.find_path_by_inode(self.root_ino()).cloned();
+        self.virtual_root = name;
 
-            if reply.add(
-                ino,
-                offset + i as i64 + 1,
-                FileType::RegularFile,
-                &file_name,
-            ) {
-                break;
-            }
+        if let Some(data_dir) = data_dir {
+            self.tree = match self.virtual_root {
+                Some(_) => FileTree::with_virtual_root(data_dir),
+                None => FileTree::new(data_dir),
+            };
         }
 
-        Ok(())
+        self
     }
 
-    fn readdir_(
-        &mut self,
-        ino: INode,
-        _fh: FileHandle,
-        offset: i64,
-        reply: &mut fuser::ReplyDirectory,
-    ) -> Result<(), FuseError> {
-        let path = self.get_data_path(ino)?;
+    /// Order directory listings by `sort`
+    pub fn with_sort(mut self, sort: SortOrder) -> Self {
+        self.sort = sort;
+        self
+    }
 
-        if let Some((zip_path, file_path)) = ZipFs::get_zip_paths(&path) {
-            return self.readdir_zip(ino, offset, &zip_path, &file_path, reply);
-        }
+    /// Open the archives of listed directories on `concurrency` background threads, so that
+    /// the first access into them is served from the cache. Disabled when 0.
+    pub fn with_warm_concurrency(mut self, concurrency: usize) -> Self {
+        self.warmer = match concurrency {
+            0 => None,
+            concurrency => Some(CacheWarmer::new(concurrency)),
+        };
+        self
+    }
 
-        let metadata = fs::metadata(&path).map_err(map_io_error)?;
-        if !metadata.is_dir() {
-            return Err(libc::ENOTDIR);
-        }
+    /// List archives as directories named without their extension, `photos.zip` as `photos`
+    pub fn with_strip_ext(mut self, strip_ext: bool) -> Self {
+        self.strip_ext = strip_ext;
+        self
+    }
 
-        let entries = fs::read_dir(&path).map_err(map_io_error)?;
+    /// Keep archives as regular files, serving their contents in a sibling directory named
+    /// after them with `suffix` appended, `foo.zip.d` for `foo.zip` and `.d`
+    pub fn with_dual_view(mut self, suffix: Option<String>) -> Self {
+        self.dual_view = suffix;
+        self
+    }
 
-        for (i, entry) in entries.skip(offset as usize).enumerate() {
-            let entry = entry.map_err(map_io_error)?;
+    /// Serve archives as regular files like any other, without looking into them. A baseline
+    /// to compare against, or a plain bottom layer of an overlay.
+    pub fn with_passthrough_only(mut self, passthrough_only: bool) -> Self {
+        self.passthrough_only = passthrough_only;
+        self
+    }
 
-            let file_type = entry.file_type().map_err(map_io_error).map(map_ft)??;
-            let file_name = entry.file_name().to_string_lossy().to_string();
+    /// Shorten archive entry names longer than `len` bytes, at most `NAME_MAX`
+    pub fn with_max_entry_name_length(mut self, len: usize) -> Self {
+        self.max_name_len = len.min(NAME_MAX as usize);
+        self
+    }
 
-            let file_path = path.join(&file_name);
+    /// Hide the files and archive entries not passing `filter`
+    pub fn with_filter(self, filter: PathFilter) -> Self {
+        *self.filter.write().unwrap() = filter;
+        self
+    }
 
-            // TODO: If extension is .zip, say it's a directory
+    /// Handle for inspecting and evicting cached archives while the filesystem is mounted
+    pub fn control_handle(&self) -> ControlHandle {
+        ControlHandle {
+            data_dirs: self.sources.clone(),
+            cache: self.open_files.clone(),
+            stats: self.stats.clone(),
+            filter: self.filter.clone(),
+            generation: self.generation.clone(),
+            draining: self.draining.clone(),
+            forced: Arc::default(),
+            rescan_requested: self.rescan_requested.clone(),
+            notifier: self.notifier.clone(),
+            log_filter: None,
+        }
+    }
 
-            let ino = self.get_or_create_inode(file_path);
-            if reply.add(ino, offset + i as i64 + 1, file_type, file_name) {
-                break;
-            }
+    /// Job opening every archive ahead of the first access until the cache is full, to be
+    /// spawned once mounted
+    pub fn prefetch(&self) -> Prefetch {
+        Prefetch {
+            sources: self.sources.clone(),
+            archive_exts: self.archive_exts.clone(),
+            chroot: self.chroot,
+            preloader: self.preloader(),
         }
+    }
 
-        Ok(())
+    fn preloader(&self) -> Preloader {
+        Preloader {
+            open_archive: self.open_file.clone(),
+            open_timeout: self.open_timeout,
+            cache: self.open_files.clone(),
+            mtimes: self.archive_mtimes.clone(),
+        }
     }
 
-    fn lookup_(
+    /// Stream the decompressed contents of `inner_path` in the archive at `archive_path`,
+    /// relative to the data directory, without reading the whole entry into memory.
+    pub fn stream_entry(
         &mut self,
-        parent: INode,
-        name: &std::ffi::OsStr,
-    ) -> std::result::Result<FileAttr, FuseError> {
-        let parent_path = self.get_data_path(parent)?;
-        let path = parent_path.join(name);
-        let ino = self.get_or_create_inode(path);
-        self.getattr_(ino)
+        archive_path: &Path,
+        inner_path: &Path,
+    ) -> io::Result<EntryStream> {
+        let (zip_path, mut archive) = self.open_source_zip(archive_path)?;
+        let name =
+            resolve_alias(archive_path, &archive, &inner_path.to_string_lossy()).into_owned();
+        let password = self.entry_password(&zip_path, &mut archive, &name);
+        EntryStream::new(archive, name, password)
     }
 
-    fn read_(
+    /// Open `inner_path` in the archive at `archive_path`, relative to the data directory, for
+    /// reading and seeking through its decompressed contents.
+    pub fn open_reader(
         &mut self,
-        ino: INode,
-        _fh: FileHandle,
-        offset: i64,
-        size: u32,
-    ) -> std::result::Result<Vec<u8>, FuseError> {
-        let path = self.get_data_path(ino)?;
-
-        if let Some((zip_path, file_path)) = ZipFs::get_zip_paths(&path) {
-            if let Some(mut archive) = self.open_zip(&zip_path.to_path_buf())? {
-                let entry = archive
-                    .by_name(file_path.to_string_lossy().as_ref())
-                    .map_err(map_io_error)?;
+        archive_path: &Path,
+        inner_path: &Path,
+    ) -> io::Result<EntryReader> {
+        let (zip_path, mut archive) = self.open_source_zip(archive_path)?;
+        let name =
+            resolve_alias(archive_path, &archive, &inner_path.to_string_lossy()).into_owned();
+        let password = self.entry_password(&zip_path, &mut archive, &name);
+        EntryReader::new(archive, name, password)
+    }
 
-                let data = entry
-                    .bytes()
-                    .skip(offset as usize)
-                    .take(size as usize)
-                    .collect::<std::result::Result<Vec<u8>, _>>()
-                    .map_err(map_io_error)?;
+    /// Open the archive at `archive_path` in the first data directory having it, along with
+    /// its full path
+    fn open_source_zip(
+        &mut self,
+        archive_path: &Path,
+    ) -> io::Result<(PathBuf, ZipArchive<PositionedReader>)> {
+        let zip_path = self
+            .sources
+            .iter()
+            .map(|source| source.join(archive_path))
+            .find(|zip_path| self.exists(zip_path))
+            .unwrap_or_else(|| self.sources[0].join(archive_path));
 
-                return Ok(data);
-            }
-        }
+        let archive = self.open_zip(&zip_path)?;
 
-        let data = fs::read(path)
-            .map_err(map_io_error)?
-            .into_iter()
-            .skip(offset as usize)
-            .take(size as usize)
-            .collect::<Vec<u8>>();
+        let Some(archive) = archive else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid zip file: {:?}", zip_path),
+            ));
+        };
 
-        Ok(data)
+        Ok((zip_path, archive))
     }
-}
 
-impl Filesystem for ZipFs {
-    fn getattr(&mut self, _req: &fuser::Request<'_>, ino: INode, reply: fuser::ReplyAttr) {
-        debug!("getattr: ino={}", ino);
+    /// Password of entry `name` of the archive at `zip_path`, none unless the entry is
+    /// encrypted. The provider is asked the first time and its answer kept for the whole
+    /// archive until it leaves the archive cache, so that a wrong one is eventually retried.
+    fn entry_password(
+        &self,
+        zip_path: &Path,
+        archive: &mut ZipArchive<PositionedReader>,
+        name: &str,
+    ) -> Option<Arc<[u8]>> {
+        let index = entry_index(archive, name)?;
+        if !archive.by_index_raw(index).ok()?.encrypted() {
+            return None;
+        }
 
-        match self.getattr_(ino) {
-            Ok(attrs) => reply.attr(&TTL, &attrs),
-            Err(errno) => reply.error(errno),
+        let is_cached = self.open_files.lock().unwrap().contains(zip_path);
+        let mut passwords = self.passwords.lock().unwrap();
+        match passwords.get(zip_path) {
+            Some(password) if is_cached => return password.clone(),
+            _ => {}
         }
+
+        // NOTE: Known by its path relative to its data directory
+        let relative = match self.chroot {
+            true => Path::new(zip_path.file_name()?),
+            false => self
+                .sources
+                .iter()
+                .find_map(|source| zip_path.strip_prefix(source).ok())?,
+        };
+
+        let password = self
+            .password_provider
+            .password_for(relative, Some(name))
+            .map(|password| Arc::from(password.expose_secret().as_bytes()));
+        passwords.insert(zip_path.to_path_buf(), password.clone());
+        password
     }
 
-    fn readdir(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        ino: INode,
-        fh: FileHandle,
-        offset: i64,
-        mut reply: fuser::ReplyDirectory,
-    ) {
-        debug!("readdir: ino={}, fh={}, offset={}", ino, fh, offset);
+    fn get_data_path(&self, ino: INode) -> Result<PathBuf, ZipFsError> {
+        let Some(path) = self.tree.find_path_by_inode(ino) else {
+            return Err(ZipFsError::UnknownInode(ino));
+        };
 
-        match self.readdir_(ino, fh, offset, &mut reply) {
-            Ok(_) => reply.ok(),
-            Err(errno) => reply.error(errno),
-        }
+        Span::current().record("path", field::debug(path));
+        Ok(path.to_path_buf())
     }
 
-    fn lookup(
-        &mut self,
-        _req: &fuser::Request<'_>,
-        parent: INode,
-        name: &std::ffi::OsStr,
-        reply: fuser::ReplyEntry,
-    ) {
-        debug!("lookup: parent={}, name={:?}", parent, name);
+    /// Errno to reply to `req` with for `err`, logged along with the path of `ino` in the data
+    /// directory and in the mount
+    fn errno(&self, req: &fuser::Request<'_>, ino: INode, err: ZipFsError) -> libc::c_int {
+        let path = self.tree.find_path_by_inode(ino);
+        let mount_path = path.map(|path| self.mount_path(ino, path));
+        let err = match path {
+            Some(path) => err.with_path(path),
+            None => err,
+        };
 
-        match self.lookup_(parent, name) {
-            Ok(attrs) => reply.entry(&TTL, &attrs, 0),
-            Err(errno) => reply.error(errno),
+        let errno = err.errno();
+        match errno {
+            // NOTE: Looking up missing names and attributes is routine
+            ENOENT | ENODATA => debug!(errno, ?mount_path, "{}", err),
+            _ => warn!(errno, ?mount_path, "{}", err),
         }
-    }
 
-    fn read(
+        if let (Some(listener), Some(mount_path)) = (&self.listener, &mount_path) {
+            listener.on_error(mount_path, errno, req.into());
+        }
+
+        errno
+    }
+
+    /// Path of `ino`, found at `path`, as seen in the mount
+    fn mount_path(&self, ino: INode, path: &Path) -> PathBuf {
+        let mut mount_path = PathBuf::from("/");
+        if ino != FUSE_ROOT_ID {
+            mount_path.extend(&self.virtual_root);
+            mount_path.extend(self.get_relative_path(ino, path).components());
+        }
+
+        mount_path
+    }
+
+    /// Notify the event listener, if any, about `ino` with its path in the mount
+    fn notify(&self, ino: INode, event: impl FnOnce(&dyn EventListener, &Path)) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+
+        if let Some(path) = self.tree.find_path_by_inode(ino) {
+            event(listener.as_ref(), &self.mount_path(ino, path));
+        }
+    }
+
+    fn get_or_create_inode(&mut self, path: PathBuf, source: usize) -> INode {
+        let ino = self.tree.get_or_add_file(path, source);
+        self.stats.set_inodes(self.tree.len());
+        ino
+    }
+
+    /// Inode to list `path` with, without creating one, see [`UNKNOWN_INO`]
+    fn listed_ino(&self, path: PathBuf) -> INode {
+        self.tree.find_inode_by_path(&path).unwrap_or(UNKNOWN_INO)
+    }
+
+    /// Inode of the data directory, the root of the mount unless there is a virtual root
+    fn root_ino(&self) -> INode {
+        match self.virtual_root {
+            Some(_) => FUSE_ROOT_ID + 1,
+            None => FUSE_ROOT_ID,
+        }
+    }
+
+    /// Inode of the directory holding `ino`, the root of the mount holds itself
+    fn parent_ino(&self, ino: INode, path: &Path) -> INode {
+        let parent = match path.parent() {
+            Some(parent) if ino > self.root_ino() => parent,
+            _ => return FUSE_ROOT_ID,
+        };
+
+        // NOTE: The parent may have been found in another of the union data directories
+        let relative = self.get_relative_path(ino, parent);
+        std::iter::once(parent.to_path_buf())
+            .chain(self.sources.iter().map(|source| source.join(&relative)))
+            .find_map(|parent| self.tree.find_inode_by_path(&parent))
+            .unwrap_or(FUSE_ROOT_ID)
+    }
+
+    /// Path of `ino` relative to the data directory it was found in
+    fn get_relative_path(&self, ino: INode, path: &Path) -> PathBuf {
+        let source = &self.sources[self.tree.source(ino)];
+        path.strip_prefix(source).unwrap_or(path).to_path_buf()
+    }
+
+    /// Find `name` in the first data directory having it, falling back to the parent's own
+    fn resolve_child(
+        &self,
+        parent: INode,
+        parent_path: &Path,
+        name: &std::ffi::OsStr,
+    ) -> (PathBuf, usize) {
+        let fallback = (parent_path.join(name), self.tree.source(parent));
+
+        // NOTE: Archive contents are never merged
+        if self.get_zip_paths(parent_path).is_some() {
+            return fallback;
+        }
+
+        let relative = self.get_relative_path(parent, parent_path).join(name);
+        self.sources
+            .iter()
+            .map(|source| source.join(&relative))
+            .enumerate()
+            .find(|(_, path)| self.exists(path))
+            .map(|(source, path)| (path, source))
+            .unwrap_or(fallback)
+    }
+
+    /// Entries of the directory merged from every data directory having it, first one wins
+    fn read_union_dir(
+        &self,
+        ino: INode,
+        path: &Path,
+    ) -> Result<Vec<(usize, SourceEntry)>, ZipFsError> {
+        let relative = self.get_relative_path(ino, path);
+        let mut names = HashSet::new();
+        let mut entries = Vec::new();
+
+        for (source, dir) in self.sources.iter().enumerate() {
+            let dir = dir.join(&relative);
+            let listed = match self.source.list(&dir) {
+                Ok(listed) => listed,
+                Err(err) if dir == path => return Err(err.into()),
+                Err(_) => continue,
+            };
+
+            for entry in listed {
+                if names.insert(entry.name.clone()) {
+                    entries.push((source, entry));
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Entries of the directory merged from every data directory, without the filtered ones
+    fn listed_entries(
+        &self,
+        ino: INode,
+        path: &Path,
+    ) -> Result<Vec<(usize, SourceEntry)>, ZipFsError> {
+        let relative = self.get_relative_path(ino, path);
+        let mut entries = self.read_union_dir(ino, path)?;
+        entries.retain(|(_, entry)| {
+            let child = relative.join(&entry.name);
+            let is_dir = entry.kind == FileType::Directory || self.get_zip_paths(&child).is_some();
+
+            self.is_visible(&child, is_dir)
+        });
+
+        Ok(entries)
+    }
+
+    /// Names `entries` are listed under, archives lose their extension with `strip_ext`.
+    /// On collisions real entries keep the name, then archives in bytewise order.
+    fn listed_names(&self, entries: &[(usize, SourceEntry)]) -> Vec<std::ffi::OsString> {
+        let mut names = entries
+            .iter()
+            .map(|(_, entry)| entry.name.clone())
+            .collect::<Vec<_>>();
+
+        if !self.strip_ext {
+            return names;
+        }
+
+        let stems = entries
+            .iter()
+            .map(|(_, entry)| {
+                let name = entry.name.to_str()?;
+                let is_archive = entry.kind == FileType::RegularFile && self.is_archive(name);
+
+                let (stem, _) = name.rsplit_once('.').filter(|_| is_archive)?;
+                Some(std::ffi::OsString::from(stem))
+            })
+            .collect::<Vec<_>>();
+
+        let mut taken = names
+            .iter()
+            .zip(&stems)
+            .filter(|(_, stem)| stem.is_none())
+            .map(|(name, _)| name.clone())
+            .collect::<HashSet<_>>();
+
+        let mut archives = stems
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, stem)| Some((i, stem?)))
+            .collect::<Vec<_>>();
+        archives.sort_by_key(|(i, _)| names[*i].clone().into_vec());
+
+        for (i, stem) in archives {
+            if taken.insert(stem.clone()) {
+                names[i] = stem;
+            } else {
+                warn!(
+                    "{:?} is listed with its extension, {:?} is taken",
+                    names[i], stem
+                );
+            }
+        }
+
+        names
+    }
+
+    /// Name of the entry of the passthrough directory at `parent_path` listed as `name`,
+    /// which is an archive with its extension stripped unless it exists as is
+    fn unstrip_name(
+        &self,
+        parent: INode,
+        parent_path: &Path,
+        name: &std::ffi::OsStr,
+    ) -> std::ffi::OsString {
+        if self.exists(&self.resolve_child(parent, parent_path, name).0) {
+            return name.to_os_string();
+        }
+
+        let Ok(entries) = self.listed_entries(parent, parent_path) else {
+            return name.to_os_string();
+        };
+
+        let names = self.listed_names(&entries);
+        entries
+            .iter()
+            .zip(names)
+            .find(|(_, listed)| listed == name)
+            .map_or(name.to_os_string(), |((_, entry), _)| entry.name.clone())
+    }
+
+    /// Whether `path`, relative to the mount, passes the include/exclude filter
+    fn is_visible(&self, path: &Path, is_dir: bool) -> bool {
+        self.filter.read().unwrap().is_visible(path, is_dir)
+    }
+
+    /// Whether `path`, relative to the root of its archive, passes the entry excludes
+    fn is_entry_visible(&self, path: &Path, is_dir: bool) -> bool {
+        self.filter.read().unwrap().is_entry_visible(path, is_dir)
+    }
+
+    fn has_metadata_dir(&self) -> bool {
+        (self.manifest || self.expose_metadata) && !self.chroot
+    }
+
+    fn get_synthetic(&self, path: &Path) -> Option<Synthetic> {
+        if self.virtual_root.is_some() && path.as_os_str().is_empty() {
+            return Some(Synthetic::MountRoot);
+        }
+
+        if !self.has_metadata_dir() {
+            return None;
+        }
+
+        let data_dir = self.tree.find_path_by_inode(self.root_ino())?;
+        let metadata_dir = data_dir.join(METADATA_DIR);
+
+        match path {
+            path if path == metadata_dir => Some(Synthetic::MetadataDir),
+            path if self.manifest && path == metadata_dir.join(MANIFEST_FILE) => {
+                Some(Synthetic::Manifest)
+            }
+            path if self.expose_metadata && path == metadata_dir.join(STATUS_FILE) => {
+                Some(Synthetic::Status)
+            }
+            _ => None,
+        }
+    }
+
+    /// Kind of the file at `path` in the data directories, none if it is missing
+    fn kind(&self, path: &Path) -> Option<FileType> {
+        self.source.stat(path).ok().map(|attrs| attrs.kind)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.kind(path).is_some()
+    }
+
+    /// Whether the file named `name` is served as an archive
+    fn is_archive(&self, name: &str) -> bool {
+        !self.passthrough_only && is_archive_name(name, &self.archive_exts)
+    }
+
+    fn get_zip_paths(&self, path: &Path) -> Option<(PathBuf, PathBuf)> {
+        if self.passthrough_only {
+            return None;
+        }
+
+        if self.chroot {
+            let zip_path = &self.sources[0];
+            let file_path = path.strip_prefix(zip_path).ok()?;
+            return Some((zip_path.clone(), file_path.to_path_buf()));
+        }
+
+        if let Some(suffix) = &self.dual_view {
+            return self.get_dual_view_paths(path, suffix);
+        }
+
+        let components = path.components().rev().collect::<Vec<_>>();
+        for (index, component) in components.iter().enumerate() {
+            let Some(name) = component.as_os_str().to_str() else {
+                continue;
+            };
+
+            if !self.is_archive(name) {
+                continue;
+            }
+
+            // NOTE: Real directories named like archives stay directories, e.g. in a data
+            // directory that is another zipfs mount. Missing ones may be staged archives.
+            let zip_path = components[index..].iter().rev().collect::<PathBuf>();
+            if self.kind(&zip_path) != Some(FileType::Directory) {
+                let file_path = components[..index].iter().rev().collect::<PathBuf>();
+                return Some((zip_path, file_path));
+            }
+        }
+
+        None
+    }
+
+    /// Like `get_zip_paths` with archives served as `suffix`ed directories next to their
+    /// files, unless a real directory by that name exists
+    fn get_dual_view_paths(&self, path: &Path, suffix: &str) -> Option<(PathBuf, PathBuf)> {
+        let components = path.components().rev().collect::<Vec<_>>();
+        for (index, component) in components.iter().enumerate() {
+            let Some(name) = component.as_os_str().to_str() else {
+                continue;
+            };
+
+            let Some(archive_name) = name.strip_suffix(suffix) else {
+                continue;
+            };
+
+            let view_path = components[index..].iter().rev().collect::<PathBuf>();
+            if self.is_archive(archive_name) && self.kind(&view_path) != Some(FileType::Directory) {
+                let zip_path = view_path.with_file_name(archive_name);
+                let file_path = components[..index].iter().rev().collect::<PathBuf>();
+                return Some((zip_path, file_path));
+            }
+        }
+
+        None
+    }
+
+    /// Where the contents of the archive at `zip_path` are served
+    fn zip_root(&self, zip_path: &Path) -> PathBuf {
+        match &self.dual_view {
+            Some(suffix) => {
+                let mut root = zip_path.as_os_str().to_os_string();
+                root.push(suffix);
+                root.into()
+            }
+            None => zip_path.to_path_buf(),
+        }
+    }
+
+    fn get_staged_zip_paths(&self, path: &Path) -> Result<(PathBuf, PathBuf), ZipFsError> {
+        let Some(staging) = &self.staging else {
+            return Err(ZipFsError::ReadOnly);
+        };
+
+        match self.get_zip_paths(path) {
+            Some((zip_path, file_path)) if staging.get(&zip_path).is_some() => {
+                Ok((zip_path, file_path))
+            }
+            _ => Err(ZipFsError::ReadOnly),
+        }
+    }
+
+    fn getattr_staged(&self, ino: INode, path: &Path) -> Option<Result<FileAttr, ZipFsError>> {
+        let (zip_path, file_path) = self.get_staged_zip_paths(path).ok()?;
+        let archive = self.staging.as_ref()?.get(&zip_path)?;
+
+        let attrs = self
+            .get_data_path(self.root_ino())
+            .and_then(|data_dir| Ok(self.source.stat(&data_dir)?))
+            .and_then(|mut attrs| {
+                attrs.ino = ino;
+
+                if archive.is_dir(&file_path) {
+                    attrs.kind = FileType::Directory;
+                    attrs.perm = 0o755;
+                    attrs.size = 0;
+                } else {
+                    let data = archive.file(&file_path).ok_or(ZipFsError::NotFound)?;
+                    attrs.kind = FileType::RegularFile;
+                    attrs.perm = 0o644;
+                    attrs.size = data.len() as u64;
+                    attrs.nlink = 1;
+                }
+
+                attrs.blocks = attrs.size.div_ceil(512);
+                Ok(attrs)
+            });
+
+        Some(attrs)
+    }
+
+    fn getattr_synthetic(&self, ino: INode, synthetic: Synthetic) -> Result<FileAttr, ZipFsError> {
+        let data_dir = self.get_data_path(self.root_ino())?;
+        let mut attrs = self.source.stat(&data_dir)?;
+        attrs.ino = ino;
+
+        match synthetic {
+            Synthetic::MountRoot | Synthetic::MetadataDir => {
+                attrs.kind = FileType::Directory;
+                attrs.perm = 0o555;
+            }
+            Synthetic::Manifest | Synthetic::Status => {
+                // NOTE: The size is unknown until generated, reads bypass the page cache
+                attrs.kind = FileType::RegularFile;
+                attrs.perm = 0o444;
+                attrs.size = 0;
+                attrs.blocks = 0;
+                attrs.nlink = 1;
+            }
+        }
+
+        Ok(attrs)
+    }
+
+    fn getattr_(&mut self, ino: INode) -> Result<FileAttr, ZipFsError> {
+        self.rescan_if_requested();
+
+        let mut attrs = self.getattr_unmasked(ino)?;
+        attrs.perm = self.effective_perm(attrs.perm);
+        attrs.uid = self.uid.unwrap_or(attrs.uid);
+        attrs.gid = self.gid.unwrap_or(attrs.gid);
+        Ok(attrs)
+    }
+
+    /// Clamp `perm` to what the mount allows: the umask is cleared and, unless archives are
+    /// writable, so are the write bits
+    fn effective_perm(&self, perm: u16) -> u16 {
+        let perm = perm & 0o7777 & !self.umask;
+
+        match self.staging {
+            Some(_) => perm,
+            None => perm & !0o222,
+        }
+    }
+
+    fn getattr_unmasked(&mut self, ino: INode) -> Result<FileAttr, ZipFsError> {
+        let path = self.get_data_path(ino)?;
+
+        if let Some(synthetic) = self.get_synthetic(&path) {
+            return self.getattr_synthetic(ino, synthetic);
+        }
+
+        if let Some(attrs) = self.getattr_staged(ino, &path) {
+            return attrs;
+        }
+
+        if let Some((ref zip_path, file_path)) = self.get_zip_paths(&path) {
+            if let Some(attrs) = self.attr_cache.as_mut().and_then(|cache| cache.get(&ino)) {
+                return Ok(*attrs);
+            }
+
+            let mut attrs = self.source.stat(zip_path)?;
+            attrs.ino = ino;
+
+            // NOTE: Not cached, the archive is retried on the next call
+            let Some(mut archive) = self.open_zip(zip_path)? else {
+                attrs.kind = FileType::Directory;
+                attrs.perm = 0o555;
+                return Ok(attrs);
+            };
+
+            let name = file_path.to_string_lossy();
+            let name = resolve_alias(zip_path, &archive, &name).into_owned();
+            let password = self.entry_password(zip_path, &mut archive, &name);
+            let size = match entry_index(&archive, &name) {
+                Some(index) => entry_metadata(&mut archive.clone(), index, password.as_deref())
+                    .filter(|entry| !entry.is_dir())
+                    .map(|entry| entry.size()),
+                // NOTE: Implicit directories have no entry of their own, only entries below
+                None if name.is_empty() || is_implicit_dir(&archive, &name) => None,
+                None => return Err(ZipFsError::NotFound),
+            };
+
+            match size {
+                Some(size) => {
+                    attrs.kind = FileType::RegularFile;
+                    attrs.perm = 0o444;
+                    attrs.size = size;
+                    attrs.blocks = size.div_ceil(512);
+                }
+                None => {
+                    attrs.kind = FileType::Directory;
+                    attrs.perm = 0o555;
+                }
+            }
+
+            if let Some(attr_cache) = &mut self.attr_cache {
+                attr_cache.put(ino, attrs);
+            }
+
+            Ok(attrs)
+        } else {
+            let mut attrs = self.source.stat(&path)?;
+            attrs.ino = ino;
+            Ok(attrs)
+        }
+    }
+
+    fn open_zip(
+        &mut self,
+        zip_path: &PathBuf,
+    ) -> Result<Option<ZipArchive<PositionedReader>>, ZipFsError> {
+        // Get from cache
+        if let Some(archive) = self.open_files.lock().unwrap().get(zip_path) {
+            self.stats.record_cache_hit();
+            return Ok(Some(archive.clone()));
+        }
+
+        self.stats.record_cache_miss();
+
+        debug!("open_zip: {:?}", zip_path);
+        let file = self.open_zip_file(zip_path)?;
+        let archive = match read_archive(file, self.open_timeout) {
+            Ok(archive) => archive,
+            Err(ZipFsError::Io(err)) if err.kind() == io::ErrorKind::TimedOut => {
+                error!("Timed out opening zip file: {:?}", zip_path);
+                return Ok(None);
+            }
+            Err(err) => {
+                error!("Error opening zip file {:?}: {}", zip_path, err);
+                return Ok(None);
+            }
+        };
+
+        // NOTE: Only known once the central directory is read, the archive is dropped again
+        let memory = archive.len() as u64 * ARCHIVE_ENTRY_MEMORY;
+        self.reserve_memory(memory)
+            .map_err(|err| err.with_path(zip_path))?;
+
+        self.open_files
+            .lock()
+            .unwrap()
+            .put(zip_path.to_path_buf(), archive.clone());
+        self.passwords.lock().unwrap().remove(zip_path);
+
+        if let Ok(mtime) = self.source.stat(zip_path).map(|attrs| attrs.mtime) {
+            self.archive_mtimes
+                .lock()
+                .unwrap()
+                .insert(zip_path.to_path_buf(), mtime);
+        }
+
+        Ok(Some(archive))
+    }
+
+    /// Open the file of an archive, retrying once after a stale NFS handle
+    fn open_zip_file(&mut self, zip_path: &PathBuf) -> Result<Box<dyn ReadAtSize>, ZipFsError> {
+        let is_stale = |err: &io::Error| err.raw_os_error() == Some(ESTALE);
+
+        match (self.open_file)(zip_path) {
+            Err(err) if is_stale(&err) => {
+                warn!("Stale file handle opening {:?}, retrying", zip_path);
+                self.open_files.lock().unwrap().pop(zip_path);
+                thread::sleep(ESTALE_RETRY_DELAY);
+            }
+            result => return result.map_err(|err| ZipFsError::from(err).with_path(zip_path)),
+        }
+
+        match (self.open_file)(zip_path) {
+            Err(err) if is_stale(&err) => {
+                error!(
+                    "File handle of {:?} is still stale after retrying",
+                    zip_path
+                );
+                Err(ZipFsError::StaleHandle.with_path(zip_path))
+            }
+            result => result.map_err(|err| ZipFsError::from(err).with_path(zip_path)),
+        }
+    }
+
+    fn readdir_zip(
+        &mut self,
+        ino: INode,
+        fh: FileHandle,
+        offset: i64,
+        zip_path: &Path,
+        file_path: &Path,
+        reply: &mut DirListing,
+    ) -> Result<(), ZipFsError> {
+        trace!("zip_path = {:?}, file_path = {:?}", zip_path, file_path);
+        check_path_length(&self.zip_root(zip_path).join(file_path))?;
+
+        // NOTE: Built on the first page of a handle and kept until it is released, the kernel
+        // asks for the later pages by offset into the same order
+        let listing = match self.dir_listings.remove(&fh) {
+            Some(listing) if offset > 0 => listing,
+            _ => match self.list_zip_dir(ino, zip_path, file_path)? {
+                Some(listing) => listing,
+                None => return Ok(()),
+            },
+        };
+
+        for (i, entry) in listing.iter().enumerate().skip(offset as usize) {
+            let ino = match entry.kind {
+                FileType::Directory => ino,
+                _ => self.listed_ino(self.zip_root(zip_path).join(&entry.name)),
+            };
+
+            if reply.add(ino, i as i64 + 1, entry.kind, &entry.shown) {
+                break;
+            }
+        }
+
+        // NOTE: Handle 0 comes from no opendir, as for the full listings of extract
+        if fh != 0 {
+            self.dir_listings.insert(fh, listing);
+        }
+
+        Ok(())
+    }
+
+    /// Sorted entries of the directory `ino`, `file_path` inside the archive at `zip_path`.
+    /// `None` if the archive can't be opened.
+    fn list_zip_dir(
+        &mut self,
+        ino: INode,
+        zip_path: &Path,
+        file_path: &Path,
+    ) -> Result<Option<Vec<ZipDirEntry>>, ZipFsError> {
+        let Some(mut archive) = self.open_zip(&zip_path.to_path_buf())? else {
+            return Ok(None);
+        };
+
+        let file_string = file_path.to_string_lossy().to_string() + "/";
+        let file_string = file_string.strip_prefix('/').unwrap_or(&file_string);
+
+        let cloned_archive = archive.clone();
+        let names = entry_names(&cloned_archive);
+        let mut all_names = names.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        if file_string.is_empty() && wheel_metadata(zip_path, &cloned_archive).is_some() {
+            all_names.push(WHEEL_METADATA);
+        }
+
+        let mut file_names = dir_children(&all_names, file_string);
+
+        let child_dirs = all_names
+            .iter()
+            .filter_map(|name| name.strip_prefix(file_string)?.split_once('/'))
+            .map(|(dir, _)| dir)
+            .collect::<HashSet<_>>();
+
+        let shortened = shorten_names(&file_names, self.max_name_len);
+        for (name, short) in &shortened {
+            warn!("Shortened entry name {:?} to {:?}", name, short);
+        }
+
+        let shown = |name: &str| match shortened.get(name) {
+            Some(short) => short.clone(),
+            None => name.to_string(),
+        };
+
+        let dir_path = self.get_relative_path(ino, zip_path).join(file_path);
+        file_names.retain(|name| {
+            let path = self.zip_root(zip_path).join(file_path).join(shown(name));
+            if check_path_length(&path).is_err() {
+                warn!("Skipping entry with a name too long: {:?}", path);
+                return false;
+            }
+
+            let is_dir = child_dirs.contains(name);
+            self.is_visible(&dir_path.join(name), is_dir)
+                && self.is_entry_visible(&file_path.join(name), is_dir)
+        });
+
+        // NOTE: Implicit directories have no entry, they sort as empty and undated. Entries
+        // are only opened for their size and time when sorting by them.
+        sort_entries(
+            self.sort,
+            &mut file_names,
+            |name| name.as_bytes().to_vec(),
+            |name| {
+                let entry_name = format!("{}{}", file_string, name);
+                let entry_name = resolve_alias(zip_path, &cloned_archive, &entry_name);
+                entry_index(&cloned_archive, &entry_name)
+                    .and_then(|index| {
+                        let password = self.entry_password(zip_path, &mut archive, &entry_name);
+                        let entry = entry_metadata(&mut archive, index, password.as_deref())?;
+                        Some((entry.size(), entry.last_modified()))
+                    })
+                    .unwrap_or((0, None))
+            },
+        );
+
+        trace!("file_string = {:?}", file_string);
+        trace!("file_names = {:?}", file_names);
+
+        // NOTE: Encrypted entries that can't be opened, without the right password, are listed
+        // as directories like `getattr` reports them. Ones with an unsupported method are files
+        // failing to read.
+        let mut is_locked = |name: &str| {
+            let entry_name = format!("{}{}", file_string, name);
+            let entry_name = resolve_alias(zip_path, &cloned_archive, &entry_name);
+            let Some(index) = entry_index(&cloned_archive, &entry_name) else {
+                return false;
+            };
+
+            if !archive
+                .by_index_raw(index)
+                .is_ok_and(|entry| entry.encrypted())
+            {
+                return false;
+            }
+
+            let password = self.entry_password(zip_path, &mut archive, &entry_name);
+            entry_metadata(&mut archive, index, password.as_deref()).is_none()
+        };
+
+        // NOTE: Names are normalized, `.` and `..` can't lead out of the archive
+        let listing = file_names
+            .into_iter()
+            .map(|name| ZipDirEntry {
+                shown: shown(name),
+                name: format!("{}{}", file_string, name),
+                kind: match child_dirs.contains(name) || is_locked(name) {
+                    true => FileType::Directory,
+                    false => FileType::RegularFile,
+                },
+            })
+            .collect();
+
+        Ok(Some(listing))
+    }
+
+    fn readdir_staged(
+        &mut self,
+        offset: i64,
+        zip_path: &Path,
+        file_path: &Path,
+        reply: &mut DirListing,
+    ) -> Result<(), ZipFsError> {
+        let Some(archive) = self.staging.as_ref().and_then(|s| s.get(zip_path)) else {
+            return Err(ZipFsError::NotFound);
+        };
+
+        let children = archive.children(file_path);
+        let dir_path = self.zip_root(zip_path).join(file_path);
+
+        for (i, (name, is_dir)) in children.into_iter().enumerate().skip(offset as usize) {
+            let ino = self.listed_ino(dir_path.join(&name));
+            let file_type = match is_dir {
+                true => FileType::Directory,
+                false => FileType::RegularFile,
+            };
+
+            if reply.add(ino, i as i64 + 1, file_type, name) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn readdir_(
+        &mut self,
+        ino: INode,
+        fh: FileHandle,
+        offset: i64,
+        reply: &mut dyn DirSink,
+    ) -> Result<(), ZipFsError> {
+        self.rescan_if_requested();
+
+        let path = self.get_data_path(ino)?;
+
+        let dots = [(ino, "."), (self.parent_ino(ino, &path), "..")];
+        for (i, (ino, name)) in dots.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(ino, i as i64 + 1, FileType::Directory, name.as_ref()) {
+                return Ok(());
+            }
+        }
+
+        let offset = (offset - DOT_ENTRIES).max(0);
+        let reply = &mut DirListing(reply);
+
+        match self.get_synthetic(&path) {
+            Some(Synthetic::MountRoot) => {
+                let name = self.virtual_root.as_deref().unwrap_or_default();
+                if offset == 0 {
+                    let _ = reply.add(self.root_ino(), 1, FileType::Directory, name);
+                }
+
+                return Ok(());
+            }
+            Some(Synthetic::MetadataDir) => {
+                let files = [
+                    (self.manifest, MANIFEST_FILE),
+                    (self.expose_metadata, STATUS_FILE),
+                ];
+                let files = files
+                    .into_iter()
+                    .filter_map(|(enabled, name)| enabled.then_some(name));
+
+                for (i, name) in files.enumerate().skip(offset as usize) {
+                    let ino = self.listed_ino(path.join(name));
+                    if reply.add(ino, (i + 1) as i64, FileType::RegularFile, name) {
+                        break;
+                    }
+                }
+
+                return Ok(());
+            }
+            Some(Synthetic::Manifest | Synthetic::Status) => return Err(ZipFsError::NotADirectory),
+            None => {}
+        }
+
+        if let Ok((zip_path, file_path)) = self.get_staged_zip_paths(&path) {
+            return self.readdir_staged(offset, &zip_path, &file_path, reply);
+        }
+
+        if let Some((zip_path, file_path)) = self.get_zip_paths(&path) {
+            return self.readdir_zip(ino, fh, offset, &zip_path, &file_path, reply);
+        }
+
+        if self.source.stat(&path)?.kind != FileType::Directory {
+            return Err(ZipFsError::NotADirectory);
+        }
+
+        let mut entries = self.listed_entries(ino, &path)?;
+        sort_entries(
+            self.sort,
+            &mut entries,
+            |(_, entry)| entry.name.clone().into_vec(),
+            |(_, entry)| match self.source.stat(&entry.path) {
+                Ok(attrs) => (attrs.size, Some(attrs.mtime)),
+                Err(_) => (0, None),
+            },
+        );
+
+        // NOTE: The metadata directory comes first in the root, shifting the other offsets
+        let first = match self.has_metadata_dir() && ino == self.root_ino() {
+            true => 1,
+            false => 0,
+        };
+
+        if first == 1 && offset == 0 {
+            let ino = self.listed_ino(path.join(METADATA_DIR));
+            if reply.add(ino, 1, FileType::Directory, METADATA_DIR) {
+                return Ok(());
+            }
+        }
+
+        let names = self.listed_names(&entries);
+        let taken = names.iter().cloned().collect::<HashSet<_>>();
+
+        let mut listing = Vec::new();
+        for ((_, entry), name) in entries.into_iter().zip(names) {
+            let path = entry.path;
+            let file_type = map_ft(entry.kind)?;
+            let is_archive = file_type == FileType::RegularFile
+                && self.is_archive(&entry.name.to_string_lossy());
+
+            if is_archive {
+                let preloader = self.preloader();
+                if let Some(warmer) = &mut self.warmer {
+                    warmer.warm(path.clone(), preloader);
+                }
+            }
+
+            // NOTE: A real entry by the name of the view wins, get_zip_paths agrees
+            let view = match &self.dual_view {
+                Some(suffix) if is_archive => {
+                    let mut view = entry.name;
+                    view.push(suffix);
+                    Some(view).filter(|view| !taken.contains(view))
+                }
+                _ => None,
+            };
+
+            // NOTE: Match what getattr reports, archives are presented as directories
+            let file_type = match file_type {
+                FileType::RegularFile if self.get_zip_paths(&path).is_some() => FileType::Directory,
+                file_type => file_type,
+            };
+
+            if let Some(view) = view {
+                let view_path = path.with_file_name(&view);
+                listing.push((path, name, file_type));
+                listing.push((view_path, view, FileType::Directory));
+            } else {
+                listing.push((path, name, file_type));
+            }
+        }
+
+        // NOTE: Archives still being staged exist only in memory, they come last
+        if let Some(staging) = &self.staging {
+            let mut staged = staging
+                .archives()
+                .map(|zip_path| self.zip_root(zip_path))
+                .filter(|root| root.parent() == Some(path.as_path()))
+                .filter_map(|root| Some((root.file_name()?.to_os_string(), root)))
+                .collect::<Vec<_>>();
+            staged.sort();
+
+            for (name, root) in staged {
+                listing.push((root, name, FileType::Directory));
+            }
+        }
+
+        let skip = (offset - first).max(0);
+        let listing = listing.into_iter().enumerate().skip(skip as usize);
+        for (i, (path, name, file_type)) in listing {
+            let ino = self.listed_ino(path);
+            if reply.add(ino, first + i as i64 + 1, file_type, name) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lookup_(&mut self, parent: INode, name: &std::ffi::OsStr) -> Result<FileAttr, ZipFsError> {
+        self.rescan_if_requested();
+
+        let parent_path = self.get_data_path(parent)?;
+        check_path_length(&parent_path.join(name))?;
+
+        if let Some(negative_lookups) = &mut self.negative_lookups {
+            if negative_lookups.contains(parent, name, Instant::now()) {
+                return Err(ZipFsError::NotFound.with_path(parent_path.join(name)));
+            }
+        }
+
+        let result = self.lookup_child(parent, &parent_path, name);
+        if matches!(&result, Err(err) if err.errno() == ENOENT) {
+            self.remember_missing(parent, &parent_path, name);
+        }
+
+        result
+    }
+
+    /// Remember that `name` is missing from `parent`, if it would be inside an archive
+    fn remember_missing(&mut self, parent: INode, parent_path: &Path, name: &std::ffi::OsStr) {
+        let path = parent_path.join(name);
+        if self.get_staged_zip_paths(&path).is_ok() {
+            return;
+        }
+
+        let Some((zip_path, file_path)) = self.get_zip_paths(&path) else {
+            return;
+        };
+
+        if let Some(negative_lookups) = &mut self.negative_lookups {
+            if !file_path.as_os_str().is_empty() {
+                negative_lookups.insert(parent, name, &zip_path, Instant::now());
+            }
+        }
+    }
+
+    fn lookup_child(
+        &mut self,
+        parent: INode,
+        parent_path: &Path,
+        name: &std::ffi::OsStr,
+    ) -> Result<FileAttr, ZipFsError> {
+        // NOTE: Nothing but the virtual root is found above it
+        if let Some(Synthetic::MountRoot) = self.get_synthetic(parent_path) {
+            if self.virtual_root.as_deref().map(std::ffi::OsStr::new) != Some(name) {
+                return Err(ZipFsError::NotFound.with_path(parent_path.join(name)));
+            }
+
+            let ino = self.root_ino();
+            let attrs = self.getattr_(ino)?;
+            self.tree.lookup(ino);
+            return Ok(attrs);
+        }
+
+        let name = match self.get_zip_paths(&parent_path.join(name)) {
+            Some((zip_path, file_path)) if !file_path.as_os_str().is_empty() => {
+                self.unshorten_name(&zip_path, &file_path, name)
+            }
+            _ if self.strip_ext && self.get_zip_paths(parent_path).is_none() => {
+                self.unstrip_name(parent, parent_path, name)
+            }
+            _ => name.to_os_string(),
+        };
+        let (path, source) = self.resolve_child(parent, parent_path, &name);
+
+        // NOTE: Dropped again unless found, the kernel only forgets the entries it was replied
+        let ino = self.get_or_create_inode(path.clone(), source);
+        let result = self.lookup_inode(ino, &path);
+        if result.is_err() {
+            self.release_inode(ino);
+        }
+
+        result
+    }
+
+    fn lookup_inode(&mut self, ino: INode, path: &Path) -> Result<FileAttr, ZipFsError> {
+        let attrs = self.getattr_(ino)?;
+
+        // NOTE: Parents were checked when they were looked up themselves
+        let relative = self.get_relative_path(ino, path);
+        let is_dir = attrs.kind == FileType::Directory;
+        if self.get_synthetic(path).is_none() && !self.is_visible(&relative, is_dir) {
+            return Err(ZipFsError::NotFound.with_path(path));
+        }
+
+        if let Some((_, file_path)) = self.get_zip_paths(path) {
+            if !self.is_entry_visible(&file_path, is_dir) {
+                return Err(ZipFsError::NotFound.with_path(path));
+            }
+        }
+
+        self.tree.lookup(ino);
+        Ok(attrs)
+    }
+
+    /// Original name of the entry listed as the last component of `file_path`, which may
+    /// have been shortened by `readdir_zip`
+    fn unshorten_name(
+        &mut self,
+        zip_path: &Path,
+        file_path: &Path,
+        name: &std::ffi::OsStr,
+    ) -> std::ffi::OsString {
+        let Some(name_str) = name.to_str().filter(|name| name.contains('~')) else {
+            return name.to_os_string();
+        };
+
+        // NOTE: Names of staged archives are never shortened
+        let Ok(Some(archive)) = self.open_zip(&zip_path.to_path_buf()) else {
+            return name.to_os_string();
+        };
+
+        let dir = file_path.parent().unwrap_or(Path::new(""));
+        let dir_string = match dir.as_os_str().is_empty() {
+            true => String::new(),
+            false => dir.to_string_lossy().to_string() + "/",
+        };
+
+        let names = entry_names(&archive);
+        let all_names = names.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let children = dir_children(&all_names, &dir_string);
+        let original = shorten_names(&children, self.max_name_len)
+            .into_iter()
+            .find(|(_, short)| short == name_str)
+            .map_or(name_str, |(original, _)| original);
+
+        original.into()
+    }
+
+    /// Central directory file header of the entry of `ino` followed by its name and extra
+    /// field, without the comment
+    fn central_header(&mut self, ino: INode) -> Result<Option<Vec<u8>>, ZipFsError> {
+        let path = self.get_data_path(ino)?;
+        let Some((zip_path, file_path)) = self.get_zip_paths(&path) else {
+            return Ok(None);
+        };
+
+        let Some(mut archive) = self.open_zip(&zip_path)? else {
+            return Ok(None);
+        };
+
+        // NOTE: Implicit directories have no entry of their own
+        let name = file_path.to_string_lossy();
+        let Some(index) = entry_index(&archive, &resolve_alias(&zip_path, &archive, &name)) else {
+            return Ok(None);
+        };
+
+        // NOTE: The zip crate exposes neither the attributes nor the raw extra field
+        let offset = archive.by_index_raw(index)?.central_header_start();
+        let reader = archive.into_inner();
+
+        let mut header = vec![0; CENTRAL_HEADER_LEN];
+        reader.source().read_exact_at(&mut header, offset)?;
+
+        let len = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]) as usize;
+        let variable = len(CENTRAL_HEADER_NAME_LEN) + len(CENTRAL_HEADER_EXTRA_LEN);
+        header.resize(CENTRAL_HEADER_LEN + variable, 0);
+        reader.source().read_exact_at(
+            &mut header[CENTRAL_HEADER_LEN..],
+            offset + CENTRAL_HEADER_LEN as u64,
+        )?;
+
+        Ok(Some(header))
+    }
+
+    /// Host system and external attributes of the entry of `ino`
+    fn external_attributes(&mut self, ino: INode) -> Result<Option<(u8, u32)>, ZipFsError> {
+        let Some(header) = self.central_header(ino)? else {
+            return Ok(None);
+        };
+
+        let attrs = &header[CENTRAL_HEADER_EXTERNAL_ATTRS..CENTRAL_HEADER_EXTERNAL_ATTRS + 4];
+        Ok(Some((
+            header[CENTRAL_HEADER_HOST],
+            u32::from_le_bytes(attrs.try_into().unwrap()),
+        )))
+    }
+
+    /// DOS modification date and time of the entry of `ino` as stored, along with whether
+    /// an extended timestamp gives the time in UTC as well
+    fn raw_mtime(&mut self, ino: INode) -> Result<Option<(u32, bool)>, ZipFsError> {
+        let Some(header) = self.central_header(ino)? else {
+            return Ok(None);
+        };
+
+        let mtime = &header[CENTRAL_HEADER_MTIME..CENTRAL_HEADER_MTIME + 4];
+        let name_len = u16::from_le_bytes([
+            header[CENTRAL_HEADER_NAME_LEN],
+            header[CENTRAL_HEADER_NAME_LEN + 1],
+        ]) as usize;
+
+        Ok(Some((
+            u32::from_le_bytes(mtime.try_into().unwrap()),
+            has_extended_mtime(&header[CENTRAL_HEADER_LEN + name_len..]),
+        )))
+    }
+
+    /// Unix mode of the entry of `ino`, only for entries made on Unix that have one
+    fn unix_mode(&mut self, ino: INode) -> Result<Option<u32>, ZipFsError> {
+        Ok(self
+            .external_attributes(ino)?
+            .filter(|(host, _)| *host == HOST_UNIX)
+            .map(|(_, attrs)| attrs >> 16)
+            .filter(|mode| *mode != 0))
+    }
+
+    fn getxattr_(&mut self, ino: INode, name: &std::ffi::OsStr) -> Result<Vec<u8>, ZipFsError> {
+        if name == POSIX_ACL_XATTR {
+            let mode = self.unix_mode(ino)?.ok_or(ZipFsError::NoAttribute)?;
+            return Ok(format_posix_acl(mode));
+        }
+
+        if name == MTIME_RAW_XATTR || name == MTIME_SOURCE_XATTR {
+            let (raw, extended) = self.raw_mtime(ino)?.ok_or(ZipFsError::NoAttribute)?;
+            let value = match (name == MTIME_RAW_XATTR, extended) {
+                (true, _) => format!("{:#010x}", raw),
+                (false, true) => "unix-extra".to_string(),
+                (false, false) => "dos".to_string(),
+            };
+            return Ok(value.into_bytes());
+        }
+
+        if name != DOS_ATTRS_XATTR {
+            return Err(ZipFsError::NoAttribute);
+        }
+
+        let (_, attrs) = self
+            .external_attributes(ino)?
+            .ok_or(ZipFsError::NoAttribute)?;
+        Ok(format_dos_attrs(attrs).into_bytes())
+    }
+
+    fn listxattr_(&mut self, ino: INode) -> Result<Vec<u8>, ZipFsError> {
+        let mut names = Vec::new();
+
+        if self.external_attributes(ino)?.is_some() {
+            for name in [DOS_ATTRS_XATTR, MTIME_RAW_XATTR, MTIME_SOURCE_XATTR] {
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+            }
+        }
+
+        if self.unix_mode(ino)?.is_some() {
+            names.extend_from_slice(POSIX_ACL_XATTR.as_bytes());
+            names.push(0);
+        }
+
+        Ok(names)
+    }
+
+    /// Generation of the inodes of a new mount, one more than the one in the generation file
+    /// which is updated
+    fn next_inode_generation(&self) -> io::Result<u64> {
+        let Some(path) = &self.generation_file else {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            return Ok(now.as_secs().max(1));
+        };
+
+        let last = match fs::read_to_string(path) {
+            Ok(content) => content.trim().parse::<u64>().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid generation file {:?}: {}", path, err),
+                )
+            })?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+
+        let generation = last + 1;
+        fs::write(path, format!("{}\n", generation))?;
+        Ok(generation)
+    }
+
+    /// Forget the state derived from archives cached before the last reload, so that open
+    /// files continue on freshly opened archives
+    fn drop_stale_state(&mut self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        if generation == self.seen_generation {
+            return;
+        }
+
+        let entry_readers = self.entry_readers.get_mut().unwrap();
+        debug!(
+            "Dropping {} entry readers after reload",
+            entry_readers.len()
+        );
+        entry_readers.clear();
+        self.clear_chunk_cache();
+        self.clear_attr_cache();
+        self.clear_negative_lookups();
+        self.validated_entries.clear();
+        self.manifest_reader = None;
+        self.seen_generation = generation;
+    }
+
+    fn clear_chunk_cache(&self) {
+        if let Some(chunk_cache) = &self.chunk_cache {
+            chunk_cache.lock().unwrap().clear();
+        }
+    }
+
+    fn clear_attr_cache(&mut self) {
+        if let Some(attr_cache) = &mut self.attr_cache {
+            attr_cache.clear();
+        }
+    }
+
+    fn clear_negative_lookups(&mut self) {
+        if let Some(negative_lookups) = &mut self.negative_lookups {
+            negative_lookups.clear();
+        }
+    }
+
+    fn rescan_if_requested(&mut self) {
+        if self.rescan_requested.swap(false, Ordering::Relaxed) {
+            self.rescan();
+        }
+    }
+
+    /// Catch up with changes to the data directories: forget the inodes of paths that are
+    /// gone, evict the archives modified since they were opened and have the kernel drop
+    /// what it cached about either. Returns the number of inodes affected.
+    pub fn rescan(&mut self) -> usize {
+        let gone = self
+            .tree
+            .iter()
+            .filter(|(ino, path)| *ino != self.root_ino() && !self.path_exists(path))
+            .map(|(ino, path)| (ino, path.clone()))
+            .collect::<Vec<_>>();
+
+        // NOTE: Parents are resolved before removal, they may be gone themselves
+        let entries = gone
+            .iter()
+            .filter_map(|(_, path)| {
+                let parent = self
+                    .tree
+                    .find_inode_by_path(&path.parent()?.to_path_buf())?;
+                Some((parent, path.file_name()?.to_os_string()))
+            })
+            .collect::<Vec<_>>();
+
+        for (ino, _) in &gone {
+            self.tree.remove(*ino);
+            if let Some(attr_cache) = &mut self.attr_cache {
+                attr_cache.pop(ino);
+            }
+        }
+        self.stats.set_inodes(self.tree.len());
+
+        let mut archive_mtimes = self.archive_mtimes.lock().unwrap();
+        let changed = archive_mtimes
+            .iter()
+            .filter(|(path, mtime)| {
+                self.source.stat(path).map(|attrs| attrs.mtime).ok() != Some(**mtime)
+            })
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+
+        let mut inodes = Vec::new();
+        for zip_path in &changed {
+            debug!("Archive {:?} changed since it was opened", zip_path);
+            archive_mtimes.remove(zip_path);
+            self.open_files.lock().unwrap().pop(zip_path);
+            inodes.extend(
+                self.tree
+                    .iter()
+                    .filter(|(_, path)| path.starts_with(zip_path))
+                    .map(|(ino, _)| ino),
+            );
+        }
+
+        if !changed.is_empty() {
+            self.entry_readers.get_mut().unwrap().clear();
+            self.clear_chunk_cache();
+            if let Some(attr_cache) = &mut self.attr_cache {
+                attr_cache.clear();
+            }
+            self.validated_entries.clear();
+        }
+
+        info!(
+            "Rescan forgot {} inodes and evicted {} changed archives",
+            gone.len(),
+            changed.len()
+        );
+
+        // NOTE: The kernel may hold locks of the directory being operated on, waiting for the
+        // notifications here could deadlock
+        if let Some(notifier) = self.notifier.lock().unwrap().clone() {
+            let affected = inodes.clone();
+            thread::spawn(move || {
+                for (parent, name) in entries {
+                    if let Err(err) = notifier.inval_entry(parent, &name) {
+                        debug!("Error invalidating entry {:?}: {:?}", name, err);
+                    }
+                }
+
+                for ino in affected {
+                    if let Err(err) = notifier.inval_inode(ino, 0, 0) {
+                        debug!("Error invalidating ino = {}: {:?}", ino, err);
+                    }
+                }
+            });
+        }
+
+        gone.len() + inodes.len()
+    }
+
+    /// Whether `path` is still backed by the data directories. Paths inside archives only
+    /// need the archive itself, staged and synthetic paths exist in memory.
+    fn path_exists(&self, path: &Path) -> bool {
+        if self.get_synthetic(path).is_some() || self.get_staged_zip_paths(path).is_ok() {
+            return true;
+        }
+
+        if let Some((zip_path, _)) = self.get_zip_paths(path) {
+            return self.kind(&zip_path) == Some(FileType::RegularFile);
+        }
+
+        path.ancestors()
+            .find_map(|ancestor| Some((ancestor, self.kind(ancestor)?)))
+            .is_some_and(|(ancestor, kind)| ancestor == path || kind == FileType::RegularFile)
+    }
+
+    fn record_read(&self, ino: INode, bytes: usize) {
+        if let Ok(path) = self.get_data_path(ino) {
+            let relative = self.get_relative_path(ino, &path);
+            self.stats.record_read(relative, bytes as u64);
+        }
+    }
+
+    fn forget_(&mut self, ino: INode, nlookup: u64) {
+        if let Some(path) = self.tree.forget(ino, nlookup) {
+            self.evicted(ino, &path);
+        }
+    }
+
+    /// Evict `ino` unless the kernel holds a reference to it
+    fn release_inode(&mut self, ino: INode) {
+        if let Some(path) = self.tree.release(ino) {
+            self.evicted(ino, &path);
+        }
+    }
+
+    /// Drop what was cached for `ino`, just evicted from the tree
+    fn evicted(&mut self, ino: INode, path: &Path) {
+        debug!("Evicted ino = {}, inodes left = {}", ino, self.tree.len());
+        self.stats.set_inodes(self.tree.len());
+        self.open_files.lock().unwrap().pop(path);
+        self.entry_readers.get_mut().unwrap().pop(path);
+        if let Some(attr_cache) = &mut self.attr_cache {
+            attr_cache.pop(&ino);
+        }
+
+        if let Some(chunk_cache) = &self.chunk_cache {
+            chunk_cache.lock().unwrap().remove(path);
+        }
+    }
+
+    fn read_(
+        &mut self,
+        ino: INode,
+        fh: FileHandle,
+        offset: i64,
+        size: u32,
+    ) -> Result<Vec<u8>, ZipFsError> {
+        self.drop_stale_state();
+        let path = self.get_data_path(ino)?;
+
+        if let Some((source, data)) = self.direct_handles.get(&fh) {
+            let start = data.start.saturating_add(offset as u64).min(data.end);
+            let mut reader = source.clone();
+            reader.seek(SeekFrom::Start(start))?;
+
+            return read_available(&mut reader.take(data.end - start), size as usize);
+        }
+
+        match self.get_synthetic(&path) {
+            Some(Synthetic::Manifest) => return self.read_manifest(offset as u64, size as usize),
+            Some(Synthetic::Status) => {
+                let status = self.render_status()?;
+                let start = status.len().min(offset as usize);
+                let end = status.len().min(start + size as usize);
+                return Ok(status[start..end].to_vec());
+            }
+            _ => {}
+        }
+
+        if let Ok((zip_path, file_path)) = self.get_staged_zip_paths(&path) {
+            let staging = self.staging.as_ref().ok_or(ZipFsError::ReadOnly)?;
+            let archive = staging.get(&zip_path).ok_or(ZipFsError::NotFound)?;
+            let data = archive.file(&file_path).ok_or(ZipFsError::NotFound)?;
+
+            let start = data.len().min(offset as usize);
+            let end = data.len().min(start + size as usize);
+            return Ok(data[start..end].to_vec());
+        }
+
+        if let Some((zip_path, file_path)) = self.get_zip_paths(&path) {
+            if let Some(mut archive) = self.open_zip(&zip_path.to_path_buf())? {
+                let name = file_path.to_string_lossy();
+                let name = raw_entry_name(&archive, &resolve_alias(&zip_path, &archive, &name));
+
+                if self.strict && !self.validated_entries.contains(&path) {
+                    validate_local_header(&mut archive, &name)?;
+                    self.validated_entries.insert(path.clone());
+                }
+
+                // NOTE: Stored entries are read in place with pread(2), sharing the page cache
+                // of the archive instead of going through a decompressor
+                if let Some(data) = stored_data(&mut archive, &name)? {
+                    let start = data.start.saturating_add(offset as u64).min(data.end);
+                    let mut reader = archive.into_inner();
+                    reader.seek(SeekFrom::Start(start))?;
+
+                    return read_available(&mut reader.take(data.end - start), size as usize);
+                }
+
+                let password = self.entry_password(&zip_path, &mut archive, &name);
+                let reader = self.entry_readers.get_mut().unwrap().pop(&path);
+                if reader.is_none() {
+                    self.reserve_memory(ENTRY_READER_MEMORY)?;
+                }
+
+                let mut reader = match (reader, &self.chunk_cache) {
+                    (Some(reader), _) => reader,
+                    (None, Some(chunk_cache)) => EntryReader::new(archive, name, password)?
+                        .with_chunk_cache(chunk_cache.clone(), path.clone()),
+                    (None, None) => EntryReader::new(archive, name, password)?,
+                };
+
+                let decompressed = reader.decompressed();
+                reader.seek(SeekFrom::Start(offset as u64))?;
+                let data = read_available(&mut reader, size as usize)?;
+                self.stats
+                    .record_decompressed(reader.decompressed() - decompressed);
+
+                // NOTE: A short read is the end of the entry or an error, start over next time
+                if data.len() == size as usize {
+                    self.entry_readers.get_mut().unwrap().put(path, reader);
+                }
+
+                return Ok(data);
+            }
+        }
+
+        let mut reader = PositionedReader::new(self.source.open(&path)?);
+        reader.seek(SeekFrom::Start(offset as u64))?;
+
+        read_available(&mut reader, size as usize)
+    }
+
+    /// Continue generating the manifest where the last read stopped, restarting from the
+    /// beginning on a backwards seek
+    fn read_manifest(&mut self, offset: u64, size: usize) -> Result<Vec<u8>, ZipFsError> {
+        let mut reader = match self.manifest_reader.take() {
+            Some(reader) if reader.position() <= offset => reader,
+            _ => ManifestReader::new(
+                self.sources.clone(),
+                self.filter.read().unwrap().clone(),
+                self.archive_exts.clone(),
+            ),
+        };
+
+        let skip = offset - reader.position();
+        io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+
+        let data = read_available(&mut reader, size)?;
+        self.manifest_reader = Some(reader);
+        Ok(data)
+    }
+
+    /// The configuration of the mount followed by the stats also served over the control socket
+    fn render_status(&self) -> Result<Vec<u8>, ZipFsError> {
+        // NOTE: Taken apart from the snapshot, which locks the cache as well
+        let archive_cache_entries = self.open_files.lock().unwrap().cap();
+        let content_cache_bytes = self
+            .chunk_cache
+            .as_ref()
+            .map(|cache| cache.lock().unwrap().budget());
+
+        let status = json!({
+            "generation": self.inode_generation,
+            "options": {
+                "data_dirs": self.sources,
+                "archive_cache_entries": archive_cache_entries,
+                "open_timeout": self.open_timeout.map(|timeout| timeout.as_millis() as u64),
+                "writable_archives": self.staging.is_some(),
+                "strict": self.strict,
+                "direct_stored": self.direct_stored,
+                "max_ratio": self.max_ratio,
+                "max_open_archives": self.max_open_archives,
+                "max_memory": self.max_memory,
+                "content_cache_bytes": content_cache_bytes,
+                "attr_cache_entries": self.attr_cache.as_ref().map(LruCache::cap),
+                "negative_cache_ttl": self.negative_ttl.as_millis() as u64,
+                "negative_lookup_entries": self.negative_lookups.as_ref().map(NegativeCache::cap),
+                "chroot_into_zip": self.chroot,
+                "virtual_root": self.virtual_root,
+                "umask": self.umask,
+                "uid": self.uid,
+                "gid": self.gid,
+                "archive_exts": self.archive_exts,
+                "manifest": self.manifest,
+                "warm": self.warmer.is_some(),
+                "sort": self.sort,
+                "max_readahead": self.max_readahead,
+                "strip_ext": self.strip_ext,
+                "dual_view_suffix": self.dual_view,
+                "passthrough_only": self.passthrough_only,
+                "max_entry_name_length": self.max_name_len,
+            },
+            "stats": self.control_handle().snapshot(),
+            "memory_usage": self.memory_usage(),
+        });
+
+        let mut data = serde_json::to_vec_pretty(&status).map_err(io::Error::from)?;
+        data.push(b'\n');
+        Ok(data)
+    }
+
+    /// File handle and open flags of `ino`
+    fn open_(&mut self, ino: INode) -> Result<(FileHandle, u32), ZipFsError> {
+        if self.draining.load(Ordering::Relaxed) {
+            return Err(ZipFsError::ShuttingDown);
+        }
+
+        let path = self.get_data_path(ino)?;
+
+        if let Some(Synthetic::Manifest | Synthetic::Status) = self.get_synthetic(&path) {
+            return Ok((0, fuser::consts::FOPEN_DIRECT_IO));
+        }
+
+        self.check_ratio(&path)?;
+        let zip_path = self.check_open_archives(&path)?;
+
+        let mut opened = (0, 0);
+        if self.direct_stored {
+            if let Some(fh) = self.open_direct(&path)? {
+                opened = (fh, fuser::consts::FOPEN_KEEP_CACHE);
+            }
+        }
+
+        // NOTE: Entries get a handle of their own, counting their archive open until released
+        if let Some(zip_path) = zip_path {
+            if opened.0 == 0 {
+                self.next_fh += 1;
+                opened.0 = self.next_fh;
+            }
+
+            self.archive_handles.insert(opened.0, zip_path);
+        }
+
+        Ok(opened)
+    }
+
+    /// Forget the file handle `fh` once released
+    fn close_(&mut self, fh: FileHandle) {
+        self.direct_handles.remove(&fh);
+        self.archive_handles.remove(&fh);
+    }
+
+    /// Refuse the entry at `path` if opening it would hold more archives open than allowed,
+    /// returning its archive to be tracked otherwise
+    fn check_open_archives(&mut self, path: &Path) -> Result<Option<PathBuf>, ZipFsError> {
+        let Some(max_open_archives) = self.max_open_archives else {
+            return Ok(None);
+        };
+
+        if self.get_staged_zip_paths(path).is_ok() {
+            return Ok(None);
+        }
+
+        let Some((zip_path, _)) = self.get_zip_paths(path) else {
+            return Ok(None);
+        };
+
+        let open = self.archive_handles.values().collect::<HashSet<_>>();
+        if !open.contains(&zip_path) && open.len() >= max_open_archives {
+            return Err(ZipFsError::TooManyOpenArchives {
+                limit: max_open_archives,
+            });
+        }
+
+        Ok(Some(zip_path))
+    }
+
+    /// Bytes taken by the cached archives, the kept entry readers and the decompressed
+    /// chunks, as far as they are accounted for
+    pub fn memory_usage(&self) -> u64 {
+        let archives = self
+            .open_files
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, archive)| archive.len() as u64 * ARCHIVE_ENTRY_MEMORY)
+            .sum::<u64>();
+        let readers = self.entry_readers.lock().unwrap().len() as u64 * ENTRY_READER_MEMORY;
+        let chunks = self
+            .chunk_cache
+            .as_ref()
+            .map_or(0, |cache| cache.lock().unwrap().size());
+
+        archives + readers + chunks
+    }
+
+    /// Make sure `bytes` more fit within the memory limit, emptying the content cache if
+    /// that is what it takes
+    fn reserve_memory(&mut self, bytes: u64) -> Result<(), ZipFsError> {
+        let Some(limit) = self.max_memory else {
+            return Ok(());
+        };
+
+        if self.memory_usage() + bytes > limit {
+            if let Some(cache) = &self.chunk_cache {
+                debug!("Emptying the content cache to stay within {} bytes", limit);
+                cache.lock().unwrap().clear();
+            }
+        }
+
+        if self.memory_usage() + bytes > limit {
+            return Err(ZipFsError::MemoryLimit { limit });
+        }
+
+        Ok(())
+    }
+
+    /// Refuse the entry at `path` if it claims to expand beyond the maximum ratio, going by the
+    /// central directory before anything is decompressed
+    fn check_ratio(&mut self, path: &Path) -> Result<(), ZipFsError> {
+        let Some(max_ratio) = self.max_ratio else {
+            return Ok(());
+        };
+
+        if self.get_staged_zip_paths(path).is_ok() {
+            return Ok(());
+        }
+
+        let Some((zip_path, file_path)) = self.get_zip_paths(path) else {
+            return Ok(());
+        };
+
+        let Some(mut archive) = self.open_zip(&zip_path)? else {
+            return Ok(());
+        };
+
+        let name = file_path.to_string_lossy();
+        let name = raw_entry_name(&archive, &resolve_alias(&zip_path, &archive, &name));
+        let Some(index) = archive.index_for_name(&name) else {
+            return Ok(());
+        };
+
+        let entry = archive.by_index_raw(index)?;
+        let ratio = entry.size() / entry.compressed_size().max(1);
+        if ratio > max_ratio {
+            return Err(ZipFsError::ExcessiveRatio {
+                ratio,
+                limit: max_ratio,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Hand out a file handle reading the entry at `path` straight from its archive, if it is
+    /// stored and not encrypted. Anything else is left to the regular read path.
+    fn open_direct(&mut self, path: &Path) -> Result<Option<FileHandle>, ZipFsError> {
+        if self.get_staged_zip_paths(path).is_ok() {
+            return Ok(None);
+        }
+
+        let Some((zip_path, file_path)) = self.get_zip_paths(path) else {
+            return Ok(None);
+        };
+
+        let Ok(Some(mut archive)) = self.open_zip(&zip_path) else {
+            return Ok(None);
+        };
+
+        let name = file_path.to_string_lossy();
+        let name = raw_entry_name(&archive, &resolve_alias(&zip_path, &archive, &name));
+        if self.strict && !self.validated_entries.contains(path) {
+            validate_local_header(&mut archive, &name)?;
+            self.validated_entries.insert(path.to_path_buf());
+        }
+
+        let Some(data) = stored_data(&mut archive, &name)? else {
+            return Ok(None);
+        };
+
+        self.next_fh += 1;
+        self.direct_handles
+            .insert(self.next_fh, (archive.into_inner(), data));
+        Ok(Some(self.next_fh))
+    }
+
+    /// Write the tree the mount would present below `dest` without mounting: files are copied
+    /// and archive entries decompressed, with the modes and modification times the mount
+    /// reports for them
+    pub fn extract(&mut self, dest: &Path) -> Result<ExtractReport, ZipFsError> {
+        let mut report = ExtractReport::default();
+        fs::create_dir_all(dest)?;
+        self.extract_dir(self.root_ino(), dest, Path::new(""), &mut report)?;
+        Ok(report)
+    }
+
+    fn extract_dir(
+        &mut self,
+        ino: INode,
+        dest: &Path,
+        relative: &Path,
+        report: &mut ExtractReport,
+    ) -> Result<(), ZipFsError> {
+        let mut entries = Vec::new();
+        self.readdir_(ino, 0, 0, &mut entries)?;
+
+        for (kind, name) in entries.into_iter().skip(DOT_ENTRIES as usize) {
+            // NOTE: Looked up like the kernel would, listings don't carry the inode of every
+            // entry, e.g. of encrypted ones presented as directories
+            let attrs = self.lookup_(ino, &name)?;
+            let path = dest.join(&name);
+            let relative = relative.join(&name);
+
+            match kind {
+                FileType::Directory => {
+                    fs::create_dir(&path)?;
+                    self.extract_dir(attrs.ino, &path, &relative, report)?;
+                    report.directories += 1;
+                }
+                FileType::RegularFile => match self.extract_file(attrs.ino, &path) {
+                    Ok(()) => report.files += 1,
+                    Err(err) => {
+                        warn!("Failed to extract {:?}: {}", relative, err);
+                        let _ = fs::remove_file(&path);
+                        report.failed.push((relative, err.to_string()));
+                        continue;
+                    }
+                },
+                kind => {
+                    warn!("Skipping {:?} of type {:?}", relative, kind);
+                    continue;
+                }
+            }
+
+            // NOTE: Set last, the mode may take away the write permission of directories
+            File::open(&path)?.set_modified(attrs.mtime)?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(attrs.perm as u32))?;
+        }
+
+        Ok(())
+    }
+
+    fn extract_file(&mut self, ino: INode, path: &Path) -> Result<(), ZipFsError> {
+        let (fh, _) = self.open_(ino)?;
+        let mut file = File::create(path)?;
+
+        let mut offset = 0;
+        let result = loop {
+            match self.read_(ino, fh, offset, EXTRACT_CHUNK) {
+                Ok(data) if data.is_empty() => break Ok(()),
+                Ok(data) => {
+                    offset += data.len() as i64;
+                    if let Err(err) = file.write_all(&data) {
+                        break Err(err.into());
+                    }
+                }
+                Err(err) => break Err(err),
+            }
+        };
+
+        self.close_(fh);
+        result
+    }
+
+    /// Read up to `len` bytes at `offset` of the file the mount would present at `path`,
+    /// relative to its root, without mounting
+    pub fn read_path(
+        &mut self,
+        path: &Path,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, ZipFsError> {
+        let attrs = self.resolve_path(path)?;
+        if attrs.kind == FileType::Directory {
+            return Err(ZipFsError::IsADirectory.with_path(path));
+        }
+
+        let (fh, _) = self.open_(attrs.ino)?;
+        let mut data = Vec::new();
+        let result = loop {
+            let size = (len - data.len()).min(EXTRACT_CHUNK as usize) as u32;
+            let offset = offset.saturating_add(data.len() as u64) as i64;
+
+            match self.read_(attrs.ino, fh, offset, size) {
+                Ok(read) if read.is_empty() => break Ok(()),
+                Ok(read) => {
+                    data.extend_from_slice(&read);
+                    if data.len() == len {
+                        break Ok(());
+                    }
+                }
+                Err(err) => break Err(err),
+            }
+        };
+
+        self.close_(fh);
+        result.map(|()| data)
+    }
+
+    /// Entries of the directory the mount would present at `path`, relative to its root,
+    /// without mounting. `.` and `..` are left out.
+    pub fn list_path(&mut self, path: &Path) -> Result<Vec<DirEntryInfo>, ZipFsError> {
+        let attrs = self.resolve_path(path)?;
+        if attrs.kind != FileType::Directory {
+            return Err(ZipFsError::NotADirectory.with_path(path));
+        }
+
+        let mut entries = Vec::new();
+        self.readdir_(attrs.ino, 0, 0, &mut entries)?;
+
+        entries
+            .into_iter()
+            .skip(DOT_ENTRIES as usize)
+            .map(|(kind, name)| {
+                let attrs = self.lookup_(attrs.ino, &name)?;
+                Ok(DirEntryInfo {
+                    name,
+                    kind,
+                    size: attrs.size,
+                    perm: attrs.perm,
+                    mtime: attrs.mtime,
+                })
+            })
+            .collect()
+    }
+
+    /// Look up every component of `path` from the root of the mount, the way the kernel would
+    fn resolve_path(&mut self, path: &Path) -> Result<FileAttr, ZipFsError> {
+        let mut attrs = self.getattr_(self.root_ino())?;
+
+        for component in path.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::Normal(name) => attrs = self.lookup_(attrs.ino, name)?,
+                Component::ParentDir | Component::Prefix(_) => {
+                    return Err(ZipFsError::NotFound.with_path(path));
+                }
+            }
+        }
+
+        Ok(attrs)
+    }
+
+    fn mkdir_(&mut self, parent: INode, name: &std::ffi::OsStr) -> Result<FileAttr, ZipFsError> {
+        let path = self.get_data_path(parent)?.join(name);
+        let zip_paths = self.get_zip_paths(&path);
+        let Some(staging) = &mut self.staging else {
+            return Err(ZipFsError::ReadOnly);
+        };
+
+        match zip_paths {
+            Some((zip_path, file_path)) if file_path.as_os_str().is_empty() => {
+                debug!("Staging new archive = {:?}", zip_path);
+                staging.create_archive(zip_path)?;
+            }
+            Some((zip_path, file_path)) if staging.get(&zip_path).is_some() => {
+                staging.add_dir(&zip_path, &file_path)?;
+            }
+            _ => return Err(ZipFsError::ReadOnly),
+        }
+
+        if let Some(negative_lookups) = &mut self.negative_lookups {
+            negative_lookups.remove(parent, name);
+        }
+
+        let ino = self.get_or_create_inode(path, self.tree.source(parent));
+        let attrs = self.getattr_(ino)?;
+
+        self.tree.lookup(ino);
+        Ok(attrs)
+    }
+
+    fn rmdir_(&mut self, parent: INode, name: &std::ffi::OsStr) -> Result<(), ZipFsError> {
+        let path = self.get_data_path(parent)?.join(name);
+        let (zip_path, file_path) = self.get_staged_zip_paths(&path)?;
+        let staging = self.staging.as_mut().ok_or(ZipFsError::ReadOnly)?;
+
+        match file_path.as_os_str().is_empty() {
+            true => {
+                debug!("Discarding staged archive = {:?}", zip_path);
+                staging.remove_archive(&zip_path)
+            }
+            false => staging.remove_dir(&zip_path, &file_path),
+        }
+    }
+
+    fn create_(&mut self, parent: INode, name: &std::ffi::OsStr) -> Result<FileAttr, ZipFsError> {
+        let path = self.get_data_path(parent)?.join(name);
+        let (zip_path, file_path) = self.get_staged_zip_paths(&path)?;
+
+        let staging = self.staging.as_mut().ok_or(ZipFsError::ReadOnly)?;
+        staging.add_file(&zip_path, &file_path)?;
+
+        if let Some(negative_lookups) = &mut self.negative_lookups {
+            negative_lookups.remove(parent, name);
+        }
+
+        let ino = self.get_or_create_inode(path, self.tree.source(parent));
+        let attrs = self.getattr_(ino)?;
+
+        self.tree.lookup(ino);
+        Ok(attrs)
+    }
+
+    fn write_(&mut self, ino: INode, offset: i64, data: &[u8]) -> Result<u32, ZipFsError> {
+        let path = self.get_data_path(ino)?;
+        let (zip_path, file_path) = self.get_staged_zip_paths(&path)?;
+
+        let staging = self.staging.as_mut().ok_or(ZipFsError::ReadOnly)?;
+        staging.write(&zip_path, &file_path, offset as u64, data)?;
+
+        Ok(data.len() as u32)
+    }
+
+    /// Resize a staged file, as done for `O_TRUNC`. Other attributes of staged entries are
+    /// left as they are, the ones of everything else can't be changed.
+    fn setattr_(&mut self, ino: INode, size: Option<u64>) -> Result<FileAttr, ZipFsError> {
+        let path = self.get_data_path(ino)?;
+        let (zip_path, file_path) = self.get_staged_zip_paths(&path)?;
+
+        if let Some(size) = size {
+            let staging = self.staging.as_mut().ok_or(ZipFsError::ReadOnly)?;
+            staging.truncate(&zip_path, &file_path, size)?;
+        }
+
+        self.getattr_(ino)
+    }
+
+    /// Copy up to `len` bytes into a staged file, read like any other read so that stored
+    /// entries are taken in place from the archive and compressed ones are decompressed
+    fn copy_file_range_(
+        &mut self,
+        ino_in: INode,
+        fh_in: FileHandle,
+        offset_in: i64,
+        ino_out: INode,
+        offset_out: i64,
+        len: u64,
+    ) -> Result<u32, ZipFsError> {
+        // NOTE: The kernel falls back to reading and writing on EXDEV, which then fails like
+        // any other write outside of a staged archive
+        let path_out = self.get_data_path(ino_out)?;
+        if self.get_staged_zip_paths(&path_out).is_err() {
+            return Err(ZipFsError::CrossDevice);
+        }
+
+        let data = self.read_(ino_in, fh_in, offset_in, len.min(COPY_CHUNK) as u32)?;
+        self.write_(ino_out, offset_out, &data)
+    }
+
+    fn fsyncdir_(&mut self, ino: INode) -> Result<(), ZipFsError> {
+        let path = self.get_data_path(ino)?;
+        let Ok((zip_path, file_path)) = self.get_staged_zip_paths(&path) else {
+            return Ok(());
+        };
+
+        if !file_path.as_os_str().is_empty() {
+            return Ok(());
+        }
+
+        debug!("Committing staged archive = {:?}", zip_path);
+        let staging = self.staging.as_mut().ok_or(ZipFsError::ReadOnly)?;
+        staging.commit(&zip_path).inspect_err(|err| {
+            error!("Error committing zip file {:?}: {}", zip_path, err);
+        })?;
+
+        self.open_files.lock().unwrap().pop(&zip_path);
+        self.clear_attr_cache();
+        self.clear_negative_lookups();
+        Ok(())
+    }
+}
+
+impl Filesystem for ZipFs {
+    fn init(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        config: &mut fuser::KernelConfig,
+    ) -> Result<(), libc::c_int> {
+        // NOTE: Readdirplus isn't implemented, and max_write can't be lowered to 0 through
+        // fuser, writes to staged archives go through anyway
+        if let Err(unsupported) = config.add_capabilities(FUSE_ASYNC_READ) {
+            debug!("Kernel lacks capabilities = {:#x}", unsupported);
+        }
+
+        match config.set_max_readahead(self.max_readahead) {
+            Ok(_) => debug!("Negotiated max_readahead = {}", self.max_readahead),
+            Err(max) => {
+                debug!("Kernel caps max_readahead = {}", max);
+                let _ = config.set_max_readahead(max);
+            }
+        }
+
+        self.inode_generation = self.next_inode_generation().map_err(|err| {
+            error!("Failed to bump the inode generation: {}", err);
+            libc::EIO
+        })?;
+        debug!("Inode generation = {}", self.inode_generation);
+
+        Ok(())
+    }
+
+    fn getattr(&mut self, req: &fuser::Request<'_>, ino: INode, reply: fuser::ReplyAttr) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("getattr", req, ino).entered();
+        trace!("getattr: ino={}", ino);
+
+        match self.getattr_(ino) {
+            Ok(attrs) => reply.attr(&TTL, &attrs),
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        fh: FileHandle,
+        offset: i64,
+        mut reply: fuser::ReplyDirectory,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("readdir", req, ino).entered();
+        span.record("fh", fh).record("offset", offset);
+        trace!("readdir: ino={}, fh={}, offset={}", ino, fh, offset);
+
+        match self.readdir_(ino, fh, offset, &mut reply) {
+            Ok(_) => {
+                if offset == 0 {
+                    self.notify(ino, |listener, path| listener.on_readdir(path, req.into()));
+                }
+
+                reply.ok()
+            }
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+
+    fn lookup(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: INode,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEntry,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("lookup", req, parent).entered();
+        trace!("lookup: parent={}, name={:?}", parent, name);
+
+        match self.lookup_(parent, name) {
+            Ok(attrs) => reply.entry(&TTL, &attrs, self.inode_generation),
+            Err(err) => match self.errno(req, parent, err) {
+                ENOENT if !self.negative_ttl.is_zero() => {
+                    reply.entry(&self.negative_ttl, &negative_entry(), 0)
+                }
+                errno => reply.error(errno),
+            },
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        name: &std::ffi::OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("getxattr", req, ino).entered();
+        span.record("size", size);
+        trace!("getxattr: ino={}, name={:?}, size={}", ino, name, size);
+        let data = self.getxattr_(ino, name);
+        reply_xattr(reply, size, data.map_err(|err| self.errno(req, ino, err)));
+    }
+
+    fn listxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("listxattr", req, ino).entered();
+        span.record("size", size);
+        trace!("listxattr: ino={}, size={}", ino, size);
+        let data = self.listxattr_(ino);
+        reply_xattr(reply, size, data.map_err(|err| self.errno(req, ino, err)));
+    }
+
+    fn forget(&mut self, req: &fuser::Request<'_>, ino: INode, nlookup: u64) {
+        // NOTE: The kernel forgets on its own, which doesn't make the mount busy
+        let _span = op_span("forget", req, ino).entered();
+        trace!("forget: ino={}, nlookup={}", ino, nlookup);
+        self.forget_(ino, nlookup);
+    }
+
+    fn batch_forget(&mut self, _req: &fuser::Request<'_>, nodes: &[fuser::fuse_forget_one]) {
+        trace!("batch_forget: nodes={}", nodes.len());
+
+        for node in nodes {
+            self.forget_(node.nodeid, node.nlookup);
+        }
+    }
+
+    fn read(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        fh: FileHandle,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: fuser::ReplyData,
+    ) {
+        let operation = self.stats.begin_operation();
+        let span = op_span("read", req, ino).entered();
+        span.record("fh", fh)
+            .record("offset", offset)
+            .record("size", size);
+        trace!(
+            "read: ino={}, fh={}, offset={}, size={}, flags={}, lock_owner={:?}",
+            ino,
+            fh,
+            offset,
+            size,
+            flags,
+            lock_owner
+        );
+
+        self.stats.record_read_request(size);
+
+        match self.read_(ino, fh, offset, size) {
+            Ok(data) => {
+                self.record_read(ino, data.len());
+                self.notify(ino, |listener, path| {
+                    listener.on_read(path, offset as u64, data.len(), req.into())
+                });
+
+                // NOTE: Only the reply waits for the rate limit, still counted as in flight
+                match &self.rate_limiter {
+                    Some(limiter) => limiter.delay(data.len() as u64, move || {
+                        reply.data(&data);
+                        drop(operation);
+                    }),
+                    None => reply.data(&data),
+                }
+            }
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+
+    fn open(&mut self, req: &fuser::Request<'_>, ino: INode, flags: i32, reply: fuser::ReplyOpen) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("open", req, ino).entered();
+        trace!("open: ino={}, flags={}", ino, flags);
+
+        match self.open_(ino) {
+            Ok((fh, open_flags)) => {
+                self.stats.record_open();
+                self.notify(ino, |listener, path| listener.on_open(path, req.into()));
+                reply.opened(fh, open_flags)
+            }
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+
+    fn release(
         &mut self,
-        _req: &fuser::Request<'_>,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        fh: FileHandle,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("release", req, ino).entered();
+        span.record("fh", fh);
+        trace!(
+            "release: ino={}, fh={}, flags={}, lock_owner={:?}, flush={}",
+            ino,
+            fh,
+            flags,
+            lock_owner,
+            flush
+        );
+
+        self.close_(fh);
+        self.stats.record_release();
+        reply.ok();
+    }
+
+    fn opendir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        flags: i32,
+        reply: fuser::ReplyOpen,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("opendir", req, ino).entered();
+        trace!("opendir: ino={}, flags={}", ino, flags);
+
+        // NOTE: Refused like files while shutting down, so that the mount drains
+        if self.draining.load(Ordering::Relaxed) {
+            reply.error(self.errno(req, ino, ZipFsError::ShuttingDown));
+            return;
+        }
+
+        // NOTE: A handle of its own, keeping the listing of an archive directory across pages
+        self.next_fh += 1;
+        self.stats.record_opendir();
+        reply.opened(self.next_fh, 0);
+    }
+
+    fn releasedir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        fh: FileHandle,
+        flags: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("releasedir", req, ino).entered();
+        span.record("fh", fh);
+        trace!("releasedir: ino={}, fh={}, flags={}", ino, fh, flags);
+
+        self.dir_listings.remove(&fh);
+        self.stats.record_releasedir();
+        reply.ok();
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: INode,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("mkdir", req, parent).entered();
+        trace!(
+            "mkdir: parent={}, name={:?}, mode={:o}, umask={:o}",
+            parent,
+            name,
+            mode,
+            umask
+        );
+
+        match self.mkdir_(parent, name) {
+            Ok(attrs) => reply.entry(&TTL, &attrs, self.inode_generation),
+            Err(err) => reply.error(self.errno(req, parent, err)),
+        }
+    }
+
+    fn rmdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: INode,
+        name: &std::ffi::OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("rmdir", req, parent).entered();
+        trace!("rmdir: parent={}, name={:?}", parent, name);
+
+        match self.rmdir_(parent, name) {
+            Ok(_) => reply.ok(),
+            Err(err) => reply.error(self.errno(req, parent, err)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: INode,
+        name: &std::ffi::OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: fuser::ReplyCreate,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("create", req, parent).entered();
+        trace!(
+            "create: parent={}, name={:?}, mode={:o}, umask={:o}, flags={}",
+            parent,
+            name,
+            mode,
+            umask,
+            flags
+        );
+
+        match self.create_(parent, name) {
+            Ok(attrs) => reply.created(&TTL, &attrs, self.inode_generation, 0, 0),
+            Err(err) => reply.error(self.errno(req, parent, err)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        req: &fuser::Request<'_>,
         ino: INode,
         fh: FileHandle,
         offset: i64,
-        size: u32,
+        data: &[u8],
+        write_flags: u32,
         flags: i32,
         lock_owner: Option<u64>,
-        reply: fuser::ReplyData,
+        reply: fuser::ReplyWrite,
     ) {
-        debug!(
-            "read: ino={}, fh={}, offset={}, size={}, flags={}, lock_owner={:?}",
-            ino, fh, offset, size, flags, lock_owner
+        let _operation = self.stats.begin_operation();
+        let span = op_span("write", req, ino).entered();
+        span.record("fh", fh)
+            .record("offset", offset)
+            .record("size", data.len());
+        trace!(
+            "write: ino={}, fh={}, offset={}, size={}, write_flags={}, flags={}, lock_owner={:?}",
+            ino,
+            fh,
+            offset,
+            data.len(),
+            write_flags,
+            flags,
+            lock_owner
         );
 
-        match self.read_(ino, fh, offset, size) {
-            Ok(data) => reply.data(&data),
-            Err(errno) => reply.error(errno),
+        match self.write_(ino, offset, data) {
+            Ok(written) => reply.written(written),
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<fuser::TimeOrNow>,
+        _mtime: Option<fuser::TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        fh: Option<FileHandle>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: fuser::ReplyAttr,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let _span = op_span("setattr", req, ino).entered();
+        trace!(
+            "setattr: ino={}, mode={:?}, uid={:?}, gid={:?}, size={:?}, fh={:?}, flags={:?}",
+            ino,
+            mode,
+            uid,
+            gid,
+            size,
+            fh,
+            flags
+        );
+
+        match self.setattr_(ino, size) {
+            Ok(attrs) => reply.attr(&TTL, &attrs),
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino_in: INode,
+        fh_in: FileHandle,
+        offset_in: i64,
+        ino_out: INode,
+        fh_out: FileHandle,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: fuser::ReplyWrite,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("copy_file_range", req, ino_in).entered();
+        span.record("fh", fh_in)
+            .record("offset", offset_in)
+            .record("size", len);
+        trace!(
+            "copy_file_range: ino_in={}, fh_in={}, offset_in={}, \
+             ino_out={}, fh_out={}, offset_out={}, len={}, flags={}",
+            ino_in,
+            fh_in,
+            offset_in,
+            ino_out,
+            fh_out,
+            offset_out,
+            len,
+            flags
+        );
+
+        match self.copy_file_range_(ino_in, fh_in, offset_in, ino_out, offset_out, len) {
+            Ok(copied) => {
+                self.record_read(ino_in, copied as usize);
+                self.notify(ino_in, |listener, path| {
+                    listener.on_read(path, offset_in as u64, copied as usize, req.into())
+                });
+                reply.written(copied)
+            }
+            Err(err) => reply.error(self.errno(req, ino_in, err)),
+        }
+    }
+
+    fn fsyncdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: INode,
+        fh: FileHandle,
+        datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let _operation = self.stats.begin_operation();
+        let span = op_span("fsyncdir", req, ino).entered();
+        span.record("fh", fh);
+        trace!("fsyncdir: ino={}, fh={}, datasync={}", ino, fh, datasync);
+
+        match self.fsyncdir_(ino) {
+            Ok(_) => reply.ok(),
+            Err(err) => reply.error(self.errno(req, ino, err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use temp_dir::TempDir;
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    use super::*;
+    use crate::source::MemorySource;
+
+    /// A data directory holding the fixture archives
+    fn data_dir() -> TempDir {
+        let data = TempDir::new().unwrap();
+        crate::fixtures::create(data.path()).unwrap();
+        fs::write(data.path().join("passthrough.txt"), "test\n").unwrap();
+        data
+    }
+
+    #[test]
+    fn test_forget_shrinks_tree() {
+        let data = data_dir();
+        let mut fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None);
+        let names = ["passthrough.txt", "stored.zip", "compressed.zip"];
+
+        let inodes = names
+            .iter()
+            .map(|name| fs.lookup_(FUSE_ROOT_ID, name.as_ref()).unwrap().ino)
+            .collect::<Vec<_>>();
+        for name in names {
+            fs.lookup_(FUSE_ROOT_ID, name.as_ref()).unwrap();
+        }
+
+        let size = fs.tree.len();
+        assert!(size > names.len());
+
+        for ino in &inodes {
+            fs.forget_(*ino, 1);
+        }
+        assert_eq!(fs.tree.len(), size);
+
+        for ino in &inodes {
+            fs.forget_(*ino, 1);
+        }
+        assert_eq!(fs.tree.len(), size - names.len());
+
+        for ino in inodes {
+            assert!(matches!(
+                fs.get_data_path(ino),
+                Err(ZipFsError::UnknownInode(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_inodes_only_for_found_entries() {
+        let data = data_dir();
+        let mut fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None);
+        let zip = fs.lookup_(FUSE_ROOT_ID, "stored.zip".as_ref()).unwrap().ino;
+        let size = fs.tree.len();
+
+        // NOTE: Listing hands the kernel no reference, neither does a failed lookup
+        assert!(list(&mut fs, FUSE_ROOT_ID).contains(&"compressed.zip".to_string()));
+        assert_eq!(list(&mut fs, zip), ["some"]);
+        for _ in 0..3 {
+            assert!(fs.lookup_(FUSE_ROOT_ID, "missing.zip".as_ref()).is_err());
+            assert!(fs.lookup_(zip, "missing.txt".as_ref()).is_err());
+        }
+        assert_eq!(fs.tree.len(), size);
+
+        let some = fs.lookup_(zip, "some".as_ref()).unwrap().ino;
+        assert_eq!(fs.tree.len(), size + 1);
+        fs.forget_(some, 1);
+        assert_eq!(fs.tree.len(), size);
+    }
+
+    /// Reply buffer with room for `room` entries, keeping their offsets
+    struct Page {
+        room: usize,
+        entries: Vec<(i64, String)>,
+    }
+
+    impl DirSink for Page {
+        fn add(
+            &mut self,
+            _ino: INode,
+            offset: i64,
+            _kind: FileType,
+            name: &std::ffi::OsStr,
+        ) -> bool {
+            if self.entries.len() == self.room {
+                return true;
+            }
+
+            self.entries
+                .push((offset, name.to_string_lossy().to_string()));
+            false
+        }
+    }
+
+    #[test]
+    fn test_readdir_zip_pages() {
+        let names = (0..10).map(|i| format!("{}.txt", i)).collect::<Vec<_>>();
+        let entries = names
+            .iter()
+            .map(|name| (name.as_str(), &b"x"[..]))
+            .collect::<Vec<_>>();
+        let source = MemorySource::new().with_file(
+            "/data/many.zip",
+            zip_bytes(&entries, CompressionMethod::Stored),
+        );
+        let mut fs = memory_fs(source);
+        let zip = fs.lookup_(FUSE_ROOT_ID, "many.zip".as_ref()).unwrap().ino;
+
+        // NOTE: The kernel asks again from the offset of the last entry it got
+        let mut listed = Vec::new();
+        let mut offset = 0;
+        loop {
+            let mut page = Page {
+                room: 4,
+                entries: Vec::new(),
+            };
+            fs.readdir_(zip, 0, offset, &mut page).unwrap();
+            let Some(&(last, _)) = page.entries.last() else {
+                break;
+            };
+
+            offset = last;
+            listed.extend(page.entries.into_iter().map(|(_, name)| name));
+        }
+
+        listed.retain(|name| name != "." && name != "..");
+        listed.sort();
+        let mut expected = names;
+        expected.sort();
+        assert_eq!(listed, expected);
+    }
+
+    #[test]
+    fn test_readdir_zip_listing_per_handle() {
+        let names = (0..10).map(|i| format!("{}.txt", i)).collect::<Vec<_>>();
+        let entries = names
+            .iter()
+            .map(|name| (name.as_str(), &b"x"[..]))
+            .collect::<Vec<_>>();
+        let source = MemorySource::new().with_file(
+            "/data/many.zip",
+            zip_bytes(&entries, CompressionMethod::Stored),
+        );
+        let mut fs = memory_fs(source);
+        let zip = fs.lookup_(FUSE_ROOT_ID, "many.zip".as_ref()).unwrap().ino;
+        let fh = 1;
+
+        let mut page = Page {
+            room: 4,
+            entries: Vec::new(),
+        };
+        fs.readdir_(zip, fh, 0, &mut page).unwrap();
+        assert!(fs.dir_listings.contains_key(&fh));
+        let opened = fs.stats.cache_hits() + fs.stats.cache_misses();
+
+        // NOTE: The later pages come from the listing, without going back to the archive
+        let mut offset = page.entries.last().unwrap().0;
+        loop {
+            let mut page = Page {
+                room: 4,
+                entries: Vec::new(),
+            };
+            fs.readdir_(zip, fh, offset, &mut page).unwrap();
+            let Some(&(last, _)) = page.entries.last() else {
+                break;
+            };
+
+            offset = last;
+        }
+
+        assert_eq!(fs.stats.cache_hits() + fs.stats.cache_misses(), opened);
+        assert_eq!(offset, names.len() as i64 + DOT_ENTRIES);
+    }
+
+    #[test]
+    fn test_rescan_forgets_removed_and_evicts_changed() {
+        let data = data_dir();
+        let mut fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None);
+
+        let text = fs
+            .lookup_(FUSE_ROOT_ID, "passthrough.txt".as_ref())
+            .unwrap()
+            .ino;
+        let zip = fs.lookup_(FUSE_ROOT_ID, "stored.zip".as_ref()).unwrap().ino;
+        let some = fs.lookup_(zip, "some".as_ref()).unwrap().ino;
+        assert_eq!(fs.rescan(), 0);
+
+        fs::remove_file(data.path().join("passthrough.txt")).unwrap();
+        let archive = File::options()
+            .append(true)
+            .open(data.path().join("stored.zip"))
+            .unwrap();
+        archive
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+
+        // NOTE: The archive and its entry stay known, only their cached state goes
+        assert_eq!(fs.rescan(), 3);
+        assert!(matches!(
+            fs.get_data_path(text),
+            Err(ZipFsError::UnknownInode(_))
+        ));
+        assert!(fs.get_data_path(some).is_ok());
+        assert!(fs.open_files.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inode_generation_bumped_per_mount() {
+        let data = data_dir();
+        let state = TempDir::new().unwrap();
+        let generation_file = state.path().join("generation");
+
+        let fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None)
+            .with_generation_file(Some(generation_file.clone()));
+        assert_eq!(fs.next_inode_generation().unwrap(), 1);
+        assert_eq!(fs.next_inode_generation().unwrap(), 2);
+        assert_eq!(fs::read_to_string(&generation_file).unwrap(), "2\n");
+
+        fs::write(&generation_file, "garbage").unwrap();
+        assert!(fs.next_inode_generation().is_err());
+
+        let fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None);
+        assert!(fs.next_inode_generation().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_open_zip_retries_stale_handle() {
+        let data = data_dir();
+        let stale = AtomicBool::new(true);
+        let mut fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None)
+            .with_archive_source(move |path| match stale.swap(false, Ordering::Relaxed) {
+                true => Err(io::Error::from_raw_os_error(ESTALE)),
+                false => DiskSource.open(path),
+            });
+
+        let zip_ino = fs.lookup_(FUSE_ROOT_ID, "stored.zip".as_ref()).unwrap().ino;
+        let some_ino = fs.lookup_(zip_ino, "some".as_ref()).unwrap().ino;
+        assert_eq!(fs.getattr_(some_ino).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.stats.cache_misses(), 1);
+    }
+
+    #[test]
+    fn test_attr_cache_skips_reopening_archives() {
+        let data = data_dir();
+        let caches = CacheConfig {
+            attr_entries: 16,
+            ..CacheConfig::default()
+        };
+        let mut fs = ZipFs::new(data.path().to_path_buf(), caches, None);
+
+        let zip_ino = fs.lookup_(FUSE_ROOT_ID, "stored.zip".as_ref()).unwrap().ino;
+        let some_ino = fs.lookup_(zip_ino, "some".as_ref()).unwrap().ino;
+
+        fs.open_files.lock().unwrap().clear();
+        assert_eq!(fs.getattr_(some_ino).unwrap().kind, FileType::Directory);
+        assert!(fs.open_files.lock().unwrap().is_empty());
+
+        // NOTE: A reload drops the cached attributes along with the archives
+        fs.generation.fetch_add(1, Ordering::Relaxed);
+        fs.drop_stale_state();
+        assert_eq!(fs.getattr_(some_ino).unwrap().kind, FileType::Directory);
+        assert_eq!(fs.open_files.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_open_zip_persistently_stale_handle() {
+        let data = data_dir();
+        let mut fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None)
+            .with_archive_source(|_| Err(io::Error::from_raw_os_error(ESTALE)));
+
+        let Err(err) = fs.open_zip(&data.path().join("stored.zip")) else {
+            panic!("Opened the archive through a stale handle");
+        };
+        assert!(matches!(err.inner(), ZipFsError::StaleHandle));
+        assert_eq!(libc::c_int::from(err), libc::EIO);
+    }
+
+    #[test]
+    fn test_read_compressed_at_random_offsets() {
+        let data = TempDir::new().unwrap();
+
+        // NOTE: xorshift, spanning several stream chunks without being trivially compressible
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let content = (0..300_000)
+            .map(|_| b"abcdefghij\n"[next() as usize % 11])
+            .collect::<Vec<_>>();
+
+        let file = File::create(data.path().join("random.zip")).unwrap();
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut zip = ZipWriter::new(file);
+        zip.start_file("file.txt", options).unwrap();
+        zip.write_all(&content).unwrap();
+        zip.finish().unwrap();
+
+        let mut fs = ZipFs::new(data.path().to_path_buf(), CacheConfig::default(), None);
+        let zip_ino = fs.lookup_(FUSE_ROOT_ID, "random.zip".as_ref()).unwrap().ino;
+        let ino = fs.lookup_(zip_ino, "file.txt".as_ref()).unwrap().ino;
+
+        let mut offset = 0;
+        for _ in 0..200 {
+            // NOTE: Mostly sequential like the kernel, with occasional jumps in either direction
+            offset = match next() % 4 {
+                0 => next() as usize % (content.len() + 1000),
+                _ => offset,
+            };
+            let size = 1 + next() as usize % 100_000;
+
+            let read = fs.read_(ino, 0, offset as i64, size as u32).unwrap();
+
+            let start = offset.min(content.len());
+            let end = (offset + size).min(content.len());
+            assert_eq!(
+                read,
+                content[start..end],
+                "offset = {}, size = {}",
+                offset,
+                size
+            );
+
+            offset += read.len();
+        }
+    }
+
+    /// A file system over a data directory held in memory at `/data`
+    fn memory_fs(source: MemorySource) -> ZipFs {
+        ZipFs::new(PathBuf::from("/data"), CacheConfig::default(), None)
+            .with_source(Box::new(source))
+    }
+
+    fn zip_bytes(entries: &[(&str, &[u8])], method: CompressionMethod) -> Vec<u8> {
+        let options = SimpleFileOptions::default().compression_method(method);
+        let mut zip = ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// Names listed in the directory `ino` after `.` and `..`, sorted
+    fn list(fs: &mut ZipFs, ino: INode) -> Vec<String> {
+        let mut entries = Vec::new();
+        fs.readdir_(ino, 0, 0, &mut entries).unwrap();
+
+        let mut names = entries
+            .into_iter()
+            .skip(DOT_ENTRIES as usize)
+            .map(|(_, name)| name.into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_get_zip_paths() {
+        let fs = memory_fs(MemorySource::new());
+        let zip_paths = |path: &str| fs.get_zip_paths(Path::new(path));
+
+        assert_eq!(
+            zip_paths("/data/a/b.zip/c/d.txt"),
+            Some((PathBuf::from("/data/a/b.zip"), PathBuf::from("c/d.txt")))
+        );
+        assert_eq!(
+            zip_paths("/data/b.zip"),
+            Some((PathBuf::from("/data/b.zip"), PathBuf::new()))
+        );
+        assert_eq!(zip_paths("/data/a/b.txt"), None);
+
+        let fs = memory_fs(MemorySource::new()).with_passthrough_only(true);
+        assert_eq!(fs.get_zip_paths(Path::new("/data/b.zip/c")), None);
+    }
+
+    #[test]
+    fn test_readdir_memory_source() {
+        let stored =
+            |content: &[u8]| zip_bytes(&[("file.txt", content)], CompressionMethod::Stored);
+        let source = MemorySource::new()
+            .with_file("/data/photos.zip", stored(b"photo"))
+            .with_file("/data/music.zip", stored(b"song"))
+            .with_file("/data/music/file.txt", &b"real"[..])
+            .with_file("/data/debug.log", &b"hidden"[..]);
+
+        // NOTE: The real directory keeps its name, the archive is listed as is
+        let filter = PathFilter::new(&[], &["*.log".to_string()]).unwrap();
+        let mut fs = memory_fs(source).with_strip_ext(true).with_filter(filter);
+        assert_eq!(
+            list(&mut fs, FUSE_ROOT_ID),
+            ["music", "music.zip", "photos"]
+        );
+
+        let photos = fs.lookup_(FUSE_ROOT_ID, "photos".as_ref()).unwrap();
+        assert_eq!(photos.kind, FileType::Directory);
+        assert_eq!(list(&mut fs, photos.ino), ["file.txt"]);
+
+        let music = fs.lookup_(FUSE_ROOT_ID, "music".as_ref()).unwrap().ino;
+        let file = fs.lookup_(music, "file.txt".as_ref()).unwrap().ino;
+        assert_eq!(fs.read_(file, 0, 0, 4096).unwrap(), b"real");
+    }
+
+    #[test]
+    fn test_read_offsets_memory_source() {
+        let content = "some content\n".repeat(15);
+        let entries = [("some/nested/file.txt", content.as_bytes())];
+        let mut fs = memory_fs(
+            MemorySource::new()
+                .with_file(
+                    "/data/stored.zip",
+                    zip_bytes(&entries, CompressionMethod::Stored),
+                )
+                .with_file(
+                    "/data/compressed.zip",
+                    zip_bytes(&entries, CompressionMethod::Deflated),
+                ),
+        );
+
+        for zip in ["stored.zip", "compressed.zip"] {
+            let mut ino = fs.lookup_(FUSE_ROOT_ID, zip.as_ref()).unwrap().ino;
+            for name in ["some", "nested", "file.txt"] {
+                ino = fs.lookup_(ino, name.as_ref()).unwrap().ino;
+            }
+
+            assert_eq!(fs.read_(ino, 0, 13, 7).unwrap(), b"some co", "{}", zip);
+            assert_eq!(fs.read_(ino, 0, 0, 4096).unwrap(), content.as_bytes());
+            assert!(fs.read_(ino, 0, 4096, 16).unwrap().is_empty(), "{}", zip);
+        }
+    }
+
+    #[test]
+    fn test_read_and_list_path() {
+        let content = "some content\n".repeat(15);
+        let entries = [("some/nested/file.txt", content.as_bytes())];
+        let mut fs = memory_fs(
+            MemorySource::new()
+                .with_file(
+                    "/data/compressed.zip",
+                    zip_bytes(&entries, CompressionMethod::Deflated),
+                )
+                .with_file("/data/passthrough.txt", &b"passthrough"[..]),
+        );
+
+        let file = Path::new("compressed.zip/some/nested/file.txt");
+        assert_eq!(
+            fs.read_path(file, 0, usize::MAX).unwrap(),
+            content.as_bytes()
+        );
+        assert_eq!(fs.read_path(file, 13, 7).unwrap(), b"some co");
+        assert_eq!(
+            fs.read_path(Path::new("/passthrough.txt"), 4, 64).unwrap(),
+            b"through"
+        );
+
+        let names = |entries: Vec<DirEntryInfo>| {
+            let mut names = entries
+                .into_iter()
+                .map(|entry| (entry.name.into_string().unwrap(), entry.kind))
+                .collect::<Vec<_>>();
+            names.sort_by(|a, b| a.0.cmp(&b.0));
+            names
+        };
+        assert_eq!(
+            names(fs.list_path(Path::new("")).unwrap()),
+            [
+                ("compressed.zip".to_string(), FileType::Directory),
+                ("passthrough.txt".to_string(), FileType::RegularFile),
+            ]
+        );
+
+        let listed = fs
+            .list_path(Path::new("compressed.zip/some/nested"))
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].size, content.len() as u64);
+
+        assert!(matches!(
+            fs.read_path(Path::new("compressed.zip/some"), 0, 1)
+                .unwrap_err()
+                .inner(),
+            ZipFsError::IsADirectory
+        ));
+        assert!(matches!(
+            fs.list_path(file).unwrap_err().inner(),
+            ZipFsError::NotADirectory
+        ));
+        assert!(matches!(
+            fs.list_path(Path::new("compressed.zip/../passthrough.txt"))
+                .unwrap_err()
+                .inner(),
+            ZipFsError::NotFound
+        ));
+    }
+
+    #[test]
+    fn test_max_open_archives() {
+        let content = "some content\n".repeat(15);
+        let entries = [("file.txt", content.as_bytes())];
+        let archive = zip_bytes(&entries, CompressionMethod::Deflated);
+        let mut fs = memory_fs(
+            MemorySource::new()
+                .with_file("/data/a.zip", archive.clone())
+                .with_file("/data/b.zip", archive)
+                .with_file("/data/passthrough.txt", &b"passthrough"[..]),
+        )
+        .with_max_open_archives(Some(1));
+
+        let resolve = |fs: &mut ZipFs, path: &str| fs.resolve_path(Path::new(path)).unwrap().ino;
+        let a = resolve(&mut fs, "a.zip/file.txt");
+        let b = resolve(&mut fs, "b.zip/file.txt");
+        let passthrough = resolve(&mut fs, "passthrough.txt");
+
+        let (first, _) = fs.open_(a).unwrap();
+        let (second, _) = fs.open_(a).unwrap();
+        assert_eq!(fs.read_(a, first, 0, 12).unwrap(), b"some content");
+
+        let err = fs.open_(b).unwrap_err();
+        assert!(matches!(err, ZipFsError::TooManyOpenArchives { limit: 1 }));
+        assert_eq!(err.errno(), libc::EMFILE);
+        fs.open_(passthrough).unwrap();
+
+        // NOTE: The archive stays open until every one of its entries is released
+        fs.close_(first);
+        assert!(fs.open_(b).is_err());
+        fs.close_(second);
+        fs.open_(b).unwrap();
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let content = "some content\n".repeat(15);
+        let deflated = zip_bytes(
+            &[("file.txt", content.as_bytes())],
+            CompressionMethod::Deflated,
+        );
+        let stored = zip_bytes(
+            &[("file.txt", content.as_bytes())],
+            CompressionMethod::Stored,
+        );
+        let source = MemorySource::new()
+            .with_file("/data/deflated.zip", deflated)
+            .with_file("/data/stored.zip", stored);
+
+        let archive = ARCHIVE_ENTRY_MEMORY;
+        let mut fs = memory_fs(source.clone()).with_max_memory(Some(archive - 1));
+        let err = fs
+            .read_path(Path::new("stored.zip/file.txt"), 0, 4096)
+            .unwrap_err();
+        assert!(matches!(
+            err.inner(),
+            ZipFsError::MemoryLimit { limit } if *limit == archive - 1
+        ));
+        assert_eq!(err.errno(), libc::ENOMEM);
+
+        // NOTE: Stored entries are read in place, without a decompressor to account for
+        let limit = 2 * archive + ENTRY_READER_MEMORY - 1;
+        let mut fs = memory_fs(source.clone()).with_max_memory(Some(limit));
+        let read = fs.read_path(Path::new("stored.zip/file.txt"), 0, 4096);
+        assert_eq!(read.unwrap(), content.as_bytes());
+        let err = fs
+            .read_path(Path::new("deflated.zip/file.txt"), 0, 4096)
+            .unwrap_err();
+        assert_eq!(err.errno(), libc::ENOMEM);
+        assert_eq!(fs.memory_usage(), 2 * archive);
+
+        let mut fs = memory_fs(source).with_max_memory(Some(limit + 1));
+        let read = fs.read_path(Path::new("deflated.zip/file.txt"), 0, 4096);
+        assert_eq!(read.unwrap(), content.as_bytes());
+        assert!(fs.memory_usage() <= limit + 1);
+    }
+
+    #[test]
+    fn test_operations_alongside_control_handle() {
+        let content = "some content\n".repeat(64);
+        let entries = [("dir/file.txt", content.as_bytes())];
+        let archive = zip_bytes(&entries, CompressionMethod::Deflated);
+
+        let mut source = MemorySource::new();
+        for i in 0..8 {
+            source.insert(format!("/data/{}.zip", i), archive.clone());
+        }
+
+        let fs = memory_fs(source);
+        let control = fs.control_handle();
+        let fs = Arc::new(Mutex::new(fs));
+        let (done, finished) = std::sync::mpsc::channel();
+
+        for worker in 0..16 {
+            let fs = fs.clone();
+            let control = control.clone();
+            let done = done.clone();
+            let content = content.clone();
+
+            thread::spawn(move || {
+                for i in 0..100 {
+                    let zip = PathBuf::from(format!("{}.zip", (worker + i) % 8));
+
+                    // NOTE: Operations take turns through the mutex, the control handle locks the
+                    // shared state behind their back, as it does from the control socket
+                    match (i % 4, worker % 3) {
+                        (0, 0) => control.request_rescan(),
+                        (0, 1) => {
+                            control.reload();
+                        }
+                        (0, _) => {
+                            control.snapshot();
+                        }
+                        (1, _) => {
+                            let listed = fs.lock().unwrap().list_path(&zip).unwrap();
+                            assert_eq!(listed.len(), 1);
+                        }
+                        _ => {
+                            let path = zip.join("dir/file.txt");
+                            let offset = (i * 7) as u64;
+                            let data = fs.lock().unwrap().read_path(&path, offset, 64).unwrap();
+                            assert_eq!(data, &content.as_bytes()[offset as usize..][..64]);
+                        }
+                    }
+                }
+
+                done.send(()).unwrap();
+            });
+        }
+        drop(done);
+
+        for _ in 0..16 {
+            finished
+                .recv_timeout(Duration::from_secs(30))
+                .expect("an operation panicked or deadlocked");
         }
     }
+
+    #[test]
+    fn test_directory_named_like_archive() {
+        let content = "some content\n".repeat(15);
+        let entries = [("file.txt", content.as_bytes())];
+        let mut fs = memory_fs(
+            MemorySource::new()
+                .with_file("/data/test.zip/inner.txt", &b"inner"[..])
+                .with_file(
+                    "/data/test.zip/nested.zip",
+                    zip_bytes(&entries, CompressionMethod::Stored),
+                ),
+        );
+
+        assert_eq!(
+            fs.get_zip_paths(Path::new("/data/test.zip/inner.txt")),
+            None
+        );
+        assert_eq!(
+            fs.get_zip_paths(Path::new("/data/test.zip/nested.zip/file.txt")),
+            Some((
+                PathBuf::from("/data/test.zip/nested.zip"),
+                PathBuf::from("file.txt")
+            ))
+        );
+
+        let dir = fs.lookup_(FUSE_ROOT_ID, "test.zip".as_ref()).unwrap();
+        assert_eq!(dir.kind, FileType::Directory);
+        assert_eq!(list(&mut fs, dir.ino), ["inner.txt", "nested.zip"]);
+
+        let inner = fs.read_path(Path::new("test.zip/inner.txt"), 0, 4096);
+        assert_eq!(inner.unwrap(), b"inner");
+        let nested = fs.read_path(Path::new("test.zip/nested.zip/file.txt"), 0, 4096);
+        assert_eq!(nested.unwrap(), content.as_bytes());
+    }
 }
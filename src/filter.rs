@@ -0,0 +1,121 @@
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use crate::options::Options;
+
+/// Globs matched against the file name when they contain no `/`, and against the whole
+/// virtual path otherwise
+#[derive(Debug, Clone)]
+struct Patterns {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Patterns {
+    fn new(globs: &[String]) -> Result<Self, globset::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for glob in globs {
+            let pattern = GlobBuilder::new(glob).literal_separator(true).build()?;
+            match glob.contains('/') {
+                true => paths.add(pattern),
+                false => names.add(pattern),
+            };
+        }
+
+        Ok(Self {
+            names: names.build()?,
+            paths: paths.build()?,
+        })
+    }
+
+    fn is_match(&self, path: &Path) -> bool {
+        self.paths.is_match(path)
+            || path
+                .file_name()
+                .is_some_and(|name| self.names.is_match(name))
+    }
+
+    /// Whether every glob matches `path`, false when there are none
+    fn is_match_all(&self, path: &Path) -> bool {
+        let total = self.names.len() + self.paths.len();
+        let names = path
+            .file_name()
+            .map_or(0, |name| self.names.matches(name).len());
+
+        total != 0 && names + self.paths.matches(path).len() == total
+    }
+}
+
+/// Hides paths of the mount matching `--exclude`, or files not matching any `--include`.
+/// Files inside archives are also hidden when they match every `--exclude-pattern`.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    include: Option<Patterns>,
+    exclude: Patterns,
+    entry_exclude: Patterns,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self::new(&[], &[]).unwrap()
+    }
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+        let include = match include.is_empty() {
+            true => None,
+            false => Some(Patterns::new(include)?),
+        };
+
+        Ok(Self {
+            include,
+            exclude: Patterns::new(exclude)?,
+            entry_exclude: Patterns::new(&[])?,
+        })
+    }
+
+    pub fn from_options(options: &Options) -> Result<Self, globset::Error> {
+        Self::new(&options.include, &options.exclude)?
+            .with_entry_excludes(&options.exclude_patterns)
+    }
+
+    /// Hide files inside archives matching all of `globs`, matched against the path within
+    /// the archive
+    pub fn with_entry_excludes(mut self, globs: &[String]) -> Result<Self, globset::Error> {
+        self.entry_exclude = Patterns::new(globs)?;
+        Ok(self)
+    }
+
+    /// Whether `path`, relative to the mount, is shown. Includes only apply to files so that
+    /// directories can still be traversed.
+    pub fn is_visible(&self, path: &Path, is_dir: bool) -> bool {
+        if self.exclude.is_match(path) {
+            return false;
+        }
+
+        is_dir
+            || self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(path))
+    }
+
+    /// Whether `path`, relative to the root of its archive, is shown. Directories always are,
+    /// so that the files kept inside them stay reachable.
+    pub fn is_entry_visible(&self, path: &Path, is_dir: bool) -> bool {
+        is_dir || !self.entry_exclude.is_match_all(path)
+    }
+
+    /// Like [`PathFilter::is_visible`], also checking every parent directory of `path`
+    pub fn is_visible_with_parents(&self, path: &Path, is_dir: bool) -> bool {
+        path.ancestors()
+            .skip(1)
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .all(|parent| self.is_visible(parent, true))
+            && self.is_visible(path, is_dir)
+    }
+}
@@ -0,0 +1,163 @@
+use std::{ffi::OsStr, path::Path};
+
+use clap::Command;
+
+const PROGRAM: &str = "mount.zipfs";
+const FSTAB_COMPAT: &str = "--fstab-compat";
+
+/// Options consumed by mount(8) itself that must not be passed to FUSE
+const IGNORED_OPTIONS: [&str; 10] = [
+    "defaults", "auto", "noauto", "user", "users", "nouser", "owner", "group", "nofail", "_netdev",
+];
+
+fn is_ignored(key: &str) -> bool {
+    IGNORED_OPTIONS.contains(&key) || key.starts_with("x-") || key == "comment"
+}
+
+/// Whether we were started as a mount(8) helper, either through the `mount.zipfs` name or
+/// with `--fstab-compat`
+pub fn is_fstab_invocation() -> bool {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_default();
+
+    Path::new(&program).file_name() == Some(OsStr::new(PROGRAM))
+        || args.any(|arg| arg == FSTAB_COMPAT)
+}
+
+/// Translate `source mountpoint -o options` as passed by mount(8) into regular arguments.
+/// Options matching one of our long flags (`archive_cache_entries=2048` ->
+/// `--archive-cache-entries 2048`) are taken out of the `-o` string, everything else is
+/// passed to FUSE. Boolean options are given by name alone, like the ones FUSE handles.
+pub fn translate_args(
+    args: impl IntoIterator<Item = String>,
+    command: &Command,
+) -> Result<Vec<String>, String> {
+    let mut args = args.into_iter();
+    let mut translated = vec![args.next().unwrap_or_else(|| PROGRAM.to_string())];
+    let mut positional = Vec::new();
+    let mut mount_options = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let options = match arg.as_str() {
+            FSTAB_COMPAT => continue,
+            "-o" => args.next().unwrap_or_default(),
+            arg if arg.starts_with("-o") => arg[2..].to_string(),
+            // NOTE: -f, -n, -s and -v are meant for mount(8) helpers with their own mtab handling
+            arg if arg.starts_with('-') => continue,
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+
+            let long = key.replace('_', "-");
+            let ours = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(&long));
+
+            if let Some(arg) = ours {
+                match (arg.get_action().takes_values(), value) {
+                    (false, Some(_)) => {
+                        return Err(format!("mount option {} takes no value", key));
+                    }
+                    (true, None) => return Err(format!("mount option {} expects a value", key)),
+                    _ => {}
+                }
+
+                translated.push(format!("--{}", long));
+                translated.extend(value.map(str::to_string));
+            } else if !is_ignored(key) {
+                mount_options.push(option.to_string());
+            }
+        }
+    }
+
+    if let Some(source) = positional.first_mut() {
+        if let Some(data_dir) = source.strip_prefix("zipfs#") {
+            *source = data_dir.to_string();
+        }
+    }
+
+    translated.extend(positional);
+
+    if !mount_options.is_empty() {
+        translated.push("-o".to_string());
+        translated.push(mount_options.join(","));
+    }
+
+    Ok(translated)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction};
+
+    use super::*;
+
+    fn translate(args: &[&str]) -> Result<Vec<String>, String> {
+        let command = Command::new("zipfs")
+            .arg(Arg::new("cache-size").long("cache-size"))
+            .arg(
+                Arg::new("foreground")
+                    .long("foreground")
+                    .action(ArgAction::SetTrue),
+            );
+
+        translate_args(args.iter().map(|arg| arg.to_string()), &command)
+    }
+
+    #[test]
+    fn test_translate_fstab_args() {
+        let args = translate(&[
+            "/sbin/mount.zipfs",
+            "zipfs#/srv/archives",
+            "/mnt/archives",
+            "-n",
+            "-o",
+            "ro,noauto,allow_other,cache_size=2048,x-systemd.automount,foreground",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args,
+            vec![
+                "/sbin/mount.zipfs",
+                "--cache-size",
+                "2048",
+                "--foreground",
+                "/srv/archives",
+                "/mnt/archives",
+                "-o",
+                "ro,allow_other",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_translate_fstab_compat_flag() {
+        let args =
+            translate(&["zipfs", "--fstab-compat", "/srv/archives", "/mnt/archives"]).unwrap();
+        assert_eq!(args, vec!["zipfs", "/srv/archives", "/mnt/archives"]);
+    }
+
+    #[test]
+    fn test_translate_flag_with_value() {
+        for value in ["1", "0", "true"] {
+            let option = format!("foreground={}", value);
+            let err = translate(&["mount.zipfs", "/srv", "/mnt", "-o", &option]).unwrap_err();
+            assert_eq!(err, "mount option foreground takes no value");
+        }
+    }
+
+    #[test]
+    fn test_translate_value_missing() {
+        let err = translate(&["mount.zipfs", "/srv", "/mnt", "-o", "ro,cache_size"]).unwrap_err();
+        assert_eq!(err, "mount option cache_size expects a value");
+    }
+}
@@ -1,4 +1,45 @@
+mod archive;
+mod builder;
+mod chunk_cache;
+mod control;
+mod error;
+mod events;
 mod file_tree;
 mod filesystem;
+mod filter;
+#[cfg(test)]
+#[path = "../tests/fixtures/mod.rs"]
+mod fixtures;
+mod listing;
+#[cfg(feature = "lzma")]
+mod lzma;
+mod manifest;
+mod negative_cache;
+mod options;
+mod password;
+mod platform;
+mod positioned_reader;
+mod prefetch;
+mod rate_limit;
+mod source;
+mod staging;
+mod stats;
+mod stream;
+mod verify;
 
-pub use filesystem::ZipFs;
+pub use archive::{inspect, InspectedEntry, Inspection, Presentation};
+pub use builder::{MountHandle, ZipFsBuilder};
+pub use control::{CacheStats, ControlHandle};
+pub use error::ZipFsError;
+pub use events::{EventListener, Requester};
+pub use filesystem::{DirEntryInfo, ExtractReport, ZipFs};
+pub use filter::PathFilter;
+pub use listing::{dir_children, dir_children_parallel, dir_children_serial};
+pub use options::{CacheConfig, LogFormat, MountMode, Options, SortOrder};
+pub use password::{FixedPassword, PasswordMap, PasswordProvider, SecretString};
+pub use platform::default_mount_options;
+pub use positioned_reader::ReadAtSize;
+pub use prefetch::{CancellationToken, Prefetch};
+pub use source::{DiskSource, MemorySource, Source, SourceEntry};
+pub use stream::{EntryReader, EntryStream};
+pub use verify::{verify_archives, verify_tree, ArchiveReport};
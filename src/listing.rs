@@ -0,0 +1,130 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+use rayon::prelude::*;
+
+use crate::options::SortOrder;
+
+/// Below this many entries the rayon overhead outweighs the parallel scan
+const PARALLEL_THRESHOLD: usize = 1000;
+
+/// Direct children of `dir` (empty or ending with `/`) among the entry names of an archive,
+/// with consecutive duplicates removed
+pub fn dir_children<'a>(file_names: &[&'a str], dir: &str) -> Vec<&'a str> {
+    match file_names.len() < PARALLEL_THRESHOLD {
+        true => dir_children_serial(file_names, dir),
+        false => dir_children_parallel(file_names, dir),
+    }
+}
+
+pub fn dir_children_serial<'a>(file_names: &[&'a str], dir: &str) -> Vec<&'a str> {
+    let slash_count = dir.matches('/').count();
+
+    let mut children = file_names
+        .iter()
+        .filter(|name| name.starts_with(dir))
+        .filter_map(|name| name.split('/').nth(slash_count))
+        .collect::<Vec<_>>();
+
+    children.dedup();
+    children
+}
+
+pub fn dir_children_parallel<'a>(file_names: &[&'a str], dir: &str) -> Vec<&'a str> {
+    let slash_count = dir.matches('/').count();
+
+    let mut children = file_names
+        .par_iter()
+        .filter(|name| name.starts_with(dir))
+        .filter_map(|name| name.split('/').nth(slash_count))
+        .collect::<Vec<_>>();
+
+    children.dedup();
+    children
+}
+
+/// Order directory `entries` as configured by `--sort`. Names compare bytewise and break ties,
+/// sizes and modification times are only looked up by `metadata` when sorting by them.
+pub(crate) fn sort_entries<T, M: Ord>(
+    order: SortOrder,
+    entries: &mut [T],
+    name: impl Fn(&T) -> Vec<u8>,
+    mut metadata: impl FnMut(&T) -> (u64, M),
+) {
+    match order {
+        SortOrder::Name => entries.sort_by_cached_key(name),
+        SortOrder::Size => {
+            entries.sort_by_cached_key(|entry| (Reverse(metadata(entry).0), name(entry)))
+        }
+        SortOrder::Mtime => {
+            entries.sort_by_cached_key(|entry| (Reverse(metadata(entry).1), name(entry)))
+        }
+        SortOrder::None => {}
+    }
+}
+
+/// Shortened names of the `names` longer than `max_len` bytes, keyed by the original name.
+/// Like DOS 8.3 names they end with `~N`, numbered in bytewise order of the originals so that
+/// they stay unique among the siblings and the same across listings.
+pub(crate) fn shorten_names<'a>(names: &[&'a str], max_len: usize) -> HashMap<&'a str, String> {
+    let mut taken = names
+        .iter()
+        .filter(|name| name.len() <= max_len)
+        .map(|name| name.to_string())
+        .collect::<HashSet<_>>();
+
+    let mut long = names
+        .iter()
+        .copied()
+        .filter(|name| name.len() > max_len)
+        .collect::<Vec<_>>();
+    long.sort_unstable();
+    long.dedup();
+
+    let mut shortened = HashMap::new();
+    for name in long {
+        let short = (1..)
+            .map(|n| {
+                let suffix = format!("~{}", n);
+                let mut end = max_len.saturating_sub(suffix.len());
+                while !name.is_char_boundary(end) {
+                    end -= 1;
+                }
+
+                format!("{}{}", &name[..end], suffix)
+            })
+            .find(|short| !taken.contains(short))
+            .unwrap();
+
+        taken.insert(short.clone());
+        shortened.insert(name, short);
+    }
+
+    shortened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shorten_names() {
+        let long_a = format!("{}a", "x".repeat(20));
+        let long_b = format!("{}b", "x".repeat(20));
+        let names = [long_b.as_str(), "short.txt", long_a.as_str(), "xxxxxxxx~1"];
+
+        let shortened = shorten_names(&names, 10);
+        assert_eq!(shortened.len(), 2);
+        assert_eq!(shortened[long_a.as_str()], "xxxxxxxx~2");
+        assert_eq!(shortened[long_b.as_str()], "xxxxxxxx~3");
+    }
+
+    #[test]
+    fn test_shorten_names_on_char_boundary() {
+        let name = "żółw".repeat(10);
+        let shortened = shorten_names(&[name.as_str()], 10);
+        assert_eq!(shortened[name.as_str()], "żółw~1");
+    }
+}
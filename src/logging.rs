@@ -0,0 +1,141 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::Result;
+use tracing::Level;
+use tracing_subscriber::{
+    fmt::{
+        self,
+        format::FmtSpan,
+        writer::{BoxMakeWriter, MakeWriter, MutexGuardWriter},
+    },
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+use zipfs::{LogFormat, Options};
+
+/// Log file appended to by every event, reopened on SIGHUP so that logrotate can move it away.
+///
+/// NOTE: Writes are not handed to a background worker, as a thread spawned before
+/// daemonizing would not survive the fork
+#[derive(Debug, Clone)]
+pub struct LogFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(Self::open_file(path)?)),
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        File::options().create(true).append(true).open(path)
+    }
+
+    /// Start writing to a fresh file at the original path
+    pub fn reopen(&self) -> io::Result<()> {
+        *self.file.lock().unwrap() = Self::open_file(&self.path)?;
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = MutexGuardWriter<'a, File>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        MakeWriter::make_writer(&*self.file)
+    }
+}
+
+/// Parts of the global subscriber that can be changed while running
+#[derive(Clone)]
+pub struct Logging {
+    log_file: Option<LogFile>,
+    filter: reload::Handle<EnvFilter, Registry>,
+}
+
+impl Logging {
+    /// Reopen the log file, if logging to one
+    pub fn reopen(&self) -> io::Result<()> {
+        match &self.log_file {
+            Some(log_file) => log_file.reopen(),
+            None => Ok(()),
+        }
+    }
+
+    /// Apply the verbosity of `options`, unless RUST_LOG is set
+    pub fn set_verbosity(&self, options: &Options) -> Result<()> {
+        if std::env::var_os(EnvFilter::DEFAULT_ENV).is_none() {
+            self.filter.reload(filter(options))?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the filter with `directives` in the syntax of RUST_LOG
+    pub fn set_filter(&self, directives: &str) -> Result<()> {
+        self.filter.reload(EnvFilter::try_new(directives)?)?;
+        Ok(())
+    }
+}
+
+/// Install the global subscriber, logging to `options.log_file` or stderr
+pub fn init(options: &Options) -> Result<Logging> {
+    let log_file = options.log_file.as_deref().map(LogFile::open).transpose()?;
+
+    let writer = match &log_file {
+        Some(log_file) => BoxMakeWriter::new(log_file.clone()),
+        None => BoxMakeWriter::new(io::stderr),
+    };
+
+    let layer = fmt::layer().with_writer(writer);
+    let layer = match options.log_format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        // NOTE: Closing spans adds the time spent in each operation
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_events(FmtSpan::CLOSE)
+            .boxed(),
+    };
+
+    let (filter, handle) = reload::Layer::new(filter(options));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .init();
+
+    Ok(Logging {
+        log_file,
+        filter: handle,
+    })
+}
+
+fn filter(options: &Options) -> EnvFilter {
+    // NOTE: RUST_LOG overrides the verbosity flags entirely
+    match std::env::var_os(EnvFilter::DEFAULT_ENV) {
+        Some(_) => EnvFilter::from_default_env(),
+        None => EnvFilter::new(format!("warn,zipfs={}", level(options.verbosity))),
+    }
+}
+
+fn level(verbosity: i8) -> Level {
+    match verbosity {
+        ..=-1 => Level::WARN,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    }
+}
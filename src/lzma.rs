@@ -0,0 +1,102 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+};
+
+use lzma_rs::decompress::{Options, Stream, UnpackedSize};
+use zip::read::ZipFile;
+
+/// Size of the LZMA properties WinZip stores in front of the compressed data
+const PROPERTIES_SIZE: u16 = 5;
+
+/// Decompresses WinZip LZMA entries (method 14), which the zip crate hands to the decoder along
+/// with the header WinZip puts in front of the compressed data
+pub struct LzmaReader<'a> {
+    entry: ZipFile<'a>,
+    stream: Option<Stream<VecDeque<u8>>>,
+    /// What is left once the compressed data has been read to the end
+    rest: VecDeque<u8>,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a> LzmaReader<'a> {
+    pub fn new(mut entry: ZipFile<'a>) -> io::Result<Self> {
+        // NOTE: The version of the LZMA SDK, then the size of the properties following it
+        let mut header = [0; 4];
+        entry.read_exact(&mut header)?;
+        if u16::from_le_bytes([header[2], header[3]]) != PROPERTIES_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid LZMA properties",
+            ));
+        }
+
+        let options = Options {
+            unpacked_size: UnpackedSize::UseProvided(Some(entry.size())),
+            ..Default::default()
+        };
+        Ok(Self {
+            entry,
+            stream: Some(Stream::new_with_options(&options, VecDeque::new())),
+            rest: VecDeque::new(),
+            hasher: crc32fast::Hasher::new(),
+        })
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+
+        let mut compressed = [0; 8192];
+        let len = self.entry.read(&mut compressed)?;
+        let mut written = 0;
+        while written < len {
+            match stream.write(&compressed[written..len])? {
+                // NOTE: Done once the size of the entry is reached, with an end marker left over
+                0 => break,
+                consumed => written += consumed,
+            }
+        }
+
+        if len > 0 && written == len {
+            return Ok(());
+        }
+
+        let stream = self.stream.take().unwrap();
+        self.rest = stream
+            .finish()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(())
+    }
+}
+
+impl Read for LzmaReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let output = match &mut self.stream {
+                Some(stream) => stream.get_output_mut().unwrap(),
+                None => &mut self.rest,
+            };
+
+            let len = output.read(buf)?;
+            if len > 0 {
+                self.hasher.update(&buf[..len]);
+                return Ok(len);
+            }
+
+            // NOTE: Checked like the zip crate does for the other methods, at the end of the entry
+            if self.stream.is_none() {
+                if !buf.is_empty() && self.hasher.clone().finalize() != self.entry.crc32() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid checksum",
+                    ));
+                }
+                return Ok(0);
+            }
+
+            self.fill()?;
+        }
+    }
+}
@@ -1,89 +1,1359 @@
-use std::num::NonZeroUsize;
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    num::NonZeroUsize,
+    ops::RangeInclusive,
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{fs::OpenOptionsExt, net::UnixStream},
+    },
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+    },
+    thread,
+    time::Duration,
+};
 
-use clap::Parser;
-use color_eyre::Result;
-use fuser::MountOption;
-use tracing::{debug, info};
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
-use zipfs::ZipFs;
+use clap::{CommandFactory, Parser};
+use color_eyre::{
+    eyre::{bail, ensure, eyre},
+    Result,
+};
+use fuser::{FileType, MountOption};
+use serde_json::json;
+use tracing::{debug, error, info, warn};
+use zipfs::{
+    verify_archives, verify_tree, CancellationToken, ControlHandle, FixedPassword, LogFormat,
+    MountHandle, MountMode, Options, PathFilter, SortOrder, ZipFs, ZipFsBuilder,
+};
 
-#[derive(Parser)]
+use crate::{logging::Logging, mount_options::MountOptions, signals::Signal};
+
+mod audit;
+mod fstab;
+mod logging;
+mod mount;
+mod mount_options;
+mod signals;
+#[cfg(feature = "systemd")]
+mod systemd;
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Exit status once unmounting cut open files short, as after an unhandled SIGINT
+const FORCED_SHUTDOWN_EXIT_CODE: i32 = 130;
+
+/// Data directory standing for a single archive piped to stdin
+const STDIN_DATA_DIR: &str = "-";
+
+/// At least a page, and at most the 32 pages the kernel sends without negotiating max_pages
+const MAX_READ_BOUNDS: RangeInclusive<u32> = 4 * 1024..=128 * 1024;
+const READAHEAD_KB_BOUNDS: RangeInclusive<u32> = 4..=16 * 1024;
+
+/// Bytes read at once by `zipfs cat`
+const CAT_CHUNK: usize = 128 * 1024;
+
+/// Shortened names keep a few bytes of the original besides their `~N` suffix
+const MIN_ENTRY_NAME_LENGTH: usize = 8;
+
+#[derive(Clone, Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+
+    /// Directory of archives, or `-` to serve a single archive read from stdin
     #[arg(index = 1)]
-    data_dir: std::path::PathBuf,
+    data_dir: Option<PathBuf>,
 
     #[arg(index = 2)]
-    mount_point: std::path::PathBuf,
+    mount_point: Option<PathBuf>,
+
+    #[arg(long = "data-dir", value_name = "DATA_DIR", global = true)]
+    data_dirs: Vec<PathBuf>,
+
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Archives kept open with their central directory read [default: 1024]
+    #[arg(short = 'c', long, value_name = "ARCHIVES", global = true)]
+    archive_cache_entries: Option<NonZeroUsize>,
+
+    /// Deprecated alias of --archive-cache-entries
+    #[arg(long, hide = true, global = true)]
+    cache_size: Option<NonZeroUsize>,
+
+    /// Bytes of decompressed entries kept for out of order reads, 0 to disable
+    /// [default: 67108864]
+    #[arg(long, value_name = "BYTES", global = true)]
+    content_cache_bytes: Option<u64>,
+
+    /// Deprecated alias of --content-cache-bytes
+    #[arg(long, hide = true, global = true)]
+    chunk_cache_size: Option<u64>,
+
+    /// Attributes of archive entries kept between lookups, 0 to disable [default: 0]
+    #[arg(long, value_name = "ENTRIES", global = true)]
+    attr_cache_entries: Option<usize>,
+
+    /// How long the kernel may remember that a name doesn't exist, e.g. `500ms` or `10s`,
+    /// 0 to look it up every time [default: 0]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    negative_cache_ttl: Option<u64>,
+
+    /// Names found missing inside archives remembered by zipfs itself, 0 to disable
+    /// [default: 256]
+    #[arg(long, value_name = "ENTRIES", global = true)]
+    negative_lookup_entries: Option<usize>,
+
+    /// How long zipfs remembers a name missing inside an archive, unless the archive changes
+    /// first [default: 1s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    negative_lookup_ttl: Option<u64>,
+
+    #[arg(short = 'o', long, global = true)]
+    mount_options: Option<String>,
+
+    #[arg(long, value_enum, global = true)]
+    mount_mode: Option<MountMode>,
+
+    /// Filesystem type listed as fuse.<FSTYPE> in /proc/mounts, ignored on macOS [default: zipfs]
+    #[arg(long, global = true)]
+    fstype: Option<String>,
+
+    #[arg(long, value_name = "MILLISECONDS", global = true)]
+    open_timeout: Option<u64>,
+
+    #[arg(long, global = true)]
+    writable_archives: bool,
+
+    #[arg(long, value_name = "BYTES", global = true)]
+    max_staging_size: Option<u64>,
+
+    #[arg(long, value_name = "BYTES", global = true)]
+    max_read: Option<u32>,
+
+    #[arg(long, value_name = "KB", global = true)]
+    readahead_kb: Option<u32>,
+
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Read stored entries straight from their archive and keep them in the page cache
+    #[arg(long, global = true)]
+    direct_stored: bool,
+
+    /// Refuse entries claiming to decompress to more than this many times their size
+    /// [default: 1000]
+    #[arg(long, value_name = "RATIO", global = true)]
+    max_ratio: Option<u64>,
+
+    /// Serve entries whatever their compression ratio
+    #[arg(long, global = true)]
+    allow_bombs: bool,
+
+    /// Refuse to open entries with EMFILE while entries of this many other archives are open
+    #[arg(long, value_name = "COUNT", global = true)]
+    max_open_archives: Option<usize>,
+
+    /// Refuse with ENOMEM to open archives or decompress entries once the caches would take
+    /// more than this
+    #[arg(long, value_name = "BYTES", global = true)]
+    max_memory: Option<u64>,
+
+    #[arg(long, global = true)]
+    verify_on_mount: bool,
+
+    #[arg(long, global = true)]
+    chroot_into_zip: bool,
+
+    /// Serve everything below a single directory of this name in the root of the mount
+    #[arg(long, value_name = "NAME", global = true)]
+    virtual_root: Option<String>,
+
+    #[arg(long, global = true)]
+    manifest: bool,
+
+    #[arg(long, global = true)]
+    expose_metadata: bool,
+
+    #[arg(long, global = true)]
+    prefetch: bool,
+
+    #[arg(long, value_name = "THREADS", global = true)]
+    warm_concurrency: Option<usize>,
+
+    /// TOML file mapping archive paths, relative to the data directory, to their password
+    #[arg(
+        long,
+        visible_alias = "password-file",
+        value_name = "FILE",
+        global = true
+    )]
+    passwords: Option<PathBuf>,
 
-    #[arg(short, long, default_value_t = NonZeroUsize::new(1024).unwrap())]
-    cache_size: NonZeroUsize,
+    /// Password of the encrypted archives missing from the passwords file
+    #[arg(long, value_name = "PASSWORD", global = true)]
+    password: Option<String>,
 
-    #[arg(short = 'o', long, default_value_t = String::from("ro"))]
-    mount_options: String,
+    /// Bytes per second served across all reads of the mount rather than per file, only
+    /// approximate over short periods
+    #[arg(long, value_name = "BYTES_PER_SECOND", global = true)]
+    rate_limit: Option<u64>,
+
+    /// File keeping the generation of the last mount, for re-exporting over NFS
+    #[arg(long, value_name = "FILE", global = true)]
+    generation_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "GLOB", global = true)]
+    include: Vec<String>,
+
+    #[arg(long, value_name = "GLOB", global = true)]
+    exclude: Vec<String>,
+
+    #[arg(long = "exclude-pattern", value_name = "GLOB", global = true)]
+    exclude_patterns: Vec<String>,
+
+    #[arg(long, value_enum, global = true)]
+    sort: Option<SortOrder>,
+
+    #[arg(long, value_name = "BYTES", global = true)]
+    max_entry_name_length: Option<usize>,
+
+    #[arg(long, global = true)]
+    strip_ext: bool,
+
+    #[arg(long, global = true)]
+    dual_view: bool,
+
+    #[arg(long, value_name = "SUFFIX", global = true)]
+    dual_view_suffix: Option<String>,
+
+    /// Serve archives as regular files, without looking into any of them
+    #[arg(long, conflicts_with = "chroot_into_zip", global = true)]
+    passthrough_only: bool,
+
+    #[arg(long = "archive-ext", value_name = "EXT", global = true)]
+    archive_exts: Vec<String>,
+
+    #[arg(long, value_name = "OCTAL", value_parser = parse_umask, global = true)]
+    umask: Option<u16>,
+
+    #[arg(short, long, global = true)]
+    foreground: bool,
+
+    #[arg(long, global = true)]
+    pidfile: Option<PathBuf>,
+
+    #[arg(long, visible_alias = "control-socket", global = true)]
+    socket: Option<PathBuf>,
+
+    #[arg(long, value_name = "MILLISECONDS", global = true)]
+    mount_check_interval: Option<u64>,
+
+    #[arg(long, value_name = "FD", global = true)]
+    ready_fd: Option<i32>,
+
+    #[arg(long, value_name = "MILLISECONDS", global = true)]
+    mount_timeout: Option<u64>,
+
+    #[arg(long, value_name = "MILLISECONDS", global = true)]
+    shutdown_timeout: Option<u64>,
+
+    /// Write the tree the mount would present to this directory instead of mounting
+    #[arg(long, value_name = "DEST", conflicts_with = "mount_point")]
+    extract: Option<PathBuf>,
+
+    /// Unmount and exit after this long without operations or open files, e.g. `90s` or `10m`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    idle_timeout: Option<u64>,
+
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    #[arg(short, long, conflicts_with = "verbose", global = true)]
+    quiet: bool,
+
+    #[arg(long, value_enum, global = true)]
+    log_format: Option<LogFormat>,
+
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Append a JSON line for every read served to this file
+    #[arg(long, value_name = "PATH", global = true)]
+    audit_log: Option<PathBuf>,
+}
+
+#[derive(Clone, clap::Subcommand)]
+enum Subcommand {
+    /// Validate the configuration and print the effective options
+    CheckConfig,
+    /// Send a command to a running mount through its control socket
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Check every archive below a directory, decompressing each entry to compare its CRC32
+    Verify {
+        #[arg(value_name = "DATA_DIR")]
+        dir: PathBuf,
+
+        /// Archives verified at once, one per CPU by default
+        #[arg(long)]
+        jobs: Option<NonZeroUsize>,
+
+        /// Only read the central directories, without decompressing anything
+        #[arg(long)]
+        quick: bool,
+    },
+    /// Open an archive the way the mount would and describe what it contains
+    Inspect {
+        archive: PathBuf,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Number of entries to list
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Print a file the way the mount would present it, e.g. data/photos.zip/2024/cat.jpg
+    Cat { path: PathBuf },
+    /// List a directory the way the mount would present it, marking directories with a `/`
+    Ls { path: PathBuf },
+}
+
+#[derive(Clone, clap::Subcommand)]
+enum CtlCommand {
+    /// Print the statistics of the mount
+    Stats,
+    /// Drop every cached archive
+    Flush,
+    /// Drop a single cached archive
+    Evict {
+        /// Relative to the data directory
+        path: PathBuf,
+    },
+    /// Replace the log filter, in the syntax of RUST_LOG
+    Loglevel { filter: String },
+}
+
+impl CtlCommand {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            CtlCommand::Stats => json!({ "cmd": "stats" }),
+            CtlCommand::Flush => json!({ "cmd": "flush" }),
+            CtlCommand::Evict { path } => json!({ "cmd": "evict", "path": path }),
+            CtlCommand::Loglevel { filter } => json!({ "cmd": "loglevel", "filter": filter }),
+        }
+    }
+}
+
+impl Args {
+    /// Merge the config file with the command line and check the result can be mounted
+    fn into_options(self) -> Result<Options> {
+        let options = self.merge_options()?;
+
+        ensure!(
+            !options.data_dir.as_os_str().is_empty(),
+            "No data directory given"
+        );
+        ensure!(
+            !options.mount_point.as_os_str().is_empty(),
+            "No mount point given"
+        );
+
+        ensure!(
+            !options.dual_view
+                || !(options.dual_view_suffix.is_empty() || options.dual_view_suffix.contains('/')),
+            "--dual-view-suffix must be a non-empty file name suffix"
+        );
+        ensure!(
+            options.max_entry_name_length >= MIN_ENTRY_NAME_LENGTH,
+            "--max-entry-name-length must leave room for the ~N suffix, at least {}",
+            MIN_ENTRY_NAME_LENGTH
+        );
+
+        ensure!(
+            options
+                .max_read
+                .is_none_or(|max_read| MAX_READ_BOUNDS.contains(&max_read)),
+            "--max-read must be between {} and {} bytes",
+            MAX_READ_BOUNDS.start(),
+            MAX_READ_BOUNDS.end()
+        );
+        ensure!(options.max_ratio >= 1, "--max-ratio must be at least 1");
+        ensure!(
+            options.max_open_archives != Some(0),
+            "--max-open-archives must be at least 1"
+        );
+        ensure!(
+            READAHEAD_KB_BOUNDS.contains(&options.readahead_kb),
+            "--readahead-kb must be between {} and {}",
+            READAHEAD_KB_BOUNDS.start(),
+            READAHEAD_KB_BOUNDS.end()
+        );
+
+        let mount_options =
+            MountOptions::parse(&options.mount_options).map_err(|err| eyre!(err))?;
+        ensure!(
+            !mount_options.read_write || options.writable_archives,
+            "-o rw needs --writable-archives, the file system is read-only otherwise"
+        );
+        ensure!(
+            !mount_options.read_only || !options.writable_archives,
+            "-o ro conflicts with --writable-archives"
+        );
+        ensure!(
+            !options.fstype.is_empty()
+                && !options
+                    .fstype
+                    .contains(|c: char| c == ',' || c.is_whitespace()),
+            "--fstype must be a non-empty name without commas or spaces"
+        );
+        ensure!(
+            !mount_options.has_subtype() || options.fstype == Options::default().fstype,
+            "-o subtype conflicts with --fstype"
+        );
+
+        PathFilter::from_options(&options)?;
+
+        Ok(options)
+    }
+
+    /// Merge the config file with the command line, flags take precedence
+    fn merge_options(self) -> Result<Options> {
+        let mut options = match &self.config {
+            Some(config) => Options::from_file(config)?,
+            None => Options::default(),
+        };
+
+        if let Some(data_dir) = self.data_dir {
+            options.data_dir = data_dir;
+        }
+
+        options.data_dirs.extend(self.data_dirs);
+        options.include.extend(self.include);
+        options.exclude.extend(self.exclude);
+        options.exclude_patterns.extend(self.exclude_patterns);
+
+        if let Some(sort) = self.sort {
+            options.sort = sort;
+        }
+
+        if let Some(concurrency) = self.warm_concurrency {
+            options.warm_concurrency = concurrency;
+        }
+
+        if let Some(rate_limit) = self.rate_limit {
+            options.rate_limit = Some(rate_limit);
+        }
+
+        if let Some(virtual_root) = self.virtual_root {
+            options.virtual_root = Some(virtual_root);
+        }
+
+        if let Some(generation_file) = self.generation_file {
+            options.generation_file = Some(generation_file);
+        }
+
+        if let Some(passwords) = self.passwords {
+            options.passwords = Some(passwords);
+        }
+
+        if let Some(suffix) = self.dual_view_suffix {
+            options.dual_view_suffix = suffix;
+        }
+
+        if let Some(len) = self.max_entry_name_length {
+            options.max_entry_name_length = len;
+        }
+
+        if let Some(mount_point) = self.mount_point {
+            options.mount_point = mount_point;
+        }
+
+        if let Some(cache_size) = self.cache_size {
+            eprintln!("Warning: --cache-size is deprecated, use --archive-cache-entries");
+            options.archive_cache_entries = cache_size;
+        }
+
+        if let Some(entries) = self.archive_cache_entries {
+            options.archive_cache_entries = entries;
+        }
+
+        if let Some(mount_options) = self.mount_options {
+            options.mount_options = mount_options;
+        }
+
+        if let Some(open_timeout) = self.open_timeout {
+            options.open_timeout = Some(open_timeout);
+        }
+
+        if let Some(max_staging_size) = self.max_staging_size {
+            options.max_staging_size = max_staging_size;
+        }
+
+        if let Some(chunk_cache_size) = self.chunk_cache_size {
+            eprintln!("Warning: --chunk-cache-size is deprecated, use --content-cache-bytes");
+            options.content_cache_bytes = chunk_cache_size;
+        }
+
+        if let Some(bytes) = self.content_cache_bytes {
+            options.content_cache_bytes = bytes;
+        }
+
+        if let Some(entries) = self.attr_cache_entries {
+            options.attr_cache_entries = entries;
+        }
+
+        if let Some(ttl) = self.negative_cache_ttl {
+            options.negative_cache_ttl = ttl;
+        }
+
+        if let Some(entries) = self.negative_lookup_entries {
+            options.negative_lookup_entries = entries;
+        }
+
+        if let Some(ttl) = self.negative_lookup_ttl {
+            options.negative_lookup_ttl = ttl;
+        }
+
+        if let Some(max_read) = self.max_read {
+            options.max_read = Some(max_read);
+        }
+
+        if let Some(max_ratio) = self.max_ratio {
+            options.max_ratio = max_ratio;
+        }
+
+        if let Some(max_open_archives) = self.max_open_archives {
+            options.max_open_archives = Some(max_open_archives);
+        }
+
+        if let Some(max_memory) = self.max_memory {
+            options.max_memory = Some(max_memory);
+        }
+
+        if let Some(readahead_kb) = self.readahead_kb {
+            options.readahead_kb = readahead_kb;
+        }
+
+        if !self.archive_exts.is_empty() {
+            options.archive_exts = self
+                .archive_exts
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_string())
+                .collect();
+        }
+
+        if let Some(umask) = self.umask {
+            options.umask = umask;
+        }
+
+        if let Some(pidfile) = self.pidfile {
+            options.pidfile = Some(pidfile);
+        }
+
+        if let Some(socket) = self.socket {
+            options.socket = Some(socket);
+        }
+
+        if let Some(interval) = self.mount_check_interval {
+            options.mount_check_interval = interval;
+        }
+
+        if let Some(ready_fd) = self.ready_fd {
+            options.ready_fd = Some(ready_fd);
+        }
+
+        if let Some(mount_timeout) = self.mount_timeout {
+            options.mount_timeout = Some(mount_timeout);
+        }
+
+        if let Some(shutdown_timeout) = self.shutdown_timeout {
+            options.shutdown_timeout = shutdown_timeout;
+        }
+
+        if let Some(idle_timeout) = self.idle_timeout {
+            options.idle_timeout = Some(idle_timeout);
+        }
+
+        if self.verbose > 0 {
+            options.verbosity = self.verbose.min(i8::MAX as u8) as i8;
+        }
+
+        if self.quiet {
+            options.verbosity = -1;
+        }
+
+        if let Some(mount_mode) = self.mount_mode {
+            options.mount_mode = mount_mode;
+        }
+
+        if let Some(fstype) = self.fstype {
+            options.fstype = fstype;
+        }
+
+        if let Some(log_format) = self.log_format {
+            options.log_format = log_format;
+        }
+
+        if let Some(log_file) = self.log_file {
+            options.log_file = Some(log_file);
+        }
+
+        if let Some(audit_log) = self.audit_log {
+            options.audit_log = Some(audit_log);
+        }
+
+        options.writable_archives |= self.writable_archives;
+        options.strict |= self.strict;
+        options.direct_stored |= self.direct_stored;
+        options.allow_bombs |= self.allow_bombs;
+        options.verify_on_mount |= self.verify_on_mount;
+        options.chroot_into_zip |= self.chroot_into_zip;
+        options.manifest |= self.manifest;
+        options.expose_metadata |= self.expose_metadata;
+        options.prefetch |= self.prefetch;
+        options.foreground |= self.foreground;
+        options.strip_ext |= self.strip_ext;
+        options.dual_view |= self.dual_view;
+        options.passthrough_only |= self.passthrough_only;
+
+        // NOTE: Our own mount options stay in the string, FUSE only gets the ones it knows
+        let mount_options =
+            MountOptions::parse(&options.mount_options).map_err(|err| eyre!(err))?;
+        options.strip_ext |= mount_options.strip_ext;
+        options.dual_view |= mount_options.dual_view;
+        options.umask = mount_options.umask.unwrap_or(options.umask);
+        options.uid = mount_options.uid.or(options.uid);
+        options.gid = mount_options.gid.or(options.gid);
+
+        Ok(options)
+    }
+}
+
+/// Milliseconds in a duration such as `500ms`, `90s`, `10m` or `1h`, seconds without a unit
+fn parse_duration(duration: &str) -> Result<u64, String> {
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+
+    let millis = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => return Err(format!("invalid duration unit: {}", duration)),
+    };
+
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(millis))
+        .ok_or_else(|| format!("invalid duration: {}", duration))
+}
+
+fn parse_umask(umask: &str) -> Result<u16, String> {
+    let digits = umask.strip_prefix("0o").unwrap_or(umask);
+
+    match u16::from_str_radix(digits, 8) {
+        Ok(umask) if umask <= 0o7777 => Ok(umask),
+        _ => Err(format!("invalid octal umask: {}", umask)),
+    }
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let args = Args::parse();
+    if !fstab::is_fstab_invocation() {
+        return dispatch(Args::parse());
+    }
+
+    let result = fstab::translate_args(std::env::args(), &Args::command())
+        .map_err(|err| eyre!(err))
+        .and_then(|args| dispatch(Args::parse_from(args)));
+    if let Err(err) = result {
+        // NOTE: mount(8) expects a single terse line and exit status 32 on mount failure
+        eprintln!("mount.zipfs: {}", err);
+        std::process::exit(32);
+    }
+
+    Ok(())
+}
+
+fn dispatch(mut args: Args) -> Result<()> {
+    match args.command.take() {
+        // NOTE: Talking to a running mount or inspecting an archive doesn't need a data
+        // directory or mount point
+        Some(Subcommand::Ctl { command }) => match &args.socket {
+            Some(socket) => ctl(socket, &command),
+            None => bail!("No control socket given"),
+        },
+        Some(Subcommand::Inspect {
+            archive,
+            json,
+            limit,
+        }) => inspect(&archive, &args.merge_options()?, json, limit),
+        Some(Subcommand::Verify { dir, jobs, quick }) => {
+            verify(&dir, &args.merge_options()?, jobs, quick)
+        }
+        Some(Subcommand::Cat { path }) => cat(&path, args),
+        Some(Subcommand::Ls { path }) => ls(&path, args),
+        Some(Subcommand::CheckConfig) => {
+            let options = args.into_options()?;
+            print!("{}", toml::to_string_pretty(&options)?);
+            Ok(())
+        }
+        None => match args.extract.take() {
+            Some(dest) => extract(&dest, args),
+            None => run(args.clone().into_options()?, args),
+        },
+    }
+}
+
+/// Send `command` to the control socket at `socket` and print the response
+fn ctl(socket: &Path, command: &CtlCommand) -> Result<()> {
+    let stream = UnixStream::connect(socket)?;
+    writeln!(&stream, "{}", command.to_json())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = serde_json::from_str::<serde_json::Value>(&line)?;
+    println!("{}", serde_json::to_string_pretty(&response)?);
+
+    if response["ok"] != true {
+        bail!("{}", response["error"].as_str().unwrap_or("command failed"));
+    }
+
+    Ok(())
+}
+
+/// Extract the data directory to `dest` the way the mount would present it, failing if any
+/// file couldn't be read
+fn extract(dest: &Path, args: Args) -> Result<()> {
+    let password = args.password.clone();
+    let options = args.merge_options()?;
+    ensure!(
+        !options.data_dir.as_os_str().is_empty(),
+        "No data directory given"
+    );
+
+    let mut builder = ZipFsBuilder::from_options(options);
+    if let Some(password) = password {
+        builder = builder.password_provider(Box::new(FixedPassword(password.into())));
+    }
+
+    let report = builder.extract(dest)?;
+    for (path, error) in &report.failed {
+        println!("FAILED {}: {}", path.display(), error);
+    }
+
+    println!(
+        "Extracted {} files and {} directories",
+        report.files, report.directories
+    );
+    ensure!(
+        report.failed.is_empty(),
+        "{} files failed to extract",
+        report.failed.len()
+    );
+
+    Ok(())
+}
+
+/// File system over the closest real directory holding `path`, along with the path left to
+/// resolve inside it
+fn resolve(path: &Path, args: Args) -> Result<(ZipFs, PathBuf)> {
+    let data_dir = path
+        .ancestors()
+        .find(|dir| dir.as_os_str().is_empty() || dir.is_dir())
+        .unwrap_or(Path::new(""));
+    let inner = path.strip_prefix(data_dir)?.to_path_buf();
+
+    let password = args.password.clone();
+    let mut options = args.merge_options()?;
+    options.data_dir = match data_dir.as_os_str().is_empty() {
+        true => PathBuf::from("."),
+        false => data_dir.to_path_buf(),
+    };
+
+    let mut builder = ZipFsBuilder::from_options(options);
+    if let Some(password) = password {
+        builder = builder.password_provider(Box::new(FixedPassword(password.into())));
+    }
+
+    Ok((builder.build()?, inner))
+}
+
+/// Write the file at `path` to stdout without mounting
+fn cat(path: &Path, args: Args) -> Result<()> {
+    let (mut fs, path) = resolve(path, args)?;
+    let mut stdout = io::stdout().lock();
+
+    let mut offset = 0;
+    loop {
+        let data = fs.read_path(&path, offset, CAT_CHUNK)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        offset += data.len() as u64;
+        stdout.write_all(&data)?;
+    }
+}
+
+/// Print the entries of the directory at `path` without mounting
+fn ls(path: &Path, args: Args) -> Result<()> {
+    let (mut fs, path) = resolve(path, args)?;
+
+    let mut entries = fs.list_path(&path)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for entry in entries {
+        let suffix = match entry.kind {
+            FileType::Directory => "/",
+            _ => "",
+        };
+        println!("{}{}", entry.name.to_string_lossy(), suffix);
+    }
+
+    Ok(())
+}
+
+/// Verify the archives below `dir`, failing if any of them is damaged
+fn verify(dir: &Path, options: &Options, jobs: Option<NonZeroUsize>, quick: bool) -> Result<()> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.map_or(0, NonZeroUsize::get))
+        .build()?;
+    let reports = pool.install(|| verify_tree(&[dir.to_path_buf()], &options.archive_exts, quick));
+
+    for report in &reports {
+        let path = report.path.display();
+        if let Some(error) = &report.error {
+            println!("FAILED {}: {}", path, error);
+            continue;
+        }
+
+        let status = match report.is_ok() {
+            true => "ok",
+            false => "FAILED",
+        };
+        println!(
+            "{} {}: {} entries, {} checked",
+            status, path, report.entries, report.checked
+        );
+
+        for (name, reason) in &report.corrupt {
+            println!("  corrupt {}: {}", name, reason);
+        }
+
+        for name in &report.unsupported {
+            println!("  unsupported compression method: {}", name);
+        }
+
+        for name in &report.encrypted {
+            println!("  encrypted, no password: {}", name);
+        }
+    }
+
+    let failed = reports.iter().filter(|report| !report.is_ok()).count();
+    ensure!(
+        failed == 0,
+        "{} of {} archives failed verification",
+        failed,
+        reports.len()
+    );
+
+    Ok(())
+}
+
+/// Print what the mount would make of `path`
+fn inspect(path: &Path, options: &Options, json: bool, limit: usize) -> Result<()> {
+    let inspection = zipfs::inspect(path, options, limit)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&inspection)?);
+        return Ok(());
+    }
+
+    println!("Format: {}", inspection.format);
+    println!("Presented as: {:?}", inspection.presented_as);
+    if let Some(error) = &inspection.error {
+        println!("Error: {}", error);
+    }
+
+    println!("Entries: {}", inspection.entry_count);
+
+    // NOTE: Archives don't need to list directories, they are printed as soon as they appear
+    let mut dirs: Vec<&str> = Vec::new();
+    for entry in &inspection.entries {
+        let mut components: Vec<&str> = entry.name.trim_end_matches('/').split('/').collect();
+        let name = if entry.is_dir { None } else { components.pop() };
 
-    let filter = EnvFilter::builder()
-        .with_default_directive("zipfs=info".parse()?)
-        .from_env_lossy();
+        let common = dirs.iter().zip(&components).take_while(|(a, b)| a == b);
+        let common = common.count();
+        dirs.truncate(common);
+
+        for &component in &components[common..] {
+            println!("  {}{}/", "  ".repeat(dirs.len()), component);
+            dirs.push(component);
+        }
+
+        let Some(name) = name else {
+            continue;
+        };
 
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(filter)
-        .init();
+        println!(
+            "  {}{} ({} bytes, {} compressed, {}{})",
+            "  ".repeat(dirs.len()),
+            name,
+            entry.size,
+            entry.compressed_size,
+            entry.method,
+            if entry.encrypted { ", encrypted" } else { "" }
+        );
+    }
+
+    if inspection.entries.len() < inspection.entry_count {
+        let remaining = inspection.entry_count - inspection.entries.len();
+        println!("  ... {} more", remaining);
+    }
+
+    for warning in &inspection.warnings {
+        println!("Warning: {}", warning);
+    }
+
+    Ok(())
+}
+
+/// Re-read the config file and apply the options that can change while mounted
+fn reload(args: &Args, logging: &Logging, control: &ControlHandle) -> Result<()> {
+    let options = args.clone().into_options()?;
+
+    let data_dir = &options.data_dir;
+    if let Err(err) = fs::metadata(data_dir) {
+        warn!("Data directory {:?} is unavailable: {}", data_dir, err);
+    }
+
+    logging.set_verbosity(&options)?;
+    control.set_filter(PathFilter::from_options(&options)?);
+    let evicted = control.reload();
+
+    info!(
+        "Reloaded configuration, dropped {} cached archives along with open entry readers",
+        evicted
+    );
+    Ok(())
+}
+
+fn run(mut options: Options, mut args: Args) -> Result<()> {
+    // NOTE: Declared first so that the spooled archive outlives the session
+    let stdin = match options.data_dir == Path::new(STDIN_DATA_DIR) {
+        true => Some(SpooledStdin::new()?),
+        false => None,
+    };
+    if let Some(stdin) = &stdin {
+        options.data_dir = stdin.path.clone();
+        options.chroot_into_zip = true;
+        args.chroot_into_zip = true;
+    }
+
+    // NOTE: The daemon changes its working directory to /
+    options.data_dir = options.data_dir.canonicalize()?;
+    for data_dir in &mut options.data_dirs {
+        *data_dir = data_dir.canonicalize()?;
+    }
+    // NOTE: A mount left behind by a crash can't even be canonicalized
+    let recovered = recover_stale_mount(&options.mount_point)?;
+    options.mount_point = options.mount_point.canonicalize()?;
+    ensure!(
+        !options.chroot_into_zip || options.data_dir.is_file(),
+        "--chroot-into-zip expects an archive in place of the data directory"
+    );
+    if let Some(log_file) = &mut options.log_file {
+        *log_file = std::env::current_dir()?.join(&log_file);
+    }
+
+    // NOTE: Reloads merge these arguments with the config file again, after daemonizing to /
+    args.data_dir = Some(options.data_dir.clone());
+    if let Some(config) = &mut args.config {
+        *config = config.canonicalize()?;
+    }
+
+    let logging = logging::init(&options)?;
 
     let (tx, rx) = std::sync::mpsc::channel();
 
+    if recovered {
+        warn!(
+            "Unmounted the stale mount left at {:?}",
+            options.mount_point
+        );
+    }
+
     info!("Mounting ZIP file system");
-    info!("Data directory: {:?}", args.data_dir);
-    info!("Mount point: {:?}", args.mount_point);
-    info!("Cache size: {}", args.cache_size);
-    let guard = fuser::spawn_mount2(
-        ZipFs::new(args.data_dir, args.cache_size, Some(tx.clone())),
-        args.mount_point,
-        &get_options(args.mount_options),
-    )?;
-
-    ctrlc::set_handler(move || {
-        debug!("Received signal to unmount");
-        tx.send(()).unwrap();
+    info!("Data directory: {:?}", options.data_dir);
+    for data_dir in &options.data_dirs {
+        info!("Data directory: {:?}", data_dir);
+    }
+    info!("Mount point: {:?}", options.mount_point);
+    info!("Archive cache entries: {}", options.archive_cache_entries);
+
+    if let Some(timeout) = options.idle_timeout {
+        info!("Idle timeout: {:?}", Duration::from_millis(timeout));
+    }
+
+    if let Some(timeout) = options.open_timeout {
+        info!("Open timeout: {:?}", Duration::from_millis(timeout));
+    }
+
+    if options.writable_archives {
+        info!("Max staging size: {}", options.max_staging_size);
+    }
+
+    if options.passthrough_only {
+        warn!("Passthrough only, archives are served as regular files");
+    }
+
+    if options.verify_on_mount && !options.passthrough_only {
+        let mut sources = vec![options.data_dir.clone()];
+        sources.extend(options.data_dirs.iter().cloned());
+
+        let failed = verify_archives(&sources, &options.archive_exts)?;
+        ensure!(
+            !options.strict || failed.is_empty(),
+            "{} archives failed verification",
+            failed.len()
+        );
+    }
+
+    let mut builder = ZipFsBuilder::from_options(options.clone()).notify_unmount(tx.clone());
+    if let Some(password) = args.password.take() {
+        builder = builder.password_provider(Box::new(FixedPassword(password.into())));
+    }
+
+    let audit_writer = match &options.audit_log {
+        Some(path) => {
+            info!("Audit log: {:?}", path);
+            let (audit_log, writer) = audit::open(path)?;
+            builder = builder.event_listener(audit_log);
+            Some(writer)
+        }
+        None => None,
+    };
+
+    let fs = builder.build()?;
+    let prefetch = fs.prefetch();
+    let control = fs.control_handle().with_log_filter({
+        let logging = logging.clone();
+        move |directives| {
+            logging
+                .set_filter(directives)
+                .map_err(|err| err.to_string())
+        }
+    });
+    let mut mount_options = MountOptions::parse(&options.mount_options)
+        .map_err(|err| eyre!(err))?
+        .to_fuse(!options.writable_archives, &options.fstype);
+    if let Some(max_read) = options.max_read {
+        mount_options.push(MountOption::CUSTOM(format!("max_read={}", max_read)));
+    }
+
+    // NOTE: Mount before forking so that mount errors are reported to the caller
+    let session = mount::mount(fs, &options.mount_point, &mount_options, options.mount_mode)?;
+    control.set_notifier(session.notifier());
+
+    #[cfg(feature = "systemd")]
+    let watchdog = systemd::watchdog_interval();
+
+    if !options.foreground {
+        daemonize()?;
+    }
+
+    if let Some(pidfile) = &options.pidfile {
+        fs::write(pidfile, format!("{}\n", std::process::id()))?;
+    }
+
+    if let Some(socket) = &options.socket {
+        control.clone().listen(socket)?;
+    }
+
+    let audit_writer = audit_writer.map(audit::AuditWriter::spawn).transpose()?;
+    let handle = MountHandle::spawn(session, &options.mount_point, control.clone())?;
+
+    let prefetch_token = CancellationToken::default();
+    let prefetch = (options.prefetch && !options.passthrough_only)
+        .then(|| prefetch.spawn(prefetch_token.clone()));
+
+    // NOTE: Install the handlers before reporting readiness, callers may signal right after
+    // NOTE: A second signal stops waiting for the mount to drain
+    let interrupted = AtomicBool::new(false);
+    ctrlc::set_handler({
+        let control = control.clone();
+        move || match interrupted.swap(true, Ordering::Relaxed) {
+            false => {
+                debug!("Received signal to unmount");
+                let _ = tx.send(());
+            }
+            true => {
+                warn!("Received another signal, unmounting right away");
+                control.force_shutdown();
+            }
+        }
     })?;
 
-    // NOTE: Drop the guard only after we have received a signal
-    rx.recv()?;
-    drop(guard);
+    Signal::Hangup.install();
+    Signal::User1.install();
+    Signal::User2.install();
+
+    if options.ready_fd.is_some() || options.mount_timeout.is_some() {
+        let timeout = options.mount_timeout.map(Duration::from_millis);
+        wait_until_ready(&options.mount_point, timeout)?;
+        info!("Mount is ready");
+    }
+
+    if let Some(fd) = options.ready_fd {
+        // SAFETY: The descriptor was handed to us for this purpose and is not used elsewhere
+        let mut ready = unsafe { File::from_raw_fd(fd) };
+        ready.write_all(b"ready\n")?;
+    }
+
+    #[cfg(feature = "systemd")]
+    systemd::notify_ready()?;
+
+    // NOTE: Unmount only after we have received a signal or the mount is gone
+    let interval = Duration::from_millis(options.mount_check_interval);
+
+    // NOTE: Ping twice per watchdog period, as systemd recommends
+    #[cfg(feature = "systemd")]
+    let interval = watchdog.map_or(interval, |watchdog| interval.min(watchdog / 2));
+
+    let idle_timeout = options.idle_timeout.map(Duration::from_millis);
+
+    let unmounted = loop {
+        match rx.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) if !is_mounted(&options.mount_point)? => {
+                info!("Mount point was externally unmounted");
+                break true;
+            }
+            Err(RecvTimeoutError::Timeout) if Signal::Hangup.take() => {
+                logging.reopen()?;
+
+                if let Err(err) = reload(&args, &logging, &control) {
+                    error!("Error reloading configuration: {:?}", err);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if Signal::User1.take() => {
+                info!(stats = %control.snapshot(), "Runtime statistics");
+            }
+            Err(RecvTimeoutError::Timeout) if Signal::User2.take() => {
+                info!("Resetting statistics");
+                control.reset_stats();
+            }
+            Err(RecvTimeoutError::Timeout)
+                if idle_timeout
+                    .is_some_and(|timeout| control.idle().is_some_and(|idle| idle >= timeout)) =>
+            {
+                info!("Mount is idle, unmounting");
+                break false;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(_) | Err(RecvTimeoutError::Disconnected) => break false,
+        }
+
+        // NOTE: Only reached once the mount point responded, a hung session stops the pings
+        #[cfg(feature = "systemd")]
+        if watchdog.is_some() {
+            systemd::notify("WATCHDOG=1")?;
+        }
+    };
+
+    prefetch_token.cancel();
+
+    #[cfg(feature = "systemd")]
+    systemd::notify("STOPPING=1")?;
+
+    let mut forced = false;
+    if !unmounted {
+        let timeout = Duration::from_millis(options.shutdown_timeout);
+        info!("Waiting up to {:?} for open files to be released", timeout);
+
+        match control.shutdown(timeout) {
+            _ if control.is_forced() => forced = true,
+            0 => debug!("All files were released"),
+            open => {
+                warn!("Unmounting with {} files still open", open);
+                forced = true;
+            }
+        }
+    }
+
+    // NOTE: The prefetch stops before its next archive, an archive being opened is finished
+    if let Some(prefetch) = prefetch.filter(|_| !forced) {
+        let _ = prefetch.join();
+    }
+
+    info!(stats = %control.snapshot(), "Final statistics");
+    // NOTE: Operations stuck in the file system would keep the session from ending
+    match forced {
+        true => drop(handle),
+        false => handle.unmount()?,
+    }
     info!("Successfully unmounted");
 
+    // NOTE: The writer stops once the file system, and with it the audit log, is dropped
+    if let Some(audit_writer) = audit_writer.filter(|_| !forced) {
+        let _ = audit_writer.join();
+    }
+
+    if let Some(pidfile) = &options.pidfile {
+        fs::remove_file(pidfile)?;
+    }
+
+    if let Some(socket) = &options.socket {
+        fs::remove_file(socket)?;
+    }
+
+    if forced {
+        drop(stdin);
+        std::process::exit(FORCED_SHUTDOWN_EXIT_CODE);
+    }
+
     Ok(())
 }
 
-fn get_options(mount_options: String) -> Vec<MountOption> {
-    let mut options = vec![MountOption::RO, MountOption::FSName("zipfs".to_string())];
-
-    for opt in mount_options.split(',') {
-        let opt = match opt {
-            "default_permissions" => MountOption::DefaultPermissions,
-            "allow_other" => MountOption::AllowOther,
-            "allow_root" => MountOption::AllowRoot,
-            "auto_unmount" => MountOption::AutoUnmount,
-            "dev" => MountOption::Dev,
-            "nodev" => MountOption::NoDev,
-            "suid" => MountOption::Suid,
-            "nosuid" => MountOption::NoSuid,
-            "atime" => MountOption::Atime,
-            "noatime" => MountOption::NoAtime,
-            "exec" => MountOption::Exec,
-            "noexec" => MountOption::NoExec,
-            other => MountOption::CUSTOM(other.to_string()),
-        };
+/// Archive piped to stdin, spooled to a file as archives are read with `pread(2)`. The file is
+/// removed once dropped.
+struct SpooledStdin {
+    path: PathBuf,
+}
+
+impl SpooledStdin {
+    fn new() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("zipfs-stdin-{}.zip", std::process::id()));
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        let spooled = Self { path };
+
+        let size = io::copy(&mut io::stdin().lock(), &mut file)?;
+        debug!("Spooled {} bytes from stdin to {:?}", size, spooled.path);
+        Ok(spooled)
+    }
+}
+
+impl Drop for SpooledStdin {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {:?}: {}", self.path, err);
+        }
+    }
+}
+
+/// Unmount what a crashed mount left at `mount_point`, the kernel answers it with ENOTCONN.
+/// Returns whether there was anything to unmount.
+fn recover_stale_mount(mount_point: &Path) -> Result<bool> {
+    match fs::metadata(mount_point) {
+        Err(err) if err.raw_os_error() == Some(libc::ENOTCONN) => {}
+        _ => return Ok(false),
+    }
+
+    for program in mount::FUSERMOUNT {
+        let unmounted = Command::new(program)
+            .arg("-u")
+            .arg(mount_point)
+            .status()
+            .is_ok_and(|status| status.success());
+
+        if unmounted {
+            return Ok(true);
+        }
+    }
+
+    bail!(
+        "{:?} is left over from a mount that is gone, unmount it with `fusermount -u {}`",
+        mount_point,
+        mount_point.display()
+    )
+}
+
+/// Check /proc/self/mountinfo rather than the mount point itself, so a wedged session
+/// can't block us and we don't keep the mount point busy
+fn is_mounted(mount_point: &Path) -> Result<bool> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    let mounted = mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|path| Path::new(&unescape_mountinfo(path)) == mount_point);
+
+    Ok(mounted)
+}
+
+/// Block until the root of the mount can be stat'ed, giving up after `timeout`
+fn wait_until_ready(mount_point: &Path, timeout: Option<Duration>) -> Result<()> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mount_point = mount_point.to_path_buf();
+
+    // NOTE: The worker can't be cancelled, a wedged stat is left behind on timeout
+    thread::spawn(move || loop {
+        if is_mounted(&mount_point).unwrap_or(false) && fs::metadata(&mount_point).is_ok() {
+            let _ = tx.send(());
+            break;
+        }
+
+        thread::sleep(READY_POLL_INTERVAL);
+    });
+
+    match timeout {
+        Some(timeout) => match rx.recv_timeout(timeout) {
+            Ok(_) => Ok(()),
+            Err(_) => bail!("Mount did not become ready within {:?}", timeout),
+        },
+        None => Ok(rx.recv()?),
+    }
+}
+
+/// Undo the octal escaping of whitespace and backslashes in mountinfo paths
+fn unescape_mountinfo(path: &str) -> String {
+    path.replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Detach from the controlling terminal. Must be called before any threads are spawned.
+fn daemonize() -> Result<()> {
+    // SAFETY: The process is still single-threaded
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error().into()),
+        0 => {}
+        // NOTE: Exit without running destructors, the child owns the mount now
+        _ => std::process::exit(0),
+    }
 
-        options.push(opt);
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error().into());
     }
 
-    options
+    std::env::set_current_dir(Path::new("/"))?;
+
+    let dev_null = File::options().read(true).write(true).open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(dev_null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
 }
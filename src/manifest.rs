@@ -0,0 +1,174 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    vec,
+};
+
+use tracing::warn;
+use zip::ZipArchive;
+
+use crate::{
+    archive::{entry_names, wheel_metadata, WHEEL_METADATA},
+    filesystem::is_archive_name,
+    filter::PathFilter,
+};
+
+/// Relative paths of the passthrough files and archive entries under the data directories,
+/// walked lazily in a stable order so that a read can be resumed after a restart.
+struct ManifestLines {
+    sources: Vec<PathBuf>,
+    filter: PathFilter,
+    archive_exts: Vec<String>,
+    current: usize,
+    pending: Vec<PathBuf>,
+    archive_entries: vec::IntoIter<String>,
+}
+
+impl ManifestLines {
+    fn new(sources: Vec<PathBuf>, filter: PathFilter, archive_exts: Vec<String>) -> Self {
+        Self {
+            pending: sources.first().cloned().into_iter().collect(),
+            sources,
+            filter,
+            archive_exts,
+            current: 0,
+            archive_entries: Vec::new().into_iter(),
+        }
+    }
+
+    /// Whether an earlier data directory hides `path`, directories are merged
+    fn is_shadowed(&self, path: &Path, relative: &Path) -> bool {
+        self.sources[..self.current].iter().any(|source| {
+            let other = source.join(relative);
+            other.exists() && !(other.is_dir() && path.is_dir())
+        })
+    }
+
+    /// Entry names of the archive prefixed by its path, directories and hidden entries excluded
+    fn archive_entries(&self, path: &PathBuf, relative: &str) -> Vec<String> {
+        let Ok(archive) = File::open(path)
+            .map_err(From::from)
+            .and_then(ZipArchive::new)
+        else {
+            return Vec::new();
+        };
+
+        let alias = wheel_metadata(path, &archive).map(|_| WHEEL_METADATA.into());
+        entry_names(&archive)
+            .into_iter()
+            .chain(alias)
+            .filter(|name| !name.ends_with('/'))
+            .filter(|name| self.filter.is_entry_visible(Path::new(&**name), false))
+            .map(|name| format!("{}/{}", relative, name))
+            .filter(|name| self.filter.is_visible_with_parents(Path::new(name), false))
+            .collect()
+    }
+}
+
+impl Iterator for ManifestLines {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(line) = self.archive_entries.next() {
+                return Some(line);
+            }
+
+            let Some(path) = self.pending.pop() else {
+                self.current += 1;
+                self.pending.push(self.sources.get(self.current)?.clone());
+                continue;
+            };
+
+            let source = &self.sources[self.current];
+            let relative = path.strip_prefix(source).ok()?.to_path_buf();
+            let name = relative.file_name().unwrap_or_default().to_string_lossy();
+            let is_archive = is_archive_name(&name, &self.archive_exts);
+
+            if !relative.as_os_str().is_empty() && self.is_shadowed(&path, &relative) {
+                continue;
+            }
+
+            // NOTE: Hidden directories are pruned along with everything below them
+            if !relative.as_os_str().is_empty()
+                && !self
+                    .filter
+                    .is_visible(&relative, path.is_dir() || is_archive)
+            {
+                continue;
+            }
+
+            if path.is_dir() {
+                let mut children = match fs::read_dir(&path) {
+                    Ok(entries) => entries
+                        .filter_map(|entry| entry.ok())
+                        .map(|entry| entry.path())
+                        .collect::<Vec<_>>(),
+                    Err(err) => {
+                        warn!("Skipping {:?} in manifest: {:?}", path, err);
+                        continue;
+                    }
+                };
+
+                // NOTE: Reversed so that popping yields the children in order
+                children.sort_by(|a, b| b.cmp(a));
+                self.pending.extend(children);
+                continue;
+            }
+
+            let relative = relative.to_string_lossy().to_string();
+
+            if is_archive {
+                self.archive_entries = self.archive_entries(&path, &relative).into_iter();
+                continue;
+            }
+
+            return Some(relative);
+        }
+    }
+}
+
+/// Newline separated manifest of every file in the mount, generated while it is read
+pub struct ManifestReader {
+    lines: ManifestLines,
+    line: Vec<u8>,
+    consumed: usize,
+    position: u64,
+}
+
+impl ManifestReader {
+    pub fn new(sources: Vec<PathBuf>, filter: PathFilter, archive_exts: Vec<String>) -> Self {
+        Self {
+            lines: ManifestLines::new(sources, filter, archive_exts),
+            line: Vec::new(),
+            consumed: 0,
+            position: 0,
+        }
+    }
+
+    /// Number of bytes read so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl Read for ManifestReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.consumed == self.line.len() {
+            let Some(line) = self.lines.next() else {
+                return Ok(0);
+            };
+
+            self.line = (line + "\n").into_bytes();
+            self.consumed = 0;
+        }
+
+        let read = buf.len().min(self.line.len() - self.consumed);
+        buf[..read].copy_from_slice(&self.line[self.consumed..self.consumed + read]);
+
+        self.consumed += read;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
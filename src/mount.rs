@@ -0,0 +1,109 @@
+use std::{env, fs, io, path::Path};
+
+use color_eyre::{
+    eyre::{bail, Report},
+    Result,
+};
+use fuser::{Filesystem, MountOption, Session};
+use tracing::{info, warn};
+use zipfs::MountMode;
+
+use crate::mount_options::{allows_user_allow_other, FUSE_CONF};
+
+const FUSE_DEVICE: &str = "/dev/fuse";
+
+/// Unmount helpers of FUSE 3 and FUSE 2, in order of preference
+pub const FUSERMOUNT: [&str; 2] = ["fusermount3", "fusermount"];
+
+/// Mount `fs` at `mount_point`, refusing up front what `mode` rules out.
+///
+/// NOTE: libfuse picks the way itself, trying mount(2) when running as root and exec'ing
+/// fusermount otherwise or for auto_unmount, which mount(2) can't provide
+pub fn mount<FS: Filesystem>(
+    fs: FS,
+    mount_point: &Path,
+    options: &[MountOption],
+    mode: MountMode,
+) -> Result<Session<FS>> {
+    let auto_unmount = options.contains(&MountOption::AutoUnmount);
+
+    if mode == MountMode::Privileged {
+        if auto_unmount {
+            bail!("auto_unmount needs fusermount, mount with --mount-mode unprivileged");
+        }
+
+        if !is_root() {
+            let err = io::Error::from_raw_os_error(libc::EPERM);
+            return Err(explain(err, MountMode::Privileged, options));
+        }
+    }
+
+    let taken = match is_root() && !auto_unmount {
+        true => {
+            if mode == MountMode::Unprivileged {
+                warn!("libfuse mounts with mount(2) as root, unless auto_unmount is set");
+            }
+
+            info!("Mounting with mount(2), libfuse falls back to fusermount if it is refused");
+            MountMode::Privileged
+        }
+        false => {
+            info!("Mounting with fusermount");
+            MountMode::Unprivileged
+        }
+    };
+
+    Session::new(fs, mount_point, options).map_err(|err| explain(err, taken, options))
+}
+
+/// Turn the usual reasons mounting fails into something actionable, rather than a bare EPERM
+fn explain(err: io::Error, mode: MountMode, options: &[MountOption]) -> Report {
+    let allow_other =
+        options.contains(&MountOption::AllowOther) || options.contains(&MountOption::AllowRoot);
+
+    let reason = if !Path::new(FUSE_DEVICE).exists() {
+        Some(format!(
+            "{} is missing, load the fuse kernel module or pass the device into the container",
+            FUSE_DEVICE
+        ))
+    } else if mode == MountMode::Privileged && err.raw_os_error() == Some(libc::EPERM) {
+        Some(
+            "mounting without fusermount needs root, mount with --mount-mode unprivileged"
+                .to_string(),
+        )
+    } else if mode == MountMode::Unprivileged && fusermount().is_none() {
+        Some(format!(
+            "none of {} is installed, install fuse3 or mount as root with --mount-mode privileged",
+            FUSERMOUNT.join(", ")
+        ))
+    } else if mode == MountMode::Unprivileged
+        && allow_other
+        && !is_root()
+        && !allows_user_allow_other(&fs::read_to_string(FUSE_CONF).unwrap_or_default())
+    {
+        Some(format!(
+            "allow_other and allow_root need user_allow_other in {}",
+            FUSE_CONF
+        ))
+    } else {
+        None
+    };
+
+    match reason {
+        Some(reason) => Report::new(err).wrap_err(reason),
+        None => Report::new(err).wrap_err("Failed to mount"),
+    }
+}
+
+fn fusermount() -> Option<&'static str> {
+    let path = env::var_os("PATH")?;
+
+    FUSERMOUNT
+        .into_iter()
+        .find(|name| env::split_paths(&path).any(|dir| dir.join(name).is_file()))
+}
+
+fn is_root() -> bool {
+    // SAFETY: geteuid can't fail
+    unsafe { libc::geteuid() == 0 }
+}
@@ -0,0 +1,399 @@
+use std::fs;
+
+use fuser::MountOption;
+use zipfs::default_mount_options;
+
+use crate::parse_umask;
+
+/// Mount options equivalent to --strip-ext and --dual-view
+const STRIP_EXT_OPTION: &str = "strip_ext";
+const DUAL_VIEW_OPTION: &str = "dual_view";
+
+/// Pass unknown options on to FUSE rather than refusing to mount
+const IGNORE_UNKNOWN_OPTION: &str = "ignore_unknown";
+
+/// Keeps `allow_other` from enabling `default_permissions`
+const NO_DEFAULT_PERMISSIONS_OPTION: &str = "no_default_permissions";
+
+/// Keeps unprivileged mounts from being unmounted once we are gone
+const NO_AUTO_UNMOUNT_OPTION: &str = "no_auto_unmount";
+
+pub const FUSE_CONF: &str = "/etc/fuse.conf";
+
+/// Options that are either given or not, refused with a value such as `allow_other=1`
+const FLAG_OPTIONS: [&str; 20] = [
+    "ro",
+    "rw",
+    "allow_other",
+    "default_permissions",
+    NO_DEFAULT_PERMISSIONS_OPTION,
+    "allow_root",
+    "auto_unmount",
+    NO_AUTO_UNMOUNT_OPTION,
+    "nonempty",
+    "dev",
+    "nodev",
+    "suid",
+    "nosuid",
+    "atime",
+    "noatime",
+    "exec",
+    "noexec",
+    STRIP_EXT_OPTION,
+    DUAL_VIEW_OPTION,
+    IGNORE_UNKNOWN_OPTION,
+];
+
+/// The `-o` string, sorted into what FUSE handles and what we handle ourselves
+#[derive(Debug, Default, PartialEq)]
+pub struct MountOptions {
+    /// Passed to FUSE after the defaults and the access mode
+    pub fuse: Vec<MountOption>,
+    pub read_only: bool,
+    pub read_write: bool,
+    /// Given with `auto_unmount` or `no_auto_unmount`, decided by our privileges otherwise
+    pub auto_unmount: Option<bool>,
+    pub strip_ext: bool,
+    pub dual_view: bool,
+    pub umask: Option<u16>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl MountOptions {
+    /// Parse a comma separated option string. Unknown options are refused unless
+    /// `ignore_unknown` is among them, and `allow_other` brings `default_permissions` along
+    /// unless `no_default_permissions` is given.
+    pub fn parse(options: &str) -> Result<Self, String> {
+        let options = options
+            .split(',')
+            .filter(|option| !option.is_empty())
+            .collect::<Vec<_>>();
+        let ignore_unknown = options.contains(&IGNORE_UNKNOWN_OPTION);
+
+        let mut parsed = Self::default();
+        let mut allow_other = false;
+        let mut default_permissions = None;
+
+        for option in options {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+
+            let fuse = match (key, value) {
+                ("ro", None) => {
+                    parsed.read_only = true;
+                    continue;
+                }
+                ("rw", None) => {
+                    parsed.read_write = true;
+                    continue;
+                }
+                ("allow_other", None) => {
+                    allow_other = true;
+                    MountOption::AllowOther
+                }
+                ("default_permissions", None) => {
+                    default_permissions = Some(true);
+                    continue;
+                }
+                (NO_DEFAULT_PERMISSIONS_OPTION, None) => {
+                    default_permissions = Some(false);
+                    continue;
+                }
+                ("allow_root", None) => MountOption::AllowRoot,
+                ("auto_unmount", None) => {
+                    parsed.auto_unmount = Some(true);
+                    continue;
+                }
+                (NO_AUTO_UNMOUNT_OPTION, None) => {
+                    parsed.auto_unmount = Some(false);
+                    continue;
+                }
+                ("nonempty", None) => MountOption::CUSTOM(option.to_string()),
+                ("dev", None) => MountOption::Dev,
+                ("nodev", None) => MountOption::NoDev,
+                ("suid", None) => MountOption::Suid,
+                ("nosuid", None) => MountOption::NoSuid,
+                ("atime", None) => MountOption::Atime,
+                ("noatime", None) => MountOption::NoAtime,
+                ("exec", None) => MountOption::Exec,
+                ("noexec", None) => MountOption::NoExec,
+                ("fsname", Some(name)) => MountOption::FSName(name.to_string()),
+                ("subtype", Some(subtype)) => MountOption::Subtype(subtype.to_string()),
+                ("umask", Some(umask)) => {
+                    parsed.umask = Some(parse_umask(umask)?);
+                    continue;
+                }
+                ("uid", Some(uid)) => {
+                    parsed.uid = Some(parse_id(key, uid)?);
+                    continue;
+                }
+                ("gid", Some(gid)) => {
+                    parsed.gid = Some(parse_id(key, gid)?);
+                    continue;
+                }
+                (STRIP_EXT_OPTION, None) => {
+                    parsed.strip_ext = true;
+                    continue;
+                }
+                (DUAL_VIEW_OPTION, None) => {
+                    parsed.dual_view = true;
+                    continue;
+                }
+                (IGNORE_UNKNOWN_OPTION, None) => continue,
+                ("fsname" | "subtype" | "umask" | "uid" | "gid", None) => {
+                    return Err(format!("mount option {} expects a value", key));
+                }
+                (key, Some(_)) if FLAG_OPTIONS.contains(&key) => {
+                    return Err(format!("mount option {} takes no value", key));
+                }
+                _ if ignore_unknown => MountOption::CUSTOM(option.to_string()),
+                _ => return Err(format!("unknown mount option: {}", option)),
+            };
+
+            parsed.fuse.push(fuse);
+        }
+
+        if parsed.read_only && parsed.read_write {
+            return Err("mount options ro and rw are mutually exclusive".to_string());
+        }
+
+        if default_permissions.unwrap_or(allow_other) {
+            parsed.fuse.push(MountOption::DefaultPermissions);
+        }
+
+        Ok(parsed)
+    }
+
+    /// Everything to mount with, read-only unless archives are writable, with our subtype
+    /// replaced by `fstype` where there is one
+    pub fn to_fuse(&self, read_only: bool, fstype: &str) -> Vec<MountOption> {
+        let mut options = default_mount_options();
+        for option in &mut options {
+            if let MountOption::Subtype(subtype) = option {
+                *subtype = fstype.to_string();
+            }
+        }
+
+        // NOTE: A given fsname replaces ours
+        if self
+            .fuse
+            .iter()
+            .any(|option| matches!(option, MountOption::FSName(_)))
+        {
+            options.retain(|option| !matches!(option, MountOption::FSName(_)));
+        }
+
+        if self.has_subtype() {
+            options.retain(|option| !matches!(option, MountOption::Subtype(_)));
+        }
+
+        options.push(match read_only {
+            true => MountOption::RO,
+            false => MountOption::RW,
+        });
+        options.extend(self.fuse.iter().cloned());
+
+        if self.auto_unmount.unwrap_or_else(auto_unmount_by_default) {
+            options.push(MountOption::AutoUnmount);
+
+            // NOTE: fuser adds allow_other to auto_unmount otherwise, opening the mount to everyone
+            if !options.contains(&MountOption::AllowOther)
+                && !options.contains(&MountOption::AllowRoot)
+            {
+                options.push(MountOption::AllowRoot);
+            }
+        }
+
+        options
+    }
+
+    /// Whether `subtype=` was given
+    pub fn has_subtype(&self) -> bool {
+        self.fuse
+            .iter()
+            .any(|option| matches!(option, MountOption::Subtype(_)))
+    }
+}
+
+/// Unprivileged mounts are unmounted by fusermount once we are gone, where fuse.conf allows
+/// the `allow_root` fuser requires along with `auto_unmount`
+fn auto_unmount_by_default() -> bool {
+    // SAFETY: geteuid can't fail
+    let unprivileged = unsafe { libc::geteuid() } != 0;
+    unprivileged && allows_user_allow_other(&fs::read_to_string(FUSE_CONF).unwrap_or_default())
+}
+
+pub fn allows_user_allow_other(fuse_conf: &str) -> bool {
+    fuse_conf
+        .lines()
+        .any(|line| line.trim() == "user_allow_other")
+}
+
+fn parse_id(key: &str, id: &str) -> Result<u32, String> {
+    id.parse()
+        .map_err(|_| format!("invalid {} mount option: {}", key, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_known_options() {
+        let parsed =
+            MountOptions::parse("ro,allow_root,noatime,fsname=archives,subtype=zip").unwrap();
+
+        assert!(parsed.read_only);
+        assert_eq!(
+            parsed.fuse,
+            vec![
+                MountOption::AllowRoot,
+                MountOption::NoAtime,
+                MountOption::FSName("archives".to_string()),
+                MountOption::Subtype("zip".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_our_options() {
+        let parsed = MountOptions::parse("strip_ext,dual_view,umask=022,uid=1000,gid=100").unwrap();
+
+        assert!(parsed.strip_ext);
+        assert!(parsed.dual_view);
+        assert_eq!(parsed.umask, Some(0o022));
+        assert_eq!(parsed.uid, Some(1000));
+        assert_eq!(parsed.gid, Some(100));
+        assert!(parsed.fuse.is_empty());
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(MountOptions::parse(""), Ok(MountOptions::default()));
+        assert_eq!(MountOptions::parse(",,"), Ok(MountOptions::default()));
+    }
+
+    #[test]
+    fn test_parse_unknown_option() {
+        let err = MountOptions::parse("allow_othr").unwrap_err();
+        assert_eq!(err, "unknown mount option: allow_othr");
+
+        let parsed = MountOptions::parse("allow_othr,ignore_unknown").unwrap();
+        assert_eq!(
+            parsed.fuse,
+            vec![MountOption::CUSTOM("allow_othr".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_values() {
+        assert!(MountOptions::parse("uid=nobody").is_err());
+        assert!(MountOptions::parse("umask=999").is_err());
+        assert!(MountOptions::parse("uid").is_err());
+        assert!(MountOptions::parse("ro,rw").is_err());
+    }
+
+    #[test]
+    fn test_parse_flag_with_value() {
+        for option in [
+            "allow_other=1",
+            "ro=true",
+            "auto_unmount=0",
+            "strip_ext=yes",
+        ] {
+            let err = MountOptions::parse(&format!("{},ignore_unknown", option)).unwrap_err();
+            let key = option.split_once('=').unwrap().0;
+            assert_eq!(err, format!("mount option {} takes no value", key));
+        }
+    }
+
+    #[test]
+    fn test_allow_other_enables_default_permissions() {
+        let parsed = MountOptions::parse("allow_other").unwrap();
+        assert_eq!(
+            parsed.fuse,
+            vec![MountOption::AllowOther, MountOption::DefaultPermissions]
+        );
+
+        let parsed = MountOptions::parse("allow_other,no_default_permissions").unwrap();
+        assert_eq!(parsed.fuse, vec![MountOption::AllowOther]);
+
+        let parsed = MountOptions::parse("default_permissions").unwrap();
+        assert_eq!(parsed.fuse, vec![MountOption::DefaultPermissions]);
+    }
+
+    #[test]
+    fn test_auto_unmount() {
+        let parsed = MountOptions::parse("auto_unmount").unwrap();
+        assert_eq!(parsed.auto_unmount, Some(true));
+
+        let options = parsed.to_fuse(true, "zipfs");
+        assert!(options.contains(&MountOption::AutoUnmount));
+        assert!(options.contains(&MountOption::AllowRoot));
+        assert!(!options.contains(&MountOption::AllowOther));
+
+        let options = MountOptions::parse("auto_unmount,allow_other")
+            .unwrap()
+            .to_fuse(true, "zipfs");
+        assert!(options.contains(&MountOption::AllowOther));
+        assert!(!options.contains(&MountOption::AllowRoot));
+
+        let options = MountOptions::parse("no_auto_unmount")
+            .unwrap()
+            .to_fuse(true, "zipfs");
+        assert!(!options.contains(&MountOption::AutoUnmount));
+    }
+
+    #[test]
+    fn test_allows_user_allow_other() {
+        assert!(allows_user_allow_other(
+            "# mount_max = 1000\nuser_allow_other\n"
+        ));
+        assert!(!allows_user_allow_other("#user_allow_other\n"));
+        assert!(!allows_user_allow_other(""));
+    }
+
+    #[test]
+    fn test_given_fsname_replaces_default() {
+        let parsed = MountOptions::parse("fsname=archives").unwrap();
+        let options = parsed.to_fuse(true, "zipfs");
+
+        let fsnames = options
+            .iter()
+            .filter(|option| matches!(option, MountOption::FSName(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(fsnames, vec![&MountOption::FSName("archives".to_string())]);
+        assert!(options.contains(&MountOption::RO));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_fstype_replaces_subtype() {
+        let subtypes = |options: Vec<MountOption>| {
+            options
+                .into_iter()
+                .filter(|option| matches!(option, MountOption::Subtype(_)))
+                .collect::<Vec<_>>()
+        };
+
+        let parsed = MountOptions::parse("").unwrap();
+        assert_eq!(
+            subtypes(parsed.to_fuse(true, "zipfs")),
+            vec![MountOption::Subtype("zipfs".to_string())]
+        );
+        assert_eq!(
+            subtypes(parsed.to_fuse(true, "archives")),
+            vec![MountOption::Subtype("archives".to_string())]
+        );
+
+        let parsed = MountOptions::parse("subtype=zip").unwrap();
+        assert!(parsed.has_subtype());
+        assert_eq!(
+            subtypes(parsed.to_fuse(true, "zipfs")),
+            vec![MountOption::Subtype("zip".to_string())]
+        );
+    }
+}
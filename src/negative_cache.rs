@@ -0,0 +1,140 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use lru::LruCache;
+
+type INode = u64;
+
+/// Names recently found missing inside archives, keyed by the inode of their parent, so that
+/// probing them again doesn't go through the archive. Entries expire after the TTL and are
+/// dropped once the archive they were missing from is modified.
+#[derive(Debug)]
+pub struct NegativeCache {
+    entries: LruCache<(INode, OsString), Miss>,
+    ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Miss {
+    expires: Instant,
+    zip_path: PathBuf,
+    /// Of the archive when the name was found missing
+    mtime: Option<SystemTime>,
+}
+
+impl NegativeCache {
+    pub fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            entries: LruCache::new(capacity),
+            ttl,
+        }
+    }
+
+    /// Remember that `name` is missing from `parent`, inside the archive at `zip_path`
+    pub fn insert(&mut self, parent: INode, name: &OsStr, zip_path: &Path, now: Instant) {
+        let miss = Miss {
+            expires: now + self.ttl,
+            zip_path: zip_path.to_path_buf(),
+            mtime: modified(zip_path),
+        };
+
+        self.entries.put((parent, name.to_os_string()), miss);
+    }
+
+    /// Whether `name` is known to be missing from `parent`, expired and outdated entries
+    /// are dropped on the way
+    pub fn contains(&mut self, parent: INode, name: &OsStr, now: Instant) -> bool {
+        let key = (parent, name.to_os_string());
+        let Some(miss) = self.entries.get(&key) else {
+            return false;
+        };
+
+        if now < miss.expires && modified(&miss.zip_path) == miss.mtime {
+            return true;
+        }
+
+        self.entries.pop(&key);
+        false
+    }
+
+    /// Forget that `name` is missing from `parent`, once it was created
+    pub fn remove(&mut self, parent: INode, name: &OsStr) {
+        self.entries.pop(&(parent, name.to_os_string()));
+    }
+
+    pub fn cap(&self) -> NonZeroUsize {
+        self.entries.cap()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_expires_after_ttl() {
+        let data = TempDir::new().unwrap();
+        let zip_path = data.path().join("a.zip");
+        fs::write(&zip_path, "").unwrap();
+
+        let mut cache = NegativeCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(1));
+        let now = Instant::now();
+        cache.insert(1, OsStr::new("missing"), &zip_path, now);
+
+        assert!(cache.contains(1, OsStr::new("missing"), now));
+        assert!(!cache.contains(2, OsStr::new("missing"), now));
+        assert!(!cache.contains(1, OsStr::new("missing"), now + Duration::from_secs(1)));
+
+        // NOTE: Expired entries are dropped rather than revived
+        assert!(!cache.contains(1, OsStr::new("missing"), now));
+    }
+
+    #[test]
+    fn test_dropped_once_archive_changes() {
+        let data = TempDir::new().unwrap();
+        let zip_path = data.path().join("a.zip");
+        fs::write(&zip_path, "").unwrap();
+
+        let mut cache = NegativeCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert(1, OsStr::new("missing"), &zip_path, now);
+
+        let file = fs::File::options().write(true).open(&zip_path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+
+        assert!(!cache.contains(1, OsStr::new("missing"), now));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let data = TempDir::new().unwrap();
+        let zip_path = data.path().join("a.zip");
+
+        let mut cache = NegativeCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(60));
+        let now = Instant::now();
+        for name in ["a", "b", "c"] {
+            cache.insert(1, OsStr::new(name), &zip_path, now);
+        }
+
+        assert!(!cache.contains(1, OsStr::new("a"), now));
+        assert!(cache.contains(1, OsStr::new("b"), now));
+        assert!(cache.contains(1, OsStr::new("c"), now));
+    }
+}
@@ -0,0 +1,329 @@
+use std::{collections::HashMap, fs, num::NonZeroUsize, path::Path, path::PathBuf, time::Duration};
+
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::password::PasswordMap;
+
+/// Extensions of the zip based formats mounted as directories unless configured otherwise
+pub const DEFAULT_ARCHIVE_EXTS: [&str; 12] = [
+    "zip", "jar", "war", "ear", "apk", "epub", "docx", "xlsx", "pptx", "odt", "ods", "odp",
+];
+
+/// Largest read ahead asked from the kernel unless configured otherwise, in bytes
+pub const DEFAULT_MAX_READAHEAD: u32 = 128 * 1024;
+
+/// Largest compression ratio served unless configured otherwise
+pub const DEFAULT_MAX_RATIO: u64 = 1000;
+
+/// Effective configuration, merged from the config file and the command line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Options {
+    pub data_dir: PathBuf,
+    /// Overlaid below `data_dir`, earlier ones win on name collisions
+    pub data_dirs: Vec<PathBuf>,
+    pub mount_point: PathBuf,
+    /// Archives kept open with their central directory read
+    #[serde(alias = "cache_size")]
+    pub archive_cache_entries: NonZeroUsize,
+    pub mount_options: String,
+    pub mount_mode: MountMode,
+    /// Listed as `fuse.<fstype>` in /proc/mounts
+    pub fstype: String,
+    /// In milliseconds
+    pub open_timeout: Option<u64>,
+    pub writable_archives: bool,
+    pub max_staging_size: u64,
+    /// Largest read the kernel sends at once, in bytes, its default when unset
+    pub max_read: Option<u32>,
+    pub readahead_kb: u32,
+    pub strict: bool,
+    /// Read stored entries straight from their archive and keep them in the page cache
+    pub direct_stored: bool,
+    /// Largest ratio of the declared uncompressed to compressed size of an entry served
+    pub max_ratio: u64,
+    /// Serve entries whatever their compression ratio
+    pub allow_bombs: bool,
+    /// Archives whose entries may be open at once, unlimited when unset
+    pub max_open_archives: Option<usize>,
+    /// Bytes the archive and content caches may take together, unlimited when unset
+    pub max_memory: Option<u64>,
+    /// Bytes of decompressed entry chunks kept for out of order reads, disabled when 0
+    #[serde(alias = "chunk_cache_size")]
+    pub content_cache_bytes: u64,
+    /// Attributes of archive entries kept between lookups, disabled when 0
+    pub attr_cache_entries: usize,
+    /// How long the kernel may remember that a name doesn't exist, in milliseconds, disabled
+    /// when 0
+    pub negative_cache_ttl: u64,
+    /// Names found missing inside archives remembered by zipfs itself, disabled when 0
+    pub negative_lookup_entries: usize,
+    /// How long zipfs remembers a name missing inside an archive, in milliseconds
+    pub negative_lookup_ttl: u64,
+    /// `data_dir` is an archive to serve as the whole mount
+    pub chroot_into_zip: bool,
+    /// Serve `data_dir` as the only directory in the root of the mount, under this name
+    pub virtual_root: Option<String>,
+    pub verify_on_mount: bool,
+    pub umask: u16,
+    /// Reported as the owner of every file instead of the real one, also `-o uid=` and `-o gid=`
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Without the leading dot
+    pub archive_exts: Vec<String>,
+    /// TOML file of the passwords of encrypted archives, by their path relative to the data
+    /// directory
+    pub passwords: Option<PathBuf>,
+    pub manifest: bool,
+    /// Serve the configuration and live stats in `.zipfs/status`
+    pub expose_metadata: bool,
+    /// Open every archive in the background once mounted, until the cache is full
+    pub prefetch: bool,
+    /// Threads opening the archives of listed directories in the background, none when 0
+    pub warm_concurrency: usize,
+    /// Bytes per second served across all reads, unlimited when unset
+    pub rate_limit: Option<u64>,
+    /// Keeps the generation of the last mount, bumped on every mount so that NFS handles
+    /// from before it are told apart. The mount time is used when unset.
+    pub generation_file: Option<PathBuf>,
+    /// Globs of the files to show, all of them when empty
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Globs hiding the files inside archives matching all of them
+    pub exclude_patterns: Vec<String>,
+    pub sort: SortOrder,
+    /// List archives as directories named without their extension, also `-o strip_ext`
+    pub strip_ext: bool,
+    /// Keep archives as files and serve their contents next to them, also `-o dual_view`
+    pub dual_view: bool,
+    /// Appended to the name of an archive for the directory of its contents in dual view
+    pub dual_view_suffix: String,
+    /// Serve archives as regular files, without looking into any of them
+    pub passthrough_only: bool,
+    /// Archive entry names longer than this many bytes are shortened, at most 255
+    pub max_entry_name_length: usize,
+    pub foreground: bool,
+    pub pidfile: Option<PathBuf>,
+    pub socket: Option<PathBuf>,
+    /// In milliseconds
+    pub mount_check_interval: u64,
+    /// Inherited file descriptor receiving a line once the mount is usable
+    pub ready_fd: Option<i32>,
+    /// In milliseconds
+    pub mount_timeout: Option<u64>,
+    /// How long to wait for open files to be released before unmounting, in milliseconds
+    pub shutdown_timeout: u64,
+    /// Unmount once no operation came in for this long and nothing is open, in milliseconds
+    pub idle_timeout: Option<u64>,
+    /// Log level of zipfs itself, -1 for warn, 0 for info, 1 for debug and 2 or more for trace
+    pub verbosity: i8,
+    pub log_format: LogFormat,
+    pub log_file: Option<PathBuf>,
+    /// Appended with a JSON line for every read served
+    pub audit_log: Option<PathBuf>,
+}
+
+/// Sizes of the caches of the file system
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheConfig {
+    /// Archives kept open with their central directory read
+    pub archive_entries: NonZeroUsize,
+    /// Bytes of decompressed entries kept in 64 KiB chunks, disabled when 0
+    pub content_bytes: u64,
+    /// Attributes of archive entries, disabled when 0
+    pub attr_entries: usize,
+    /// How long the kernel may remember that a name doesn't exist, disabled when zero
+    pub negative_ttl: Duration,
+    /// Names found missing inside archives, disabled when 0
+    pub negative_lookups: usize,
+    /// How long a name stays known as missing, unless its archive changes first
+    pub negative_lookup_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            archive_entries: NonZeroUsize::new(1024).unwrap(),
+            content_bytes: 64 * 1024 * 1024,
+            attr_entries: 0,
+            negative_ttl: Duration::ZERO,
+            negative_lookups: 256,
+            negative_lookup_ttl: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Order of directory listings, for both passthrough directories and archives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Bytewise by name
+    #[default]
+    Name,
+    /// Largest first, like `ls -S`
+    Size,
+    /// Most recently modified first, like `ls -t`
+    Mtime,
+    /// As found in the data directory or the central directory of the archive
+    None,
+}
+
+/// How the file system is expected to get mounted. libfuse picks the way on its own, mount(2)
+/// when running as root and fusermount otherwise or for auto_unmount, the mode only decides what
+/// is refused up front.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MountMode {
+    /// Whichever way libfuse takes
+    #[default]
+    Auto,
+    /// With mount(2), refused unless running as root and without auto_unmount
+    Privileged,
+    /// Through fusermount3, which is setuid root. Still mount(2) when running as root without
+    /// auto_unmount.
+    Unprivileged,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, one event per line
+    #[default]
+    Pretty,
+    Compact,
+    /// One JSON object per event, with the fields of the enclosing spans
+    Json,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let caches = CacheConfig::default();
+
+        Self {
+            data_dir: PathBuf::new(),
+            data_dirs: Vec::new(),
+            mount_point: PathBuf::new(),
+            archive_cache_entries: caches.archive_entries,
+            mount_options: String::from("ro"),
+            mount_mode: MountMode::default(),
+            fstype: String::from("zipfs"),
+            open_timeout: None,
+            writable_archives: false,
+            max_staging_size: 256 * 1024 * 1024,
+            max_read: None,
+            readahead_kb: DEFAULT_MAX_READAHEAD / 1024,
+            strict: false,
+            direct_stored: false,
+            max_ratio: DEFAULT_MAX_RATIO,
+            allow_bombs: false,
+            max_open_archives: None,
+            max_memory: None,
+            content_cache_bytes: caches.content_bytes,
+            attr_cache_entries: caches.attr_entries,
+            negative_cache_ttl: caches.negative_ttl.as_millis() as u64,
+            negative_lookup_entries: caches.negative_lookups,
+            negative_lookup_ttl: caches.negative_lookup_ttl.as_millis() as u64,
+            chroot_into_zip: false,
+            virtual_root: None,
+            verify_on_mount: false,
+            umask: 0,
+            uid: None,
+            gid: None,
+            archive_exts: DEFAULT_ARCHIVE_EXTS.map(String::from).to_vec(),
+            passwords: None,
+            manifest: false,
+            expose_metadata: false,
+            prefetch: false,
+            warm_concurrency: 0,
+            rate_limit: None,
+            generation_file: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_patterns: Vec::new(),
+            sort: SortOrder::default(),
+            strip_ext: false,
+            dual_view: false,
+            dual_view_suffix: String::from(".d"),
+            passthrough_only: false,
+            max_entry_name_length: 255,
+            foreground: false,
+            pidfile: None,
+            socket: None,
+            mount_check_interval: 1000,
+            ready_fd: None,
+            mount_timeout: None,
+            shutdown_timeout: 10000,
+            idle_timeout: None,
+            verbosity: 0,
+            log_format: LogFormat::default(),
+            log_file: None,
+            audit_log: None,
+        }
+    }
+}
+
+impl Options {
+    /// Sizes of the caches, to build the file system with
+    pub fn cache_config(&self) -> CacheConfig {
+        CacheConfig {
+            archive_entries: self.archive_cache_entries,
+            content_bytes: self.content_cache_bytes,
+            attr_entries: self.attr_cache_entries,
+            negative_ttl: Duration::from_millis(self.negative_cache_ttl),
+            negative_lookups: self.negative_lookup_entries,
+            negative_lookup_ttl: Duration::from_millis(self.negative_lookup_ttl),
+        }
+    }
+
+    /// Load options from a TOML file, unknown keys are an error
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).wrap_err_with(|| format!("Failed to read {:?}", path))?;
+
+        toml::from_str(&content).wrap_err_with(|| format!("Invalid config file {:?}", path))
+    }
+
+    /// Read the passwords file, if there is one
+    pub fn load_passwords(&self) -> Result<PasswordMap> {
+        let Some(path) = &self.passwords else {
+            return Ok(PasswordMap::default());
+        };
+
+        let content =
+            fs::read_to_string(path).wrap_err_with(|| format!("Failed to read {:?}", path))?;
+        let passwords: HashMap<PathBuf, String> = toml::from_str(&content)
+            .wrap_err_with(|| format!("Invalid passwords file {:?}", path))?;
+
+        Ok(passwords.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partial_config() {
+        let options: Options = toml::from_str(
+            r#"
+            data_dir = "/srv/archives"
+            cache_size = 16
+            open_timeout = 500
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(options.data_dir, PathBuf::from("/srv/archives"));
+        assert_eq!(options.archive_cache_entries.get(), 16);
+        assert_eq!(options.open_timeout, Some(500));
+        assert_eq!(options.mount_options, "ro");
+        assert_eq!(options.fstype, "zipfs");
+    }
+
+    #[test]
+    fn test_reject_unknown_keys() {
+        let options = toml::from_str::<Options>("cache_sise = 16");
+        assert!(options.is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use zeroize::Zeroizing;
+
+/// A password, wiped from memory once dropped and never printed
+#[derive(Clone)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self::new(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+/// Resolves the passwords of encrypted archives, e.g. from a vault.
+///
+/// Asked the first time an encrypted entry of an archive is touched, the answer is then kept
+/// for the whole archive until it is evicted from the archive cache. A wrong password is
+/// thereby asked for again once the archive was evicted, rather than kept for good.
+pub trait PasswordProvider: Send + Sync {
+    /// Password of `archive`, relative to its data directory, given the `entry` being opened
+    fn password_for(&self, archive: &Path, entry: Option<&str>) -> Option<SecretString>;
+}
+
+impl<F> PasswordProvider for F
+where
+    F: Fn(&Path, Option<&str>) -> Option<SecretString> + Send + Sync,
+{
+    fn password_for(&self, archive: &Path, entry: Option<&str>) -> Option<SecretString> {
+        self(archive, entry)
+    }
+}
+
+/// Each provider in turn, until one knows the password
+impl PasswordProvider for Vec<Box<dyn PasswordProvider>> {
+    fn password_for(&self, archive: &Path, entry: Option<&str>) -> Option<SecretString> {
+        self.iter()
+            .find_map(|provider| provider.password_for(archive, entry))
+    }
+}
+
+/// Passwords by archive path, relative to its data directory, as read from `--passwords`
+#[derive(Debug, Clone, Default)]
+pub struct PasswordMap(HashMap<PathBuf, SecretString>);
+
+impl PasswordMap {
+    pub fn insert(&mut self, archive: impl Into<PathBuf>, password: impl Into<SecretString>) {
+        self.0.insert(archive.into(), password.into());
+    }
+}
+
+impl<S: Into<SecretString>> FromIterator<(PathBuf, S)> for PasswordMap {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, S)>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(archive, password)| (archive, password.into()))
+                .collect(),
+        )
+    }
+}
+
+impl PasswordProvider for PasswordMap {
+    fn password_for(&self, archive: &Path, _entry: Option<&str>) -> Option<SecretString> {
+        self.0.get(archive).cloned()
+    }
+}
+
+/// The same password for every archive, as given with `--password`
+#[derive(Debug, Clone)]
+pub struct FixedPassword(pub SecretString);
+
+impl PasswordProvider for FixedPassword {
+    fn password_for(&self, _archive: &Path, _entry: Option<&str>) -> Option<SecretString> {
+        Some(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_providers_in_turn() {
+        let mut map = PasswordMap::default();
+        map.insert("secret.zip", "from map");
+
+        let providers: Vec<Box<dyn PasswordProvider>> = vec![
+            Box::new(map),
+            Box::new(|archive: &Path, _: Option<&str>| {
+                (archive == Path::new("vault.zip")).then(|| SecretString::from("from vault"))
+            }),
+            Box::new(FixedPassword("fallback".into())),
+        ];
+
+        let password = |archive: &str| {
+            providers
+                .password_for(Path::new(archive), Some("file.txt"))
+                .map(|password| password.expose_secret().to_string())
+        };
+        assert_eq!(password("secret.zip").as_deref(), Some("from map"));
+        assert_eq!(password("vault.zip").as_deref(), Some("from vault"));
+        assert_eq!(password("other.zip").as_deref(), Some("fallback"));
+    }
+
+    #[test]
+    fn test_secret_not_printed() {
+        let password = SecretString::from("hunter2");
+        assert!(!format!("{:?}", password).contains("hunter2"));
+    }
+}
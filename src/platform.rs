@@ -0,0 +1,104 @@
+//! Differences between the FUSE implementations, libfuse on Linux and macFUSE on macOS
+
+use std::{fs::Metadata, time::UNIX_EPOCH};
+
+use fuser::{FileAttr, MountOption};
+
+/// Options every mount gets, before the access mode and the ones given with `-o`
+pub fn default_mount_options() -> Vec<MountOption> {
+    let mut options = vec![MountOption::FSName("zipfs".to_string())];
+
+    // NOTE: Listed as fuse.zipfs in /proc/mounts, macFUSE has no subtypes
+    #[cfg(not(target_os = "macos"))]
+    options.push(MountOption::Subtype("zipfs".to_string()));
+
+    // NOTE: Finder shows the volume name, and would otherwise probe for `._` files everywhere
+    #[cfg(target_os = "macos")]
+    options.extend([
+        MountOption::CUSTOM("volname=zipfs".to_string()),
+        MountOption::CUSTOM("noappledouble".to_string()),
+    ]);
+
+    options
+}
+
+/// Set the creation time of `attr` from `metadata`. Only macOS reports it, Linux gets the
+/// epoch as FUSE has no use for it there.
+pub fn fill_crtime(attr: &mut FileAttr, metadata: &Metadata) {
+    attr.crtime = match cfg!(target_os = "macos") {
+        true => metadata.created().unwrap_or(UNIX_EPOCH),
+        false => UNIX_EPOCH,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use fuser::FileType;
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn attr() -> FileAttr {
+        FileAttr {
+            ino: 1,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_fill_crtime() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "some content\n").unwrap();
+
+        let metadata = fs::metadata(&path).unwrap();
+        let mut attr = attr();
+        fill_crtime(&mut attr, &metadata);
+
+        assert_ne!(attr.crtime, UNIX_EPOCH);
+        assert_eq!(attr.crtime, metadata.created().unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_default_mount_options_name_volume() {
+        let options = default_mount_options();
+        assert!(options.contains(&MountOption::CUSTOM("volname=zipfs".to_string())));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_default_mount_options_subtype() {
+        let options = default_mount_options();
+        assert!(options.contains(&MountOption::Subtype("zipfs".to_string())));
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_fill_crtime_is_epoch() {
+        let dir = TempDir::new().unwrap();
+        let metadata = fs::metadata(dir.path()).unwrap();
+
+        let mut attr = attr();
+        attr.crtime = metadata.modified().unwrap();
+        fill_crtime(&mut attr, &metadata);
+
+        assert_eq!(attr.crtime, UNIX_EPOCH);
+    }
+}
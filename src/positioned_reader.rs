@@ -0,0 +1,116 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    sync::Arc,
+};
+
+/// Random access to the bytes of an archive, such as a local file or an object read with
+/// HTTP range requests
+pub trait ReadAtSize: Send + Sync {
+    /// Read into `buf` from `offset`, returning how many bytes were read, 0 past the end
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Total length in bytes
+    fn size(&self) -> io::Result<u64>;
+
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    buf = &mut buf[read..];
+                    offset += read as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ReadAtSize for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        FileExt::read_at(self, buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+/// Bytes held in memory
+impl ReadAtSize for Arc<[u8]> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let start = self.len().min(offset as usize);
+        let end = self.len().min(start + buf.len());
+        buf[..end - start].copy_from_slice(&self[start..end]);
+        Ok(end - start)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+/// Reads from its own position with `pread(2)`, without loading the rest of the file.
+///
+/// Clones share the source but not the position, so an archive can be read from several
+/// threads at once without the reads interfering through the file offset.
+#[derive(Clone)]
+pub(crate) struct PositionedReader {
+    source: Arc<dyn ReadAtSize>,
+    offset: u64,
+}
+
+impl PositionedReader {
+    pub fn new(source: Box<dyn ReadAtSize>) -> Self {
+        Self {
+            source: source.into(),
+            offset: 0,
+        }
+    }
+
+    pub fn source(&self) -> &dyn ReadAtSize {
+        &*self.source
+    }
+}
+
+impl fmt::Debug for PositionedReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PositionedReader")
+            .field("offset", &self.offset)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Read for PositionedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.source.read_at(buf, self.offset)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for PositionedReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.source.size()?.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.offset.checked_add_signed(delta),
+        };
+
+        let Some(offset) = offset else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
+
+        self.offset = offset;
+        Ok(offset)
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tracing::{debug, info, warn};
+
+use crate::{
+    archive::read_archive,
+    filesystem::{is_archive_name, ArchiveCache, ArchiveMtimes, ArchiveOpener},
+};
+
+/// Stops a prefetch before its next archive once cancelled, shared by cloning
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+enum Preloaded {
+    Opened,
+    AlreadyCached,
+    CacheFull,
+    Failed,
+}
+
+/// Opens archives into the cache of the file system outside of its thread, only ever taking
+/// free slots so that nothing in use gets evicted
+#[derive(Clone)]
+pub(crate) struct Preloader {
+    pub(crate) open_archive: ArchiveOpener,
+    pub(crate) open_timeout: Option<Duration>,
+    pub(crate) cache: ArchiveCache,
+    pub(crate) mtimes: ArchiveMtimes,
+}
+
+impl Preloader {
+    fn preload(&self, path: &Path) -> Preloaded {
+        {
+            let cache = self.cache.lock().unwrap();
+            if cache.contains(path) {
+                return Preloaded::AlreadyCached;
+            }
+
+            if cache.len() == cache.cap().get() {
+                return Preloaded::CacheFull;
+            }
+        }
+
+        // NOTE: Taken before reading so that a change while reading is caught by a rescan
+        let mtime = fs::metadata(path).and_then(|metadata| metadata.modified());
+        let archive = match (self.open_archive)(path)
+            .map_err(Into::into)
+            .and_then(|source| read_archive(source, self.open_timeout))
+        {
+            Ok(archive) => archive,
+            Err(err) => {
+                debug!("Failed to preload {:?}: {}", path, err);
+                return Preloaded::Failed;
+            }
+        };
+
+        debug!("Preloaded {:?}: {} entries", path, archive.len());
+
+        if let Ok(mtime) = mtime {
+            self.mtimes
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), mtime);
+        }
+
+        // NOTE: Keep whatever the file system opened meanwhile, it is as fresh
+        let mut cache = self.cache.lock().unwrap();
+        if !cache.contains(path) && cache.len() < cache.cap().get() {
+            cache.put(path.to_path_buf(), archive);
+        }
+
+        Preloaded::Opened
+    }
+}
+
+/// Opens the archives of the data directories ahead of the first access, filling the cache
+/// of the file system it was made from until it is full
+pub struct Prefetch {
+    pub(crate) sources: Vec<PathBuf>,
+    pub(crate) archive_exts: Vec<String>,
+    pub(crate) chroot: bool,
+    pub(crate) preloader: Preloader,
+}
+
+impl Prefetch {
+    /// Run in a background thread, returning the number of archives opened
+    ///
+    /// NOTE: Threads don't survive forking, spawn only once daemonized
+    pub fn spawn(self, token: CancellationToken) -> JoinHandle<usize> {
+        thread::spawn(move || self.run(&token))
+    }
+
+    pub fn run(self, token: &CancellationToken) -> usize {
+        let mut pending = self.sources.clone();
+        if self.chroot {
+            pending.truncate(1);
+        }
+
+        let mut opened = 0;
+        while let Some(path) = pending.pop() {
+            if token.is_cancelled() {
+                info!("Prefetch cancelled after {} archives", opened);
+                return opened;
+            }
+
+            if path.is_dir() {
+                match fs::read_dir(&path) {
+                    Ok(entries) => {
+                        pending.extend(entries.filter_map(|entry| Some(entry.ok()?.path())))
+                    }
+                    Err(err) => warn!("Skipping {:?} in prefetch: {:?}", path, err),
+                }
+
+                continue;
+            }
+
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if !self.chroot && !is_archive_name(&name, &self.archive_exts) {
+                continue;
+            }
+
+            match self.preloader.preload(&path) {
+                Preloaded::Opened => opened += 1,
+                Preloaded::CacheFull => {
+                    info!(
+                        "Prefetch stopped with a full cache after {} archives",
+                        opened
+                    );
+                    return opened;
+                }
+                Preloaded::AlreadyCached | Preloaded::Failed => {}
+            }
+        }
+
+        info!("Prefetch finished after {} archives", opened);
+        opened
+    }
+}
+
+/// Opens the archives found in listed directories on a pool of background threads, so that
+/// the first access into them finds them cached
+pub(crate) struct CacheWarmer {
+    concurrency: usize,
+    /// Built on first use, as threads don't survive daemonizing
+    pool: Option<ThreadPool>,
+    /// Queued or being opened, not to be queued again meanwhile
+    pending: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl CacheWarmer {
+    pub(crate) fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency,
+            pool: None,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Queue `path` to be opened through `preloader`, unless it is queued already
+    pub(crate) fn warm(&mut self, path: PathBuf, preloader: Preloader) {
+        if !self.pending.lock().unwrap().insert(path.clone()) {
+            return;
+        }
+
+        let pool = match self.pool.take() {
+            Some(pool) => pool,
+            None => match ThreadPoolBuilder::new()
+                .num_threads(self.concurrency)
+                .thread_name(|index| format!("zipfs-warm-{}", index))
+                .build()
+            {
+                Ok(pool) => pool,
+                Err(err) => {
+                    warn!("Failed to start the cache warming threads: {}", err);
+                    self.pending.lock().unwrap().remove(&path);
+                    return;
+                }
+            },
+        };
+
+        let pending = self.pending.clone();
+        pool.spawn(move || {
+            if let Preloaded::Opened = preloader.preload(&path) {
+                debug!("Warmed up {:?}", path);
+            }
+
+            pending.lock().unwrap().remove(&path);
+        });
+
+        self.pool = Some(pool);
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    num::NonZeroU64,
+    sync::{
+        mpsc::{self, SendError, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::error;
+
+/// Share of a second of reading allowed in a burst after being idle
+const BURST_FRACTION: u64 = 10;
+
+/// Reply held back until its deadline
+type Delayed = (Instant, Box<dyn FnOnce() + Send>);
+
+/// Token bucket of bytes shared by every read of the mount. Reads larger than the bucket go
+/// into debt rather than being refused, so the limit is only approximate over short periods.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    burst: u64,
+    bucket: Mutex<Bucket>,
+    /// Spawned with the first delayed reply, so after daemonizing
+    timer: Mutex<Option<Sender<Delayed>>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while in debt
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: NonZeroU64) -> Self {
+        let burst = (bytes_per_second.get() / BURST_FRACTION).max(1);
+
+        Self {
+            bytes_per_second: bytes_per_second.get(),
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst as f64,
+                refilled: Instant::now(),
+            }),
+            timer: Mutex::new(None),
+        }
+    }
+
+    /// Send `reply` once `bytes` may be served, right away or from the timer thread, so that the
+    /// caller goes on with other requests in the meantime
+    pub fn delay(&self, bytes: u64, reply: impl FnOnce() + Send + 'static) {
+        let now = Instant::now();
+        let wait = self.reserve(bytes, now);
+        if wait.is_zero() {
+            return reply();
+        }
+
+        let delayed: Delayed = (now + wait, Box::new(reply));
+        let mut timer = self.timer.lock().unwrap();
+        let sent = match &*timer {
+            Some(sender) => sender.send(delayed),
+            None => match spawn_timer() {
+                Ok(sender) => timer.insert(sender).send(delayed),
+                Err(err) => {
+                    error!("Error spawning the rate limit timer: {:?}", err);
+                    Err(SendError(delayed))
+                }
+            },
+        };
+
+        // NOTE: Without a timer the reply goes out unlimited rather than never
+        if let Err(SendError((_, reply))) = sent {
+            reply();
+        }
+    }
+
+    /// Take `bytes` tokens at `now`, returning how long until they are paid for. Tokens are
+    /// taken right away so that concurrent readers queue up behind each other.
+    fn reserve(&self, bytes: u64, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.bytes_per_second as f64)
+            .min(self.burst as f64);
+        bucket.refilled = now;
+        bucket.tokens -= bytes as f64;
+
+        match bucket.tokens < 0.0 {
+            true => Duration::from_secs_f64(-bucket.tokens / self.bytes_per_second as f64),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+/// Thread sending the delayed replies in order. Their deadlines never go down, as every reply
+/// waits for the debt of the ones before it.
+fn spawn_timer() -> std::io::Result<Sender<Delayed>> {
+    let (sender, receiver) = mpsc::channel::<Delayed>();
+
+    thread::Builder::new()
+        .name("rate-limit".to_string())
+        .spawn(move || {
+            for (deadline, reply) in receiver {
+                thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                reply();
+            }
+        })?;
+
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_wait() {
+        let limiter = RateLimiter::new(NonZeroU64::new(1000).unwrap());
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(100, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(100, now), Duration::from_millis(100));
+        assert_eq!(limiter.reserve(100, now), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_refills_up_to_burst() {
+        let limiter = RateLimiter::new(NonZeroU64::new(1000).unwrap());
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(100, now), Duration::ZERO);
+        assert_eq!(
+            limiter.reserve(100, now + Duration::from_millis(100)),
+            Duration::ZERO
+        );
+
+        // NOTE: Idling longer doesn't save up more than the burst
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(100, later), Duration::ZERO);
+        assert_eq!(limiter.reserve(50, later), Duration::from_millis(50));
+    }
+}
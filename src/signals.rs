@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SIGNALS: [Signal; 3] = [Signal::Hangup, Signal::User1, Signal::User2];
+
+static PENDING: [AtomicBool; SIGNALS.len()] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// Signals acted upon by the main loop, the handler only marks them as pending
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    /// Reopen the log file
+    Hangup,
+    /// Log runtime statistics
+    User1,
+    /// Reset the statistics counters
+    User2,
+}
+
+impl Signal {
+    fn number(self) -> libc::c_int {
+        match self {
+            Signal::Hangup => libc::SIGHUP,
+            Signal::User1 => libc::SIGUSR1,
+            Signal::User2 => libc::SIGUSR2,
+        }
+    }
+
+    fn pending(self) -> &'static AtomicBool {
+        &PENDING[self as usize]
+    }
+
+    pub fn install(self) {
+        // SAFETY: The handler only stores to an atomic
+        unsafe { libc::signal(self.number(), on_signal as *const () as libc::sighandler_t) };
+    }
+
+    /// Whether the signal arrived since the last call
+    pub fn take(self) -> bool {
+        self.pending().swap(false, Ordering::Relaxed)
+    }
+}
+
+extern "C" fn on_signal(number: libc::c_int) {
+    for signal in SIGNALS {
+        if signal.number() == number {
+            signal.pending().store(true, Ordering::Relaxed);
+        }
+    }
+}
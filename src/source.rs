@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use fuser::{FileAttr, FileType};
+
+use crate::{error::ZipFsError, platform::fill_crtime, positioned_reader::ReadAtSize};
+
+/// Entry of a directory of a [`Source`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceEntry {
+    pub path: PathBuf,
+    pub name: OsString,
+    /// As found, links aren't followed
+    pub kind: FileType,
+}
+
+/// Where the files of the data directories are read from, the local disk unless replaced with
+/// [`crate::ZipFs::with_source`]. Paths start with the data directory they are found in.
+pub trait Source: Send + Sync {
+    /// Entries of the directory at `dir`, in no particular order
+    fn list(&self, dir: &Path) -> io::Result<Vec<SourceEntry>>;
+
+    /// Attributes of the file or directory at `path`, following links. The inode is left to
+    /// the file system.
+    fn stat(&self, path: &Path) -> io::Result<FileAttr>;
+
+    /// Open the file at `path` for reads at any offset
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadAtSize>>;
+}
+
+/// The local file system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskSource;
+
+impl Source for DiskSource {
+    fn list(&self, dir: &Path) -> io::Result<Vec<SourceEntry>> {
+        fs::read_dir(dir)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(SourceEntry {
+                    path: entry.path(),
+                    name: entry.file_name(),
+                    kind: file_type(entry.file_type()?),
+                })
+            })
+            .collect()
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<FileAttr> {
+        Ok(metadata_to_file_attrs(fs::metadata(path)?)?)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadAtSize>> {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+/// Files held in memory by path, with the directories leading to them implied, e.g. to
+/// exercise the file system without a data directory on disk or a mount
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    files: BTreeMap<PathBuf, Arc<[u8]>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file at `path`, replacing the one already there
+    pub fn insert(&mut self, path: impl Into<PathBuf>, data: impl Into<Arc<[u8]>>) {
+        self.files.insert(path.into(), data.into());
+    }
+
+    /// Like [`MemorySource::insert`], for chaining
+    pub fn with_file(mut self, path: impl Into<PathBuf>, data: impl Into<Arc<[u8]>>) -> Self {
+        self.insert(path, data);
+        self
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.files
+            .keys()
+            .any(|file| file != path && file.starts_with(path))
+    }
+}
+
+impl Source for MemorySource {
+    fn list(&self, dir: &Path) -> io::Result<Vec<SourceEntry>> {
+        if !self.is_dir(dir) {
+            return Err(match self.files.contains_key(dir) {
+                true => ZipFsError::NotADirectory.into(),
+                false => io::ErrorKind::NotFound.into(),
+            });
+        }
+
+        let mut entries = BTreeMap::new();
+        for file in self.files.keys() {
+            let Ok(relative) = file.strip_prefix(dir) else {
+                continue;
+            };
+
+            let mut components = relative.components();
+            let Some(name) = components.next() else {
+                continue;
+            };
+
+            let kind = match components.next() {
+                Some(_) => FileType::Directory,
+                None => FileType::RegularFile,
+            };
+            entries
+                .entry(name.as_os_str().to_os_string())
+                .or_insert(kind);
+        }
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, kind)| SourceEntry {
+                path: dir.join(&name),
+                name,
+                kind,
+            })
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<FileAttr> {
+        let (kind, size, perm, nlink) = match self.files.get(path) {
+            Some(data) => (FileType::RegularFile, data.len() as u64, 0o644, 1),
+            None if self.is_dir(path) => (FileType::Directory, 0, 0o755, 2),
+            None => return Err(io::ErrorKind::NotFound.into()),
+        };
+
+        Ok(FileAttr {
+            ino: 0,
+            size,
+            blocks: size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn ReadAtSize>> {
+        match self.files.get(path) {
+            Some(data) => Ok(Box::new(data.clone())),
+            None if self.is_dir(path) => Err(ZipFsError::IsADirectory.into()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+fn file_type(ft: fs::FileType) -> FileType {
+    match ft {
+        ft if ft.is_dir() => FileType::Directory,
+        ft if ft.is_symlink() => FileType::Symlink,
+        ft if ft.is_block_device() => FileType::BlockDevice,
+        ft if ft.is_char_device() => FileType::CharDevice,
+        ft if ft.is_fifo() => FileType::NamedPipe,
+        ft if ft.is_socket() => FileType::Socket,
+        _ => FileType::RegularFile,
+    }
+}
+
+/// Only regular files and directories are served
+pub(crate) fn map_ft(kind: FileType) -> Result<FileType, ZipFsError> {
+    match kind {
+        FileType::Directory | FileType::RegularFile => Ok(kind),
+        _ => Err(ZipFsError::UnsupportedFileType),
+    }
+}
+
+/// Time of a `stat` timestamp, whose nanoseconds count forward even before the epoch
+fn unix_time(secs: i64, nsecs: i64) -> SystemTime {
+    let nsecs = Duration::from_nanos(nsecs as u64);
+
+    match u64::try_from(secs) {
+        Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs) + nsecs,
+        Err(_) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nsecs,
+    }
+}
+
+fn metadata_to_file_attrs(metadata: fs::Metadata) -> Result<FileAttr, ZipFsError> {
+    let mut attrs = FileAttr {
+        ino: metadata.ino(),
+        size: metadata.size(),
+        blocks: metadata.blocks(),
+        atime: unix_time(metadata.atime(), metadata.atime_nsec()),
+        mtime: unix_time(metadata.mtime(), metadata.mtime_nsec()),
+        ctime: unix_time(metadata.ctime(), metadata.ctime_nsec()),
+        crtime: UNIX_EPOCH,
+        kind: map_ft(file_type(metadata.file_type()))?,
+        perm: metadata.permissions().mode() as u16,
+        nlink: metadata.nlink() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        rdev: metadata.rdev() as u32,
+        blksize: metadata.blksize() as u32,
+        flags: 0, // NOTE: macos only
+    };
+
+    fill_crtime(&mut attrs, &metadata);
+    Ok(attrs)
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::error::ZipFsError;
+
+/// Archive created through the mount, kept in memory until it is committed
+#[derive(Debug, Default)]
+pub struct StagedArchive {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl StagedArchive {
+    pub fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || self.dirs.contains(path)
+    }
+
+    pub fn file(&self, path: &Path) -> Option<&[u8]> {
+        self.files.get(path).map(Vec::as_slice)
+    }
+
+    /// Direct children of `dir` as `(name, is_dir)` pairs
+    pub fn children(&self, dir: &Path) -> Vec<(String, bool)> {
+        let is_child = |path: &Path| path.parent() == Some(dir);
+
+        let dirs = self.dirs.iter().filter(|path| is_child(path));
+        let files = self.files.keys().filter(|path| is_child(path));
+
+        dirs.map(|path| (path, true))
+            .chain(files.map(|path| (path, false)))
+            .filter_map(|(path, is_dir)| {
+                let name = path.file_name()?.to_string_lossy().to_string();
+                Some((name, is_dir))
+            })
+            .collect()
+    }
+
+    fn size(&self) -> u64 {
+        self.files.values().map(|data| data.len() as u64).sum()
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<(), ZipFsError> {
+        if self.dirs.contains(path) || self.files.contains_key(path) {
+            return Err(ZipFsError::AlreadyExists);
+        }
+
+        match path.parent() {
+            Some(parent) if self.is_dir(parent) => Ok(()),
+            Some(parent) if self.files.contains_key(parent) => Err(ZipFsError::NotADirectory),
+            _ => Err(ZipFsError::NotFound),
+        }
+    }
+
+    fn add_dir(&mut self, path: &Path) -> Result<(), ZipFsError> {
+        self.check_parent(path)?;
+        self.dirs.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &Path) -> Result<(), ZipFsError> {
+        self.check_parent(path)?;
+        self.files.insert(path.to_path_buf(), Vec::new());
+        Ok(())
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> Result<(), ZipFsError> {
+        if !self.dirs.contains(path) {
+            return Err(ZipFsError::NotFound);
+        }
+
+        if !self.children(path).is_empty() {
+            return Err(ZipFsError::DirectoryNotEmpty);
+        }
+
+        self.dirs.remove(path);
+        Ok(())
+    }
+
+    /// Write the archive next to `dest` and atomically move it into place
+    fn commit(&self, dest: &Path) -> zip::result::ZipResult<()> {
+        let mut tmp_name = dest.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = dest.with_file_name(tmp_name);
+
+        let options = SimpleFileOptions::default();
+        let mut zip = ZipWriter::new(fs::File::create(&tmp)?);
+
+        for dir in &self.dirs {
+            zip.add_directory(dir.to_string_lossy(), options)?;
+        }
+
+        for (path, data) in &self.files {
+            zip.start_file(path.to_string_lossy(), options)?;
+            zip.write_all(data)?;
+        }
+
+        zip.finish()?.sync_all()?;
+        fs::rename(&tmp, dest)?;
+        Ok(())
+    }
+}
+
+/// In-memory archives waiting for an `fsync` on their directory
+#[derive(Debug)]
+pub struct Staging {
+    max_size: u64,
+    archives: HashMap<PathBuf, StagedArchive>,
+}
+
+impl Staging {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            max_size,
+            archives: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, zip_path: &Path) -> Option<&StagedArchive> {
+        self.archives.get(zip_path)
+    }
+
+    /// Paths of the archives not committed yet
+    pub fn archives(&self) -> impl Iterator<Item = &Path> {
+        self.archives.keys().map(PathBuf::as_path)
+    }
+
+    fn get_mut(&mut self, zip_path: &Path) -> Result<&mut StagedArchive, ZipFsError> {
+        self.archives.get_mut(zip_path).ok_or(ZipFsError::NotFound)
+    }
+
+    pub fn create_archive(&mut self, zip_path: PathBuf) -> Result<(), ZipFsError> {
+        if zip_path.exists() || self.archives.contains_key(&zip_path) {
+            return Err(ZipFsError::AlreadyExists);
+        }
+
+        self.archives.insert(zip_path, StagedArchive::default());
+        Ok(())
+    }
+
+    pub fn remove_archive(&mut self, zip_path: &Path) -> Result<(), ZipFsError> {
+        self.archives
+            .remove(zip_path)
+            .map(|_| ())
+            .ok_or(ZipFsError::NotFound)
+    }
+
+    pub fn add_dir(&mut self, zip_path: &Path, path: &Path) -> Result<(), ZipFsError> {
+        self.get_mut(zip_path)?.add_dir(path)
+    }
+
+    pub fn remove_dir(&mut self, zip_path: &Path, path: &Path) -> Result<(), ZipFsError> {
+        self.get_mut(zip_path)?.remove_dir(path)
+    }
+
+    pub fn add_file(&mut self, zip_path: &Path, path: &Path) -> Result<(), ZipFsError> {
+        self.get_mut(zip_path)?.add_file(path)
+    }
+
+    pub fn write(
+        &mut self,
+        zip_path: &Path,
+        path: &Path,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), ZipFsError> {
+        let total_size = self.archives.values().map(StagedArchive::size).sum::<u64>();
+        let max_size = self.max_size;
+
+        let file = self.file_mut(zip_path, path)?;
+        let end = offset + data.len() as u64;
+        let growth = end.saturating_sub(file.len() as u64);
+
+        if total_size + growth > max_size {
+            return Err(ZipFsError::StagingFull { limit: max_size });
+        }
+
+        if growth > 0 {
+            file.resize(end as usize, 0);
+        }
+
+        file[offset as usize..end as usize].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Cut or zero-extend a staged file to `size` bytes
+    pub fn truncate(&mut self, zip_path: &Path, path: &Path, size: u64) -> Result<(), ZipFsError> {
+        let total_size = self.archives.values().map(StagedArchive::size).sum::<u64>();
+        let max_size = self.max_size;
+
+        let file = self.file_mut(zip_path, path)?;
+        let growth = size.saturating_sub(file.len() as u64);
+
+        if total_size + growth > max_size {
+            return Err(ZipFsError::StagingFull { limit: max_size });
+        }
+
+        file.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn file_mut(&mut self, zip_path: &Path, path: &Path) -> Result<&mut Vec<u8>, ZipFsError> {
+        let archive = self.get_mut(zip_path)?;
+        if archive.is_dir(path) {
+            return Err(ZipFsError::IsADirectory);
+        }
+
+        archive.files.get_mut(path).ok_or(ZipFsError::NotFound)
+    }
+
+    /// Write the archive to disk and stop tracking it in memory
+    pub fn commit(&mut self, zip_path: &Path) -> zip::result::ZipResult<()> {
+        if let Some(archive) = self.archives.get(zip_path) {
+            archive.commit(zip_path)?;
+            self.archives.remove(zip_path);
+        }
+
+        Ok(())
+    }
+}
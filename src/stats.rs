@@ -0,0 +1,183 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// An operation in flight, see [`Stats::begin_operation`]
+pub struct Operation(Arc<Stats>);
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters shared between the filesystem and its control interfaces
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    inodes: AtomicU64,
+    open_handles: AtomicU64,
+    open_dirs: AtomicU64,
+    /// Milliseconds from `started` to the start of the latest operation
+    last_operation: AtomicU64,
+    /// Operations started and not yet replied to
+    in_flight: AtomicU64,
+    bytes_served: AtomicU64,
+    /// Largest read the kernel asked for, bounded by max_read
+    largest_read_request: AtomicU64,
+    /// Bytes taken from decompressors, more than served when reads skip ahead or go back
+    bytes_decompressed: AtomicU64,
+    /// Bytes served per path, relative to its data directory
+    entry_reads: Mutex<HashMap<PathBuf, u64>>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            cache_hits: AtomicU64::default(),
+            cache_misses: AtomicU64::default(),
+            inodes: AtomicU64::default(),
+            open_handles: AtomicU64::default(),
+            open_dirs: AtomicU64::default(),
+            last_operation: AtomicU64::default(),
+            in_flight: AtomicU64::default(),
+            bytes_served: AtomicU64::default(),
+            bytes_decompressed: AtomicU64::default(),
+            largest_read_request: AtomicU64::default(),
+            entry_reads: Mutex::default(),
+        }
+    }
+}
+
+impl Stats {
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_inodes(&self, inodes: usize) {
+        self.inodes.store(inodes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_open(&self) {
+        self.open_handles.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_release(&self) {
+        self.open_handles.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn record_opendir(&self) {
+        self.open_dirs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_releasedir(&self) {
+        self.open_dirs.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Count an operation as in flight until the returned guard is dropped
+    pub fn begin_operation(self: &Arc<Self>) -> Operation {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_operation.fetch_max(now, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        Operation(self.clone())
+    }
+
+    pub fn record_read(&self, path: PathBuf, bytes: u64) {
+        self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+        *self.entry_reads.lock().unwrap().entry(path).or_default() += bytes;
+    }
+
+    pub fn record_read_request(&self, size: u32) {
+        self.largest_read_request
+            .fetch_max(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_decompressed(&self, bytes: u64) {
+        self.bytes_decompressed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.load(Ordering::Relaxed)
+    }
+
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.load(Ordering::Relaxed)
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn inodes(&self) -> u64 {
+        self.inodes.load(Ordering::Relaxed)
+    }
+
+    pub fn open_handles(&self) -> u64 {
+        self.open_handles.load(Ordering::Relaxed)
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn open_dirs(&self) -> u64 {
+        self.open_dirs.load(Ordering::Relaxed)
+    }
+
+    /// Time since the latest operation, or since mounting if there was none
+    pub fn idle(&self) -> Duration {
+        let last_operation = Duration::from_millis(self.last_operation.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last_operation)
+    }
+
+    pub fn bytes_served(&self) -> u64 {
+        self.bytes_served.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_decompressed(&self) -> u64 {
+        self.bytes_decompressed.load(Ordering::Relaxed)
+    }
+
+    pub fn largest_read_request(&self) -> u64 {
+        self.largest_read_request.load(Ordering::Relaxed)
+    }
+
+    /// The `count` paths most bytes were served from, most read first
+    pub fn top_entries(&self, count: usize) -> Vec<(PathBuf, u64)> {
+        let mut entries = self
+            .entry_reads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, bytes)| (path.clone(), *bytes))
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        entries.truncate(count);
+        entries
+    }
+
+    /// Zero the counters. Uptime and gauges such as the number of inodes are kept.
+    pub fn reset(&self) {
+        self.cache_hits.store(0, Ordering::Relaxed);
+        self.cache_misses.store(0, Ordering::Relaxed);
+        self.bytes_served.store(0, Ordering::Relaxed);
+        self.bytes_decompressed.store(0, Ordering::Relaxed);
+        self.largest_read_request.store(0, Ordering::Relaxed);
+        self.entry_reads.lock().unwrap().clear();
+    }
+}
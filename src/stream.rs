@@ -0,0 +1,338 @@
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+};
+
+use tracing::debug;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    archive::read_entry, chunk_cache::SharedChunkCache, positioned_reader::PositionedReader,
+};
+
+pub(crate) const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Decompressed contents of a zip entry, yielded lazily in chunks of up to 64 KiB.
+///
+/// The entry is decompressed on a worker thread which stays at most one chunk ahead of the
+/// consumer and stops once the stream is dropped.
+pub struct EntryStream {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+}
+
+impl EntryStream {
+    pub(crate) fn new(
+        mut archive: ZipArchive<PositionedReader>,
+        name: String,
+        password: Option<Arc<[u8]>>,
+    ) -> io::Result<Self> {
+        // NOTE: Fail early for missing or unreadable entries, such as ones with a wrong password
+        let index = archive
+            .index_for_name(&name)
+            .ok_or(ZipError::FileNotFound)?;
+        read_entry(&mut archive, index, password.as_deref())?;
+
+        let (tx, rx) = mpsc::sync_channel(0);
+        thread::spawn(move || {
+            let mut entry = match read_entry(&mut archive, index, password.as_deref()) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let _ = tx.send(Err(err.into()));
+                    return;
+                }
+            };
+
+            loop {
+                let mut chunk = Vec::with_capacity(CHUNK_SIZE as usize);
+                let result = (&mut entry).take(CHUNK_SIZE).read_to_end(&mut chunk);
+
+                // NOTE: Whatever was read before an error is still delivered
+                if !chunk.is_empty() && tx.send(Ok(chunk)).is_err() {
+                    break;
+                }
+
+                match result {
+                    Ok(0) => break,
+                    Ok(_) => continue,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self { chunks: rx })
+    }
+}
+
+impl Iterator for EntryStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.chunks.recv().ok()
+    }
+}
+
+/// Sequential reader over an [`EntryStream`], keeping track of its position so that a later
+/// read can continue where the previous one stopped
+struct EntryCursor {
+    stream: EntryStream,
+    chunk: Vec<u8>,
+    consumed: usize,
+    position: u64,
+}
+
+impl EntryCursor {
+    pub fn new(stream: EntryStream) -> Self {
+        Self {
+            stream,
+            chunk: Vec::new(),
+            consumed: 0,
+            position: 0,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The rest of the current chunk or the next one, `None` at the end of the entry
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let chunk = match self.consumed < self.chunk.len() {
+            true => self.chunk.split_off(self.consumed),
+            false => match self.stream.next() {
+                Some(chunk) => chunk?,
+                None => return Ok(None),
+            },
+        };
+
+        self.chunk.clear();
+        self.consumed = 0;
+        self.position += chunk.len() as u64;
+        Ok(Some(chunk))
+    }
+}
+
+impl Read for EntryCursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.consumed == self.chunk.len() {
+            let Some(chunk) = self.stream.next() else {
+                return Ok(0);
+            };
+
+            self.chunk = chunk?;
+            self.consumed = 0;
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.consumed);
+        buf[..read].copy_from_slice(&self.chunk[self.consumed..self.consumed + read]);
+
+        self.consumed += read;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// Seekable reader over the decompressed contents of a zip entry. Seeking forwards skips ahead
+/// in the decompressor, seeking backwards restarts it from the beginning of the entry.
+///
+/// With a chunk cache, every chunk the decompressor passes is kept, so that seeking back only
+/// restarts it for chunks that were evicted since.
+pub struct EntryReader {
+    archive: ZipArchive<PositionedReader>,
+    name: String,
+    password: Option<Arc<[u8]>>,
+    size: u64,
+    cursor: EntryCursor,
+    position: u64,
+    /// The cache shared by every entry, along with the key of this entry in it
+    chunks: Option<(SharedChunkCache, PathBuf)>,
+    decompressed: u64,
+}
+
+impl EntryReader {
+    pub(crate) fn new(
+        mut archive: ZipArchive<PositionedReader>,
+        name: String,
+        password: Option<Arc<[u8]>>,
+    ) -> io::Result<Self> {
+        // NOTE: From the central directory, readable without the password of encrypted entries
+        let index = archive
+            .index_for_name(&name)
+            .ok_or(ZipError::FileNotFound)?;
+        let size = archive.by_index_raw(index)?.size();
+        let stream = EntryStream::new(archive.clone(), name.clone(), password.clone())?;
+        let cursor = EntryCursor::new(stream);
+
+        Ok(Self {
+            archive,
+            name,
+            password,
+            size,
+            cursor,
+            position: 0,
+            chunks: None,
+            decompressed: 0,
+        })
+    }
+
+    /// Keep the decompressed chunks of the entry in `cache`, under `key`
+    pub(crate) fn with_chunk_cache(mut self, cache: SharedChunkCache, key: PathBuf) -> Self {
+        self.chunks = Some((cache, key));
+        self
+    }
+
+    /// Uncompressed size of the entry as recorded in the central directory
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Bytes taken from the decompressor so far, including the ones skipped over
+    pub fn decompressed(&self) -> u64 {
+        self.decompressed
+    }
+
+    fn restart(&mut self, position: u64) -> io::Result<()> {
+        debug!(
+            "Read of {:?} went back from {} to {}, restarting",
+            self.name,
+            self.cursor.position(),
+            position
+        );
+
+        let stream = EntryStream::new(
+            self.archive.clone(),
+            self.name.clone(),
+            self.password.clone(),
+        )?;
+        self.cursor = EntryCursor::new(stream);
+        Ok(())
+    }
+
+    fn read_streamed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cursor.position() > self.position {
+            self.restart(self.position)?;
+        }
+
+        let skip = self.position - self.cursor.position();
+        let skipped = io::copy(&mut (&mut self.cursor).take(skip), &mut io::sink())?;
+
+        // NOTE: Past the end of the entry the skip falls short and nothing is left to read
+        let read = match self.cursor.position() == self.position {
+            true => self.cursor.read(buf)?,
+            false => 0,
+        };
+
+        self.decompressed += skipped + read as u64;
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    fn read_cached(
+        &mut self,
+        cache: &SharedChunkCache,
+        key: &Path,
+        buf: &mut [u8],
+    ) -> io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+
+        let index = self.position / CHUNK_SIZE;
+        let cached = cache.lock().unwrap().get(key, index);
+
+        let chunk = match cached {
+            Some(chunk) => chunk,
+            None => match self.decompress_chunk(cache, key, index)? {
+                Some(chunk) => chunk,
+                None => return Ok(0),
+            },
+        };
+
+        let start = (self.position % CHUNK_SIZE) as usize;
+        let read = buf.len().min(chunk.len().saturating_sub(start));
+        buf[..read].copy_from_slice(&chunk[start..start + read]);
+
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    /// Run the decompressor up to chunk `index`, caching every chunk on the way.
+    ///
+    /// NOTE: The state of the decompressor can't be restored, a chunk behind it restarts it
+    fn decompress_chunk(
+        &mut self,
+        cache: &SharedChunkCache,
+        key: &Path,
+        index: u64,
+    ) -> io::Result<Option<Arc<[u8]>>> {
+        if self.cursor.position() > index * CHUNK_SIZE {
+            self.restart(index * CHUNK_SIZE)?;
+        }
+
+        loop {
+            let at = self.cursor.position();
+            let Some(chunk) = self.cursor.next_chunk()? else {
+                return Ok(None);
+            };
+
+            self.decompressed += chunk.len() as u64;
+            let chunk = Arc::<[u8]>::from(chunk);
+
+            // NOTE: A short chunk is only complete at the end of the entry, not before an error
+            if chunk.len() as u64 == CHUNK_SIZE || at + chunk.len() as u64 == self.size {
+                cache
+                    .lock()
+                    .unwrap()
+                    .insert(key, at / CHUNK_SIZE, chunk.clone());
+            }
+
+            if at / CHUNK_SIZE == index {
+                return Ok(Some(chunk));
+            }
+        }
+    }
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.chunks.take() {
+            Some((cache, key)) => {
+                let read = self.read_cached(&cache, &key, buf);
+                self.chunks = Some((cache, key));
+                read
+            }
+            None => self.read_streamed(buf),
+        }
+    }
+}
+
+impl Seek for EntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
+
+        self.position = position;
+        Ok(position)
+    }
+}
@@ -0,0 +1,44 @@
+use std::{
+    env, io,
+    os::{linux::net::SocketAddrExt, unix::net::SocketAddr, unix::net::UnixDatagram},
+    time::Duration,
+};
+
+use tracing::debug;
+
+/// Send `state` to the service manager, nothing happens outside of a `Type=notify` unit
+pub fn notify(state: &str) -> io::Result<()> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+
+    let path = path.to_string_lossy();
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+
+    debug!("sd_notify: {:?}", state);
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Tell the service manager we are up. The main PID is sent along as we may have forked.
+pub fn notify_ready() -> io::Result<()> {
+    notify(&format!("READY=1\nMAINPID={}", std::process::id()))
+}
+
+/// How often the watchdog expects a ping, if it is enabled for this process.
+///
+/// NOTE: Must be called before daemonizing, as WATCHDOG_PID names the process systemd started
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid != std::process::id().to_string() {
+            return None;
+        }
+    }
+
+    Some(Duration::from_micros(usec))
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::PathBuf,
+};
+
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::{
+    archive::{read_archive, read_entry, unsupported_method},
+    filesystem::is_archive_name,
+};
+
+/// Outcome of verifying a single archive
+#[derive(Debug, Default, Serialize)]
+pub struct ArchiveReport {
+    pub path: PathBuf,
+    pub entries: usize,
+    /// Entries read to the end with a matching CRC32
+    pub checked: usize,
+    /// Entries failing to decompress or to match their CRC32, with the reason
+    pub corrupt: Vec<(String, String)>,
+    pub unsupported: Vec<String>,
+    /// Skipped as there is no password to use
+    pub encrypted: Vec<String>,
+    /// Why the archive could not be opened at all
+    pub error: Option<String>,
+}
+
+impl ArchiveReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.corrupt.is_empty() && self.unsupported.is_empty()
+    }
+}
+
+/// Read every entry of every archive below `sources`, told apart by `archive_exts`, so that
+/// its CRC32 is checked, like `unzip -t`. Returns the archives that failed.
+///
+/// NOTE: Runs on a pool of its own, gone once verified, as this is done before daemonizing and
+/// a child forked afterwards would inherit the global pool without its threads
+pub fn verify_archives(sources: &[PathBuf], archive_exts: &[String]) -> io::Result<Vec<PathBuf>> {
+    let pool = ThreadPoolBuilder::new()
+        .thread_name(|index| format!("zipfs-verify-{}", index))
+        .build()
+        .map_err(io::Error::other)?;
+    let reports = pool.install(|| verify_tree(sources, archive_exts, false));
+
+    let failed = reports
+        .into_iter()
+        .filter_map(|report| match report.is_ok() {
+            true => {
+                info!("Verified {:?}: {} entries", report.path, report.checked);
+                None
+            }
+            false => {
+                warn!(
+                    error = ?report.error,
+                    corrupt = report.corrupt.len(),
+                    unsupported = report.unsupported.len(),
+                    "Verification of {:?} failed",
+                    report.path
+                );
+                Some(report.path)
+            }
+        })
+        .collect();
+    Ok(failed)
+}
+
+/// Verify the archives below `sources` in parallel on the current rayon pool, ordered by
+/// path. With `quick` only the central directories are read.
+pub fn verify_tree(
+    sources: &[PathBuf],
+    archive_exts: &[String],
+    quick: bool,
+) -> Vec<ArchiveReport> {
+    let mut archives = find_archives(sources, archive_exts);
+    archives.sort();
+
+    archives
+        .into_par_iter()
+        .map(|path| verify_archive(path, quick))
+        .collect()
+}
+
+fn find_archives(sources: &[PathBuf], archive_exts: &[String]) -> Vec<PathBuf> {
+    let mut archives = Vec::new();
+    let mut pending = sources.to_vec();
+
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            match fs::read_dir(&path) {
+                Ok(entries) => pending.extend(entries.filter_map(|entry| Some(entry.ok()?.path()))),
+                Err(err) => warn!("Skipping {:?} in verification: {:?}", path, err),
+            }
+
+            continue;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if is_archive_name(&name, archive_exts) {
+            archives.push(path);
+        }
+    }
+
+    archives
+}
+
+/// Open the archive the way the mount does, then read each entry unless `quick`
+fn verify_archive(path: PathBuf, quick: bool) -> ArchiveReport {
+    let mut report = ArchiveReport {
+        path,
+        ..Default::default()
+    };
+
+    let mut archive = match File::open(&report.path)
+        .map_err(Into::into)
+        .and_then(|file| read_archive(Box::new(file), None))
+    {
+        Ok(archive) => archive,
+        Err(err) => {
+            report.error = Some(err.to_string());
+            return report;
+        }
+    };
+
+    report.entries = archive.len();
+    for index in 0..archive.len() {
+        let (name, encrypted, unsupported) = match archive.by_index_raw(index) {
+            Ok(entry) => (
+                entry.name().to_string(),
+                entry.encrypted(),
+                unsupported_method(&entry).is_some(),
+            ),
+            Err(err) => {
+                report
+                    .corrupt
+                    .push((format!("#{}", index), err.to_string()));
+                continue;
+            }
+        };
+
+        if encrypted {
+            debug!("Skipping encrypted entry {:?} of {:?}", name, report.path);
+            report.encrypted.push(name);
+            continue;
+        }
+
+        if unsupported {
+            report.unsupported.push(name);
+            continue;
+        }
+
+        if quick {
+            continue;
+        }
+
+        // NOTE: The reader compares the CRC32 once the entry has been read to the end
+        let result = read_entry(&mut archive, index, None)
+            .map_err(io::Error::from)
+            .and_then(|mut entry| io::copy(&mut entry, &mut io::sink()));
+
+        match result {
+            Ok(_) => report.checked += 1,
+            Err(err) => report.corrupt.push((name, err.to_string())),
+        }
+    }
+
+    report
+}
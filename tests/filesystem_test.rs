@@ -1,24 +1,127 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::{FileExt, MetadataExt, PermissionsExt},
+            net::UnixStream,
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex, Once},
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
 use color_eyre::Result;
-use fuser::BackgroundSession;
+use fuser::MountOption;
 use temp_dir::TempDir;
-use zipfs::ZipFs;
+use zip::{write::SimpleFileOptions, ZipWriter};
+use zipfs::{
+    CacheConfig, EventListener, MountHandle, Requester, SecretString, SortOrder, ZipFsBuilder,
+};
+
+mod fixtures;
 
 const DATA_DIR: &str = "tests/data";
 
-fn mount() -> Result<(TempDir, BackgroundSession)> {
-    let mnt = TempDir::new()?;
+/// The fixture archives, generated once per test run
+fn data_dir() -> PathBuf {
+    static CREATE: Once = Once::new();
+    CREATE.call_once(|| fixtures::create(Path::new(DATA_DIR)).unwrap());
+    PathBuf::from(DATA_DIR)
+}
 
-    let guard = fuser::spawn_mount2(
-        ZipFs::new(PathBuf::from(DATA_DIR), 1024.try_into()?, None),
-        mnt.path(),
-        &[fuser::MountOption::RO],
-    )?;
+fn mount() -> Result<(TempDir, MountHandle)> {
+    mount_fs(ZipFsBuilder::new(data_dir()))
+}
+
+fn mount_fs(fs: ZipFsBuilder) -> Result<(TempDir, MountHandle)> {
+    mount_fs_with_options(fs, &[MountOption::RO])
+}
 
+fn mount_fs_with_options(
+    fs: ZipFsBuilder,
+    options: &[MountOption],
+) -> Result<(TempDir, MountHandle)> {
+    let mnt = TempDir::new()?;
+    let guard = fs.spawn_mount(mnt.path(), options)?;
     Ok((mnt, guard))
 }
 
+fn getxattr(path: &Path, name: &str) -> Result<Option<String>> {
+    match getxattr_bytes(path, name)? {
+        Some(value) => Ok(Some(String::from_utf8(value)?)),
+        None => Ok(None),
+    }
+}
+
+fn getxattr_bytes(path: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name)?;
+    let mut buf = vec![0u8; 256];
+
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+
+    if len < 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENODATA) => Ok(None),
+            _ => Err(err.into()),
+        };
+    }
+
+    buf.truncate(len as usize);
+    Ok(Some(buf))
+}
+
+/// Inodes and names of the raw entries of the directory at `path`, dots included
+fn getdents(path: &Path) -> Result<Vec<(u64, String)>> {
+    let dir = fs::File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut entries = Vec::new();
+
+    loop {
+        let len = unsafe {
+            libc::syscall(
+                libc::SYS_getdents64,
+                dir.as_raw_fd(),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if len == 0 {
+            return Ok(entries);
+        }
+
+        // NOTE: struct linux_dirent64, the name starts after the inode, offset, length and type
+        let mut at = 0;
+        while at < len as usize {
+            let ino = u64::from_ne_bytes(buf[at..at + 8].try_into()?);
+            let reclen = u16::from_ne_bytes(buf[at + 16..at + 18].try_into()?) as usize;
+            let name = &buf[at + 19..at + reclen];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            entries.push((ino, String::from_utf8(name.to_vec())?));
+            at += reclen;
+        }
+    }
+}
+
 #[test]
 fn test_mount() -> Result<()> {
     let (_mnt, guard) = mount()?;
@@ -26,9 +129,32 @@ fn test_mount() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_readdir_dot_entries() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    let ino = |path: &str| fs::metadata(mnt.path().join(path)).map(|metadata| metadata.ino());
+    for (dir, parent) in [
+        ("", ""),
+        ("stored.zip", ""),
+        ("stored.zip/some", "stored.zip"),
+        ("stored.zip/some/nested", "stored.zip/some"),
+    ] {
+        let entries = getdents(&mnt.path().join(dir))?;
+        assert_eq!(entries[0], (ino(dir)?, ".".to_string()), "{}", dir);
+        assert_eq!(entries[1], (ino(parent)?, "..".to_string()), "{}", dir);
+        assert!(entries[2..]
+            .iter()
+            .all(|(_, name)| name != "." && name != ".."));
+    }
+
+    drop(guard);
+    Ok(())
+}
+
 #[test]
 fn test_readdir_passthrough() -> Result<()> {
-    let data = PathBuf::from(DATA_DIR);
+    let data = data_dir();
     let (mnt, guard) = mount()?;
 
     let entries_data: Vec<_> = fs::read_dir(data)?.map(|e| e.unwrap()).collect();
@@ -36,10 +162,12 @@ fn test_readdir_passthrough() -> Result<()> {
 
     assert_eq!(entries_mnt.len(), entries_data.len());
 
-    let names_data = entries_data
+    // NOTE: Listings are sorted by name by default
+    let mut names_data = entries_data
         .into_iter()
         .map(|entry| entry.file_name())
         .collect::<Vec<_>>();
+    names_data.sort();
 
     let names_mnt = entries_mnt
         .into_iter()
@@ -52,9 +180,192 @@ fn test_readdir_passthrough() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_readdir_passthrough_file_types() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    // NOTE: The type comes from the listing itself, as used by `ls -F` and `find -type d`
+    for entry in fs::read_dir(mnt.path())? {
+        let entry = entry?;
+        let is_archive = entry.path().extension().is_some_and(|ext| ext == "zip");
+        assert_eq!(
+            entry.file_type()?.is_dir(),
+            is_archive,
+            "{:?}",
+            entry.path()
+        );
+    }
+
+    let output = Command::new("ls").arg("-F").arg(mnt.path()).output()?;
+    let listing = String::from_utf8(output.stdout)?;
+    assert!(listing.lines().any(|line| line == "stored.zip/"));
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_strip_ext() -> Result<()> {
+    let data = TempDir::new()?;
+
+    for (archive, content) in [("photos.zip", "photo"), ("music.zip", "song")] {
+        let mut zip = ZipWriter::new(fs::File::create(data.path().join(archive))?);
+        zip.start_file("file.txt", SimpleFileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+        zip.finish()?;
+    }
+
+    // NOTE: The real directory keeps its name, the archive is listed as is
+    fs::create_dir(data.path().join("music"))?;
+    fs::write(data.path().join("music/file.txt"), "real")?;
+
+    let fs = ZipFsBuilder::new(data.path()).strip_ext(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let mut names = fs::read_dir(mnt.path())?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    assert_eq!(names, ["music", "music.zip", "photos"]);
+
+    let read = |path: &str| fs::read_to_string(mnt.path().join(path));
+    assert!(mnt.path().join("photos").is_dir());
+    assert_eq!(read("photos/file.txt")?, "photo");
+    assert_eq!(read("music/file.txt")?, "real");
+    assert_eq!(read("music.zip/file.txt")?, "song");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_passthrough_only() -> Result<()> {
+    // NOTE: Stripping extensions only applies to archives, of which there are none
+    let fs = ZipFsBuilder::new(data_dir())
+        .passthrough_only(true)
+        .strip_ext(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    for zip in ["stored.zip", "compressed.zip", "corrupt.zip"] {
+        let path = mnt.path().join(zip);
+        assert!(path.is_file(), "{}", zip);
+        assert_eq!(fs::read(&path)?, fs::read(data_dir().join(zip))?, "{}", zip);
+        assert!(!path.join("some").exists(), "{}", zip);
+    }
+
+    let entry = fs::read_dir(mnt.path())?
+        .map(|entry| entry.unwrap())
+        .find(|entry| entry.file_name() == "stored.zip")
+        .unwrap();
+    assert!(entry.file_type()?.is_file());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_dual_view() -> Result<()> {
+    let data = TempDir::new()?;
+
+    for (archive, content) in [("photos.zip", "photo"), ("music.zip", "song")] {
+        let mut zip = ZipWriter::new(fs::File::create(data.path().join(archive))?);
+        zip.start_file("file.txt", SimpleFileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+        zip.finish()?;
+    }
+
+    // NOTE: The real directory wins over the view of music.zip
+    fs::create_dir(data.path().join("music.zip.d"))?;
+    fs::write(data.path().join("music.zip.d/file.txt"), "real")?;
+
+    let fs = ZipFsBuilder::new(data.path()).dual_view(".d");
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let mut names = fs::read_dir(mnt.path())?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    assert_eq!(
+        names,
+        ["music.zip", "music.zip.d", "photos.zip", "photos.zip.d"]
+    );
+
+    assert!(mnt.path().join("photos.zip").is_file());
+    assert_eq!(
+        fs::read(mnt.path().join("photos.zip"))?,
+        fs::read(data.path().join("photos.zip"))?
+    );
+
+    let read = |path: &str| fs::read_to_string(mnt.path().join(path));
+    assert!(mnt.path().join("photos.zip.d").is_dir());
+    assert_eq!(read("photos.zip.d/file.txt")?, "photo");
+    assert_eq!(read("music.zip.d/file.txt")?, "real");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_readdir_sort() -> Result<()> {
+    let data = TempDir::new()?;
+
+    // NOTE: Each order differs, including the one entries are written in
+    let entries = [("a", 3, 1), ("c", 10, 2), ("B", 1, 3)];
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("sorted.zip"))?);
+    for (name, size, day) in entries {
+        let file = fs::File::create(data.path().join(name))?;
+        file.set_len(size)?;
+        file.set_modified(std::time::UNIX_EPOCH + Duration::from_secs(day * 86400))?;
+
+        let time = zip::DateTime::from_date_and_time(2024, 1, day as u8, 0, 0, 0)?;
+        zip.start_file(name, SimpleFileOptions::default().last_modified_time(time))?;
+        zip.write_all(&vec![b'x'; size as usize])?;
+    }
+    zip.finish()?;
+
+    let list = |dir: &Path| -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if name != "sorted.zip" {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    };
+
+    for (sort, expected) in [
+        (SortOrder::Name, ["B", "a", "c"]),
+        (SortOrder::Size, ["c", "a", "B"]),
+        (SortOrder::Mtime, ["B", "c", "a"]),
+    ] {
+        let fs = ZipFsBuilder::new(data.path()).sort(sort);
+        let (mnt, guard) = mount_fs(fs)?;
+
+        assert_eq!(list(mnt.path())?, expected, "{:?}", sort);
+        assert_eq!(
+            list(&mnt.path().join("sorted.zip"))?,
+            expected,
+            "{:?}",
+            sort
+        );
+
+        drop(guard);
+    }
+
+    let fs = ZipFsBuilder::new(data.path()).sort(SortOrder::None);
+    let (mnt, guard) = mount_fs(fs)?;
+    assert_eq!(list(&mnt.path().join("sorted.zip"))?, ["a", "c", "B"]);
+
+    drop(guard);
+    Ok(())
+}
+
 #[test]
 fn test_read_passthrough() -> Result<()> {
-    let data = PathBuf::from(DATA_DIR);
+    let data = data_dir();
     let (mnt, guard) = mount()?;
 
     let content_data = fs::read_to_string(data.join("passthrough.txt"))?;
@@ -66,6 +377,49 @@ fn test_read_passthrough() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_passthrough_subsecond_mtime() -> Result<()> {
+    let data = TempDir::new()?;
+    let mtime = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+
+    let file = fs::File::create(data.path().join("file.txt"))?;
+    file.set_modified(mtime)?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let metadata = fs::metadata(mnt.path().join("file.txt"))?;
+    assert_eq!(metadata.modified()?, mtime);
+    assert_eq!(metadata.mtime_nsec(), 123_456_789);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_read_large_passthrough() -> Result<()> {
+    let data = TempDir::new()?;
+    let size = 1024 * 1024 * 1024;
+
+    // NOTE: Sparse file, only the tail is actually written
+    let file = fs::File::create(data.path().join("large.bin"))?;
+    file.set_len(size)?;
+    file.write_all_at(b"some content\n", size - 13)?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let file = fs::File::open(mnt.path().join("large.bin"))?;
+    let mut buf = vec![0; 4096];
+    file.read_exact_at(&mut buf, size - 4096)?;
+
+    assert!(buf[..4096 - 13].iter().all(|b| *b == 0));
+    assert_eq!(&buf[4096 - 13..], b"some content\n");
+
+    drop(guard);
+    Ok(())
+}
+
 #[test]
 fn test_readdir_zip() -> Result<()> {
     let (mnt, guard) = mount()?;
@@ -127,12 +481,82 @@ fn test_read_zip() -> Result<()> {
 }
 
 #[test]
-fn test_encrypted_zip_mounts_dirs() -> Result<()> {
+fn test_read_zip_direct_stored() -> Result<()> {
+    let fs = ZipFsBuilder::new(data_dir()).direct_stored(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let content = "some content\n".to_string().repeat(15);
+
+    // NOTE: Compressed entries fall back to decompressing, reopening hits the page cache
+    for zip in ["stored.zip", "compressed.zip", "stored.zip"] {
+        let path = mnt.path().join(zip).join("some/nested/file.txt");
+        assert_eq!(fs::read_to_string(&path)?, content, "{}", zip);
+
+        let mut buf = [0; 7];
+        fs::File::open(&path)?.read_exact_at(&mut buf, 13)?;
+        assert_eq!(&buf, b"some co", "{}", zip);
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_read_zip_random_access() -> Result<()> {
+    let data = TempDir::new()?;
+    let content = (0..64 * 1024)
+        .map(|line| format!("line {:011}\n", line))
+        .collect::<String>();
+    let size = content.len() as u64;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    zip.start_file("large.txt", SimpleFileOptions::default())?;
+    zip.write_all(content.as_bytes())?;
+    zip.finish()?;
+
+    let caches = CacheConfig {
+        content_bytes: 16 * 1024 * 1024,
+        ..CacheConfig::default()
+    };
+    let fs = ZipFsBuilder::new(data.path()).cache_config(caches);
+    let (mnt, guard) = mount_fs(fs)?;
+    let control = guard.control_handle().clone();
+
+    let file = fs::File::open(mnt.path().join("large.zip/large.txt"))?;
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut buf = vec![0; 4096];
+
+    for _ in 0..100 {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        let offset = state % (size - buf.len() as u64);
+        file.read_exact_at(&mut buf, offset)?;
+        assert_eq!(buf, content.as_bytes()[offset as usize..][..buf.len()]);
+    }
+
+    let decompressed = control.snapshot()["bytes_decompressed"].as_u64().unwrap();
+    assert!(decompressed > 0);
+    assert!(
+        decompressed <= 2 * size,
+        "decompressed {} bytes",
+        decompressed
+    );
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "lzma")]
+fn test_read_lzma_zip() -> Result<()> {
     let (mnt, guard) = mount()?;
 
-    for zip in ["stored.zip", "compressed.zip", "encrypted.zip"] {
-        let meta = fs::metadata(mnt.path().join(zip).join("some/nested/file.txt"))?;
-        assert_eq!(meta.is_dir(), zip == "encrypted.zip");
+    // NOTE: WinZip LZMA (method 14) and XZ (method 95) entries
+    for name in ["lzma.txt", "xz.txt"] {
+        let content = fs::read_to_string(mnt.path().join("lzma.zip/some/nested").join(name))?;
+        assert_eq!(content, "some content\n".to_string().repeat(15), "{}", name);
     }
 
     drop(guard);
@@ -140,12 +564,2066 @@ fn test_encrypted_zip_mounts_dirs() -> Result<()> {
 }
 
 #[test]
-fn test_corrupt_zip_mounts_dir() -> Result<()> {
+#[cfg(not(feature = "lzma"))]
+fn test_read_lzma_zip_unsupported() -> Result<()> {
     let (mnt, guard) = mount()?;
 
-    let meta = fs::metadata(mnt.path().join("corrupt.zip"))?;
-    assert!(meta.is_dir());
+    for name in ["lzma.txt", "xz.txt"] {
+        let path = mnt.path().join("lzma.zip/some/nested").join(name);
+        assert!(path.is_file(), "{}", name);
+
+        let err = fs::read(path).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP), "{}", name);
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_read_ppmd_zip_unsupported() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    // NOTE: Listed as a file, only reading it fails
+    let path = mnt.path().join("ppmd.zip/file.txt");
+    assert!(path.is_file());
+
+    let err = fs::read(path).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP));
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_wheel_metadata_alias() -> Result<()> {
+    let fs = ZipFsBuilder::new(data_dir()).archive_exts(["zip", "whl"]);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let wheel = mnt.path().join("example-1.0.0-py3-none-any.whl");
+    let mut names = fs::read_dir(&wheel)?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<Result<Vec<_>>>()?;
+    names.sort();
+    assert_eq!(names, ["METADATA", "example", "example-1.0.0.dist-info"]);
+
+    let alias = fs::read_to_string(wheel.join("METADATA"))?;
+    let metadata = fs::read_to_string(wheel.join("example-1.0.0.dist-info/METADATA"))?;
+    assert!(alias.starts_with("Metadata-Version: "));
+    assert_eq!(alias, metadata);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_wheel_with_several_dist_infos_has_no_alias() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("odd.whl"))?);
+    for name in ["a-1.0.dist-info/METADATA", "b-1.0.dist-info/METADATA"] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(name.as_bytes())?;
+    }
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path()).archive_exts(["whl"]);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    assert!(mnt.path().join("odd.whl").is_dir());
+    assert!(!mnt.path().join("odd.whl/METADATA").exists());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_readdir_jar() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("app.jar"))?);
+    for name in ["META-INF/MANIFEST.MF", "com/example/Main.class"] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(name.as_bytes())?;
+    }
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let meta_inf = fs::read_dir(mnt.path().join("app.jar/META-INF"))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(meta_inf, ["MANIFEST.MF"]);
+
+    let manifest = fs::read_to_string(mnt.path().join("app.jar/META-INF/MANIFEST.MF"))?;
+    assert_eq!(manifest, "META-INF/MANIFEST.MF");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_chroot_into_zip() -> Result<()> {
+    let data = TempDir::new()?;
+    fs::write(data.path().join("sibling.txt"), "sibling")?;
+
+    let archive = data.path().join("archive.bin");
+    let mut zip = ZipWriter::new(fs::File::create(&archive)?);
+    for name in ["top.txt", "dir/nested.txt"] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(name.as_bytes())?;
+    }
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(archive)
+        .chroot_into_zip(true)
+        .manifest(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let mut root = fs::read_dir(mnt.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    root.sort();
+    assert_eq!(root, ["dir", "top.txt"]);
+
+    assert!(fs::metadata(mnt.path())?.is_dir());
+    assert_eq!(fs::read_to_string(mnt.path().join("top.txt"))?, "top.txt");
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("dir/nested.txt"))?,
+        "dir/nested.txt"
+    );
+
+    // NOTE: Unknown paths inside archives are implicit directories, never files
+    for hidden in ["sibling.txt", ".zipfs/manifest.txt"] {
+        assert!(!mnt.path().join(hidden).is_file(), "{}", hidden);
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_archive_as_data_dir() -> Result<()> {
+    let (mnt, guard) = mount_fs(ZipFsBuilder::new(data_dir().join("stored.zip")))?;
+
+    let root = fs::read_dir(mnt.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(root, ["some"]);
+
+    let read = fs::read_to_string(mnt.path().join("some/nested/file.txt"))?;
+    assert_eq!(read, "some content\n".repeat(15));
+
+    drop(guard);
+
+    let Err(err) = ZipFsBuilder::new(data_dir().join("passthrough.txt")).build() else {
+        panic!("a file without an archive extension was mounted");
+    };
+    assert!(err.to_string().contains("--chroot-into-zip"), "{}", err);
+    Ok(())
+}
+
+/// Archive bytes held in memory, standing in for a remote object
+struct MemorySource(Vec<u8>);
+
+// NOTE: Not imported, its read_at would be ambiguous with the one of FileExt
+impl zipfs::ReadAtSize for MemorySource {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let start = self.0.len().min(offset as usize);
+        let end = self.0.len().min(start + buf.len());
+        buf[..end - start].copy_from_slice(&self.0[start..end]);
+        Ok(end - start)
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.0.len() as u64)
+    }
+}
+
+#[test]
+fn test_archive_source() -> Result<()> {
+    let data = TempDir::new()?;
+    let compressed = fs::read(data_dir().join("compressed.zip"))?;
+
+    // NOTE: Only listed from the data directory, the bytes come from the source
+    fs::write(data.path().join("remote.zip"), "")?;
+
+    let fs = ZipFsBuilder::new(data.path()).archive_source(move |path| match path.file_name() {
+        Some(name) if name == "remote.zip" => Ok(Box::new(MemorySource(compressed.clone()))),
+        _ => Err(std::io::ErrorKind::NotFound.into()),
+    });
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let path = mnt.path().join("remote.zip/some/nested/file.txt");
+    assert_eq!(fs::read_to_string(&path)?, "some content\n".repeat(15));
+
+    let file = fs::File::open(&path)?;
+    let mut buf = [0; 12];
+    file.read_exact_at(&mut buf, 13)?;
+    assert_eq!(&buf, b"some content");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_dot_components_in_entry_names() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("dots.zip"))?);
+    for name in ["./foo", "a/../b", "../escaped", "."] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(name.as_bytes())?;
+    }
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let mut names = fs::read_dir(mnt.path().join("dots.zip"))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    names.sort();
+    assert_eq!(names, vec!["b", "foo"]);
+
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("dots.zip/foo"))?,
+        "./foo"
+    );
+    assert_eq!(fs::read_to_string(mnt.path().join("dots.zip/b"))?, "a/../b");
+    assert!(!mnt.path().join("dots.zip/a").exists());
+    assert!(!mnt.path().join("escaped").exists());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limit() -> Result<()> {
+    const LIMIT: u64 = 1024 * 1024;
+
+    let data = TempDir::new()?;
+    let content = vec![0xa5; 2 * LIMIT as usize];
+
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    zip.start_file("large.bin", options)?;
+    zip.write_all(&content)?;
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path()).rate_limit(LIMIT);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let start = Instant::now();
+    let read = fs::read(mnt.path().join("large.zip/large.bin"))?;
+    let elapsed = start.elapsed();
+    assert_eq!(read, content);
+
+    let throughput = read.len() as f64 / elapsed.as_secs_f64();
+    assert!(
+        throughput <= LIMIT as f64 * 1.1,
+        "read at {:.0} bytes per second",
+        throughput
+    );
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limit_leaves_other_operations() -> Result<()> {
+    const LIMIT: u64 = 64 * 1024;
+
+    let data = TempDir::new()?;
+    let content = vec![0xa5; 4 * LIMIT as usize];
+
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    zip.start_file("large.bin", options)?;
+    zip.write_all(&content)?;
+    zip.start_file("small.txt", options)?;
+    zip.write_all(b"small")?;
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path()).rate_limit(LIMIT);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let large = mnt.path().join("large.zip/large.bin");
+    let reader = thread::spawn(move || fs::read(large));
+    thread::sleep(Duration::from_millis(500));
+
+    // NOTE: The large read waits for seconds, the session goes on meanwhile
+    let start = Instant::now();
+    assert!(fs::metadata(mnt.path().join("large.zip/small.txt"))?.is_file());
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(!reader.is_finished());
+
+    assert_eq!(reader.join().unwrap()?, content);
 
     drop(guard);
     Ok(())
 }
+
+#[test]
+fn test_read_truncated_zip() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    let mut file = fs::File::open(mnt.path().join("truncated.zip/file.txt"))?;
+    let mut buf = vec![0; 4096];
+    let read = file.read(&mut buf)?;
+
+    let content = "some content\n".to_string().repeat(15);
+    assert!(read >= content.len());
+    assert_eq!(&buf[..content.len()], content.as_bytes());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_entry_name_too_long() -> Result<()> {
+    let data = TempDir::new()?;
+    let long_a = format!("{}a", "x".repeat(299));
+    let long_b = format!("{}b", "x".repeat(299));
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("long.zip"))?);
+    let entries = [
+        (long_a.as_str(), "a\n"),
+        (long_b.as_str(), "b\n"),
+        ("short.txt", "s\n"),
+    ];
+    for (name, content) in entries {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+    }
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let err = fs::metadata(mnt.path().join("long.zip").join(&long_a)).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENAMETOOLONG));
+
+    let names = fs::read_dir(mnt.path().join("long.zip"))?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<Result<Vec<_>>>()?;
+    let short_a = format!("{}~1", "x".repeat(253));
+    let short_b = format!("{}~2", "x".repeat(253));
+    assert_eq!(names, ["short.txt", short_a.as_str(), short_b.as_str()]);
+
+    let read = |name: &str| fs::read_to_string(mnt.path().join("long.zip").join(name));
+    assert_eq!(read(&short_a)?, "a\n");
+    assert_eq!(read(&short_b)?, "b\n");
+    drop(guard);
+
+    let fs = ZipFsBuilder::new(data.path()).max_entry_name_length(16);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let names = fs::read_dir(mnt.path().join("long.zip"))?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(names, ["short.txt", "xxxxxxxxxxxxxx~1", "xxxxxxxxxxxxxx~2"]);
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("long.zip/xxxxxxxxxxxxxx~2"))?,
+        "b\n"
+    );
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_read_mismatched_local_header() -> Result<()> {
+    let content = "some content\n".to_string().repeat(15);
+
+    let (mnt, guard) = mount()?;
+    let read = fs::read_to_string(mnt.path().join("mismatch.zip/file.txt"))?;
+    assert_eq!(read, content);
+    drop(guard);
+
+    let fs = ZipFsBuilder::new(data_dir()).strict(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let err = fs::read(mnt.path().join("mismatch.zip/file.txt")).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EIO));
+
+    let read = fs::read_to_string(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    assert_eq!(read, content);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_extract() -> Result<()> {
+    let dest = TempDir::new()?;
+    let report = ZipFsBuilder::new(data_dir()).extract(dest.path())?;
+    assert!(report.files > 0);
+
+    let (mnt, guard) = mount()?;
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(mnt.path().join(&dir))? {
+            let relative = dir.join(entry?.file_name());
+            let mounted = mnt.path().join(&relative);
+            let extracted = dest.path().join(&relative);
+
+            if mounted.is_dir() {
+                assert!(extracted.is_dir(), "{:?}", relative);
+                pending.push(relative.clone());
+            } else {
+                match fs::read(&mounted) {
+                    Ok(data) => assert_eq!(fs::read(&extracted)?, data, "{:?}", relative),
+                    Err(_) => {
+                        // NOTE: Entries failing to read, such as PPMd ones, are left out
+                        assert!(!extracted.exists(), "{:?}", relative);
+                        assert!(report.failed.iter().any(|(path, _)| path == &relative));
+                        continue;
+                    }
+                }
+            }
+
+            let (mounted, extracted) = (fs::metadata(&mounted)?, fs::metadata(&extracted)?);
+            assert_eq!(
+                mounted.permissions().mode() & 0o7777,
+                extracted.permissions().mode() & 0o7777,
+                "{:?}",
+                relative
+            );
+            assert_eq!(mounted.modified()?, extracted.modified()?, "{:?}", relative);
+        }
+    }
+
+    drop(guard);
+
+    // NOTE: Read-only like the mount, the directories wouldn't be removed otherwise
+    Command::new("chmod")
+        .arg("-R")
+        .arg("u+w")
+        .arg(dest.path())
+        .status()?;
+    Ok(())
+}
+
+#[test]
+fn test_zip_bomb_refused() -> Result<()> {
+    let (mnt, guard) = mount()?;
+    let path = mnt.path().join("bomb.zip/zeros.bin");
+    assert!(path.is_file());
+
+    let err = fs::read(&path).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EFBIG));
+    drop(guard);
+
+    for fs in [
+        ZipFsBuilder::new(data_dir()).allow_bombs(true),
+        ZipFsBuilder::new(data_dir()).max_ratio(u64::MAX),
+    ] {
+        let (mnt, guard) = mount_fs(fs)?;
+        let data = fs::read(mnt.path().join("bomb.zip/zeros.bin"))?;
+        assert_eq!(data.len(), 4 * 1024 * 1024);
+        assert!(data.iter().all(|&byte| byte == 0));
+        drop(guard);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_read_data_descriptor_zip() -> Result<()> {
+    let content = "some content\n".to_string().repeat(15);
+
+    // NOTE: The local header has no sizes, neither reading in place nor validating may rely on
+    // them
+    for fs in [
+        ZipFsBuilder::new(data_dir()),
+        ZipFsBuilder::new(data_dir()).direct_stored(true),
+        ZipFsBuilder::new(data_dir()).strict(true),
+    ] {
+        let (mnt, guard) = mount_fs(fs)?;
+        let path = mnt.path().join("descriptor.zip/file.txt");
+        assert_eq!(fs::read_to_string(&path)?, content);
+
+        let mut buf = [0; 7];
+        fs::File::open(&path)?.read_exact_at(&mut buf, 13)?;
+        assert_eq!(&buf, b"some co");
+
+        drop(guard);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest() -> Result<()> {
+    let fs = ZipFsBuilder::new(data_dir()).manifest(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let root = fs::read_dir(mnt.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert!(root.contains(&".zipfs".into()));
+    assert!(root.contains(&"passthrough.txt".into()));
+
+    let manifest = fs::read_to_string(mnt.path().join(".zipfs/manifest.txt"))?;
+    let lines = manifest.lines().collect::<Vec<_>>();
+
+    assert!(lines.contains(&"passthrough.txt"));
+    assert!(lines.contains(&"stored.zip/some/nested/file.txt"));
+    assert!(lines.contains(&"compressed.zip/some/nested/file.txt"));
+    assert!(!lines.contains(&"stored.zip"));
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_negative_cache_ttl() -> Result<()> {
+    let data = TempDir::new()?;
+    let caches = CacheConfig {
+        negative_ttl: Duration::from_secs(60),
+        ..CacheConfig::default()
+    };
+    let (mnt, guard) = mount_fs(ZipFsBuilder::new(data.path()).cache_config(caches))?;
+
+    assert!(!mnt.path().join("late.txt").exists());
+
+    // NOTE: The kernel keeps answering from the negative entry rather than asking again
+    fs::write(data.path().join("late.txt"), "late\n")?;
+    assert!(!mnt.path().join("late.txt").exists());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_negative_lookups_skip_archive() -> Result<()> {
+    let (mnt, guard) = mount()?;
+    let control = guard.control_handle().clone();
+    let archive_lookups = || {
+        let snapshot = control.snapshot();
+        snapshot["cache_hits"].as_u64().unwrap() + snapshot["cache_misses"].as_u64().unwrap()
+    };
+
+    let missing = mnt.path().join("stored.zip/some/missing.swp");
+    assert!(!missing.exists());
+    let before = archive_lookups();
+
+    for _ in 0..99 {
+        assert!(!missing.exists());
+    }
+    assert_eq!(archive_lookups(), before);
+
+    // NOTE: Implicit directories still exist, only missing names are remembered
+    assert!(mnt.path().join("stored.zip/some/nested").is_dir());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_expose_metadata() -> Result<()> {
+    let caches = CacheConfig {
+        archive_entries: 1234.try_into()?,
+        ..CacheConfig::default()
+    };
+    let fs = ZipFsBuilder::new(data_dir())
+        .cache_config(caches)
+        .expose_metadata(true);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let metadata_dir = fs::read_dir(mnt.path().join(".zipfs"))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(metadata_dir, vec!["status"]);
+
+    let status = fs::read_to_string(mnt.path().join(".zipfs/status"))?;
+    let status: serde_json::Value = serde_json::from_str(&status)?;
+    assert_eq!(status["options"]["archive_cache_entries"], 1234);
+    assert_eq!(status["stats"]["cache_capacity"], 1234);
+
+    // NOTE: The generation of the inodes stays the same for the whole mount
+    let generation = status["generation"].as_u64().unwrap();
+    assert_ne!(generation, 0);
+
+    let status = fs::read_to_string(mnt.path().join(".zipfs/status"))?;
+    let status: serde_json::Value = serde_json::from_str(&status)?;
+    assert_eq!(status["generation"], generation);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_virtual_root() -> Result<()> {
+    let fs = ZipFsBuilder::new(data_dir()).virtual_root("test");
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let root = fs::read_dir(mnt.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(root, ["test"]);
+
+    let entries = fs::read_dir(mnt.path().join("test"))?.count();
+    assert_eq!(entries, fs::read_dir(data_dir())?.count());
+
+    let content = fs::read_to_string(mnt.path().join("test/stored.zip/some/nested/file.txt"))?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+
+    // NOTE: Nothing is served without the prefix
+    for path in [
+        "stored.zip",
+        "stored.zip/some/nested/file.txt",
+        "passthrough.txt",
+    ] {
+        let err = fs::metadata(mnt.path().join(path)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+    }
+
+    drop(guard);
+    assert!(ZipFsBuilder::new(data_dir())
+        .virtual_root("nested/root")
+        .build()
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_union_data_dirs() -> Result<()> {
+    let first = TempDir::new()?;
+    let second = TempDir::new()?;
+
+    fs::create_dir(first.path().join("dir"))?;
+    fs::create_dir(second.path().join("dir"))?;
+    fs::write(first.path().join("dir/first.txt"), "first")?;
+    fs::write(second.path().join("dir/second.txt"), "second")?;
+    fs::write(first.path().join("shared.txt"), "first")?;
+    fs::write(second.path().join("shared.txt"), "second")?;
+
+    let mut zip = ZipWriter::new(fs::File::create(second.path().join("archive.zip"))?);
+    zip.start_file("file.txt", SimpleFileOptions::default())?;
+    zip.write_all(b"zipped")?;
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(first.path()).data_dir(second.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let mut root = fs::read_dir(mnt.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    root.sort();
+    assert_eq!(root, ["archive.zip", "dir", "shared.txt"]);
+
+    let mut dir = fs::read_dir(mnt.path().join("dir"))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    dir.sort();
+    assert_eq!(dir, ["first.txt", "second.txt"]);
+
+    let read = |path: &str| fs::read_to_string(mnt.path().join(path));
+    assert_eq!(read("shared.txt")?, "first");
+    assert_eq!(read("dir/second.txt")?, "second");
+    assert_eq!(read("archive.zip/file.txt")?, "zipped");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_umask() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("private.zip"))?);
+    zip.start_file(
+        "file.txt",
+        SimpleFileOptions::default().unix_permissions(0o400),
+    )?;
+    zip.write_all(b"private")?;
+    zip.finish()?;
+
+    for (umask, expected) in [(0o000, 0o444), (0o022, 0o444), (0o077, 0o400)] {
+        let fs = ZipFsBuilder::new(data.path()).umask(umask);
+        let (mnt, guard) = mount_fs(fs)?;
+
+        let metadata = fs::metadata(mnt.path().join("private.zip/file.txt"))?;
+        assert_eq!(metadata.permissions().mode() & 0o7777, expected);
+
+        drop(guard);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_exclude_zip_entry() -> Result<()> {
+    let data = TempDir::new()?;
+    fs::write(data.path().join("download.part"), "partial")?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("archive.zip"))?);
+    for name in ["file.txt", "file.txt.sha256", "tmp/cache.txt"] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(name.as_bytes())?;
+    }
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path()).exclude(["*.part", "*.sha256", "**/tmp"]);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let root = fs::read_dir(mnt.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(root, ["archive.zip"]);
+
+    let archive = fs::read_dir(mnt.path().join("archive.zip"))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(archive, ["file.txt"]);
+
+    for hidden in [
+        "download.part",
+        "archive.zip/file.txt.sha256",
+        "archive.zip/tmp",
+    ] {
+        let err = fs::metadata(mnt.path().join(hidden)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT), "{}", hidden);
+    }
+
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("archive.zip/file.txt"))?,
+        "file.txt"
+    );
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_exclude_pattern_zip_entry() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("app.jar"))?);
+    for name in [
+        "META-INF/MANIFEST.MF",
+        "com/example/Main.class",
+        "com/example/Main.java",
+        "com/example/generated/Stub.class",
+        "lib/Other.class",
+    ] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        zip.write_all(name.as_bytes())?;
+    }
+    zip.finish()?;
+
+    // NOTE: Only entries matching every pattern are hidden, lib/Other.class is kept
+    let fs = ZipFsBuilder::new(data.path()).exclude_patterns(["*.class", "com/**"]);
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let mut example = fs::read_dir(mnt.path().join("app.jar/com/example"))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<Vec<_>, _>>()?;
+    example.sort();
+    assert_eq!(example, ["Main.java", "generated"]);
+
+    let generated = fs::read_dir(mnt.path().join("app.jar/com/example/generated"))?;
+    assert_eq!(generated.count(), 0);
+
+    for hidden in [
+        "app.jar/com/example/Main.class",
+        "app.jar/com/example/generated/Stub.class",
+    ] {
+        let err = fs::metadata(mnt.path().join(hidden)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT), "{}", hidden);
+    }
+
+    for visible in [
+        "app.jar/META-INF/MANIFEST.MF",
+        "app.jar/com/example/Main.java",
+        "app.jar/lib/Other.class",
+    ] {
+        let name = visible.strip_prefix("app.jar/").unwrap();
+        assert_eq!(fs::read_to_string(mnt.path().join(visible))?, name);
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_read_only_clears_write_bits() -> Result<()> {
+    let data = TempDir::new()?;
+    let path = data.path().join("file.txt");
+    fs::write(&path, "content")?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let metadata = fs::metadata(mnt.path().join("file.txt"))?;
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o444);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_zip_mounts_dirs() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    for zip in ["stored.zip", "compressed.zip", "encrypted.zip"] {
+        let meta = fs::metadata(mnt.path().join(zip).join("some/nested/file.txt"))?;
+        assert_eq!(meta.is_dir(), zip == "encrypted.zip");
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_zip_with_password() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let passwords = Path::new(DATA_DIR).join("passwords.toml");
+    let args = ["--passwords", passwords.to_str().unwrap()];
+    let mut child = spawn_ready(&data_dir(), mnt.path(), &args)?;
+
+    let content = fs::read_to_string(mnt.path().join("encrypted.zip/some/nested/file.txt"))?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_builder_password() -> Result<()> {
+    let fs = ZipFsBuilder::new(data_dir()).password("encrypted.zip", "password");
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let content = fs::read_to_string(mnt.path().join("encrypted.zip/some/nested/file.txt"))?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+
+    guard.unmount()?;
+    assert!(!mnt.path().join("encrypted.zip").exists());
+    Ok(())
+}
+
+/// Unmount `mnt` behind the back of its mount handle
+fn unmount_externally(mnt: &Path) {
+    let unmounted = ["fusermount3", "fusermount"].iter().any(|program| {
+        Command::new(program)
+            .arg("-u")
+            .arg(mnt)
+            .status()
+            .is_ok_and(|status| status.success())
+    });
+    assert!(unmounted, "{:?} could not be unmounted", mnt);
+}
+
+#[test]
+fn test_mount_handle() -> Result<()> {
+    let (mnt, guard) = mount()?;
+    assert_eq!(guard.mount_point(), mnt.path());
+    assert!(guard.is_healthy());
+
+    fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    let stats = guard.stats();
+    assert!(stats.cached_archives >= 1);
+    assert!(stats.misses >= 1);
+
+    unmount_externally(mnt.path());
+    guard.wait();
+    assert!(!guard.is_healthy());
+
+    guard.unmount()?;
+    Ok(())
+}
+
+#[test]
+fn test_password_provider() -> Result<()> {
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let provider = {
+        let asked = asked.clone();
+        move |archive: &Path, entry: Option<&str>| {
+            let mut asked = asked.lock().unwrap();
+            asked.push((archive.to_path_buf(), entry.map(str::to_string)));
+
+            // NOTE: Wrong the first time, as if the vault was out of date
+            let password = match asked.len() {
+                1 => "wrong",
+                _ => "password",
+            };
+            Some(SecretString::from(password))
+        }
+    };
+
+    let fs = ZipFsBuilder::new(data_dir()).password_provider(Box::new(provider));
+    let (mnt, guard) = mount_fs(fs)?;
+
+    // NOTE: Only encrypted entries ask for a password
+    fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    assert!(asked.lock().unwrap().is_empty());
+
+    let file = mnt.path().join("encrypted.zip/some/nested/file.txt");
+    assert!(fs::read(&file).is_err());
+    assert!(fs::read(&file).is_err());
+    assert_eq!(
+        *asked.lock().unwrap(),
+        [(
+            PathBuf::from("encrypted.zip"),
+            Some("some/nested/file.txt".to_string())
+        )]
+    );
+
+    // NOTE: Asked again once the archive is evicted, after the kernel forgot the entry. Waiting
+    // for exactly its TTL of a second races with it expiring.
+    guard.control_handle().reload();
+    thread::sleep(Duration::from_secs(2));
+
+    let content = fs::read_to_string(&file)?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+    assert_eq!(asked.lock().unwrap().len(), 2);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_event_listener() -> Result<()> {
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl EventListener for Recorder {
+        fn on_open(&self, path: &Path, requester: Requester) {
+            let event = format!("open {} by {}", path.display(), requester.uid);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_read(&self, path: &Path, offset: u64, len: usize, _requester: Requester) {
+            let event = format!("read {} at {} for {}", path.display(), offset, len);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_readdir(&self, path: &Path, _requester: Requester) {
+            let event = format!("readdir {}", path.display());
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_error(&self, path: &Path, errno: i32, _requester: Requester) {
+            let event = format!("error {} {}", path.display(), errno);
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    let recorder = Recorder::default();
+    let fs = ZipFsBuilder::new(data_dir()).event_listener(recorder.clone());
+    let (mnt, guard) = mount_fs(fs)?;
+
+    fs::read_dir(mnt.path().join("stored.zip"))?.count();
+    fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    assert!(fs::metadata(mnt.path().join("stored.zip/missing")).is_err());
+
+    let uid = unsafe { libc::getuid() };
+    let events = recorder.0.lock().unwrap().clone();
+    for event in [
+        "readdir /stored.zip".to_string(),
+        format!("open /stored.zip/some/nested/file.txt by {}", uid),
+        "read /stored.zip/some/nested/file.txt at 0 for 195".to_string(),
+        format!("error /stored.zip {}", libc::ENOENT),
+    ] {
+        assert!(events.contains(&event), "{} not in {:?}", event, events);
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_corrupt_zip_mounts_dir() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    let meta = fs::metadata(mnt.path().join("corrupt.zip"))?;
+    assert!(meta.is_dir());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_open_timeout_presents_empty_dir() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("huge.zip"))?);
+    for i in 0..200_000 {
+        zip.start_file(format!("entry-{i}"), SimpleFileOptions::default())?;
+    }
+    zip.finish()?.flush()?;
+
+    let fs = ZipFsBuilder::new(data.path()).open_timeout(Duration::from_millis(1));
+    let (mnt, guard) = mount_fs(fs)?;
+
+    let start = Instant::now();
+    let entries = fs::read_dir(mnt.path().join("huge.zip"))?.count();
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(entries, 0);
+
+    let meta = fs::metadata(mnt.path().join("huge.zip"))?;
+    assert!(meta.is_dir());
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_writable_archives_commit_on_fsync() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let fs = ZipFsBuilder::new(data.path()).writable_archives(1024 * 1024);
+    let (mnt, guard) = mount_fs_with_options(fs, &[MountOption::RW])?;
+
+    let archive = mnt.path().join("new.zip");
+    fs::create_dir(&archive)?;
+    fs::create_dir(archive.join("some"))?;
+    fs::write(archive.join("some/file.txt"), "some content\n")?;
+
+    assert_eq!(
+        fs::read_to_string(archive.join("some/file.txt"))?,
+        "some content\n"
+    );
+    assert!(!data.path().join("new.zip").exists());
+
+    fs::File::open(&archive)?.sync_all()?;
+
+    let mut zip = zip::ZipArchive::new(fs::File::open(data.path().join("new.zip"))?)?;
+    let mut content = String::new();
+    zip.by_name("some/file.txt")?.read_to_string(&mut content)?;
+    assert_eq!(content, "some content\n");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_writable_archives_listed_and_truncated() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let fs = ZipFsBuilder::new(data.path()).writable_archives(1024 * 1024);
+    let (mnt, guard) = mount_fs_with_options(fs, &[MountOption::RW])?;
+
+    let archive = mnt.path().join("new.zip");
+    fs::create_dir(&archive)?;
+
+    let names = fs::read_dir(mnt.path())?
+        .map(|entry| Ok(entry?.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(names, ["new.zip"]);
+    assert!(fs::metadata(&archive)?.is_dir());
+
+    // NOTE: Writing a file again opens it with O_TRUNC
+    let file = archive.join("file.txt");
+    fs::write(&file, "some longer content\n")?;
+    fs::write(&file, "some content\n")?;
+    assert_eq!(fs::read_to_string(&file)?, "some content\n");
+
+    fs::OpenOptions::new().write(true).open(&file)?.set_len(4)?;
+    assert_eq!(fs::read_to_string(&file)?, "some");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_writable_archives_max_staging_size() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let fs = ZipFsBuilder::new(data.path()).writable_archives(16);
+    let (mnt, guard) = mount_fs_with_options(fs, &[MountOption::RW])?;
+
+    let archive = mnt.path().join("new.zip");
+    fs::create_dir(&archive)?;
+
+    let err = fs::write(archive.join("file.txt"), "some content\n".repeat(15)).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+
+    drop(guard);
+    Ok(())
+}
+
+/// Copy the rest of `from` to `to` with copy_file_range(2), returning the bytes copied
+#[cfg(target_os = "linux")]
+fn copy_file_range_all(from: &fs::File, to: &fs::File) -> Result<u64> {
+    let mut copied = 0;
+
+    loop {
+        // SAFETY: Both descriptors are open, null offsets use and advance the file offsets
+        let len = unsafe {
+            libc::copy_file_range(
+                from.as_raw_fd(),
+                std::ptr::null_mut(),
+                to.as_raw_fd(),
+                std::ptr::null_mut(),
+                1 << 30,
+                0,
+            )
+        };
+
+        match len {
+            0 => return Ok(copied),
+            len if len < 0 => return Err(std::io::Error::last_os_error().into()),
+            len => copied += len as u64,
+        }
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_copy_file_range() -> Result<()> {
+    let data = TempDir::new()?;
+    for archive in ["stored.zip", "compressed.zip"] {
+        fs::copy(data_dir().join(archive), data.path().join(archive))?;
+    }
+
+    let fs = ZipFsBuilder::new(data.path()).writable_archives(1024 * 1024);
+    let (mnt, guard) = mount_fs_with_options(fs, &[MountOption::RW])?;
+
+    let staged = mnt.path().join("copies.zip");
+    fs::create_dir(&staged)?;
+
+    // NOTE: The stored entry is copied in place, the compressed one decompressed
+    for (archive, name) in [
+        ("stored.zip", "stored.txt"),
+        ("compressed.zip", "compressed.txt"),
+    ] {
+        let source = mnt.path().join(archive).join("some/nested/file.txt");
+        let copy = staged.join(name);
+
+        let copied = copy_file_range_all(&fs::File::open(&source)?, &fs::File::create(&copy)?)?;
+        let expected = fs::read(&source)?;
+        assert_eq!(copied, expected.len() as u64, "{}", archive);
+        assert_eq!(fs::read(&copy)?, expected, "{}", archive);
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_dos_attrs_xattr() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    let attrs = getxattr(
+        &mnt.path().join("hidden.zip/hidden.txt"),
+        "user.zipfs.dos_attrs",
+    )?;
+    assert_eq!(attrs.as_deref(), Some("hidden,archive"));
+
+    let attrs = getxattr(
+        &mnt.path().join("hidden.zip/visible.txt"),
+        "user.zipfs.dos_attrs",
+    )?;
+    assert_eq!(attrs.as_deref(), Some("archive"));
+
+    let attrs = getxattr(&mnt.path().join("passthrough.txt"), "user.zipfs.dos_attrs")?;
+    assert_eq!(attrs, None);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_mtime_xattrs() -> Result<()> {
+    let (mnt, guard) = mount()?;
+    let dos = mnt.path().join("timestamps.zip/dos.txt");
+    let unix = mnt.path().join("timestamps.zip/unix.txt");
+
+    assert_eq!(
+        getxattr(&dos, "user.zipfs.mtime_source")?.as_deref(),
+        Some("dos")
+    );
+    assert_eq!(
+        getxattr(&unix, "user.zipfs.mtime_source")?.as_deref(),
+        Some("unix-extra")
+    );
+
+    // NOTE: 2024-06-26 23:14:00, the date in the high half
+    let raw = getxattr(&dos, "user.zipfs.mtime_raw")?;
+    assert_eq!(raw.as_deref(), Some("0x58dab9c0"));
+    assert_eq!(getxattr(&unix, "user.zipfs.mtime_raw")?, raw);
+
+    let mtime = getxattr(
+        &mnt.path().join("passthrough.txt"),
+        "user.zipfs.mtime_source",
+    )?;
+    assert_eq!(mtime, None);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_posix_acl_xattr() -> Result<()> {
+    let (mnt, guard) = mount()?;
+
+    // NOTE: Version 2, then the tag, permissions and undefined id of each entry
+    let acl = getxattr_bytes(
+        &mnt.path().join("executable.zip/run.sh"),
+        "system.posix_acl_access",
+    )?
+    .expect("entries made on Unix have an ACL");
+    let entries = acl[4..]
+        .chunks(8)
+        .map(|entry| {
+            let tag = u16::from_le_bytes([entry[0], entry[1]]);
+            let perm = u16::from_le_bytes([entry[2], entry[3]]);
+            (tag, perm)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(acl[..4], 2u32.to_le_bytes());
+    assert_eq!(entries, [(0x01, 0o7), (0x04, 0o5), (0x20, 0o5)]);
+
+    // NOTE: Entries made on DOS have no Unix mode to derive it from
+    let acl = getxattr_bytes(
+        &mnt.path().join("hidden.zip/hidden.txt"),
+        "system.posix_acl_access",
+    )?;
+    assert_eq!(acl, None);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_control_socket() -> Result<()> {
+    let sockets = TempDir::new()?;
+    let socket = sockets.path().join("zipfs.sock");
+
+    let (mnt, guard) = mount_fs(ZipFsBuilder::new(data_dir()))?;
+    guard.control_handle().clone().listen(&socket)?;
+
+    let stream = UnixStream::connect(&socket)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut send = |command: &str| -> Result<serde_json::Value> {
+        writeln!(&stream, "{}", command)?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    };
+
+    fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    fs::read(mnt.path().join("compressed.zip/some/nested/file.txt"))?;
+
+    let stats = send(r#"{"cmd": "stats"}"#)?;
+    assert_eq!(stats["cached_archives"], 2);
+    assert_eq!(stats["cache_capacity"], 1024);
+    assert_eq!(stats["top_entries"].as_array().map(Vec::len), Some(2));
+    assert!(stats["bytes_served"].as_u64() >= Some(2 * 195));
+
+    let invalidate = send(r#"{"cmd": "invalidate", "path": "stored.zip"}"#)?;
+    assert_eq!(invalidate["evicted"], true);
+    assert_eq!(send(r#"{"cmd": "stats"}"#)?["cached_archives"], 1);
+
+    let reload = send(r#"{"cmd": "reload"}"#)?;
+    assert_eq!(reload["evicted"], 1);
+    assert_eq!(send(r#"{"cmd": "stats"}"#)?["cached_archives"], 0);
+
+    let unknown = send(r#"{"cmd": "unknown"}"#)?;
+    assert_eq!(unknown["ok"], false);
+
+    let content = fs::read_to_string(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_control_socket_reload_rescans() -> Result<()> {
+    let data = TempDir::new()?;
+    let sockets = TempDir::new()?;
+    let socket = sockets.path().join("zipfs.sock");
+    fs::copy(data_dir().join("stored.zip"), data.path().join("old.zip"))?;
+
+    let (mnt, guard) = mount_fs(ZipFsBuilder::new(data.path()))?;
+    guard.control_handle().clone().listen(&socket)?;
+
+    let stream = UnixStream::connect(&socket)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut send = |command: &str| -> Result<serde_json::Value> {
+        writeln!(&stream, "{}", command)?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        Ok(serde_json::from_str(&line)?)
+    };
+
+    fs::read(mnt.path().join("old.zip/some/nested/file.txt"))?;
+    let inodes = send(r#"{"cmd": "stats"}"#)?["inodes"].as_u64().unwrap();
+
+    fs::remove_file(data.path().join("old.zip"))?;
+    assert_eq!(send(r#"{"cmd": "reload"}"#)?["evicted"], 1);
+
+    // NOTE: The rescan runs before the next operation, which forgets the inodes of old.zip
+    assert_eq!(fs::read_dir(mnt.path())?.count(), 0);
+    let rescanned = send(r#"{"cmd": "stats"}"#)?["inodes"].as_u64().unwrap();
+    assert_eq!(rescanned, inodes - 4);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_warm_listed_archives() -> Result<()> {
+    let fs = ZipFsBuilder::new(data_dir()).warm_concurrency(2);
+    let (mnt, guard) = mount_fs(fs)?;
+    let control = guard.control_handle().clone();
+
+    // NOTE: Every fixture but corrupt.zip and truncated.zip opens
+    assert!(fs::read_dir(mnt.path())?.count() > 0);
+    let started = Instant::now();
+    while control.snapshot()["cached_archives"] != 12 {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "Archives not warmed up"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let stats = control.snapshot();
+    assert_eq!(stats["cache_hits"], 0);
+    assert_eq!(stats["cache_misses"], 0);
+
+    fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    assert_eq!(control.snapshot()["cache_misses"], 0);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_control_socket_commands() -> Result<()> {
+    let sockets = TempDir::new()?;
+    let socket = sockets.path().join("zipfs.sock");
+
+    let filters = Arc::new(Mutex::new(Vec::new()));
+    let (mnt, guard) = mount_fs(ZipFsBuilder::new(data_dir()))?;
+    guard
+        .control_handle()
+        .clone()
+        .with_log_filter({
+            let filters = filters.clone();
+            move |filter| {
+                if filter.is_empty() {
+                    return Err("empty filter".to_string());
+                }
+
+                filters.lock().unwrap().push(filter.to_string());
+                Ok(())
+            }
+        })
+        .listen(&socket)?;
+
+    let mode = fs::metadata(&socket)?.permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let ctl = |args: &[&str]| -> Result<(bool, serde_json::Value)> {
+        let output = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+            .arg("--control-socket")
+            .arg(&socket)
+            .arg("ctl")
+            .args(args)
+            .output()?;
+        Ok((
+            output.status.success(),
+            serde_json::from_slice(&output.stdout)?,
+        ))
+    };
+
+    fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    fs::read(mnt.path().join("compressed.zip/some/nested/file.txt"))?;
+
+    let (ok, stats) = ctl(&["stats"])?;
+    assert!(ok);
+    assert_eq!(stats["cached_archives"], 2);
+
+    let (ok, evict) = ctl(&["evict", "stored.zip"])?;
+    assert!(ok);
+    assert_eq!(evict["evicted"], true);
+    assert_eq!(ctl(&["stats"])?.1["cached_archives"], 1);
+
+    let (ok, flush) = ctl(&["flush"])?;
+    assert!(ok);
+    assert_eq!(flush["evicted"], 1);
+    assert_eq!(ctl(&["stats"])?.1["cached_archives"], 0);
+
+    let (ok, _) = ctl(&["loglevel", "zipfs=trace"])?;
+    assert!(ok);
+    assert_eq!(*filters.lock().unwrap(), ["zipfs=trace"]);
+
+    let (ok, loglevel) = ctl(&["loglevel", ""])?;
+    assert!(!ok);
+    assert_eq!(loglevel["error"], "empty filter");
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_stream_entry() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let content = (0..4 * 1024 * 1024)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("some/large.bin", options)?;
+    zip.write_all(&content)?;
+    zip.finish()?;
+
+    let mut fs = ZipFsBuilder::new(data.path()).build()?;
+    let mut streamed = Vec::new();
+    for chunk in fs.stream_entry(Path::new("large.zip"), Path::new("some/large.bin"))? {
+        streamed.extend(chunk?);
+    }
+
+    let mut extracted = Vec::new();
+    zip::ZipArchive::new(fs::File::open(data.path().join("large.zip"))?)?
+        .by_name("some/large.bin")?
+        .read_to_end(&mut extracted)?;
+
+    assert_eq!(streamed.len(), content.len());
+    assert_eq!(streamed, extracted);
+
+    assert!(fs
+        .stream_entry(Path::new("large.zip"), Path::new("missing"))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_shutdown_waits_for_open_files() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let content = (0..4 * 1024 * 1024)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("large.bin", options)?;
+    zip.write_all(&content)?;
+    zip.finish()?;
+
+    let fs = ZipFsBuilder::new(data.path());
+    let (mnt, guard) = mount_fs(fs)?;
+    let control = guard.control_handle().clone();
+    let path = mnt.path().join("large.zip/large.bin");
+
+    let mut file = fs::File::open(&path)?;
+    let mut read = vec![0; 64 * 1024];
+    file.read_exact(&mut read)?;
+
+    let shutdown = thread::spawn({
+        let control = control.clone();
+        move || control.shutdown(Duration::from_secs(10))
+    });
+
+    // NOTE: Files opened before draining started are released right away
+    let err = loop {
+        match fs::File::open(&path) {
+            Ok(_) => thread::sleep(Duration::from_millis(10)),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.raw_os_error(), Some(libc::ESHUTDOWN));
+
+    file.read_to_end(&mut read)?;
+    assert_eq!(read.len(), content.len());
+    assert!(read == content);
+
+    drop(file);
+    assert_eq!(shutdown.join().unwrap(), 0);
+
+    drop(guard);
+    Ok(())
+}
+
+#[test]
+fn test_open_reader_seek() -> Result<()> {
+    let data = TempDir::new()?;
+
+    let content = (0..1024 * 1024)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("large.bin", options)?;
+    zip.write_all(&content)?;
+    zip.finish()?;
+
+    let mut fs = ZipFsBuilder::new(data.path()).build()?;
+    let mut reader = fs.open_reader(Path::new("large.zip"), Path::new("large.bin"))?;
+    assert_eq!(reader.len(), content.len() as u64);
+
+    let mut read_at = |pos: SeekFrom, len: usize| -> Result<(u64, Vec<u8>)> {
+        let offset = reader.seek(pos)?;
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+        Ok((offset, buf))
+    };
+
+    let (offset, buf) = read_at(SeekFrom::Start(300_000), 1000)?;
+    assert_eq!(buf, content[offset as usize..offset as usize + 1000]);
+
+    // NOTE: Backwards, restarting the decompressor
+    let (offset, buf) = read_at(SeekFrom::Start(10), 1000)?;
+    assert_eq!(buf, content[offset as usize..offset as usize + 1000]);
+
+    let (offset, buf) = read_at(SeekFrom::End(-100), 100)?;
+    assert_eq!(offset, content.len() as u64 - 100);
+    assert_eq!(buf, content[content.len() - 100..]);
+
+    let (offset, buf) = read_at(SeekFrom::Current(-200_000), 5000)?;
+    assert_eq!(offset, content.len() as u64 - 200_000);
+    assert_eq!(buf, content[offset as usize..offset as usize + 5000]);
+
+    let mut rest = Vec::new();
+    reader.seek(SeekFrom::End(-10))?;
+    reader.read_to_end(&mut rest)?;
+    assert_eq!(rest, content[content.len() - 10..]);
+
+    assert!(reader
+        .seek(SeekFrom::Current(-(content.len() as i64) - 1))
+        .is_err());
+
+    Ok(())
+}
+
+/// Run the zipfs binary in the foreground, returning once it reported the mount as ready
+fn spawn_ready(data: &Path, mnt: &Path, args: &[&str]) -> Result<Child> {
+    spawn_ready_with_stdin(data, mnt, args, Stdio::inherit())
+}
+
+fn spawn_ready_with_stdin(data: &Path, mnt: &Path, args: &[&str], stdin: Stdio) -> Result<Child> {
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let [read_fd, write_fd] = fds;
+
+    // NOTE: Only the write end is meant for the child
+    assert_eq!(
+        unsafe { libc::fcntl(read_fd, libc::F_SETFD, libc::FD_CLOEXEC) },
+        0
+    );
+
+    let child = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+        .arg(data)
+        .arg(mnt)
+        .args(["--foreground", "--mount-timeout", "5000"])
+        .args(["--ready-fd", &write_fd.to_string()])
+        .args(args)
+        .stdin(stdin)
+        .spawn()?;
+
+    unsafe { libc::close(write_fd) };
+
+    let mut ready = String::new();
+    BufReader::new(unsafe { fs::File::from_raw_fd(read_fd) }).read_line(&mut ready)?;
+    assert_eq!(ready, "ready\n");
+
+    Ok(child)
+}
+
+#[test]
+fn test_ready_fd() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let mut child = spawn_ready(&data_dir(), mnt.path(), &[])?;
+
+    let content = fs::read_to_string(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_archive_from_stdin() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let archive = fs::File::open(data_dir().join("compressed.zip"))?;
+    let mut child = spawn_ready_with_stdin(Path::new("-"), mnt.path(), &[], archive.into())?;
+
+    let content = fs::read_to_string(mnt.path().join("some/nested/file.txt"))?;
+    assert_eq!(content, "some content\n".to_string().repeat(15));
+    assert!(!mnt.path().join("stored.zip").exists());
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    let spooled = std::env::temp_dir().join(format!("zipfs-stdin-{}.zip", child.id()));
+    assert!(!spooled.exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_max_read() -> Result<()> {
+    let data = TempDir::new()?;
+    let mnt = TempDir::new()?;
+
+    let content = "some content\n".repeat(64 * 1024);
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    zip.start_file("large.txt", SimpleFileOptions::default())?;
+    zip.write_all(content.as_bytes())?;
+    zip.finish()?;
+
+    let args = ["--max-read", "16384", "--expose-metadata"];
+    let mut child = spawn_ready(data.path(), mnt.path(), &args)?;
+
+    let read = fs::read_to_string(mnt.path().join("large.zip/large.txt"))?;
+    assert_eq!(read, content);
+
+    let status = fs::read_to_string(mnt.path().join(".zipfs/status"))?;
+    let status: serde_json::Value = serde_json::from_str(&status)?;
+    assert_eq!(status["stats"]["largest_read_request"], 16384);
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+/// Filesystem type of the mount at `mnt` as listed in /proc/mounts
+fn mounted_fstype(mnt: &Path) -> Result<Option<String>> {
+    let mnt = mnt.canonicalize()?;
+    let mounts = fs::read_to_string("/proc/mounts")?;
+
+    Ok(mounts.lines().find_map(|line| {
+        let mut fields = line.split(' ').skip(1);
+        let point = fields.next()?;
+        (Path::new(point) == mnt).then(|| fields.next().map(str::to_string))?
+    }))
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_fstype() -> Result<()> {
+    for (args, fstype) in [
+        (&[][..], "fuse.zipfs"),
+        (&["--fstype", "archives"][..], "fuse.archives"),
+    ] {
+        let mnt = TempDir::new()?;
+        let mut child = spawn_ready(&data_dir(), mnt.path(), args)?;
+
+        assert_eq!(mounted_fstype(mnt.path())?.as_deref(), Some(fstype));
+
+        unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+        assert!(child.wait()?.success());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_log() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let log = TempDir::new()?;
+    let log = log.path().join("audit.jsonl");
+    let mut child = spawn_ready(
+        &data_dir(),
+        mnt.path(),
+        &["--audit-log", log.to_str().unwrap()],
+    )?;
+
+    let entry = fs::read(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    let passthrough = fs::read(mnt.path().join("passthrough.txt"))?;
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    let mut read = HashMap::<String, u64>::new();
+    for line in fs::read_to_string(&log)?.lines() {
+        let event: serde_json::Value = serde_json::from_str(line)?;
+        assert!(event["ts"].as_str().is_some_and(|ts| ts.ends_with('Z')));
+        assert_eq!(event["uid"], unsafe { libc::getuid() });
+        assert_eq!(event["gid"], unsafe { libc::getgid() });
+        assert!(event["pid"].as_u64().is_some_and(|pid| pid > 0));
+        assert!(event["offset"].is_u64());
+
+        let path = event["path"].as_str().unwrap().to_string();
+        *read.entry(path).or_default() += event["size"].as_u64().unwrap();
+    }
+
+    assert_eq!(
+        read.get("/stored.zip/some/nested/file.txt"),
+        Some(&(entry.len() as u64))
+    );
+    assert_eq!(
+        read.get("/passthrough.txt"),
+        Some(&(passthrough.len() as u64))
+    );
+    Ok(())
+}
+
+/// Wait until the mount at `mnt` is gone and the empty directory below shows again
+fn wait_until_unmounted(mnt: &Path) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    while !fs::read_dir(mnt).is_ok_and(|mut entries| entries.next().is_none()) {
+        assert!(Instant::now() < deadline, "{:?} is still mounted", mnt);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_auto_unmount_after_kill() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let mut child = spawn_ready(&data_dir(), mnt.path(), &["-o", "auto_unmount"])?;
+    assert!(mnt.path().join("stored.zip").exists());
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGKILL) };
+    child.wait()?;
+
+    wait_until_unmounted(mnt.path());
+    Ok(())
+}
+
+#[test]
+fn test_recover_stale_mount() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let mut child = spawn_ready(&data_dir(), mnt.path(), &["-o", "no_auto_unmount"])?;
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGKILL) };
+    child.wait()?;
+
+    let err = fs::read_dir(mnt.path()).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOTCONN));
+
+    let mut child = spawn_ready(&data_dir(), mnt.path(), &[])?;
+    assert!(mnt.path().join("stored.zip").exists());
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    wait_until_unmounted(mnt.path());
+    Ok(())
+}
+
+#[test]
+fn test_second_interrupt_forces_shutdown() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let mut child = spawn_ready(&data_dir(), mnt.path(), &["--shutdown-timeout", "60000"])?;
+
+    let file = fs::File::open(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    thread::sleep(Duration::from_millis(500));
+    assert!(child.try_wait()?.is_none());
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert_eq!(child.wait()?.code(), Some(130));
+
+    drop(file);
+    wait_until_unmounted(mnt.path());
+    Ok(())
+}
+
+#[test]
+fn test_idle_timeout() -> Result<()> {
+    let mnt = TempDir::new()?;
+    let mut child = spawn_ready(
+        &data_dir(),
+        mnt.path(),
+        &["--idle-timeout", "500ms", "--mount-check-interval", "50"],
+    )?;
+
+    // NOTE: Open files and directories keep the mount busy however long they go unused
+    let file = fs::File::open(mnt.path().join("stored.zip/some/nested/file.txt"))?;
+    let dir = fs::File::open(mnt.path().join("stored.zip"))?;
+    thread::sleep(Duration::from_secs(1));
+    assert!(child.try_wait()?.is_none());
+
+    drop(file);
+    thread::sleep(Duration::from_secs(1));
+    assert!(child.try_wait()?.is_none());
+
+    drop(dir);
+    assert!(child.wait()?.success());
+
+    wait_until_unmounted(mnt.path());
+    Ok(())
+}
+
+#[test]
+fn test_rescan_on_sighup() -> Result<()> {
+    let data = TempDir::new()?;
+    let mnt = TempDir::new()?;
+
+    let write_zip = |name: &str, content: &str| -> Result<()> {
+        let mut zip = ZipWriter::new(fs::File::create(data.path().join(name))?);
+        zip.start_file("file.txt", SimpleFileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+        zip.finish()?;
+        Ok(())
+    };
+
+    write_zip("old.zip", "old")?;
+    write_zip("changed.zip", "before")?;
+
+    let mut child = spawn_ready(data.path(), mnt.path(), &[])?;
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("old.zip/file.txt"))?,
+        "old"
+    );
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("changed.zip/file.txt"))?,
+        "before"
+    );
+
+    fs::remove_file(data.path().join("old.zip"))?;
+    write_zip("new.zip", "new")?;
+    write_zip("changed.zip", "after")?;
+    unsafe { libc::kill(child.id() as i32, libc::SIGHUP) };
+
+    // NOTE: The signal is picked up by the main loop, once per mount check interval
+    let started = Instant::now();
+    while fs::read_to_string(mnt.path().join("changed.zip/file.txt"))? != "after" {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "Archive not rescanned"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    assert_eq!(
+        fs::read_to_string(mnt.path().join("new.zip/file.txt"))?,
+        "new"
+    );
+    assert!(!mnt.path().join("old.zip").exists());
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_on_mount_strict() -> Result<()> {
+    let data = TempDir::new()?;
+    let mnt = TempDir::new()?;
+
+    let content = "some content\n".repeat(15);
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("corrupt.zip"))?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    zip.start_file("file.txt", options)?;
+    zip.write_all(content.as_bytes())?;
+    zip.finish()?;
+
+    // NOTE: Flip a byte of the stored data so that only the CRC32 gives it away
+    let mut archive = fs::read(data.path().join("corrupt.zip"))?;
+    let at = archive
+        .windows(content.len())
+        .position(|window| window == content.as_bytes())
+        .unwrap();
+    archive[at] ^= 0xff;
+    fs::write(data.path().join("corrupt.zip"), archive)?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+        .arg(data.path())
+        .arg(mnt.path())
+        .args(["--foreground", "--verify-on-mount", "--strict"])
+        .output()?;
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed verification"));
+    assert_eq!(fs::read_dir(mnt.path())?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_inspect() -> Result<()> {
+    let inspect = |archive: &str| -> Result<serde_json::Value> {
+        let output = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+            .args(["inspect", "--json"])
+            .arg(data_dir().join(archive))
+            .output()?;
+        assert!(output.status.success());
+        Ok(serde_json::from_slice(&output.stdout)?)
+    };
+
+    let stored = inspect("stored.zip")?;
+    assert_eq!(stored["format"], "zip");
+    assert_eq!(stored["presented_as"], "directory");
+    assert_eq!(stored["entry_count"], 1);
+    assert_eq!(stored["entries"][0]["name"], "some/nested/file.txt");
+    assert_eq!(stored["entries"][0]["size"], 195);
+    assert_eq!(stored["entries"][0]["method"], "Stored");
+    assert_eq!(stored["error"], serde_json::Value::Null);
+
+    let encrypted = inspect("encrypted.zip")?;
+    assert_eq!(encrypted["entries"][0]["encrypted"], true);
+    assert_eq!(encrypted["warnings"].as_array().unwrap().len(), 1);
+
+    let mismatch = inspect("mismatch.zip")?;
+    let warning = mismatch["warnings"][0].as_str().unwrap();
+    assert!(warning.contains("compressed size, uncompressed size"));
+
+    let corrupt = inspect("corrupt.zip")?;
+    assert_eq!(corrupt["format"], "unknown");
+    assert_eq!(corrupt["presented_as"], "empty_directory");
+    assert!(corrupt["error"].is_string());
+
+    let passthrough = inspect("passthrough.txt")?;
+    assert_eq!(passthrough["presented_as"], "file");
+
+    Ok(())
+}
+
+#[test]
+fn test_cat_and_ls() -> Result<()> {
+    let zipfs = |command: &str, path: &Path| -> Result<(bool, String)> {
+        let output = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+            .arg(command)
+            .arg(path)
+            .output()?;
+        Ok((output.status.success(), String::from_utf8(output.stdout)?))
+    };
+
+    let (ok, output) = zipfs(
+        "cat",
+        &data_dir().join("compressed.zip/some/nested/file.txt"),
+    )?;
+    assert!(ok);
+    assert_eq!(output, "some content\n".repeat(15));
+
+    let (ok, output) = zipfs("cat", &data_dir().join("passthrough.txt"))?;
+    assert!(ok);
+    assert_eq!(
+        output,
+        fs::read_to_string(data_dir().join("passthrough.txt"))?
+    );
+
+    let (ok, output) = zipfs("ls", &data_dir().join("stored.zip/some"))?;
+    assert!(ok);
+    assert_eq!(output, "nested/\n");
+
+    let (ok, output) = zipfs("ls", &data_dir())?;
+    assert!(ok);
+    assert!(output.lines().any(|line| line == "stored.zip/"));
+    assert!(output.lines().any(|line| line == "passthrough.txt"));
+
+    let (ok, _) = zipfs("cat", &data_dir().join("stored.zip/some"))?;
+    assert!(!ok);
+    let (ok, _) = zipfs("ls", &data_dir().join("stored.zip/missing"))?;
+    assert!(!ok);
+
+    Ok(())
+}
+
+#[test]
+fn test_prefetch() -> Result<()> {
+    let data = TempDir::new()?;
+    let mnt = TempDir::new()?;
+    let socket = data.path().join("zipfs.sock");
+
+    // NOTE: Enough entries for parsing the central directory to take a noticeable while
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("large.zip"))?);
+    for i in 0..20_000 {
+        zip.start_file(format!("file-{}.txt", i), SimpleFileOptions::default())?;
+        zip.write_all(b"some content\n")?;
+    }
+    zip.finish()?;
+
+    let socket_arg = socket.to_string_lossy();
+    let args = ["--prefetch", "--socket", socket_arg.as_ref()];
+    let mut child = spawn_ready(data.path(), mnt.path(), &args)?;
+
+    let stats = || -> Result<serde_json::Value> {
+        let output = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+            .arg("--socket")
+            .arg(&socket)
+            .args(["ctl", "stats"])
+            .output()?;
+        Ok(serde_json::from_slice(&output.stdout)?)
+    };
+
+    let started = Instant::now();
+    while stats()?["cached_archives"] != 1 {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "Archive not prefetched"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let started = Instant::now();
+    fs::metadata(mnt.path().join("large.zip/file-19999.txt"))?;
+    println!("First getattr took {:?}", started.elapsed());
+
+    let stats = stats()?;
+    assert_eq!(stats["cache_misses"], 0);
+    assert!(stats["cache_hits"].as_u64().unwrap() > 0);
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    assert!(child.wait()?.success());
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_subcommand() -> Result<()> {
+    let verify = |dir: &Path, args: &[&str]| -> Result<(bool, String)> {
+        let output = Command::new(env!("CARGO_BIN_EXE_zipfs"))
+            .arg("verify")
+            .arg(dir)
+            .args(args)
+            .output()?;
+        Ok((output.status.success(), String::from_utf8(output.stdout)?))
+    };
+
+    let (ok, output) = verify(&data_dir(), &["--jobs", "2"])?;
+    assert!(!ok);
+
+    let line = |name: &str| {
+        let path = data_dir().join(name).display().to_string();
+        output
+            .lines()
+            .find(|line| line.contains(&path))
+            .unwrap_or_else(|| panic!("no report for {}", name))
+            .to_string()
+    };
+    assert!(line("stored.zip").starts_with("ok "));
+    assert!(line("encrypted.zip").starts_with("ok "));
+    assert!(line("corrupt.zip").starts_with("FAILED "));
+    assert!(line("truncated.zip").starts_with("FAILED "));
+    assert!(output.contains("encrypted, no password: some/nested/file.txt"));
+
+    // NOTE: Flip a byte of the stored data, only decompressing gives it away
+    let data = TempDir::new()?;
+    let content = "some content\n".repeat(15);
+    let mut zip = ZipWriter::new(fs::File::create(data.path().join("flipped.zip"))?);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    zip.start_file("file.txt", options)?;
+    zip.write_all(content.as_bytes())?;
+    zip.finish()?;
+
+    let mut archive = fs::read(data.path().join("flipped.zip"))?;
+    let at = archive
+        .windows(content.len())
+        .position(|window| window == content.as_bytes())
+        .unwrap();
+    archive[at] ^= 0xff;
+    fs::write(data.path().join("flipped.zip"), archive)?;
+
+    let (ok, output) = verify(data.path(), &["--quick"])?;
+    assert!(ok, "{}", output);
+    assert!(output.contains("1 entries, 0 checked"));
+
+    let (ok, output) = verify(data.path(), &[])?;
+    assert!(!ok);
+    assert!(output.contains("corrupt file.txt"));
+
+    Ok(())
+}
@@ -0,0 +1,348 @@
+//! Archives used by the tests, written to `tests/data` on first use or by
+//! `cargo run --example create_test_data`.
+//!
+//! Every archive is reproducible: entries carry a fixed timestamp and the garbage of
+//! `corrupt.zip` comes from a seeded generator.
+
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::Path,
+};
+
+use zip::{
+    result::ZipResult, unstable::write::FileOptionsExt, write::SimpleFileOptions,
+    CompressionMethod, DateTime, ZipWriter,
+};
+
+/// Shared by most entries, 195 bytes
+const CONTENT: &str = "some content\n";
+const CONTENT_REPEAT: usize = 15;
+
+const ENCRYPTED_PASSWORD: &[u8] = b"password";
+
+/// Signature of a central directory file header
+const CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+const DATA_DESCRIPTOR: &[u8] = b"PK\x07\x08";
+
+/// General purpose flag of entries whose sizes and CRC follow their data
+const FLAG_DATA_DESCRIPTOR: u16 = 0x08;
+
+/// Extra field of Info-ZIP with Unix timestamps, and its flag of the modification time
+const EXTENDED_TIMESTAMP: u16 = 0x5455;
+const EXTENDED_TIMESTAMP_MTIME: u8 = 0x01;
+
+/// 2024-06-26 23:14:00 UTC, the time of `options`
+const MTIME: u32 = 1_719_443_640;
+
+/// DOS attributes as stored in the low byte of the external attributes
+const DOS_HIDDEN: u32 = 0x02;
+const DOS_ARCHIVE: u32 = 0x20;
+
+const CORRUPT_LEN: usize = 16 * 1024;
+
+/// Zeros in `bomb.zip`, compressing well beyond the default maximum ratio
+const BOMB_LEN: usize = 4 * 1024 * 1024;
+
+/// Compression method unsupported by the zip crate
+const PPMD: u16 = 98;
+
+/// Compression methods the zip crate reads but doesn't write
+const LZMA: u16 = 14;
+const XZ: u16 = 95;
+
+/// General purpose flag of LZMA entries ending with an end of stream marker
+const FLAG_LZMA_EOS: u16 = 0x02;
+
+/// Version of the LZMA SDK WinZip puts in front of the properties, and their size
+const LZMA_VERSION: [u8; 2] = [9, 4];
+const LZMA_PROPERTIES_SIZE: usize = 5;
+
+/// Unpacked size in the header of an LZMA-alone stream, after the properties
+const LZMA_ALONE_SIZE_LEN: usize = 8;
+
+const WHEEL_METADATA: &str = "Metadata-Version: 2.1\nName: example\nVersion: 1.0.0\n";
+
+pub fn create(dir: &Path) -> ZipResult<()> {
+    fs::create_dir_all(dir)?;
+
+    let content = CONTENT.repeat(CONTENT_REPEAT);
+    let nested = "some/nested/file.txt";
+
+    let stored = options().compression_method(CompressionMethod::Stored);
+    fs::write(
+        dir.join("stored.zip"),
+        archive(&[(nested, &content)], stored)?,
+    )?;
+
+    let compressed = options().compression_method(CompressionMethod::Deflated);
+    fs::write(
+        dir.join("compressed.zip"),
+        archive(&[(nested, &content)], compressed)?,
+    )?;
+
+    let encrypted = stored.with_deprecated_encryption(ENCRYPTED_PASSWORD);
+    fs::write(
+        dir.join("encrypted.zip"),
+        archive(&[(nested, &content)], encrypted)?,
+    )?;
+
+    fs::write(dir.join("corrupt.zip"), garbage(CORRUPT_LEN))?;
+    fs::write(dir.join("hidden.zip"), hidden()?)?;
+    fs::write(dir.join("example-1.0.0-py3-none-any.whl"), wheel()?)?;
+
+    let executable = options().unix_permissions(0o755);
+    fs::write(
+        dir.join("executable.zip"),
+        archive(&[("run.sh", "#!/bin/sh\n")], executable)?,
+    )?;
+
+    // NOTE: The local header claims fewer bytes than the central directory
+    let mut mismatch = archive(&[("file.txt", &content)], stored)?;
+    set_local_sizes(&mut mismatch, 100);
+    fs::write(dir.join("mismatch.zip"), mismatch)?;
+
+    // NOTE: The central directory claims more bytes than the archive holds
+    let mut truncated = archive(&[("file.txt", &content)], stored)?;
+    set_central_sizes(&mut truncated, "file.txt", 4096);
+    fs::write(dir.join("truncated.zip"), truncated)?;
+
+    let bzip2 = options().compression_method(CompressionMethod::Bzip2);
+    fs::write(
+        dir.join("bomb.zip"),
+        archive(&[("zeros.bin", &"\0".repeat(BOMB_LEN))], bzip2)?,
+    )?;
+
+    // NOTE: As written by streaming archivers, the local header leaves the sizes to a data
+    // descriptor after the data
+    let descriptor = archive(&[("file.txt", &content)], stored)?;
+    fs::write(
+        dir.join("descriptor.zip"),
+        with_data_descriptor(&descriptor, "file.txt"),
+    )?;
+
+    // NOTE: Only one of the entries carries its modification time in UTC as well
+    let entries = [
+        ("dos.txt", content.as_str()),
+        ("unix.txt", content.as_str()),
+    ];
+    let timestamps = archive(&entries, stored)?;
+    fs::write(
+        dir.join("timestamps.zip"),
+        with_extended_timestamp(&timestamps, "unix.txt", MTIME),
+    )?;
+
+    // NOTE: Stored data labelled as PPMd, which can't be decompressed either way
+    let mut ppmd = archive(&[("file.txt", &content)], stored)?;
+    set_method(&mut ppmd, "file.txt", PPMD);
+    fs::write(dir.join("ppmd.zip"), ppmd)?;
+
+    fs::write(dir.join("lzma.zip"), lzma(&content)?)?;
+
+    Ok(())
+}
+
+fn options() -> SimpleFileOptions {
+    let modified = DateTime::from_date_and_time(2024, 6, 26, 23, 14, 0).unwrap();
+    SimpleFileOptions::default()
+        .last_modified_time(modified)
+        .unix_permissions(0o644)
+}
+
+fn archive(entries: &[(&str, &str)], options: SimpleFileOptions) -> ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in entries {
+        zip.start_file(*name, options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Entries made on DOS, one of them with the hidden attribute
+fn hidden() -> ZipResult<Vec<u8>> {
+    let entries = [("hidden.txt", CONTENT), ("visible.txt", CONTENT)];
+    let mut archive = archive(&entries, options())?;
+
+    set_dos_attributes(&mut archive, "hidden.txt", DOS_HIDDEN | DOS_ARCHIVE);
+    set_dos_attributes(&mut archive, "visible.txt", DOS_ARCHIVE);
+    Ok(archive)
+}
+
+/// A Python wheel with its metadata in its `.dist-info` directory
+fn wheel() -> ZipResult<Vec<u8>> {
+    let entries = [
+        ("example/__init__.py", "__version__ = \"1.0.0\"\n"),
+        ("example-1.0.0.dist-info/METADATA", WHEEL_METADATA),
+        ("example-1.0.0.dist-info/WHEEL", "Wheel-Version: 1.0\n"),
+        ("example-1.0.0.dist-info/RECORD", ""),
+    ];
+
+    archive(
+        &entries,
+        options().compression_method(CompressionMethod::Deflated),
+    )
+}
+
+/// A WinZip LZMA (method 14) and an XZ (method 95) entry, compressed with lzma-rs and stored
+/// before being labelled with their method
+fn lzma(content: &str) -> ZipResult<Vec<u8>> {
+    // NOTE: WinZip replaces the unpacked size of the LZMA-alone header with the SDK version and
+    // the size of the properties, in front of them
+    let mut alone = Vec::new();
+    lzma_rs::lzma_compress(&mut content.as_bytes(), &mut alone)?;
+    let (properties, rest) = alone.split_at(LZMA_PROPERTIES_SIZE);
+
+    let mut lzma = LZMA_VERSION.to_vec();
+    lzma.extend_from_slice(&(LZMA_PROPERTIES_SIZE as u16).to_le_bytes());
+    lzma.extend_from_slice(properties);
+    lzma.extend_from_slice(&rest[LZMA_ALONE_SIZE_LEN..]);
+
+    let mut xz = Vec::new();
+    lzma_rs::xz_compress(&mut content.as_bytes(), &mut xz)?;
+
+    let stored = options().compression_method(CompressionMethod::Stored);
+    let entries = [
+        ("some/nested/lzma.txt", lzma, LZMA, FLAG_LZMA_EOS),
+        ("some/nested/xz.txt", xz, XZ, 0),
+    ];
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data, _, _) in &entries {
+        zip.start_file(*name, stored)?;
+        zip.write_all(data)?;
+    }
+
+    let mut archive = zip.finish()?.into_inner();
+    let crc = crc32fast::hash(content.as_bytes());
+    for (name, _, method, flags) in entries {
+        set_compressed(&mut archive, name, method, flags, crc, content.len() as u32);
+    }
+
+    Ok(archive)
+}
+
+/// Deterministic noise from xorshift64, not an archive at all
+fn garbage(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+/// Offset of the central directory header of `name`
+fn central_header(archive: &[u8], name: &str) -> usize {
+    (0..archive.len() - CENTRAL_HEADER.len())
+        .filter(|&at| archive[at..].starts_with(CENTRAL_HEADER))
+        .find(|&at| {
+            let name_len = u16::from_le_bytes([archive[at + 28], archive[at + 29]]) as usize;
+            archive.get(at + 46..at + 46 + name_len) == Some(name.as_bytes())
+        })
+        .unwrap_or_else(|| panic!("no central directory header for {}", name))
+}
+
+/// Overwrite the sizes in the local header of the first entry
+fn set_local_sizes(archive: &mut [u8], size: u32) {
+    archive[18..22].copy_from_slice(&size.to_le_bytes());
+    archive[22..26].copy_from_slice(&size.to_le_bytes());
+}
+
+fn set_central_sizes(archive: &mut [u8], name: &str, size: u32) {
+    let at = central_header(archive, name);
+    archive[at + 20..at + 24].copy_from_slice(&size.to_le_bytes());
+    archive[at + 24..at + 28].copy_from_slice(&size.to_le_bytes());
+}
+
+/// Overwrite the compression method in both headers of the first entry, named `name`
+fn set_method(archive: &mut [u8], name: &str, method: u16) {
+    archive[8..10].copy_from_slice(&method.to_le_bytes());
+    let at = central_header(archive, name);
+    archive[at + 10..at + 12].copy_from_slice(&method.to_le_bytes());
+}
+
+/// Label the entry `name` as compressed with `method`, decompressing to `size` bytes with the
+/// checksum `crc`, and add `flags` to its general purpose flags
+fn set_compressed(archive: &mut [u8], name: &str, method: u16, flags: u16, crc: u32, size: u32) {
+    let central = central_header(archive, name);
+    let local = u32::from_le_bytes(archive[central + 42..central + 46].try_into().unwrap());
+
+    // NOTE: The central directory header has the same fields 2 bytes further, after the
+    // version made by
+    for at in [local as usize, central + 2] {
+        let flag = u16::from_le_bytes([archive[at + 6], archive[at + 7]]) | flags;
+        archive[at + 6..at + 8].copy_from_slice(&flag.to_le_bytes());
+        archive[at + 8..at + 10].copy_from_slice(&method.to_le_bytes());
+        archive[at + 14..at + 18].copy_from_slice(&crc.to_le_bytes());
+        archive[at + 22..at + 26].copy_from_slice(&size.to_le_bytes());
+    }
+}
+
+/// Move the CRC and sizes of the only entry, named `name`, from its local header to a data
+/// descriptor between its data and the central directory
+fn with_data_descriptor(archive: &[u8], name: &str) -> Vec<u8> {
+    let at = central_header(archive, name);
+    let mut archive = archive.to_vec();
+
+    for flags in [6, at + 8] {
+        let flag = u16::from_le_bytes([archive[flags], archive[flags + 1]]) | FLAG_DATA_DESCRIPTOR;
+        archive[flags..flags + 2].copy_from_slice(&flag.to_le_bytes());
+    }
+
+    let mut descriptor = DATA_DESCRIPTOR.to_vec();
+    descriptor.extend_from_slice(&archive[at + 16..at + 28]);
+    archive[14..26].fill(0);
+    archive.splice(at..at, descriptor.iter().copied());
+
+    // NOTE: The central directory moved by the length of the descriptor
+    let end = (0..archive.len() - END_OF_CENTRAL_DIRECTORY.len())
+        .rev()
+        .find(|&at| archive[at..].starts_with(END_OF_CENTRAL_DIRECTORY))
+        .expect("no end of central directory record");
+    let offset = u32::from_le_bytes(archive[end + 16..end + 20].try_into().unwrap());
+    let offset = offset + descriptor.len() as u32;
+    archive[end + 16..end + 20].copy_from_slice(&offset.to_le_bytes());
+
+    archive
+}
+
+/// Add an extended timestamp field with the modification time `mtime` to the central
+/// directory header of `name`
+fn with_extended_timestamp(archive: &[u8], name: &str, mtime: u32) -> Vec<u8> {
+    let at = central_header(archive, name);
+    let mut archive = archive.to_vec();
+
+    let mut field = EXTENDED_TIMESTAMP.to_le_bytes().to_vec();
+    field.extend_from_slice(&5u16.to_le_bytes());
+    field.push(EXTENDED_TIMESTAMP_MTIME);
+    field.extend_from_slice(&mtime.to_le_bytes());
+
+    let name_len = u16::from_le_bytes([archive[at + 28], archive[at + 29]]) as usize;
+    let extra_len = u16::from_le_bytes([archive[at + 30], archive[at + 31]]) as usize;
+    let extra_end = at + 46 + name_len + extra_len;
+    archive[at + 30..at + 32].copy_from_slice(&((extra_len + field.len()) as u16).to_le_bytes());
+    archive.splice(extra_end..extra_end, field.iter().copied());
+
+    // NOTE: The central directory grew by the length of the field
+    let end = (0..archive.len() - END_OF_CENTRAL_DIRECTORY.len())
+        .rev()
+        .find(|&at| archive[at..].starts_with(END_OF_CENTRAL_DIRECTORY))
+        .expect("no end of central directory record");
+    let size = u32::from_le_bytes(archive[end + 12..end + 16].try_into().unwrap());
+    let size = size + field.len() as u32;
+    archive[end + 12..end + 16].copy_from_slice(&size.to_le_bytes());
+
+    archive
+}
+
+/// Mark the entry as made on DOS, version 2.0, with `attributes`
+fn set_dos_attributes(archive: &mut [u8], name: &str, attributes: u32) {
+    let at = central_header(archive, name);
+    archive[at + 4..at + 6].copy_from_slice(&20u16.to_le_bytes());
+    archive[at + 38..at + 42].copy_from_slice(&attributes.to_le_bytes());
+}
@@ -0,0 +1,228 @@
+//! Development tasks, run with `cargo xtask <task>` from anywhere in the workspace
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::Parser;
+use color_eyre::{
+    eyre::{bail, ensure},
+    Result,
+};
+
+/// Where `create_test_data` writes the archives unless told otherwise
+const DATA_DIR: &str = "tests/data";
+
+/// Integration tests, the ones mounting file systems
+const INTEGRATION_TEST: &str = "filesystem_test";
+
+/// Unmount helpers of FUSE 3 and FUSE 2, in order of preference
+const FUSERMOUNT: [&str; 2] = ["fusermount3", "fusermount"];
+
+#[derive(Parser)]
+#[command(about = "Development tasks of zipfs")]
+enum Task {
+    /// Regenerate the test archives
+    Fixtures {
+        #[arg(default_value = DATA_DIR)]
+        dir: PathBuf,
+    },
+    /// Run the tests mounting file systems, skipped where FUSE is unavailable
+    TestIntegration {
+        /// Fail rather than skip when FUSE is unavailable
+        #[arg(long)]
+        require_fuse: bool,
+
+        /// Passed on to the test binary, e.g. a test name filter
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Unmount FUSE file systems leaked below the temporary directory by failed tests
+    CleanMounts {
+        /// Only list the mounts
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Run the benchmarks, comparing against a saved baseline
+    Bench {
+        /// Baseline to compare against, saved beforehand with --save-baseline
+        #[arg(long, default_value = "master", conflicts_with = "save_baseline")]
+        baseline: String,
+
+        /// Save the results as this baseline instead of comparing
+        #[arg(long)]
+        save_baseline: Option<String>,
+
+        /// Benchmark target to run, all of them by default
+        #[arg(long)]
+        bench: Option<String>,
+    },
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    // NOTE: Paths are relative to the workspace root, wherever cargo was run from
+    env::set_current_dir(workspace_root())?;
+
+    match Task::parse() {
+        Task::Fixtures { dir } => run(cargo()
+            .args(["run", "--example", "create_test_data", "--"])
+            .arg(dir)),
+        Task::TestIntegration { require_fuse, args } => {
+            if let Err(reason) = fuse_available() {
+                ensure!(!require_fuse, "FUSE is unavailable: {}", reason);
+
+                println!(
+                    "Skipping integration tests, FUSE is unavailable: {}",
+                    reason
+                );
+                return Ok(());
+            }
+
+            // NOTE: Mounting tests share fixtures and mount points, they run one at a time
+            run(cargo()
+                .args(["test", "--test", INTEGRATION_TEST, "--", "--test-threads=1"])
+                .args(args))
+        }
+        Task::CleanMounts { dry_run } => clean_mounts(dry_run),
+        Task::Bench {
+            baseline,
+            save_baseline,
+            bench,
+        } => {
+            let mut command = cargo();
+            command.arg("bench");
+            if let Some(bench) = bench {
+                command.args(["--bench", &bench]);
+            }
+
+            command.arg("--");
+            match save_baseline {
+                Some(name) => command.args(["--save-baseline", &name]),
+                None => command.args(["--baseline", &baseline]),
+            };
+
+            run(&mut command)
+        }
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+/// The cargo running us, so that the toolchain stays the same
+fn cargo() -> Command {
+    Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let status = command.status()?;
+    ensure!(status.success(), "{:?} failed with {}", command, status);
+    Ok(())
+}
+
+/// Why mounting can't work here, as on CI runners without /dev/fuse or fusermount
+fn fuse_available() -> Result<(), String> {
+    if !Path::new("/dev/fuse").exists() {
+        return Err("/dev/fuse does not exist".to_string());
+    }
+
+    if fusermount().is_none() {
+        return Err(format!("none of {} is installed", FUSERMOUNT.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn fusermount() -> Option<&'static str> {
+    let path = env::var_os("PATH")?;
+
+    FUSERMOUNT
+        .into_iter()
+        .find(|name| env::split_paths(&path).any(|dir| dir.join(name).is_file()))
+}
+
+/// Unmount FUSE mounts whose mount point is in the temporary directory, where tests create
+/// theirs. Mounts of crashed tests are left disconnected and are unmounted all the same.
+fn clean_mounts(dry_run: bool) -> Result<()> {
+    let temp_dir = env::temp_dir().canonicalize()?;
+    let mounts = fuse_mounts()?
+        .into_iter()
+        .filter(|mount_point| mount_point.starts_with(&temp_dir))
+        .collect::<Vec<_>>();
+
+    if mounts.is_empty() {
+        println!("No leaked mounts below {:?}", temp_dir);
+        return Ok(());
+    }
+
+    let Some(fusermount) = fusermount() else {
+        bail!("Unmounting needs one of {}", FUSERMOUNT.join(", "));
+    };
+
+    let mut failed = 0;
+    for mount_point in mounts {
+        println!("Unmounting {:?}", mount_point);
+        if dry_run {
+            continue;
+        }
+
+        if let Err(err) = run(Command::new(fusermount).arg("-u").arg(&mount_point)) {
+            eprintln!("{}", err);
+            failed += 1;
+        }
+    }
+
+    ensure!(failed == 0, "{} mounts could not be unmounted", failed);
+    Ok(())
+}
+
+/// Mount points of the FUSE file systems, from the mount table of the kernel
+fn fuse_mounts() -> Result<Vec<PathBuf>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    // NOTE: The fields after the separator are the file system type and the source
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let mount_point = mount.split(' ').nth(4)?;
+            let fs_type = fs.split(' ').next()?;
+
+            (fs_type == "fuse" || fs_type.starts_with("fuse."))
+                .then(|| PathBuf::from(unescape(mount_point)))
+        })
+        .collect())
+}
+
+/// Undo the octal escapes of spaces, tabs, newlines and backslashes in mount points
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+
+        match rest
+            .get(..3)
+            .and_then(|octal| u8::from_str_radix(octal, 8).ok())
+        {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[3..];
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}